@@ -0,0 +1,637 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use zeroize::Zeroizing;
+
+use keylight2::agent_client::*;
+use keylight2::error::*;
+use keylight2::state::*;
+use keylight2::url_match::*;
+use keylight2::vaults::*;
+
+const DEFAULT_CREDENTIAL_FOLDER: &str = "Git";
+
+#[derive(Parser)]
+#[command(
+    name = "git-credential-keylight",
+    version,
+    about = "git credential helper answering from an unlocked Keylight vault",
+    after_help = "The helper never asks for the master password, unlock the vault with `keylight agent` first.\n\
+                  Enable it with `git config --global credential.helper keylight`, or\n\
+                  `git config --global credential.helper \"keylight --folder Tokens\"` for another folder."
+)]
+struct Cli {
+    /// Name of the vault whose agent is asked
+    #[arg(long, default_value = DEFAULT_VAULT_NAME)]
+    vault: String,
+
+    /// Folder new credentials are stored in, created when missing
+    #[arg(long, default_value = DEFAULT_CREDENTIAL_FOLDER)]
+    folder: String,
+
+    #[command(subcommand)]
+    operation: Operation,
+}
+
+// The operations git calls helpers with, see gitcredentials(7)
+#[derive(Subcommand)]
+enum Operation {
+    /// Print the stored username and password for the described credential
+    Get,
+    /// Save the described credential
+    Store,
+    /// Remove the described credential
+    Erase,
+}
+
+#[derive(Default)]
+struct CredentialRequest {
+    protocol: Option<String>,
+    host: Option<String>,
+    path: Option<String>,
+    username: Option<String>,
+    password: Option<Zeroizing<String>>,
+}
+
+impl CredentialRequest {
+    // key=value lines up to an empty line, unknown keys (capability[], wwwauth[], ...) are skipped
+    fn read(input: impl BufRead) -> io::Result<Self> {
+        let mut request = Self::default();
+        for line in input.lines() {
+            let line = Zeroizing::new(line?);
+            if line.is_empty() {
+                break;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match key {
+                "protocol" => request.protocol = Some(value.to_string()),
+                "host" => request.host = Some(value.to_string()),
+                "path" => request.path = Some(value.to_string()),
+                "username" => request.username = Some(value.to_string()),
+                "password" => request.password = Some(Zeroizing::new(value.to_string())),
+                // git treats a url attribute as if its parts had been sent one by one
+                "url" => {
+                    if let Ok(url) = url::Url::parse(value) {
+                        request.protocol = Some(url.scheme().to_string());
+                        request.host = url_host(&url);
+                        request.path = Some(normalize_path(url.path()).to_string())
+                            .filter(|path| !path.is_empty());
+                        if !url.username().is_empty() {
+                            request.username = Some(url.username().to_string());
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(request)
+    }
+
+    fn url(&self) -> Option<String> {
+        let protocol = self.protocol.as_deref()?;
+        let host = self.host.as_deref()?;
+        Some(match self.path.as_deref().map(normalize_path) {
+            Some(path) if !path.is_empty() => format!("{protocol}://{host}/{path}"),
+            _ => format!("{protocol}://{host}"),
+        })
+    }
+}
+
+// host[:port], as git sends it
+fn url_host(url: &url::Url) -> Option<String> {
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    })
+}
+
+// "/team/repo.git/" and "team/repo" name the same repository
+fn normalize_path(path: &str) -> &str {
+    let path = path.trim_matches('/');
+    path.strip_suffix(".git").unwrap_or(path)
+}
+
+struct StoredCredential {
+    entry_id: i64,
+    // Absent when the entry only matches through a regular expression
+    url: Option<url::Url>,
+    username: Option<String>,
+    password: Option<Zeroizing<String>>,
+}
+
+impl StoredCredential {
+    fn from_entry_data(entry_id: i64, entry_data: &[EntryData]) -> Self {
+        let section = |section_type: &str| {
+            entry_data
+                .iter()
+                .find(|section| section.section_type.as_deref() == Some(section_type))
+                .and_then(|section| section.section_data.as_deref())
+                .map(|data| String::from_utf8_lossy(data).into_owned())
+        };
+        let url = entry_data
+            .iter()
+            .filter(|section| {
+                section
+                    .section_type
+                    .as_deref()
+                    .and_then(UrlMatchMode::from_section_type)
+                    .is_some_and(|mode| !matches!(mode, UrlMatchMode::Regex | UrlMatchMode::Never))
+            })
+            .find_map(|section| {
+                parse_url(&String::from_utf8_lossy(section.section_data.as_deref()?))
+            });
+
+        StoredCredential {
+            entry_id,
+            url,
+            username: section("username"),
+            password: section("password").map(Zeroizing::new),
+        }
+    }
+
+    // Entries without a path cover the whole host, a path keeps a token to that repository
+    fn covers_path(&self, request: &CredentialRequest) -> bool {
+        let Some(url) = &self.url else {
+            return true;
+        };
+        let stored_path = normalize_path(url.path());
+        let requested_path = normalize_path(request.path.as_deref().unwrap_or_default());
+        stored_path.is_empty()
+            || requested_path == stored_path
+            || requested_path
+                .strip_prefix(stored_path)
+                .is_some_and(|rest| rest.starts_with('/'))
+    }
+
+    fn is_sendable(&self) -> bool {
+        let sendable = |value: &str| !value.contains(['\n', '\0']);
+        self.username.as_deref().is_none_or(sendable)
+            && self
+                .password
+                .as_deref()
+                .is_none_or(|password| sendable(password))
+    }
+
+    fn is_for_location(&self, request: &CredentialRequest) -> bool {
+        let Some(url) = &self.url else {
+            return false;
+        };
+        request.protocol.as_deref() == Some(url.scheme())
+            && request.host.is_some()
+            && request.host == url_host(url)
+            && normalize_path(url.path())
+                == normalize_path(request.path.as_deref().unwrap_or_default())
+    }
+
+    fn is_exactly(&self, request: &CredentialRequest) -> bool {
+        self.is_for_location(request) && self.username == request.username
+    }
+
+    // An entry saved without a username takes the one git now knows, one of another user stays
+    fn is_updated_by(&self, request: &CredentialRequest) -> bool {
+        self.is_for_location(request)
+            && (self.username.is_none() || self.username == request.username)
+    }
+}
+
+#[derive(Debug)]
+enum HelperError {
+    Vault(VaultError),
+    NoAgent(String),
+}
+
+impl fmt::Display for HelperError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HelperError::Vault(error) => write!(f, "{error}"),
+            HelperError::NoAgent(vault) => write!(
+                f,
+                "No Keylight agent is running for \"{vault}\", start one with `keylight agent`"
+            ),
+        }
+    }
+}
+
+impl From<VaultError> for HelperError {
+    fn from(error: VaultError) -> Self {
+        HelperError::Vault(error)
+    }
+}
+
+impl From<io::Error> for HelperError {
+    fn from(error: io::Error) -> Self {
+        HelperError::Vault(error.into())
+    }
+}
+
+fn unexpected_response() -> VaultError {
+    VaultError::Agent("unexpected response".to_string())
+}
+
+struct Helper {
+    client: AgentClient,
+    folder: String,
+}
+
+impl Helper {
+    async fn request(&mut self, request: AgentRequest) -> Result<AgentResponse, VaultError> {
+        self.client.request(&request).await
+    }
+
+    async fn folders(&mut self) -> Result<Vec<Folder>, VaultError> {
+        match self.request(AgentRequest::Folders).await? {
+            AgentResponse::Folders { folders } => Ok(folders),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    async fn created(&mut self, request: AgentRequest) -> Result<i64, VaultError> {
+        match self.request(request).await? {
+            AgentResponse::Created { id } => Ok(id),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    async fn set_section(
+        &mut self,
+        entry_id: i64,
+        section_type: &str,
+        section_data: &[u8],
+    ) -> Result<(), VaultError> {
+        self.request(AgentRequest::SetEntrySection {
+            entry_id,
+            section_name: section_type.to_string(),
+            section_type: section_type.to_string(),
+            section_data: Zeroizing::new(section_data.to_vec()),
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn entry_data(&mut self, entry_id: i64) -> Result<Vec<EntryData>, VaultError> {
+        match self.request(AgentRequest::EntryData { entry_id }).await? {
+            AgentResponse::EntryData { entry_data } => Ok(entry_data),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    // The entries whose URL rules match, wherever they were filed, ranked by the vault. Only
+    // their sections are fetched rather than those of every entry
+    async fn stored_credentials(
+        &mut self,
+        url: String,
+    ) -> Result<Vec<StoredCredential>, VaultError> {
+        let candidates = match self.request(AgentRequest::UrlCandidates { url }).await? {
+            AgentResponse::UrlCandidates { candidates } => candidates,
+            _ => return Err(unexpected_response()),
+        };
+        let mut credentials = Vec::new();
+        for candidate in candidates {
+            let entry_data = self.entry_data(candidate.entry_id).await?;
+            credentials.push(StoredCredential::from_entry_data(
+                candidate.entry_id,
+                &entry_data,
+            ));
+        }
+        Ok(credentials)
+    }
+
+    // The first candidate for the right user and repository answers
+    async fn get(&mut self, request: &CredentialRequest) -> Result<(), HelperError> {
+        let Some(url) = request.url() else {
+            return Ok(());
+        };
+
+        for credential in self.stored_credentials(url).await? {
+            if (request.username.is_some() && request.username != credential.username)
+                || !credential.covers_path(request)
+            {
+                continue;
+            }
+            // git reads one attribute per line and stops at NUL, such a value would smuggle in others
+            if !credential.is_sendable() {
+                eprintln!(
+                    "[ERROR]: Skipped entry {}, its username or password contains a line break or NUL",
+                    credential.entry_id
+                );
+                continue;
+            }
+
+            let mut output = Zeroizing::new(String::new());
+            if let Some(username) = &credential.username {
+                output.push_str(&format!("username={username}\n"));
+            }
+            if let Some(password) = &credential.password {
+                output.push_str(&format!("password={}\n", password.as_str()));
+            }
+            io::stdout().lock().write_all(output.as_bytes())?;
+            break;
+        }
+        Ok(())
+    }
+
+    async fn store(&mut self, request: &CredentialRequest) -> Result<(), HelperError> {
+        let (Some(url), Some(password)) = (request.url(), &request.password) else {
+            return Ok(());
+        };
+
+        // A token that changed replaces the old one instead of piling up next to it
+        let credentials = self.stored_credentials(url.clone()).await?;
+        let existing = credentials
+            .iter()
+            .find(|credential| credential.is_exactly(request))
+            .or_else(|| {
+                credentials
+                    .iter()
+                    .find(|credential| credential.is_updated_by(request))
+            });
+        if let Some(existing) = existing {
+            if let Some(username) = request
+                .username
+                .as_ref()
+                .filter(|username| existing.username.as_ref() != Some(*username))
+            {
+                self.set_section(existing.entry_id, "username", username.as_bytes())
+                    .await?;
+            }
+            return Ok(self
+                .set_section(existing.entry_id, "password", password.as_bytes())
+                .await?);
+        }
+
+        let folder_id = match self
+            .folders()
+            .await?
+            .iter()
+            .find(|folder| folder.folder_name.as_deref() == Some(self.folder.as_str()))
+        {
+            Some(folder) => folder.folder_id,
+            None => {
+                let folder_name = self.folder.clone();
+                self.created(AgentRequest::CreateFolder { folder_name })
+                    .await?
+            }
+        };
+
+        let entry_name = url
+            .split_once("://")
+            .map_or(url.as_str(), |(_, rest)| rest)
+            .to_string();
+        let entry_id = self
+            .created(AgentRequest::CreateEntry {
+                folder_id,
+                entry_name,
+            })
+            .await?;
+        self.set_section(entry_id, "url", url.as_bytes()).await?;
+        if let Some(username) = &request.username {
+            self.set_section(entry_id, "username", username.as_bytes())
+                .await?;
+        }
+        self.set_section(entry_id, "password", password.as_bytes())
+            .await?;
+        Ok(())
+    }
+
+    // git erases credentials the host rejected, so only the entry holding that exact password goes
+    async fn erase(&mut self, request: &CredentialRequest) -> Result<(), HelperError> {
+        let Some(url) = request.url() else {
+            return Ok(());
+        };
+        let credentials = self.stored_credentials(url).await?;
+        for credential in credentials.iter().filter(|credential| {
+            credential.is_exactly(request)
+                && (request.password.is_none() || request.password == credential.password)
+        }) {
+            self.request(AgentRequest::DeleteEntry {
+                entry_id: credential.entry_id,
+            })
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), HelperError> {
+    let request = CredentialRequest::read(io::stdin().lock())?;
+    let vault = VaultLocation::in_directory(StorageServices::new().vault_directory(), &cli.vault);
+    let client = AgentClient::connect(&vault)
+        .await
+        .ok_or_else(|| HelperError::NoAgent(cli.vault.clone()))?;
+    let mut helper = Helper {
+        client,
+        folder: cli.folder,
+    };
+
+    match cli.operation {
+        Operation::Get => helper.get(&request).await,
+        Operation::Store => helper.store(&request).await,
+        Operation::Erase => helper.erase(&request).await,
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        // git moves on to its other helpers or the terminal prompt, a locked vault is no failure
+        Err(error @ HelperError::NoAgent(_)) => {
+            eprintln!("[INFO]: {error}");
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("[ERROR]: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keylight2::testing::ScratchVault;
+
+    fn request(text: &str) -> CredentialRequest {
+        CredentialRequest::read(text.as_bytes()).unwrap()
+    }
+
+    fn stored(url: &str, username: Option<&str>, password: &str) -> StoredCredential {
+        StoredCredential {
+            entry_id: 1,
+            url: parse_url(url),
+            username: username.map(str::to_string),
+            password: Some(Zeroizing::new(password.to_string())),
+        }
+    }
+
+    #[test]
+    fn requests_are_read_like_git_sends_them() {
+        let read = request(
+            "protocol=https\nhost=git.example.com:8443\npath=team/repo.git\nusername=alice\n\
+             password=secret\ncapability[]=authtype\nwwwauth[]=Basic realm=\"git\"\n\nhost=ignored\n",
+        );
+        assert_eq!(read.protocol.as_deref(), Some("https"));
+        assert_eq!(read.host.as_deref(), Some("git.example.com:8443"));
+        assert_eq!(read.username.as_deref(), Some("alice"));
+        assert_eq!(read.password.as_deref().map(String::as_str), Some("secret"));
+        assert_eq!(
+            read.url().as_deref(),
+            Some("https://git.example.com:8443/team/repo")
+        );
+
+        // A url stands for its parts, later attributes still override them
+        let read = request("url=https://bob@git.example.com:8443/team/repo.git/\n");
+        assert_eq!(read.host.as_deref(), Some("git.example.com:8443"));
+        assert_eq!(read.path.as_deref(), Some("team/repo"));
+        assert_eq!(read.username.as_deref(), Some("bob"));
+        let read = request("url=https://git.example.com/\nusername=carol\n");
+        assert_eq!(read.path, None);
+        assert_eq!(read.username.as_deref(), Some("carol"));
+        assert_eq!(read.url().as_deref(), Some("https://git.example.com"));
+
+        let read = request("url=not a url\nno equals sign\n");
+        assert_eq!(read.url(), None);
+    }
+
+    #[test]
+    fn paths_name_repositories() {
+        assert_eq!(normalize_path("/team/repo.git/"), "team/repo");
+        assert_eq!(normalize_path("team/repo"), "team/repo");
+        assert_eq!(normalize_path("/"), "");
+
+        let host_wide = stored("https://git.example.com", None, "token");
+        let team = stored("https://git.example.com/team", None, "token");
+        for (path, covered) in [
+            (None, false),
+            (Some("team"), true),
+            (Some("team/repo.git"), true),
+            (Some("teams/repo"), false),
+            (Some("other/repo"), false),
+        ] {
+            let read = CredentialRequest {
+                path: path.map(str::to_string),
+                ..CredentialRequest::default()
+            };
+            assert!(host_wide.covers_path(&read));
+            assert_eq!(team.covers_path(&read), covered, "{path:?}");
+        }
+        let regex_only = StoredCredential {
+            url: None,
+            ..stored("https://git.example.com", None, "token")
+        };
+        assert!(regex_only.covers_path(&request("path=anything\n")));
+    }
+
+    #[test]
+    fn exact_matches_need_the_same_user_and_repository() {
+        let credential = stored("https://git.example.com/team/repo", Some("alice"), "token");
+        let exact = "protocol=https\nhost=git.example.com\npath=team/repo.git\nusername=alice\n";
+        assert!(credential.is_exactly(&request(exact)));
+        assert!(!credential.is_exactly(&request(&exact.replace("alice", "bob"))));
+        assert!(!credential.is_exactly(&request(&exact.replace("https", "http"))));
+        assert!(!credential.is_exactly(&request(&exact.replace("team/repo", "team/other"))));
+        assert!(!credential.is_exactly(&request(&exact.replace("example.com", "example.com:22"))));
+        assert!(!credential.is_updated_by(&request(&exact.replace("alice", "bob"))));
+
+        let anonymous = stored("https://git.example.com/team/repo", None, "token");
+        assert!(!anonymous.is_exactly(&request(exact)));
+        assert!(anonymous.is_updated_by(&request(exact)));
+    }
+
+    #[test]
+    fn line_breaks_and_nul_are_never_sent() {
+        assert!(stored("https://a.example", Some("alice"), "token").is_sendable());
+        assert!(!stored("https://a.example", Some("alice"), "tok\nen").is_sendable());
+        assert!(!stored("https://a.example", Some("alice\nhost=evil"), "token").is_sendable());
+        assert!(!stored("https://a.example", None, "token\0").is_sendable());
+        assert!(!stored("https://a.example", None, "token\r\n").is_sendable());
+    }
+
+    #[tokio::test]
+    async fn storing_again_updates_the_saved_credential() {
+        let vault = ScratchVault::new("git-credential-store").await;
+        let mut helper = Helper {
+            client: vault.agent().await,
+            folder: DEFAULT_CREDENTIAL_FOLDER.to_string(),
+        };
+        let url = "https://git.example.com/team/repo".to_string();
+
+        helper
+            .store(&request(
+                "url=https://git.example.com/team/repo.git\npassword=first\n",
+            ))
+            .await
+            .unwrap();
+        // git learned the username since, it is saved with the new token
+        helper
+            .store(&request(
+                "protocol=https\nhost=git.example.com\npath=team/repo\nusername=alice\npassword=second\n",
+            ))
+            .await
+            .unwrap();
+        let credentials = helper.stored_credentials(url.clone()).await.unwrap();
+        assert_eq!(credentials.len(), 1);
+        assert_eq!(credentials[0].username.as_deref(), Some("alice"));
+        assert_eq!(
+            credentials[0].password.as_deref().map(String::as_str),
+            Some("second")
+        );
+
+        // Another account on the same repository is kept apart
+        let bob = "url=https://bob@git.example.com/team/repo\npassword=bobs\n";
+        helper.store(&request(bob)).await.unwrap();
+        helper
+            .store(&request(
+                "url=https://alice@git.example.com/team/repo\npassword=third\n",
+            ))
+            .await
+            .unwrap();
+        let credentials = helper.stored_credentials(url.clone()).await.unwrap();
+        let mut saved: Vec<(Option<String>, String)> = credentials
+            .iter()
+            .map(|credential| {
+                (
+                    credential.username.clone(),
+                    credential.password.as_deref().cloned().unwrap_or_default(),
+                )
+            })
+            .collect();
+        saved.sort();
+        assert_eq!(
+            saved,
+            [
+                (Some("alice".to_string()), "third".to_string()),
+                (Some("bob".to_string()), "bobs".to_string())
+            ]
+        );
+        let folders = helper.folders().await.unwrap();
+        let git_folders = folders
+            .iter()
+            .filter(|folder| folder.folder_name.as_deref() == Some(DEFAULT_CREDENTIAL_FOLDER))
+            .count();
+        assert_eq!(git_folders, 1);
+
+        // Only the rejected password is erased
+        helper
+            .erase(&request(
+                "url=https://bob@git.example.com/team/repo\npassword=old\n",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            helper.stored_credentials(url.clone()).await.unwrap().len(),
+            2
+        );
+        helper.erase(&request(bob)).await.unwrap();
+        let credentials = helper.stored_credentials(url).await.unwrap();
+        assert_eq!(credentials.len(), 1);
+        assert_eq!(credentials[0].username.as_deref(), Some("alice"));
+
+        helper.request(AgentRequest::Lock).await.unwrap();
+    }
+}
@@ -0,0 +1,878 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use argon2::password_hash::rand_core::OsRng;
+use chacha20poly1305::{aead::Aead, AeadCore, KeyInit, XChaCha20Poly1305, XNonce};
+use data_encoding::{BASE64, HEXUPPER};
+use futures::StreamExt;
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+use zeroize::{Zeroize, Zeroizing};
+
+use keylight2::agent_client::*;
+use keylight2::error::*;
+use keylight2::state::*;
+use keylight2::url_match::*;
+use keylight2::vaults::*;
+
+// Native messaging host for the Keylight browser extension. Browsers start it with the extension
+// on the other end of stdin/stdout and frame every JSON message with its length as a native endian
+// u32. The vault is reached through a running `keylight agent`, the host never unlocks it itself.
+//
+// The first message exchanges X25519 keys in the clear, everything after that is sealed with
+// XChaCha20-Poly1305 under a key derived from the shared secret:
+//
+//   {"action":"change-public-keys","public_key":"<base64>"}
+//   {"action":"get-logins","message":"<base64 ciphertext>","nonce":"<base64>"}
+//
+// Before an extension sees any login it has to be paired, which the user confirms through a
+// desktop notification. Pairings are kept as sections of an entry in the vault, removing a section
+// revokes that extension. The section is named after the pairing's id, which anyone who can read
+// the vault's entries sees, so every request also carries the id_key the extension generated and
+// only the two together are accepted.
+
+const HOST_NAME: &str = "dev.schell.keylight";
+const LOGIN_FOLDER: &str = "Browser";
+const PAIRING_ENTRY_NAME: &str = "Keylight browser pairings";
+const PAIRING_SECTION_TYPE: &str = "browser_pairing";
+const PAIRING_CONFIRM_TIMEOUT: Duration = Duration::from_secs(60);
+// Browsers refuse messages from the host above 1 MiB, requests are held to the same limit
+const MAX_MESSAGE_LENGTH: usize = 1024 * 1024;
+
+#[derive(Debug)]
+enum HostError {
+    Vault(VaultError),
+    Protocol(String),
+    NotPaired,
+    PairingDenied,
+    NoAgent(String),
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostError::Vault(error) => write!(f, "{error}"),
+            HostError::Protocol(message) => write!(f, "{message}"),
+            HostError::NotPaired => write!(f, "The extension is not paired with this vault"),
+            HostError::PairingDenied => write!(f, "Pairing was not confirmed"),
+            HostError::NoAgent(vault) => write!(
+                f,
+                "The vault \"{vault}\" is locked, unlock it with `keylight agent`"
+            ),
+        }
+    }
+}
+
+impl From<VaultError> for HostError {
+    fn from(error: VaultError) -> Self {
+        HostError::Vault(error)
+    }
+}
+
+impl From<io::Error> for HostError {
+    fn from(error: io::Error) -> Self {
+        HostError::Vault(error.into())
+    }
+}
+
+impl From<serde_json::Error> for HostError {
+    fn from(error: serde_json::Error) -> Self {
+        HostError::Protocol(format!("Malformed message: {error}"))
+    }
+}
+
+impl From<data_encoding::DecodeError> for HostError {
+    fn from(error: data_encoding::DecodeError) -> Self {
+        HostError::Protocol(format!("Malformed base64: {error}"))
+    }
+}
+
+// Outer frame, only change-public-keys carries anything in the clear
+#[derive(Deserialize)]
+struct Envelope {
+    action: String,
+    public_key: Option<String>,
+    message: Option<String>,
+    nonce: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+enum HostRequest {
+    Associate {
+        id_key: String,
+    },
+    TestAssociate {
+        id: String,
+        id_key: String,
+    },
+    GetLogins {
+        id: String,
+        id_key: String,
+        url: String,
+    },
+    SetLogin {
+        id: String,
+        id_key: String,
+        url: String,
+        username: String,
+        password: Zeroizing<String>,
+        name: Option<String>,
+    },
+}
+
+#[derive(Serialize, Zeroize)]
+struct Login {
+    entry_id: i64,
+    name: String,
+    username: String,
+    password: String,
+}
+
+impl Drop for Login {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, zbus::zvariant::Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+
+    #[zbus(signal)]
+    fn action_invoked(&self, id: u32, action_key: String) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn notification_closed(&self, id: u32, reason: u32) -> zbus::Result<()>;
+}
+
+// Both sides show the same short code, so the user can tell the extension asking is the one in front of them
+fn pairing_code(id_key: &[u8]) -> String {
+    let digest = HEXUPPER.encode(&Sha256::digest(id_key)[..3]);
+    format!("{}-{}", &digest[..3], &digest[3..])
+}
+
+async fn confirm_pairing(vault_name: &str, code: &str) -> Result<bool, zbus::Error> {
+    let connection = zbus::Connection::session().await?;
+    let notifications = NotificationsProxy::new(&connection).await?;
+    let mut actions = notifications.receive_action_invoked().await?;
+    let mut closed = notifications.receive_notification_closed().await?;
+
+    let id = notifications
+        .notify(
+            "Keylight",
+            0,
+            "dialog-password",
+            "Pair browser extension?",
+            &format!(
+                "A browser extension wants to fill and save logins of the vault \"{vault_name}\". \
+                 Only allow it if the extension shows the code {code}."
+            ),
+            &["allow", "Allow", "deny", "Deny"],
+            HashMap::from([("urgency", zbus::zvariant::Value::U8(2))]),
+            0,
+        )
+        .await?;
+
+    let answer = async {
+        loop {
+            tokio::select! {
+                Some(signal) = actions.next() => {
+                    match signal.args() {
+                        Ok(args) if args.id == id => return args.action_key == "allow",
+                        _ => continue,
+                    }
+                }
+                Some(signal) = closed.next() => {
+                    match signal.args() {
+                        Ok(args) if args.id == id => return false,
+                        _ => continue,
+                    }
+                }
+                else => return false,
+            }
+        }
+    };
+    Ok(tokio::time::timeout(PAIRING_CONFIRM_TIMEOUT, answer)
+        .await
+        .unwrap_or(false))
+}
+
+fn section_text(sections: &[EntryData], section_type: &str) -> Option<String> {
+    sections
+        .iter()
+        .find(|section| section.section_type.as_deref() == Some(section_type))
+        .and_then(|section| section.section_data.as_deref())
+        .map(|data| String::from_utf8_lossy(data).into_owned())
+}
+
+fn unexpected_response() -> VaultError {
+    VaultError::Agent("unexpected response".to_string())
+}
+
+struct Host {
+    client: AgentClient,
+    vault_name: String,
+    cipher: Option<XChaCha20Poly1305>,
+}
+
+impl Host {
+    async fn request(&mut self, request: AgentRequest) -> Result<AgentResponse, VaultError> {
+        self.client.request(&request).await
+    }
+
+    async fn created(&mut self, request: AgentRequest) -> Result<i64, VaultError> {
+        match self.request(request).await? {
+            AgentResponse::Created { id } => Ok(id),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    async fn folders(&mut self) -> Result<Vec<Folder>, VaultError> {
+        match self.request(AgentRequest::Folders).await? {
+            AgentResponse::Folders { folders } => Ok(folders),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    async fn entries(&mut self, folder_id: i64) -> Result<Vec<Entry>, VaultError> {
+        match self.request(AgentRequest::Entries { folder_id }).await? {
+            AgentResponse::Entries { entries } => Ok(entries),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    async fn entry_data(&mut self, entry_id: i64) -> Result<Vec<EntryData>, VaultError> {
+        match self.request(AgentRequest::EntryData { entry_id }).await? {
+            AgentResponse::EntryData { entry_data } => Ok(entry_data),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    async fn set_section(
+        &mut self,
+        entry_id: i64,
+        section_name: &str,
+        section_type: &str,
+        section_data: &[u8],
+    ) -> Result<(), VaultError> {
+        self.request(AgentRequest::SetEntrySection {
+            entry_id,
+            section_name: section_name.to_string(),
+            section_type: section_type.to_string(),
+            section_data: Zeroizing::new(section_data.to_vec()),
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn login_folder(&mut self) -> Result<i64, VaultError> {
+        let folders = self.folders().await?;
+        match folders
+            .iter()
+            .find(|folder| folder.folder_name.as_deref() == Some(LOGIN_FOLDER))
+        {
+            Some(folder) => Ok(folder.folder_id),
+            None => {
+                self.created(AgentRequest::CreateFolder {
+                    folder_name: LOGIN_FOLDER.to_string(),
+                })
+                .await
+            }
+        }
+    }
+
+    // Entries whose URL rules apply to the page, best match first
+    async fn candidates(&mut self, url: &str) -> Result<Vec<UrlCandidate>, VaultError> {
+        let request = AgentRequest::UrlCandidates {
+            url: url.to_string(),
+        };
+        match self.request(request).await? {
+            AgentResponse::UrlCandidates { candidates } => Ok(candidates),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    async fn pairing_entry(&mut self) -> Result<Option<(i64, Vec<EntryData>)>, VaultError> {
+        let folder_id = self.login_folder().await?;
+        let entry = self
+            .entries(folder_id)
+            .await?
+            .into_iter()
+            .find(|entry| entry.entry_name.as_deref() == Some(PAIRING_ENTRY_NAME));
+        match entry {
+            Some(entry) => Ok(Some((
+                entry.entry_id,
+                self.entry_data(entry.entry_id).await?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    // The key is compared in constant time, how long a refusal takes tells nothing about it
+    async fn is_paired(&mut self, id: &str, id_key: &str) -> Result<bool, VaultError> {
+        let Some((_, sections)) = self.pairing_entry().await? else {
+            return Ok(false);
+        };
+        Ok(sections.iter().any(|section| {
+            section.section_type.as_deref() == Some(PAIRING_SECTION_TYPE)
+                && section.section_name.as_deref() == Some(id)
+                && section
+                    .section_data
+                    .as_deref()
+                    .is_some_and(|stored| bool::from(stored.ct_eq(id_key.as_bytes())))
+        }))
+    }
+
+    async fn require_pairing(&mut self, id: &str, id_key: &str) -> Result<(), HostError> {
+        match self.is_paired(id, id_key).await? {
+            true => Ok(()),
+            false => Err(HostError::NotPaired),
+        }
+    }
+
+    fn seal(&self, response: &serde_json::Value) -> Result<serde_json::Value, HostError> {
+        let cipher = self
+            .cipher
+            .as_ref()
+            .ok_or_else(|| HostError::Protocol("No keys were exchanged".to_string()))?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let plaintext = Zeroizing::new(serde_json::to_vec(response)?);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| HostError::Protocol("Could not encrypt the response".to_string()))?;
+        Ok(serde_json::json!({
+            "action": response["action"],
+            "message": BASE64.encode(&ciphertext),
+            "nonce": BASE64.encode(&nonce),
+        }))
+    }
+
+    fn open(&self, envelope: &Envelope) -> Result<Zeroizing<Vec<u8>>, HostError> {
+        let cipher = self
+            .cipher
+            .as_ref()
+            .ok_or_else(|| HostError::Protocol("No keys were exchanged".to_string()))?;
+        let (Some(message), Some(nonce)) = (&envelope.message, &envelope.nonce) else {
+            return Err(HostError::Protocol("Missing message or nonce".to_string()));
+        };
+        let nonce = BASE64.decode(nonce.as_bytes())?;
+        if nonce.len() != 24 {
+            return Err(HostError::Protocol("Nonces are 24 bytes".to_string()));
+        }
+        cipher
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                BASE64.decode(message.as_bytes())?.as_slice(),
+            )
+            .map(Zeroizing::new)
+            .map_err(|_| HostError::Protocol("Could not decrypt the message".to_string()))
+    }
+
+    fn change_public_keys(&mut self, envelope: &Envelope) -> Result<serde_json::Value, HostError> {
+        let client_key = BASE64.decode(
+            envelope
+                .public_key
+                .as_deref()
+                .ok_or_else(|| HostError::Protocol("Missing public key".to_string()))?
+                .as_bytes(),
+        )?;
+        let client_key: [u8; 32] = client_key
+            .try_into()
+            .map_err(|_| HostError::Protocol("Public keys are 32 bytes".to_string()))?;
+
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let public_key = PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&PublicKey::from(client_key));
+        if !shared.was_contributory() {
+            return Err(HostError::Protocol("Invalid public key".to_string()));
+        }
+
+        let mut key = Zeroizing::new([0u8; 32]);
+        Hkdf::<Sha256>::new(None, shared.as_bytes())
+            .expand(HOST_NAME.as_bytes(), key.as_mut())
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        self.cipher = Some(XChaCha20Poly1305::new(key.as_ref().into()));
+
+        Ok(serde_json::json!({
+            "action": "change-public-keys",
+            "public_key": BASE64.encode(public_key.as_bytes()),
+            "success": true,
+        }))
+    }
+
+    async fn associate(&mut self, id_key: String) -> Result<serde_json::Value, HostError> {
+        let key_bytes = BASE64.decode(id_key.as_bytes())?;
+        if key_bytes.len() != 32 {
+            return Err(HostError::Protocol(
+                "Identity keys are 32 bytes".to_string(),
+            ));
+        }
+
+        let code = pairing_code(&key_bytes);
+        let confirmed = confirm_pairing(&self.vault_name, &code)
+            .await
+            .map_err(|error| {
+                HostError::Protocol(format!(
+                    "Pairing needs a desktop notification service to ask for confirmation: {error}"
+                ))
+            })?;
+        if !confirmed {
+            return Err(HostError::PairingDenied);
+        }
+        let id = self.store_pairing(&id_key).await?;
+        Ok(serde_json::json!({ "action": "associate", "id": id, "success": true }))
+    }
+
+    // Keeps a confirmed pairing under a new id and returns the id
+    async fn store_pairing(&mut self, id_key: &str) -> Result<String, HostError> {
+        let entry_id = match self.pairing_entry().await? {
+            Some((entry_id, _)) => entry_id,
+            None => {
+                let folder_id = self.login_folder().await?;
+                self.created(AgentRequest::CreateEntry {
+                    folder_id,
+                    entry_name: PAIRING_ENTRY_NAME.to_string(),
+                })
+                .await?
+            }
+        };
+        let mut id_bytes = [0u8; 8];
+        argon2::password_hash::rand_core::RngCore::fill_bytes(&mut OsRng, &mut id_bytes);
+        let id = format!("extension-{}", HEXUPPER.encode(&id_bytes).to_lowercase());
+        self.set_section(entry_id, &id, PAIRING_SECTION_TYPE, id_key.as_bytes())
+            .await?;
+        eprintln!("[INFO]: Paired a browser extension as {id}");
+        Ok(id)
+    }
+
+    async fn get_logins(
+        &mut self,
+        id: String,
+        id_key: String,
+        url: String,
+    ) -> Result<serde_json::Value, HostError> {
+        self.require_pairing(&id, &id_key).await?;
+
+        let mut logins = Vec::new();
+        for candidate in self.candidates(&url).await? {
+            let sections = self.entry_data(candidate.entry_id).await?;
+            logins.push(Login {
+                entry_id: candidate.entry_id,
+                name: candidate.entry_name.clone().unwrap_or_default(),
+                username: section_text(&sections, "username").unwrap_or_default(),
+                password: section_text(&sections, "password").unwrap_or_default(),
+            });
+        }
+
+        Ok(serde_json::json!({ "action": "get-logins", "entries": logins, "success": true }))
+    }
+
+    // Saving a login for the same host and username updates its password, a match by base or
+    // equivalent domain is a different account
+    async fn set_login(
+        &mut self,
+        id: String,
+        id_key: String,
+        url: String,
+        username: String,
+        password: Zeroizing<String>,
+        name: Option<String>,
+    ) -> Result<serde_json::Value, HostError> {
+        self.require_pairing(&id, &id_key).await?;
+        let page = parse_url(&url).ok_or_else(|| HostError::Protocol("Invalid url".to_string()))?;
+        let origin = page.origin().ascii_serialization();
+
+        let mut existing = None;
+        for candidate in self.candidates(&url).await? {
+            if candidate.level < MatchLevel::Host {
+                break;
+            }
+            let sections = self.entry_data(candidate.entry_id).await?;
+            if section_text(&sections, "username").as_deref() == Some(username.as_str()) {
+                existing = Some(candidate.entry_id);
+                break;
+            }
+        }
+
+        let entry_id = match existing {
+            Some(entry_id) => entry_id,
+            None => {
+                let folder_id = self.login_folder().await?;
+                let entry_name = name
+                    .or_else(|| page.host_str().map(str::to_string))
+                    .unwrap_or_else(|| origin.clone());
+                let entry_id = self
+                    .created(AgentRequest::CreateEntry {
+                        folder_id,
+                        entry_name,
+                    })
+                    .await?;
+                self.set_section(
+                    entry_id,
+                    "url",
+                    UrlMatchMode::BaseDomain.section_type(),
+                    origin.as_bytes(),
+                )
+                .await?;
+                self.set_section(entry_id, "username", "username", username.as_bytes())
+                    .await?;
+                entry_id
+            }
+        };
+        self.set_section(entry_id, "password", "password", password.as_bytes())
+            .await?;
+
+        Ok(serde_json::json!({ "action": "set-login", "entry_id": entry_id, "success": true }))
+    }
+
+    async fn handle(&mut self, envelope: Envelope) -> Result<serde_json::Value, HostError> {
+        if envelope.action == "change-public-keys" {
+            return self.change_public_keys(&envelope);
+        }
+
+        let message = self.open(&envelope)?;
+        let response = match serde_json::from_slice::<HostRequest>(&message)? {
+            HostRequest::Associate { id_key } => self.associate(id_key).await?,
+            HostRequest::TestAssociate { id, id_key } => {
+                if !self.is_paired(&id, &id_key).await? {
+                    return Err(HostError::NotPaired);
+                }
+                serde_json::json!({ "action": "test-associate", "id": id, "success": true })
+            }
+            HostRequest::GetLogins { id, id_key, url } => self.get_logins(id, id_key, url).await?,
+            HostRequest::SetLogin {
+                id,
+                id_key,
+                url,
+                username,
+                password,
+                name,
+            } => {
+                self.set_login(id, id_key, url, username, password, name)
+                    .await?
+            }
+        };
+        self.seal(&response)
+    }
+}
+
+async fn read_message(
+    stdin: &mut tokio::io::Stdin,
+) -> Result<Option<Zeroizing<Vec<u8>>>, HostError> {
+    let mut length = [0u8; 4];
+    match stdin.read_exact(&mut length).await {
+        Ok(_) => {}
+        // The browser closes stdin when the extension disconnects
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error.into()),
+    }
+    let length = u32::from_ne_bytes(length) as usize;
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(HostError::Protocol("Message too large".to_string()));
+    }
+
+    let mut message = Zeroizing::new(vec![0u8; length]);
+    stdin.read_exact(&mut message).await?;
+    Ok(Some(message))
+}
+
+async fn write_message(
+    stdout: &mut tokio::io::Stdout,
+    message: &serde_json::Value,
+) -> Result<(), HostError> {
+    let message = Zeroizing::new(serde_json::to_vec(message)?);
+    stdout
+        .write_all(&(message.len() as u32).to_ne_bytes())
+        .await?;
+    stdout.write_all(&message).await?;
+    stdout.flush().await?;
+    Ok(())
+}
+
+// Prints the manifest the browser needs to find the host, e.g. for
+// ~/.mozilla/native-messaging-hosts/dev.schell.keylight.json
+fn print_manifest(browser: &str, extension_id: &str) -> Result<(), HostError> {
+    let path = std::env::current_exe()?;
+    let mut manifest = serde_json::json!({
+        "name": HOST_NAME,
+        "description": "Keylight password manager",
+        "path": path,
+        "type": "stdio",
+    });
+    match browser {
+        "firefox" => manifest["allowed_extensions"] = serde_json::json!([extension_id]),
+        "chrome" | "chromium" => {
+            manifest["allowed_origins"] =
+                serde_json::json!([format!("chrome-extension://{extension_id}/")])
+        }
+        _ => {
+            return Err(HostError::Protocol(format!(
+                "Unknown browser \"{browser}\", expected firefox, chrome or chromium"
+            )))
+        }
+    }
+    println!("{}", serde_json::to_string_pretty(&manifest)?);
+    Ok(())
+}
+
+async fn run() -> Result<(), HostError> {
+    // Browsers pass the caller's origin or manifest path as arguments, only --manifest is ours
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    if let [flag, browser, extension_id] = arguments.as_slice() {
+        if flag == "--manifest" {
+            return print_manifest(browser, extension_id);
+        }
+    }
+
+    // The browser gives no way to pass flags, so the vault is picked through the environment
+    let vault_name =
+        std::env::var("KEYLIGHT_VAULT").unwrap_or_else(|_| DEFAULT_VAULT_NAME.to_string());
+    let vault = VaultLocation::in_directory(StorageServices::new().vault_directory(), &vault_name);
+    let mut stdin = tokio::io::stdin();
+    let mut stdout = tokio::io::stdout();
+    let mut host: Option<Host> = None;
+
+    while let Some(message) = read_message(&mut stdin).await? {
+        let envelope: Envelope = match serde_json::from_slice(&message) {
+            Ok(envelope) => envelope,
+            Err(error) => {
+                let error = HostError::from(error);
+                write_message(
+                    &mut stdout,
+                    &serde_json::json!({ "success": false, "error": error.to_string() }),
+                )
+                .await?;
+                continue;
+            }
+        };
+        let action = envelope.action.clone();
+
+        // Connecting lazily lets the extension learn that the vault is locked instead of losing the host
+        if host.is_none() {
+            host = AgentClient::connect(&vault).await.map(|client| Host {
+                client,
+                vault_name: vault_name.clone(),
+                cipher: None,
+            });
+        }
+        let response = match host.as_mut() {
+            Some(host) => host.handle(envelope).await,
+            None => Err(HostError::NoAgent(vault_name.clone())),
+        };
+
+        let response = response.unwrap_or_else(|error| {
+            eprintln!("[ERROR]: {error}");
+            serde_json::json!({ "action": action, "success": false, "error": error.to_string() })
+        });
+        write_message(&mut stdout, &response).await?;
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("[ERROR]: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keylight2::testing::ScratchVault;
+
+    // The extension's end of the channel
+    struct Extension {
+        cipher: XChaCha20Poly1305,
+    }
+
+    impl Extension {
+        async fn connect(host: &mut Host) -> Self {
+            let secret = EphemeralSecret::random_from_rng(OsRng);
+            let envelope = Envelope {
+                action: "change-public-keys".to_string(),
+                public_key: Some(BASE64.encode(PublicKey::from(&secret).as_bytes())),
+                message: None,
+                nonce: None,
+            };
+            let answer = host.handle(envelope).await.unwrap();
+            let host_key: [u8; 32] = BASE64
+                .decode(answer["public_key"].as_str().unwrap().as_bytes())
+                .unwrap()
+                .try_into()
+                .unwrap();
+            let shared = secret.diffie_hellman(&PublicKey::from(host_key));
+            let mut key = Zeroizing::new([0u8; 32]);
+            Hkdf::<Sha256>::new(None, shared.as_bytes())
+                .expand(HOST_NAME.as_bytes(), key.as_mut())
+                .unwrap();
+            Self {
+                cipher: XChaCha20Poly1305::new(key.as_ref().into()),
+            }
+        }
+
+        async fn send(
+            &self,
+            host: &mut Host,
+            request: serde_json::Value,
+        ) -> Result<serde_json::Value, HostError> {
+            let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+            let message = self
+                .cipher
+                .encrypt(&nonce, request.to_string().as_bytes())
+                .unwrap();
+            let envelope = Envelope {
+                action: request["action"].as_str().unwrap().to_string(),
+                public_key: None,
+                message: Some(BASE64.encode(&message)),
+                nonce: Some(BASE64.encode(&nonce)),
+            };
+            let sealed = host.handle(envelope).await?;
+            let nonce = BASE64
+                .decode(sealed["nonce"].as_str().unwrap().as_bytes())
+                .unwrap();
+            let message = BASE64
+                .decode(sealed["message"].as_str().unwrap().as_bytes())
+                .unwrap();
+            let opened = self
+                .cipher
+                .decrypt(XNonce::from_slice(&nonce), message.as_slice())
+                .unwrap();
+            Ok(serde_json::from_slice(&opened).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_paired_key_gets_logins() {
+        let vault = ScratchVault::new("native-host").await;
+        let mut host = Host {
+            client: vault.agent().await,
+            vault_name: vault.location.name.clone(),
+            cipher: None,
+        };
+        let extension = Extension::connect(&mut host).await;
+        let id_key = BASE64.encode(&[7u8; 32]);
+        // What associate does once the user confirmed the notification
+        let id = host.store_pairing(&id_key).await.unwrap();
+
+        let tested = extension
+            .send(
+                &mut host,
+                serde_json::json!({ "action": "test-associate", "id": id, "id_key": id_key }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(tested["success"], true);
+
+        let saved = extension
+            .send(
+                &mut host,
+                serde_json::json!({
+                    "action": "set-login",
+                    "id": id,
+                    "id_key": id_key,
+                    "url": "https://login.example.com/signin",
+                    "username": "ann",
+                    "password": "hunter2",
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(saved["success"], true);
+        let logins = extension
+            .send(
+                &mut host,
+                serde_json::json!({
+                    "action": "get-logins",
+                    "id": id,
+                    "id_key": id_key,
+                    "url": "https://login.example.com/",
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(logins["entries"][0]["username"], "ann");
+        assert_eq!(logins["entries"][0]["password"], "hunter2");
+
+        // The id alone is readable by anything that lists the vault's entries
+        let wrong_key = BASE64.encode(&[8u8; 32]);
+        for (id, id_key) in [(id.as_str(), wrong_key.as_str()), ("extension-0", &id_key)] {
+            for request in [
+                serde_json::json!({
+                    "action": "get-logins",
+                    "id": id,
+                    "id_key": id_key,
+                    "url": "https://login.example.com/",
+                }),
+                serde_json::json!({
+                    "action": "set-login",
+                    "id": id,
+                    "id_key": id_key,
+                    "url": "https://login.example.com/",
+                    "username": "ann",
+                    "password": "stolen",
+                }),
+                serde_json::json!({ "action": "test-associate", "id": id, "id_key": id_key }),
+            ] {
+                assert!(matches!(
+                    extension.send(&mut host, request).await,
+                    Err(HostError::NotPaired)
+                ));
+            }
+        }
+        // A request without the key is not even understood
+        let keyless = extension
+            .send(
+                &mut host,
+                serde_json::json!({
+                    "action": "get-logins",
+                    "id": id,
+                    "url": "https://login.example.com/",
+                }),
+            )
+            .await;
+        assert!(matches!(keyless, Err(HostError::Protocol(_))));
+
+        let logins = extension
+            .send(
+                &mut host,
+                serde_json::json!({
+                    "action": "get-logins",
+                    "id": id,
+                    "id_key": id_key,
+                    "url": "https://login.example.com/",
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(logins["entries"][0]["password"], "hunter2");
+        host.request(AgentRequest::Lock).await.unwrap();
+    }
+}
@@ -0,0 +1,1383 @@
+use std::cmp::Reverse;
+use std::io::{self, BufRead, Read, Write};
+use std::os::fd::FromRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::{fmt, fs};
+
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::SqlitePool;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use keylight2::agent::*;
+use keylight2::agent_client::*;
+use keylight2::breach::*;
+use keylight2::error::*;
+use keylight2::generator::*;
+use keylight2::secret_service::*;
+use keylight2::ssh_agent::*;
+use keylight2::state::*;
+use keylight2::totp::*;
+use keylight2::url_match::*;
+use keylight2::vault::*;
+use keylight2::vaults::*;
+
+// Section types with a meaning of their own, any other field is stored as plain text
+const KNOWN_SECTION_TYPES: &[&str] = &[
+    "username",
+    "password",
+    "totp",
+    "url",
+    "ssh_key",
+    "ssh_confirm",
+    EQUIVALENT_DOMAINS_SECTION_TYPE,
+];
+// 2: sections that are not UTF-8 are exported as base64
+const EXPORT_FORMAT_VERSION: u32 = 2;
+const BASE64_ENCODING: &str = "base64";
+
+#[derive(Parser)]
+#[command(
+    name = "keylight",
+    version,
+    about = "Script a Keylight vault from the command line",
+    after_help = "Exit status is 0 on success, 2 when the master password is wrong and 1 for any other error."
+)]
+struct Cli {
+    /// Name of the vault in the Keylight data directory
+    #[arg(long, global = true, default_value = DEFAULT_VAULT_NAME)]
+    vault: String,
+
+    /// Database or keyfile of a vault stored anywhere else, takes precedence over --vault
+    #[arg(long, global = true, value_name = "PATH")]
+    vault_path: Option<PathBuf>,
+
+    /// Read the master password from the first line of standard input instead of the terminal
+    #[arg(long, global = true, conflicts_with = "password_fd")]
+    password_stdin: bool,
+
+    /// Read the master password from an inherited file descriptor instead of the terminal
+    #[arg(long, global = true, value_name = "FD")]
+    password_fd: Option<i32>,
+
+    /// Print machine readable JSON instead of plain text
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check that the master password unlocks the vault, or show the running agent's session
+    Unlock,
+    /// Keep the vault unlocked in the background so other calls skip the key derivation
+    Agent {
+        /// Seconds until the agent locks itself, 0 keeps it unlocked until `keylight lock`
+        #[arg(long, default_value_t = DEFAULT_AGENT_TTL.as_secs())]
+        ttl: u64,
+        /// Also provide the vault to other applications as the freedesktop Secret Service, prompts
+        /// go through $SSH_ASKPASS
+        #[arg(long)]
+        secret_service: bool,
+        /// D-Bus address to provide the Secret Service on instead of the session bus
+        #[arg(long, value_name = "ADDRESS", requires = "secret_service")]
+        bus_address: Option<String>,
+        /// Also serve the vault's SSH keys as an ssh-agent, confirmations go through $SSH_ASKPASS
+        #[arg(long)]
+        ssh_agent: bool,
+    },
+    /// Lock the vault held by a running agent
+    Lock,
+    /// List the folders of the vault
+    Folders,
+    /// List the entries, optionally only those of one folder
+    Entries {
+        /// Folder name or id
+        #[arg(long)]
+        folder: Option<String>,
+    },
+    /// Find entries by name, or by the URL they are used on, best match first
+    Search {
+        /// Part of an entry name, or a URL such as https://example.com/login
+        query: String,
+    },
+    /// Print one field of an entry, TOTP fields print the current code
+    Get {
+        /// Entry name or id
+        entry: String,
+        /// Section name or type
+        #[arg(default_value = "password")]
+        field: String,
+    },
+    /// Add an entry
+    Add {
+        name: String,
+        /// Folder name or id, defaults to the first folder
+        #[arg(long)]
+        folder: Option<String>,
+        /// Field to store, a value of "-" reads the next line of standard input. URL fields match
+        /// by base domain, the types url_host, url_starts_with, url_regex and url_never pick
+        /// another rule
+        #[arg(long = "field", value_name = "NAME[:TYPE]=VALUE")]
+        fields: Vec<String>,
+        /// Store a generated password in the password field
+        #[arg(long)]
+        generate: bool,
+        /// Length of the generated password
+        #[arg(long, default_value_t = 24)]
+        length: usize,
+        /// OpenSSH private key file to serve through `keylight agent --ssh-agent`, a passphrase
+        /// protected key is opened with the password field
+        #[arg(long, value_name = "PATH")]
+        ssh_key: Option<PathBuf>,
+        /// Ask before every signature made with the SSH key
+        #[arg(long, requires = "ssh_key")]
+        ssh_confirm: bool,
+    },
+    /// Rename an entry or change its fields
+    Edit {
+        /// Entry name or id
+        entry: String,
+        /// New name of the entry
+        #[arg(long)]
+        name: Option<String>,
+        /// Field to add or overwrite, a value of "-" reads the next line of standard input
+        #[arg(long = "set", value_name = "NAME[:TYPE]=VALUE")]
+        set: Vec<String>,
+        /// Field to remove
+        #[arg(long = "remove", value_name = "NAME")]
+        remove: Vec<String>,
+        /// Replace the password field with a generated password
+        #[arg(long)]
+        generate: bool,
+        /// Length of the generated password
+        #[arg(long, default_value_t = 24)]
+        length: usize,
+    },
+    /// Delete an entry
+    Delete {
+        /// Entry name or id
+        entry: String,
+    },
+    /// Generate a password or passphrase without opening a vault
+    Generate {
+        #[arg(long, default_value_t = 24)]
+        length: usize,
+        /// Only use letters and digits
+        #[arg(long)]
+        no_symbols: bool,
+        /// Generate a passphrase of this many words instead
+        #[arg(long, conflicts_with = "length")]
+        words: Option<usize>,
+    },
+    /// List the entries whose password appears in a local Pwned Passwords dataset
+    Breaches {
+        /// Ordered SHA-1 or NTLM hash file, a directory of range files, or an index of either
+        dataset: PathBuf,
+    },
+    /// Build a faster and smaller index from a Pwned Passwords dataset without opening a vault
+    BreachIndex {
+        /// Ordered hash file or directory of range files
+        source: PathBuf,
+        /// Index file to write
+        output: PathBuf,
+    },
+    /// Write every folder, entry and field as unencrypted JSON
+    Export {
+        /// File to create with owner-only permissions, defaults to standard output
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Add the folders and entries of a JSON export to the vault
+    Import { input: PathBuf },
+}
+
+#[derive(Debug)]
+enum CliError {
+    Vault(VaultError),
+    Invalid(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Vault(error) => write!(f, "{error}"),
+            CliError::Invalid(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl From<VaultError> for CliError {
+    fn from(error: VaultError) -> Self {
+        CliError::Vault(error)
+    }
+}
+
+impl From<BreachError> for CliError {
+    fn from(error: BreachError) -> Self {
+        match error {
+            BreachError::Vault(error) => CliError::Vault(error),
+            error => CliError::Invalid(error.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for CliError {
+    fn from(error: io::Error) -> Self {
+        CliError::Vault(error.into())
+    }
+}
+
+impl From<SecretServiceError> for CliError {
+    fn from(error: SecretServiceError) -> Self {
+        match error {
+            SecretServiceError::Vault(error) => CliError::Vault(error),
+            error => CliError::Invalid(error.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for CliError {
+    fn from(error: serde_json::Error) -> Self {
+        CliError::Invalid(format!("Invalid JSON: {error}"))
+    }
+}
+
+#[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+struct ExportedVault {
+    version: u32,
+    folders: Vec<ExportedFolder>,
+}
+
+#[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+struct ExportedFolder {
+    name: String,
+    entries: Vec<ExportedEntry>,
+}
+
+#[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+struct ExportedEntry {
+    name: String,
+    sections: Vec<ExportedSection>,
+}
+
+#[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+struct ExportedSection {
+    name: String,
+    #[serde(rename = "type")]
+    section_type: String,
+    data: String,
+    // Absent for text, "base64" for anything else
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encoding: Option<String>,
+}
+
+impl ExportedSection {
+    fn new(section: &EntryData) -> Self {
+        let data = section.section_data.as_deref().unwrap_or_default();
+        let (data, encoding) = match std::str::from_utf8(data) {
+            Ok(text) => (text.to_string(), None),
+            Err(_) => (
+                data_encoding::BASE64.encode(data),
+                Some(BASE64_ENCODING.to_string()),
+            ),
+        };
+        Self {
+            name: section.section_name.clone().unwrap_or_default(),
+            section_type: section.section_type.clone().unwrap_or_default(),
+            data,
+            encoding,
+        }
+    }
+
+    fn data(&self) -> Result<Zeroizing<Vec<u8>>, CliError> {
+        match self.encoding.as_deref() {
+            None => Ok(Zeroizing::new(self.data.as_bytes().to_vec())),
+            Some(BASE64_ENCODING) => data_encoding::BASE64
+                .decode(self.data.as_bytes())
+                .map(Zeroizing::new)
+                .map_err(|_| {
+                    CliError::Invalid(format!("The section \"{}\" is not valid base64", self.name))
+                }),
+            Some(encoding) => Err(CliError::Invalid(format!(
+                "The section \"{}\" uses the unknown encoding \"{encoding}\"",
+                self.name
+            ))),
+        }
+    }
+}
+
+// Measures the export so its buffer never grows, growing would leave copies of it behind
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0 += buffer.len();
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Vault access either goes through a running agent or a pool unlocked by this process
+enum Backend {
+    Unlocked {
+        state: Arc<ApplicationAsyncState>,
+        vault_pool: SqlitePool,
+    },
+    Agent(tokio::sync::Mutex<AgentClient>),
+}
+
+struct Session {
+    backend: Backend,
+    vault: VaultLocation,
+    json: bool,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    argon2_async::set_config(argon2_async::Config::default()).await;
+
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("[ERROR]: {error}");
+            match error {
+                CliError::Vault(VaultError::WrongPassword) => ExitCode::from(2),
+                _ => ExitCode::FAILURE,
+            }
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), CliError> {
+    if let Command::Generate {
+        length,
+        no_symbols,
+        words,
+    } = &cli.command
+    {
+        let generated = match words {
+            Some(words) => generate_passphrase(*words),
+            None => generate_password(&PasswordOptions {
+                length: *length,
+                symbols: !no_symbols,
+                ..PasswordOptions::default()
+            }),
+        };
+        print_value(cli.json, "password", &generated);
+        return Ok(());
+    }
+    if let Command::BreachIndex { source, output } = &cli.command {
+        let written = build_breach_index(source, output)?;
+        if !cli.json {
+            println!("Indexed {written} hashes into {}", output.display());
+        }
+        return Ok(());
+    }
+
+    let vault = selected_vault(&cli)?;
+    match cli.command {
+        Command::Agent {
+            ttl,
+            secret_service,
+            ref bus_address,
+            ssh_agent,
+        } => {
+            let Backend::Unlocked { state, vault_pool } = unlock(&cli, &vault).await? else {
+                unreachable!("unlock always opens the vault in this process");
+            };
+            let ttl = (ttl > 0).then(|| std::time::Duration::from_secs(ttl));
+
+            // The provider goes away with its connection once the agent locks
+            let _secret_service = match secret_service {
+                true => Some(
+                    serve_secret_service(
+                        state.clone(),
+                        vault_pool.clone(),
+                        bus_address.clone(),
+                        None,
+                    )
+                    .await?,
+                ),
+                false => None,
+            };
+            let ssh_agent_socket = match ssh_agent {
+                true => {
+                    let socket = bind_ssh_agent(ssh_agent_socket_path(&vault))?;
+                    println!(
+                        "SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;",
+                        socket.path().display()
+                    );
+                    Some(socket)
+                }
+                false => None,
+            };
+            let ssh_agent = async {
+                match ssh_agent_socket {
+                    Some(socket) => {
+                        serve_ssh_agent(state.clone(), vault_pool.clone(), socket, None).await
+                    }
+                    None => futures::future::pending().await,
+                }
+            };
+
+            // The SSH agent keeps serving until it locks with the agent
+            return tokio::select! {
+                served = serve_agent(state.clone(), vault_pool.clone(), &vault, ttl) => {
+                    Ok(served?)
+                }
+                _ = ssh_agent => Ok(()),
+            };
+        }
+        Command::Lock => {
+            let mut client = AgentClient::connect(&vault).await.ok_or_else(|| {
+                CliError::Invalid(format!("No agent is running for \"{}\"", vault.name))
+            })?;
+            client.request(&AgentRequest::Lock).await?;
+            if !cli.json {
+                println!("Vault locked");
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let backend = match AgentClient::connect(&vault).await {
+        Some(client) => Backend::Agent(tokio::sync::Mutex::new(client)),
+        None => unlock(&cli, &vault).await?,
+    };
+    let session = Session {
+        backend,
+        vault,
+        json: cli.json,
+    };
+
+    match cli.command {
+        Command::Unlock => session.status().await,
+        Command::Folders => session.list_folders().await,
+        Command::Entries { folder } => session.list_entries(folder).await,
+        Command::Search { query } => session.search(&query).await,
+        Command::Get { entry, field } => session.get_field(&entry, &field).await,
+        Command::Add {
+            name,
+            folder,
+            fields,
+            generate,
+            length,
+            ssh_key,
+            ssh_confirm,
+        } => {
+            let ssh_key_sections = read_ssh_key_sections(ssh_key, ssh_confirm)?;
+            session
+                .add_entry(name, folder, fields, generate, length, ssh_key_sections)
+                .await
+        }
+        Command::Edit {
+            entry,
+            name,
+            set,
+            remove,
+            generate,
+            length,
+        } => {
+            session
+                .edit_entry(&entry, name, set, remove, generate, length)
+                .await
+        }
+        Command::Delete { entry } => session.delete_entry(&entry).await,
+        Command::Breaches { dataset } => session.breaches(dataset).await,
+        Command::Export { output } => session.export(output).await,
+        Command::Import { input } => session.import(input).await,
+        Command::Generate { .. }
+        | Command::BreachIndex { .. }
+        | Command::Agent { .. }
+        | Command::Lock => {
+            unreachable!("handled before the vault is opened")
+        }
+    }
+}
+
+fn read_master_password(cli: &Cli, vault: &VaultLocation) -> io::Result<Zeroizing<String>> {
+    let mut password = Zeroizing::new(String::new());
+
+    if let Some(fd) = cli.password_fd {
+        // Safety: the descriptor is handed to us by the caller and only read once here
+        let mut file = unsafe { fs::File::from_raw_fd(fd) };
+        file.read_to_string(&mut password)?;
+    } else if cli.password_stdin {
+        io::stdin().lock().read_line(&mut password)?;
+    } else {
+        return rpassword::prompt_password(format!("Master password for \"{}\": ", vault.name))
+            .map(Zeroizing::new);
+    }
+
+    let trimmed_length = password.trim_end_matches(['\r', '\n']).len();
+    password.truncate(trimmed_length);
+    Ok(password)
+}
+
+fn selected_vault(cli: &Cli) -> Result<VaultLocation, CliError> {
+    match &cli.vault_path {
+        Some(path) => VaultLocation::from_path(path),
+        None => Some(VaultLocation::in_directory(
+            StorageServices::new().vault_directory(),
+            &cli.vault,
+        )),
+    }
+    .filter(|vault| vault.exists())
+    .ok_or_else(|| CliError::Invalid("No vault (database and keyfile) was found".to_string()))
+}
+
+// Goes through the same login_to_vault path as the GUI
+async fn unlock(cli: &Cli, vault: &VaultLocation) -> Result<Backend, CliError> {
+    let password = read_master_password(cli, vault)?;
+    let mut app_directory = StorageServices::new();
+    app_directory.selected_vault = Some(vault.clone());
+
+    let state = Arc::new(ApplicationAsyncState::new(app_directory));
+    state.master_password.write().unwrap().push_str(&password);
+    drop(password);
+
+    // The database file is only known once login_to_vault read the keyfile
+    let vault_pool = SqlitePool::connect_lazy_with(SqliteConnectOptions::new());
+    let unlocked = state.clone().login_to_vault(vault_pool.clone()).await;
+    state.clear_secrets();
+    unlocked?;
+
+    Ok(Backend::Unlocked { state, vault_pool })
+}
+
+fn unexpected_response() -> VaultError {
+    VaultError::Agent("unexpected response".to_string())
+}
+
+impl Backend {
+    async fn request(&self, request: AgentRequest) -> Result<AgentResponse, VaultError> {
+        match self {
+            Backend::Agent(client) => client.lock().await.request(&request).await,
+            Backend::Unlocked { .. } => unreachable!("only agent sessions send requests"),
+        }
+    }
+
+    async fn get_folders(&self) -> Result<Vec<Folder>, VaultError> {
+        match self {
+            Backend::Unlocked { state, vault_pool } => {
+                state.clone().get_folders(vault_pool.clone()).await
+            }
+            Backend::Agent(_) => match self.request(AgentRequest::Folders).await? {
+                AgentResponse::Folders { folders } => Ok(folders),
+                _ => Err(unexpected_response()),
+            },
+        }
+    }
+
+    async fn get_entries(&self, folder_id: i64) -> Result<Vec<Entry>, VaultError> {
+        match self {
+            Backend::Unlocked { state, vault_pool } => {
+                state
+                    .clone()
+                    .get_entries(vault_pool.clone(), folder_id)
+                    .await
+            }
+            Backend::Agent(_) => match self.request(AgentRequest::Entries { folder_id }).await? {
+                AgentResponse::Entries { entries } => Ok(entries),
+                _ => Err(unexpected_response()),
+            },
+        }
+    }
+
+    async fn get_entry_data(&self, entry_id: i64) -> Result<Vec<EntryData>, VaultError> {
+        match self {
+            Backend::Unlocked { state, vault_pool } => {
+                state
+                    .clone()
+                    .get_entry_data(vault_pool.clone(), entry_id)
+                    .await
+            }
+            Backend::Agent(_) => match self.request(AgentRequest::EntryData { entry_id }).await? {
+                AgentResponse::EntryData { entry_data } => Ok(entry_data),
+                _ => Err(unexpected_response()),
+            },
+        }
+    }
+
+    async fn get_url_candidates(&self, url: String) -> Result<Vec<UrlCandidate>, VaultError> {
+        match self {
+            Backend::Unlocked { state, vault_pool } => {
+                state
+                    .clone()
+                    .get_url_candidates(vault_pool.clone(), url)
+                    .await
+            }
+            Backend::Agent(_) => match self.request(AgentRequest::UrlCandidates { url }).await? {
+                AgentResponse::UrlCandidates { candidates } => Ok(candidates),
+                _ => Err(unexpected_response()),
+            },
+        }
+    }
+
+    async fn get_sections_by_type(
+        &self,
+        section_type: String,
+    ) -> Result<Vec<EntryData>, VaultError> {
+        match self {
+            Backend::Unlocked { state, vault_pool } => {
+                state
+                    .clone()
+                    .get_sections_by_type(vault_pool.clone(), section_type)
+                    .await
+            }
+            Backend::Agent(_) => match self
+                .request(AgentRequest::SectionsByType { section_type })
+                .await?
+            {
+                AgentResponse::EntryData { entry_data } => Ok(entry_data),
+                _ => Err(unexpected_response()),
+            },
+        }
+    }
+
+    async fn create_folder(&self, folder_name: String) -> Result<i64, VaultError> {
+        match self {
+            Backend::Unlocked { state, vault_pool } => {
+                state
+                    .clone()
+                    .create_folder(vault_pool.clone(), folder_name)
+                    .await
+            }
+            Backend::Agent(_) => {
+                match self
+                    .request(AgentRequest::CreateFolder { folder_name })
+                    .await?
+                {
+                    AgentResponse::Created { id } => Ok(id),
+                    _ => Err(unexpected_response()),
+                }
+            }
+        }
+    }
+
+    async fn create_entry(&self, folder_id: i64, entry_name: String) -> Result<i64, VaultError> {
+        match self {
+            Backend::Unlocked { state, vault_pool } => {
+                state
+                    .clone()
+                    .create_entry(vault_pool.clone(), folder_id, entry_name)
+                    .await
+            }
+            Backend::Agent(_) => {
+                let request = AgentRequest::CreateEntry {
+                    folder_id,
+                    entry_name,
+                };
+                match self.request(request).await? {
+                    AgentResponse::Created { id } => Ok(id),
+                    _ => Err(unexpected_response()),
+                }
+            }
+        }
+    }
+
+    async fn rename_entry(&self, entry_id: i64, entry_name: String) -> Result<(), VaultError> {
+        match self {
+            Backend::Unlocked { state, vault_pool } => {
+                state
+                    .clone()
+                    .rename_entry(vault_pool.clone(), entry_id, entry_name)
+                    .await
+            }
+            Backend::Agent(_) => {
+                let request = AgentRequest::RenameEntry {
+                    entry_id,
+                    entry_name,
+                };
+                self.request(request).await.map(|_| ())
+            }
+        }
+    }
+
+    async fn delete_entry(&self, entry_id: i64) -> Result<(), VaultError> {
+        match self {
+            Backend::Unlocked { state, vault_pool } => {
+                state
+                    .clone()
+                    .delete_entry(vault_pool.clone(), entry_id)
+                    .await
+            }
+            Backend::Agent(_) => self
+                .request(AgentRequest::DeleteEntry { entry_id })
+                .await
+                .map(|_| ()),
+        }
+    }
+
+    async fn set_entry_section(
+        &self,
+        entry_id: i64,
+        section_name: String,
+        section_type: String,
+        section_data: Zeroizing<Vec<u8>>,
+    ) -> Result<(), VaultError> {
+        match self {
+            Backend::Unlocked { state, vault_pool } => {
+                state
+                    .clone()
+                    .set_entry_section(
+                        vault_pool.clone(),
+                        entry_id,
+                        section_name,
+                        section_type,
+                        section_data,
+                    )
+                    .await
+            }
+            Backend::Agent(_) => {
+                let request = AgentRequest::SetEntrySection {
+                    entry_id,
+                    section_name,
+                    section_type,
+                    section_data,
+                };
+                self.request(request).await.map(|_| ())
+            }
+        }
+    }
+
+    async fn remove_entry_section(
+        &self,
+        entry_id: i64,
+        section_name: String,
+    ) -> Result<(), VaultError> {
+        match self {
+            Backend::Unlocked { state, vault_pool } => {
+                state
+                    .clone()
+                    .remove_entry_section(vault_pool.clone(), entry_id, section_name)
+                    .await
+            }
+            Backend::Agent(_) => {
+                let request = AgentRequest::RemoveEntrySection {
+                    entry_id,
+                    section_name,
+                };
+                self.request(request).await.map(|_| ())
+            }
+        }
+    }
+}
+
+fn print_value(json: bool, field: &str, value: &str) {
+    if json {
+        println!("{}", serde_json::json!({ "field": field, "value": value }));
+    } else {
+        println!("{value}");
+    }
+}
+
+fn matches_reference(reference: &str, id: i64, name: Option<&str>) -> bool {
+    reference.parse::<i64>().ok() == Some(id)
+        || name.is_some_and(|name| name.eq_ignore_ascii_case(reference))
+}
+
+// section_name, section_type, section_data
+type NewSection = (String, String, Zeroizing<Vec<u8>>);
+
+// Parses "NAME[:TYPE]=VALUE", reading the value from standard input when it is "-"
+fn parse_field(field: &str) -> Result<NewSection, CliError> {
+    let (name, value) = field.split_once('=').ok_or_else(|| {
+        CliError::Invalid(format!(
+            "Fields are written as NAME[:TYPE]=VALUE, not \"{field}\""
+        ))
+    })?;
+    let (name, section_type) = match name.split_once(':') {
+        Some((name, section_type)) => (name, section_type.to_string()),
+        None if KNOWN_SECTION_TYPES.contains(&name) => (name, name.to_string()),
+        None => (name, "text".to_string()),
+    };
+    if name.is_empty() {
+        return Err(CliError::Invalid("Field names cannot be empty".to_string()));
+    }
+
+    let value = if value == "-" {
+        let mut line = Zeroizing::new(String::new());
+        io::stdin().lock().read_line(&mut line)?;
+        let trimmed_length = line.trim_end_matches(['\r', '\n']).len();
+        Zeroizing::new(line.as_bytes()[..trimmed_length].to_vec())
+    } else {
+        Zeroizing::new(value.as_bytes().to_vec())
+    };
+    Ok((name.to_string(), section_type, value))
+}
+
+// The key file is checked up front so a typo does not end up as a useless entry
+fn read_ssh_key_sections(
+    path: Option<PathBuf>,
+    confirm: bool,
+) -> Result<Vec<NewSection>, CliError> {
+    let Some(path) = path else {
+        return Ok(Vec::new());
+    };
+    let pem = Zeroizing::new(fs::read(&path)?);
+    ssh_key::PrivateKey::from_openssh(&*pem).map_err(|error| {
+        CliError::Invalid(format!(
+            "{} is not an OpenSSH private key: {error}",
+            path.display()
+        ))
+    })?;
+
+    let mut sections = vec![("ssh_key".to_string(), SSH_KEY_SECTION_TYPE.to_string(), pem)];
+    if confirm {
+        sections.push((
+            "confirm".to_string(),
+            SSH_CONFIRM_SECTION_TYPE.to_string(),
+            Zeroizing::new(b"yes".to_vec()),
+        ));
+    }
+    Ok(sections)
+}
+
+impl Session {
+    async fn status(&self) -> Result<(), CliError> {
+        let expires_in = match &self.backend {
+            Backend::Agent(_) => match self.backend.request(AgentRequest::Status).await? {
+                AgentResponse::Status { expires_in, .. } => expires_in,
+                _ => return Err(unexpected_response().into()),
+            },
+            Backend::Unlocked { .. } => None,
+        };
+        let agent = matches!(self.backend, Backend::Agent(_));
+
+        if self.json {
+            println!(
+                "{}",
+                serde_json::json!({
+                    "unlocked": true,
+                    "vault": self.vault.name,
+                    "agent": agent,
+                    "expires_in": expires_in,
+                })
+            );
+        } else if !agent {
+            println!("Vault \"{}\" unlocked", self.vault.name);
+        } else {
+            match expires_in {
+                Some(expires_in) => println!(
+                    "Vault \"{}\" is held unlocked by an agent for another {expires_in}s",
+                    self.vault.name
+                ),
+                None => println!(
+                    "Vault \"{}\" is held unlocked by an agent until it is locked",
+                    self.vault.name
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    async fn find_folder(&self, reference: Option<&str>) -> Result<i64, CliError> {
+        let folders = self.backend.get_folders().await?;
+        let folder = match reference {
+            Some(reference) => folders.iter().find(|folder| {
+                matches_reference(reference, folder.folder_id, folder.folder_name.as_deref())
+            }),
+            None => folders.first(),
+        };
+
+        folder
+            .map(|folder| folder.folder_id)
+            .ok_or_else(|| CliError::Invalid("No such folder".to_string()))
+    }
+
+    async fn all_entries(&self) -> Result<Vec<Entry>, CliError> {
+        let mut entries = Vec::new();
+        for folder in self.backend.get_folders().await? {
+            entries.extend(self.backend.get_entries(folder.folder_id).await?);
+        }
+        Ok(entries)
+    }
+
+    async fn find_entry(&self, reference: &str) -> Result<i64, CliError> {
+        let matching: Vec<i64> = self
+            .all_entries()
+            .await?
+            .iter()
+            .filter(|entry| {
+                matches_reference(reference, entry.entry_id, entry.entry_name.as_deref())
+            })
+            .map(|entry| entry.entry_id)
+            .collect();
+
+        match matching.as_slice() {
+            [entry_id] => Ok(*entry_id),
+            [] => Err(CliError::Invalid(format!("No entry named \"{reference}\""))),
+            _ => Err(CliError::Invalid(format!(
+                "Several entries are named \"{reference}\", use one of the ids {matching:?} instead"
+            ))),
+        }
+    }
+
+    async fn list_folders(&self) -> Result<(), CliError> {
+        let folders = self.backend.get_folders().await?;
+        if self.json {
+            let folders: Vec<_> = folders
+                .iter()
+                .map(|folder| serde_json::json!({ "id": folder.folder_id, "name": folder.folder_name }))
+                .collect();
+            println!("{}", serde_json::Value::Array(folders));
+        } else {
+            for folder in &folders {
+                println!(
+                    "{}\t{}",
+                    folder.folder_id,
+                    folder.folder_name.as_deref().unwrap_or_default()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn list_entries(&self, folder: Option<String>) -> Result<(), CliError> {
+        let entries = match folder {
+            Some(folder) => {
+                let folder_id = self.find_folder(Some(&folder)).await?;
+                self.backend.get_entries(folder_id).await?
+            }
+            None => self.all_entries().await?,
+        };
+
+        if self.json {
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|entry| {
+                    serde_json::json!({
+                        "id": entry.entry_id,
+                        "name": entry.entry_name,
+                        "folder_id": entry.folder_id,
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::Value::Array(entries));
+        } else {
+            for entry in &entries {
+                println!(
+                    "{}\t{}",
+                    entry.entry_id,
+                    entry.entry_name.as_deref().unwrap_or_default()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // Anything that reads as a URL is ranked by the entries' url fields, the rest matches names
+    async fn search(&self, query: &str) -> Result<(), CliError> {
+        let looks_like_url = !query.contains(char::is_whitespace)
+            && (query.contains("://") || query.contains('.'))
+            && parse_url(query).is_some();
+        let results: Vec<(i64, Option<String>, Option<MatchLevel>)> = if looks_like_url {
+            self.backend
+                .get_url_candidates(query.to_string())
+                .await?
+                .into_iter()
+                .map(|candidate| {
+                    (
+                        candidate.entry_id,
+                        candidate.entry_name,
+                        Some(candidate.level),
+                    )
+                })
+                .collect()
+        } else {
+            let query = query.to_lowercase();
+            self.all_entries()
+                .await?
+                .iter()
+                .filter(|entry| {
+                    entry
+                        .entry_name
+                        .as_deref()
+                        .is_some_and(|name| name.to_lowercase().contains(&query))
+                })
+                .map(|entry| (entry.entry_id, entry.entry_name.clone(), None))
+                .collect()
+        };
+
+        if self.json {
+            let results: Vec<_> = results
+                .iter()
+                .map(|(id, name, level)| serde_json::json!({ "id": id, "name": name, "match": level }))
+                .collect();
+            println!("{}", serde_json::Value::Array(results));
+        } else {
+            for (id, name, _) in &results {
+                println!("{id}\t{}", name.as_deref().unwrap_or_default());
+            }
+        }
+        Ok(())
+    }
+
+    async fn breaches(&self, dataset: PathBuf) -> Result<(), CliError> {
+        let passwords = self
+            .backend
+            .get_sections_by_type("password".to_string())
+            .await?;
+        let counts =
+            tokio::task::spawn_blocking(move || count_breached_passwords(&dataset, &passwords))
+                .await
+                .map_err(|_| CliError::Invalid("The breach check was interrupted".to_string()))??;
+
+        let mut breached: Vec<(i64, Option<String>, u64)> = self
+            .all_entries()
+            .await?
+            .iter()
+            .filter_map(|entry| {
+                let count = *counts.get(&entry.entry_id)?;
+                Some((entry.entry_id, entry.entry_name.clone(), count))
+            })
+            .collect();
+        breached.sort_by_key(|b| Reverse(b.2));
+
+        if self.json {
+            let breached: Vec<_> = breached
+                .iter()
+                .map(|(id, name, count)| serde_json::json!({ "id": id, "name": name, "count": count }))
+                .collect();
+            println!("{}", serde_json::Value::Array(breached));
+        } else {
+            for (id, name, count) in &breached {
+                println!("{id}\t{}\t{count}", name.as_deref().unwrap_or_default());
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_field(&self, entry: &str, field: &str) -> Result<(), CliError> {
+        let entry_id = self.find_entry(entry).await?;
+        let entry_data = self.backend.get_entry_data(entry_id).await?;
+
+        // An exact section name wins over a section that merely has the requested type
+        let section = entry_data
+            .iter()
+            .find(|section| section.section_name.as_deref() == Some(field))
+            .or_else(|| {
+                entry_data
+                    .iter()
+                    .find(|section| section.section_type.as_deref() == Some(field))
+            })
+            .ok_or_else(|| CliError::Invalid(format!("The entry has no field \"{field}\"")))?;
+
+        let data = Zeroizing::new(
+            String::from_utf8_lossy(section.section_data.as_deref().unwrap_or_default())
+                .into_owned(),
+        );
+        let value = if section.section_type.as_deref() == Some("totp") {
+            let totp = Totp::parse(&data).ok_or_else(|| {
+                CliError::Invalid("This field does not hold a valid TOTP secret".to_string())
+            })?;
+            Zeroizing::new(totp.current_code())
+        } else {
+            data
+        };
+
+        print_value(self.json, field, &value);
+        Ok(())
+    }
+
+    async fn add_entry(
+        &self,
+        name: String,
+        folder: Option<String>,
+        fields: Vec<String>,
+        generate: bool,
+        length: usize,
+        ssh_key_sections: Vec<NewSection>,
+    ) -> Result<(), CliError> {
+        let mut sections = fields
+            .iter()
+            .map(|field| parse_field(field))
+            .collect::<Result<Vec<_>, _>>()?;
+        if generate {
+            sections.retain(|(name, _, _)| name != "password");
+            sections.push(generated_password_section(length));
+        }
+        sections.extend(ssh_key_sections);
+
+        let folder_id = self.find_folder(folder.as_deref()).await?;
+        let entry_id = self.backend.create_entry(folder_id, name).await?;
+        for (section_name, section_type, section_data) in sections {
+            self.backend
+                .set_entry_section(entry_id, section_name, section_type, section_data)
+                .await?;
+        }
+
+        if self.json {
+            println!("{}", serde_json::json!({ "id": entry_id }));
+        } else {
+            println!("{entry_id}");
+        }
+        Ok(())
+    }
+
+    async fn edit_entry(
+        &self,
+        entry: &str,
+        name: Option<String>,
+        set: Vec<String>,
+        remove: Vec<String>,
+        generate: bool,
+        length: usize,
+    ) -> Result<(), CliError> {
+        let mut sections = set
+            .iter()
+            .map(|field| parse_field(field))
+            .collect::<Result<Vec<_>, _>>()?;
+        if generate {
+            sections.retain(|(name, _, _)| name != "password");
+            sections.push(generated_password_section(length));
+        }
+
+        let entry_id = self.find_entry(entry).await?;
+        if let Some(name) = name {
+            self.backend.rename_entry(entry_id, name).await?;
+        }
+        for section_name in remove {
+            self.backend
+                .remove_entry_section(entry_id, section_name)
+                .await?;
+        }
+        for (section_name, section_type, section_data) in sections {
+            self.backend
+                .set_entry_section(entry_id, section_name, section_type, section_data)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn delete_entry(&self, entry: &str) -> Result<(), CliError> {
+        let entry_id = self.find_entry(entry).await?;
+        self.backend.delete_entry(entry_id).await?;
+        Ok(())
+    }
+
+    async fn export(&self, output: Option<PathBuf>) -> Result<(), CliError> {
+        let mut exported = ExportedVault {
+            version: EXPORT_FORMAT_VERSION,
+            folders: Vec::new(),
+        };
+
+        for folder in self.backend.get_folders().await? {
+            let mut exported_folder = ExportedFolder {
+                name: folder.folder_name.clone().unwrap_or_default(),
+                entries: Vec::new(),
+            };
+            for entry in self.backend.get_entries(folder.folder_id).await? {
+                let sections = self
+                    .backend
+                    .get_entry_data(entry.entry_id)
+                    .await?
+                    .iter()
+                    .map(ExportedSection::new)
+                    .collect();
+                exported_folder.entries.push(ExportedEntry {
+                    name: entry.entry_name.clone().unwrap_or_default(),
+                    sections,
+                });
+            }
+            exported.folders.push(exported_folder);
+        }
+
+        let mut length = ByteCounter(0);
+        serde_json::to_writer_pretty(&mut length, &exported)?;
+        let mut serialized = Zeroizing::new(Vec::with_capacity(length.0 + 1));
+        serde_json::to_writer_pretty(&mut *serialized, &exported)?;
+        serialized.push(b'\n');
+        match output {
+            Some(path) => {
+                let mut file = fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(&path)?;
+                file.write_all(&serialized)?;
+                eprintln!(
+                    "[INFO]: Exported the unencrypted vault to {}",
+                    path.display()
+                );
+            }
+            None => io::stdout().lock().write_all(&serialized)?,
+        }
+        Ok(())
+    }
+
+    async fn import(&self, input: PathBuf) -> Result<(), CliError> {
+        let contents = Zeroizing::new(fs::read(&input)?);
+        let imported: ExportedVault = serde_json::from_slice(&contents)?;
+        if imported.version > EXPORT_FORMAT_VERSION {
+            return Err(CliError::Invalid(format!(
+                "The export uses format version {}, but only up to {EXPORT_FORMAT_VERSION} is supported",
+                imported.version
+            )));
+        }
+        // Every section must decode before anything is written, a bad one aborts the whole import
+        for folder in &imported.folders {
+            for entry in &folder.entries {
+                for section in &entry.sections {
+                    section.data()?;
+                }
+            }
+        }
+
+        let folders = self.backend.get_folders().await?;
+        let mut imported_entries = 0;
+        for folder in &imported.folders {
+            // Entries land in the folder of the same name if the vault already has one
+            let folder_id = match folders
+                .iter()
+                .find(|existing| existing.folder_name.as_deref() == Some(folder.name.as_str()))
+            {
+                Some(existing) => existing.folder_id,
+                None => self.backend.create_folder(folder.name.clone()).await?,
+            };
+
+            for entry in &folder.entries {
+                let entry_id = self
+                    .backend
+                    .create_entry(folder_id, entry.name.clone())
+                    .await?;
+                for section in &entry.sections {
+                    self.backend
+                        .set_entry_section(
+                            entry_id,
+                            section.name.clone(),
+                            section.section_type.clone(),
+                            section.data()?,
+                        )
+                        .await?;
+                }
+                imported_entries += 1;
+            }
+        }
+
+        if self.json {
+            println!(
+                "{}",
+                serde_json::json!({ "imported_entries": imported_entries })
+            );
+        } else {
+            println!("Imported {imported_entries} entries");
+        }
+        Ok(())
+    }
+}
+
+fn generated_password_section(length: usize) -> NewSection {
+    let password = generate_password(&PasswordOptions {
+        length,
+        ..PasswordOptions::default()
+    });
+    (
+        "password".to_string(),
+        "password".to_string(),
+        Zeroizing::new(password.as_bytes().to_vec()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keylight2::testing::ScratchVault;
+
+    fn session(vault: &ScratchVault) -> Session {
+        Session {
+            backend: Backend::Unlocked {
+                state: vault.state.clone(),
+                vault_pool: vault.vault_pool.clone(),
+            },
+            vault: vault.location.clone(),
+            json: false,
+        }
+    }
+
+    async fn sections(session: &Session) -> Vec<(String, String, Vec<u8>)> {
+        let mut sections = Vec::new();
+        for folder in session.backend.get_folders().await.unwrap() {
+            for entry in session.backend.get_entries(folder.folder_id).await.unwrap() {
+                for section in session
+                    .backend
+                    .get_entry_data(entry.entry_id)
+                    .await
+                    .unwrap()
+                {
+                    sections.push((
+                        section.section_name.clone().unwrap_or_default(),
+                        section.section_type.clone().unwrap_or_default(),
+                        section.section_data.as_deref().unwrap_or_default().to_vec(),
+                    ));
+                }
+            }
+        }
+        sections.sort();
+        sections
+    }
+
+    #[tokio::test]
+    async fn binary_sections_survive_an_export_and_import() {
+        let original = ScratchVault::new("export-original").await;
+        original
+            .entry(
+                "Server",
+                &[
+                    ("password", "password", "pässword".as_bytes()),
+                    ("key", "file", &[0x00, 0xff, 0xfe, 0x80, 0x41]),
+                ],
+            )
+            .await;
+        let exported = original.directory.0.join("export.json");
+        session(&original)
+            .export(Some(exported.clone()))
+            .await
+            .unwrap();
+
+        // Text stays readable, only the binary section is encoded
+        let contents = fs::read_to_string(&exported).unwrap();
+        assert!(contents.contains("pässword"));
+        assert_eq!(contents.matches("\"encoding\": \"base64\"").count(), 1);
+
+        let copy = ScratchVault::new("export-copy").await;
+        session(&copy).import(exported).await.unwrap();
+        assert_eq!(
+            sections(&session(&copy)).await,
+            sections(&session(&original)).await
+        );
+    }
+
+    #[tokio::test]
+    async fn undecodable_sections_abort_the_import() {
+        let vault = ScratchVault::new("import-invalid").await;
+        let before = sections(&session(&vault)).await;
+        for encoding in ["base64", "rot13"] {
+            let path = vault.directory.0.join(format!("{encoding}.json"));
+            fs::write(
+                &path,
+                format!(
+                    r#"{{"version": 2, "folders": [{{"name": "Imported", "entries": [{{"name": "Entry",
+                    "sections": [{{"name": "password", "type": "password", "data": "fine"}},
+                    {{"name": "key", "type": "file", "data": "not base64!", "encoding": "{encoding}"}}]}}]}}]}}"#
+                ),
+            )
+            .unwrap();
+            assert!(session(&vault).import(path).await.is_err());
+        }
+        assert_eq!(sections(&session(&vault)).await, before);
+    }
+}
@@ -0,0 +1,73 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::ops::DerefMut;
+use std::sync::Arc;
+
+use argon2::password_hash::{rand_core::OsRng, SaltString};
+use chacha20poly1305::{aead::Aead, AeadCore, KeyInit, XChaCha20Poly1305, XNonce};
+use directories::ProjectDirs;
+use postcard::{from_bytes, to_allocvec};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode};
+use sqlx::{SqliteConnection, SqlitePool};
+use zeroize::Zeroize;
+
+// Everything that does not draw the window, shared by Keylight and its command line helpers
+
+#[path = "./lib/agent.rs"]
+pub mod agent;
+#[path = "./lib/agent_client.rs"]
+pub mod agent_client;
+#[path = "./lib/breach.rs"]
+pub mod breach;
+#[path = "./lib/clipboard.rs"]
+pub mod clipboard;
+#[path = "./lib/cloud.rs"]
+pub mod cloud;
+#[path = "./lib/error.rs"]
+pub mod error;
+#[path = "./lib/generator.rs"]
+pub mod generator;
+#[path = "./lib/health.rs"]
+pub mod health;
+#[path = "./lib/history.rs"]
+pub mod history;
+#[path = "./lib/http.rs"]
+pub mod http;
+#[path = "./lib/s3.rs"]
+pub mod s3;
+#[path = "./lib/secret_service.rs"]
+pub mod secret_service;
+#[path = "./lib/ssh_agent.rs"]
+pub mod ssh_agent;
+#[path = "./lib/state.rs"]
+pub mod state;
+#[path = "./lib/strength.rs"]
+pub mod strength;
+#[path = "./lib/sync.rs"]
+pub mod sync;
+// Fixtures for tests, here so the tests of src/bin can use them too
+#[doc(hidden)]
+#[path = "./lib/testing.rs"]
+pub mod testing;
+#[path = "./lib/totp.rs"]
+pub mod totp;
+#[path = "./lib/url_match.rs"]
+pub mod url_match;
+#[path = "./lib/vault.rs"]
+pub mod vault;
+#[path = "./lib/vaults.rs"]
+pub mod vaults;
+#[path = "./lib/webdav.rs"]
+pub mod webdav;
+use agent_client::*;
+use cloud::*;
+use error::*;
+use http::*;
+use s3::*;
+use state::*;
+use strength::*;
+use sync::*;
+use url_match::*;
+use vault::*;
+use vaults::*;
+use webdav::*;
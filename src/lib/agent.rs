@@ -0,0 +1,214 @@
+use crate::*;
+use std::io;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use zeroize::Zeroizing;
+
+// Serves the protocol of agent_client.rs for an unlocked vault
+
+pub const DEFAULT_AGENT_TTL: Duration = Duration::from_secs(15 * 60);
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => futures::future::pending().await,
+    }
+}
+
+async fn handle_request(
+    request: AgentRequest,
+    state: &Arc<ApplicationAsyncState>,
+    vault_pool: &SqlitePool,
+    vault_name: &str,
+    expires_at: Option<Instant>,
+) -> Result<AgentResponse, VaultError> {
+    let state = state.clone();
+    let vault_pool = vault_pool.clone();
+
+    Ok(match request {
+        AgentRequest::Status => AgentResponse::Status {
+            vault: vault_name.to_string(),
+            expires_in: expires_at.map(|expires_at| {
+                expires_at
+                    .saturating_duration_since(Instant::now())
+                    .as_secs()
+            }),
+        },
+        AgentRequest::Lock => AgentResponse::Done,
+        AgentRequest::Folders => AgentResponse::Folders {
+            folders: state.get_folders(vault_pool).await?,
+        },
+        AgentRequest::Entries { folder_id } => AgentResponse::Entries {
+            entries: state.get_entries(vault_pool, folder_id).await?,
+        },
+        AgentRequest::EntryData { entry_id } => AgentResponse::EntryData {
+            entry_data: state.get_entry_data(vault_pool, entry_id).await?,
+        },
+        AgentRequest::CreateFolder { folder_name } => AgentResponse::Created {
+            id: state.create_folder(vault_pool, folder_name).await?,
+        },
+        AgentRequest::CreateEntry {
+            folder_id,
+            entry_name,
+        } => AgentResponse::Created {
+            id: state
+                .create_entry(vault_pool, folder_id, entry_name)
+                .await?,
+        },
+        AgentRequest::RenameEntry {
+            entry_id,
+            entry_name,
+        } => {
+            state.rename_entry(vault_pool, entry_id, entry_name).await?;
+            AgentResponse::Done
+        }
+        AgentRequest::DeleteEntry { entry_id } => {
+            state.delete_entry(vault_pool, entry_id).await?;
+            AgentResponse::Done
+        }
+        AgentRequest::SetEntrySection {
+            entry_id,
+            section_name,
+            section_type,
+            section_data,
+        } => {
+            state
+                .set_entry_section(
+                    vault_pool,
+                    entry_id,
+                    section_name,
+                    section_type,
+                    section_data,
+                )
+                .await?;
+            AgentResponse::Done
+        }
+        AgentRequest::RemoveEntrySection {
+            entry_id,
+            section_name,
+        } => {
+            state
+                .remove_entry_section(vault_pool, entry_id, section_name)
+                .await?;
+            AgentResponse::Done
+        }
+        AgentRequest::UrlCandidates { url } => AgentResponse::UrlCandidates {
+            candidates: state.get_url_candidates(vault_pool, url).await?,
+        },
+        AgentRequest::SectionsByType { section_type } => AgentResponse::EntryData {
+            entry_data: state.get_sections_by_type(vault_pool, section_type).await?,
+        },
+    })
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    state: Arc<ApplicationAsyncState>,
+    vault_pool: SqlitePool,
+    vault_name: String,
+    expires_at: Option<Instant>,
+    shutdown: Arc<Notify>,
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        let mut line = Zeroizing::new(String::new());
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+
+        let request = serde_json::from_str::<AgentRequest>(&line);
+        let lock_requested = matches!(request, Ok(AgentRequest::Lock));
+        let response = match request {
+            Ok(request) => handle_request(request, &state, &vault_pool, &vault_name, expires_at)
+                .await
+                .unwrap_or_else(|error| AgentResponse::Error { error }),
+            Err(error) => AgentResponse::Error {
+                error: VaultError::Agent(format!("Malformed request: {error}")),
+            },
+        };
+
+        let mut response = Zeroizing::new(serde_json::to_string(&response)?);
+        response.push('\n');
+        writer.write_all(response.as_bytes()).await?;
+
+        if lock_requested {
+            shutdown.notify_one();
+            return Ok(());
+        }
+    }
+}
+
+// Serves the unlocked vault until the TTL runs out, a client asks it to lock or it is interrupted.
+// The pool and every secret are wiped on the way out, the same way the GUI locks
+pub async fn serve_agent(
+    state: Arc<ApplicationAsyncState>,
+    vault_pool: SqlitePool,
+    vault: &VaultLocation,
+    ttl: Option<Duration>,
+) -> Result<(), VaultError> {
+    let vault_name = vault.name.clone();
+    let socket_path = agent_socket_path(vault);
+    let listener = bind_agent_socket(&socket_path)?;
+    let expires_at = ttl.map(|ttl| Instant::now() + ttl);
+    let shutdown = Arc::new(Notify::new());
+    println!(
+        "[INFO]: Agent for \"{vault_name}\" listening on {:?}",
+        socket_path
+    );
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let stream = match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(error) => {
+                        println!("[ERROR]: {:?}", error);
+                        continue;
+                    }
+                };
+                if !peer_is_owner(&stream) {
+                    println!("[ERROR]: Refused a connection from another user");
+                    continue;
+                }
+
+                let connection = handle_connection(
+                    stream,
+                    state.clone(),
+                    vault_pool.clone(),
+                    vault_name.clone(),
+                    expires_at,
+                    shutdown.clone(),
+                );
+                tokio::spawn(async move {
+                    if let Err(error) = connection.await {
+                        println!("[ERROR]: {:?}", error);
+                    }
+                });
+            }
+            _ = shutdown.notified() => {
+                println!("[INFO]: Agent locked on request");
+                break;
+            }
+            _ = sleep_until(expires_at) => {
+                println!("[INFO]: Agent session expired");
+                break;
+            }
+            _ = tokio::signal::ctrl_c() => {
+                println!("[INFO]: Agent interrupted");
+                break;
+            }
+        }
+    }
+
+    let _ = fs::remove_file(&socket_path);
+    vault_pool.set_connect_options(SqliteConnectOptions::new());
+    vault_pool.close().await;
+    state.clear_secrets();
+    Ok(())
+}
@@ -0,0 +1,244 @@
+use crate::*;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use data_encoding::HEXLOWER;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use zeroize::Zeroizing;
+
+// The agent keeps an unlocked vault pool around and answers newline delimited JSON requests,
+// one response line per request line, on a socket only the owning user can reach.
+//
+//   {"request":"entries","folder_id":1}
+//   {"response":"entries","entries":[{"entry_id":1,"entry_name":"Welcome",...}]}
+//
+// The protocol, the socket handling and the client live here, so tools that only talk to a running
+// agent (like git-credential-keylight) do not need the vault code the agent itself is built on
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum AgentRequest {
+    Status,
+    Lock,
+    Folders,
+    Entries {
+        folder_id: i64,
+    },
+    EntryData {
+        entry_id: i64,
+    },
+    CreateFolder {
+        folder_name: String,
+    },
+    CreateEntry {
+        folder_id: i64,
+        entry_name: String,
+    },
+    RenameEntry {
+        entry_id: i64,
+        entry_name: String,
+    },
+    DeleteEntry {
+        entry_id: i64,
+    },
+    SetEntrySection {
+        entry_id: i64,
+        section_name: String,
+        section_type: String,
+        section_data: Zeroizing<Vec<u8>>,
+    },
+    RemoveEntrySection {
+        entry_id: i64,
+        section_name: String,
+    },
+    UrlCandidates {
+        url: String,
+    },
+    SectionsByType {
+        section_type: String,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum AgentResponse {
+    Status {
+        vault: String,
+        // Seconds until the agent locks itself, absent when it only locks on request
+        expires_in: Option<u64>,
+    },
+    Done,
+    Created {
+        id: i64,
+    },
+    Folders {
+        folders: Vec<Folder>,
+    },
+    Entries {
+        entries: Vec<Entry>,
+    },
+    EntryData {
+        entry_data: Vec<EntryData>,
+    },
+    UrlCandidates {
+        candidates: Vec<UrlCandidate>,
+    },
+    Error {
+        error: VaultError,
+    },
+}
+
+pub fn current_uid() -> u32 {
+    // Safety: geteuid cannot fail and has no preconditions
+    unsafe { libc::geteuid() }
+}
+
+// $XDG_RUNTIME_DIR/keylight, or a private directory in /tmp without a runtime directory
+pub fn agent_socket_directory() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_directory) => PathBuf::from(runtime_directory).join("keylight"),
+        None => std::env::temp_dir().join(format!("keylight-{}", current_uid())),
+    }
+}
+
+// Vaults are told apart by where they are, two vaults of the same name in different directories
+// get their own agents. The name is only kept in front, cut short to stay within the length limit
+// of socket paths
+pub fn vault_socket_name(vault: &VaultLocation) -> String {
+    let path =
+        fs::canonicalize(&vault.database_path).unwrap_or_else(|_| vault.database_path.clone());
+    let digest = Sha256::digest(path.as_os_str().as_bytes());
+    let name: String = vault.name.chars().take(32).collect();
+    format!("{name}-{}", HEXLOWER.encode(&digest[..8]))
+}
+
+pub fn agent_socket_path(vault: &VaultLocation) -> PathBuf {
+    agent_socket_directory().join(format!("{}.sock", vault_socket_name(vault)))
+}
+
+// Also used by the SSH agent, which needs the same private directory and stale socket handling
+pub fn bind_agent_socket(socket_path: &Path) -> Result<UnixListener, VaultError> {
+    let directory = socket_path.parent().unwrap_or(Path::new("."));
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(directory)?;
+
+    // A directory someone else planted in /tmp must not be trusted with the socket
+    let metadata = fs::metadata(directory)?;
+    if std::os::unix::fs::MetadataExt::uid(&metadata) != current_uid()
+        || metadata.permissions().mode() & 0o077 != 0
+    {
+        println!(
+            "[ERROR]: {:?} is not private to the current user",
+            directory
+        );
+        return Err(VaultError::Io(io::ErrorKind::PermissionDenied));
+    }
+
+    if socket_path.exists() {
+        if std::os::unix::net::UnixStream::connect(socket_path).is_ok() {
+            return Err(VaultError::Io(io::ErrorKind::AddrInUse));
+        }
+        // Left behind by an agent that did not shut down cleanly
+        fs::remove_file(socket_path)?;
+    }
+
+    let listener = UnixListener::bind(socket_path)?;
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+pub fn peer_is_owner(stream: &UnixStream) -> bool {
+    stream
+        .peer_cred()
+        .is_ok_and(|credentials| credentials.uid() == current_uid())
+}
+
+pub struct AgentClient {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl AgentClient {
+    // None when no agent runs for this vault, so the caller can unlock it by itself instead
+    pub async fn connect(vault: &VaultLocation) -> Option<Self> {
+        let stream = UnixStream::connect(agent_socket_path(vault)).await.ok()?;
+        if !peer_is_owner(&stream) {
+            eprintln!("[ERROR]: The agent socket is served by another user, ignoring it");
+            return None;
+        }
+
+        let (reader, writer) = stream.into_split();
+        Some(Self {
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+
+    pub async fn request(&mut self, request: &AgentRequest) -> Result<AgentResponse, VaultError> {
+        let mut line = Zeroizing::new(serde_json::to_string(request).expect_throw(
+            VaultError::Agent("could not encode the request".to_string()),
+        )?);
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+
+        let mut response = Zeroizing::new(String::new());
+        if self.reader.read_line(&mut response).await? == 0 {
+            return Err(VaultError::Agent(
+                "the agent closed the connection".to_string(),
+            ));
+        }
+
+        match serde_json::from_str(&response)
+            .expect_throw(VaultError::Agent("malformed response".to_string()))?
+        {
+            AgentResponse::Error { error } => Err(error),
+            response => Ok(response),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_keep_their_kind_over_the_socket() {
+        for error in [
+            VaultError::WrongPassword,
+            VaultError::SchemaMismatch {
+                found: 7,
+                supported: 5,
+            },
+            VaultError::Io(io::ErrorKind::PermissionDenied),
+            VaultError::Database("disk I/O error".to_string()),
+        ] {
+            let sent = serde_json::to_string(&AgentResponse::Error {
+                error: error.clone(),
+            })
+            .unwrap();
+            match serde_json::from_str(&sent).unwrap() {
+                AgentResponse::Error { error: received } => assert_eq!(received, error),
+                _ => panic!("expected an error"),
+            }
+        }
+    }
+
+    #[test]
+    fn vaults_of_the_same_name_get_their_own_socket() {
+        let personal = VaultLocation::in_directory(Path::new("/home/a/vaults"), "personal");
+        let copy = VaultLocation::in_directory(Path::new("/media/backup"), "personal");
+        assert_ne!(agent_socket_path(&personal), agent_socket_path(&copy));
+        assert_eq!(
+            agent_socket_path(&personal),
+            agent_socket_path(&personal.clone())
+        );
+    }
+}
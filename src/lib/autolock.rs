@@ -0,0 +1,464 @@
+use crate::*;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use zbus::zvariant::OwnedObjectPath;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockReason {
+    Idle,
+    SystemSleep,
+    ScreenLock,
+}
+
+impl fmt::Display for LockReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockReason::Idle => write!(f, "after being idle"),
+            LockReason::SystemSleep => write!(f, "because the system is going to sleep"),
+            LockReason::ScreenLock => write!(f, "because the screen was locked"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdleTimeout {
+    Minutes(u64),
+    Never,
+}
+
+impl IdleTimeout {
+    pub const ALL: [IdleTimeout; 6] = [
+        IdleTimeout::Minutes(1),
+        IdleTimeout::Minutes(5),
+        IdleTimeout::Minutes(15),
+        IdleTimeout::Minutes(30),
+        IdleTimeout::Minutes(60),
+        IdleTimeout::Never,
+    ];
+
+    pub fn duration(&self) -> Option<Duration> {
+        match self {
+            IdleTimeout::Minutes(minutes) => Some(Duration::from_secs(minutes * 60)),
+            IdleTimeout::Never => None,
+        }
+    }
+}
+
+impl fmt::Display for IdleTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdleTimeout::Minutes(1) => write!(f, "Lock after 1 minute"),
+            IdleTimeout::Minutes(minutes) => write!(f, "Lock after {minutes} minutes"),
+            IdleTimeout::Never => write!(f, "Never lock when idle"),
+        }
+    }
+}
+
+// Saved with the application settings, the last activity only lives as long as the process
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutoLock {
+    pub idle_timeout: IdleTimeout,
+    pub lock_on_sleep: bool,
+    pub lock_on_screen_lock: bool,
+    #[serde(skip)]
+    last_activity: Instant,
+}
+
+impl Default for AutoLock {
+    fn default() -> Self {
+        Self {
+            idle_timeout: IdleTimeout::Minutes(5),
+            lock_on_sleep: true,
+            lock_on_screen_lock: true,
+            last_activity: Instant::now(),
+        }
+    }
+}
+
+impl AutoLock {
+    pub fn register_activity(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    pub fn is_idle(&self, now: Instant) -> bool {
+        self.idle_timeout
+            .duration()
+            .is_some_and(|timeout| now.duration_since(self.last_activity) >= timeout)
+    }
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait LoginManager {
+    fn get_session(&self, session_id: &str) -> zbus::Result<OwnedObjectPath>;
+    fn get_session_by_pid(&self, pid: u32) -> zbus::Result<OwnedObjectPath>;
+
+    #[zbus(signal)]
+    fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.login1.Session",
+    default_service = "org.freedesktop.login1"
+)]
+trait LoginSession {
+    #[zbus(signal)]
+    fn lock(&self) -> zbus::Result<()>;
+}
+
+// Forwards logind's suspend and session lock signals, silently gives up on systems without logind
+fn logind_events() -> impl futures::Stream<Item = LockReason> {
+    iced::stream::channel(10, |mut output| async move {
+        let connection = match zbus::Connection::system().await {
+            Ok(connection) => connection,
+            Err(error) => {
+                println!("[ERROR]: Could not connect to the system bus: {:?}", error);
+                return;
+            }
+        };
+        let Ok(manager) = LoginManagerProxy::new(&connection).await else {
+            return;
+        };
+
+        let Ok(mut sleep_signals) = manager.receive_prepare_for_sleep().await else {
+            println!("[ERROR]: Could not subscribe to logind sleep signals");
+            return;
+        };
+
+        let session_path = match manager.get_session("auto").await {
+            Ok(path) => Ok(path),
+            Err(_) => manager.get_session_by_pid(std::process::id()).await,
+        };
+        let session = match session_path {
+            Ok(path) => match LoginSessionProxy::builder(&connection).path(path) {
+                Ok(builder) => builder.build().await.ok(),
+                Err(_) => None,
+            },
+            Err(error) => {
+                println!("[ERROR]: Could not find the logind session: {:?}", error);
+                None
+            }
+        };
+        let mut lock_signals = match &session {
+            Some(session) => session.receive_lock().await.ok(),
+            None => None,
+        };
+
+        println!("[INFO]: Listening for logind sleep and lock signals");
+        loop {
+            let reason = tokio::select! {
+                Some(signal) = sleep_signals.next() => {
+                    // Only the signal sent before suspending matters, not the one after resuming
+                    match signal.args() {
+                        Ok(args) if args.start => LockReason::SystemSleep,
+                        _ => continue,
+                    }
+                }
+                Some(_) = async {
+                    match lock_signals.as_mut() {
+                        Some(signals) => signals.next().await,
+                        None => futures::future::pending().await,
+                    }
+                } => LockReason::ScreenLock,
+                else => break,
+            };
+
+            if output.send(reason).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+impl Keylight {
+    pub(crate) fn auto_lock_subscription(&self) -> Subscription<Message> {
+        if !self.async_state.page.lock().unwrap().is_unlocked() {
+            return Subscription::none();
+        }
+
+        let activity = iced::event::listen_with(|event, _status, _window| match event {
+            iced::Event::Keyboard(_) | iced::Event::Mouse(_) | iced::Event::Touch(_) => {
+                Some(Message::UserActivity)
+            }
+            _ => None,
+        });
+
+        let idle_check = match self.settings.auto_lock.idle_timeout {
+            IdleTimeout::Never => Subscription::none(),
+            IdleTimeout::Minutes(_) => {
+                iced::time::every(Duration::from_secs(1)).map(Message::IdleCheck)
+            }
+        };
+
+        let system_events = if self.settings.auto_lock.lock_on_sleep
+            || self.settings.auto_lock.lock_on_screen_lock
+        {
+            Subscription::run(logind_events).map(Message::AutoLock)
+        } else {
+            Subscription::none()
+        };
+
+        Subscription::batch([activity, idle_check, system_events])
+    }
+
+    // Closes every pooled connection and wipes the unlocked state before returning to the login page
+    pub(crate) fn lock_vault(&mut self) -> Task<Message> {
+        if !self.async_state.page.lock().unwrap().is_unlocked() {
+            return Task::none();
+        }
+
+        let vault_pool = std::mem::replace(
+            &mut self.vault_pool,
+            SqlitePool::connect_lazy_with(SqliteConnectOptions::new()),
+        );
+        // Tasks still holding a clone of the old pool must not be able to reopen it with the key
+        vault_pool.set_connect_options(SqliteConnectOptions::new());
+
+        self.async_state.clear_secrets();
+        self.master_passphrase.zeroize();
+        self.vault = UnlockedVault::default();
+        *self.async_state.page.lock().unwrap() = Page::LogIn;
+        debug_assert!(
+            self.secrets_cleared(),
+            "secrets remained after locking the vault"
+        );
+        println!("[INFO]: Locked vault");
+
+        Task::future(async move {
+            vault_pool.close().await;
+        })
+        .discard()
+    }
+
+    // Locking also forgets which vault was used and everything shown about it
+    pub(crate) fn log_out(&mut self) -> Task<Message> {
+        let lock = self.lock_vault();
+
+        let mut app_directory = self.async_state.app_directory.write().unwrap();
+        app_directory.selected_vault = None;
+        app_directory.new_vault = None;
+        app_directory.refresh_vaults();
+        drop(app_directory);
+
+        self.vault_name_input.zeroize();
+        self.vault_path_input.zeroize();
+        self.vault_deletion_armed = false;
+        self.notifications.dismiss_all();
+        self.notifications.clear_history();
+
+        println!("[INFO]: Logged out");
+        lock
+    }
+
+    pub(crate) fn secrets_cleared(&self) -> bool {
+        self.master_passphrase.is_empty()
+            && self.async_state.master_password.read().unwrap().is_empty()
+            && self
+                .async_state
+                .master_password_confirmation
+                .read()
+                .unwrap()
+                .is_empty()
+            && self.async_state.display_folders.lock().unwrap().is_empty()
+            && self.async_state.display_entries.lock().unwrap().is_empty()
+            && self
+                .async_state
+                .display_entry_data
+                .lock()
+                .unwrap()
+                .is_empty()
+            && self
+                .async_state
+                .mem_block
+                .lock()
+                .unwrap()
+                .iter()
+                .all(|block| block.as_ref().iter().all(|word| *word == 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+    use std::sync::LazyLock;
+
+    // Keylight::new reads its settings and vaults from the user's directories, the tests get their
+    // own home so they never touch real vaults
+    static HOME: LazyLock<PathBuf> = LazyLock::new(|| {
+        let home = std::env::temp_dir().join(format!("keylight-autolock-{}", std::process::id()));
+        fs::create_dir_all(&home).unwrap();
+        std::env::set_var("HOME", &home);
+        for variable in ["XDG_DATA_HOME", "XDG_CONFIG_HOME", "XDG_CACHE_HOME"] {
+            std::env::remove_var(variable);
+        }
+        home
+    });
+
+    // An unlocked Keylight with every secret and everything shown about the vault filled in
+    async fn unlocked(name: &str) -> Keylight {
+        let database = HOME.join(format!("{name}.db"));
+        let _ = fs::remove_file(&database);
+        let mut keylight = Keylight::new();
+
+        keylight.vault_pool = SqlitePool::connect_lazy_with(
+            SqliteConnectOptions::new()
+                .filename(&database)
+                .pragma("key", "'correct horse battery staple'")
+                .create_if_missing(true),
+        );
+        let mut connection = keylight.vault_pool.acquire().await.unwrap();
+        connection.close_on_drop();
+        sqlx::query("CREATE TABLE Folders (folder_id INTEGER PRIMARY KEY);")
+            .execute(connection.as_mut())
+            .await
+            .unwrap();
+        drop(connection);
+
+        let state = &keylight.async_state;
+        *state.master_password.write().unwrap() = "hunter2".to_string();
+        *state.master_password_confirmation.write().unwrap() = "hunter2".to_string();
+        state.display_folders.lock().unwrap().push(Folder {
+            folder_id: 1,
+            folder_name: Some("Mail".to_string()),
+            folder_icon: None,
+        });
+        state.display_entries.lock().unwrap().push(Entry {
+            entry_id: 2,
+            entry_name: Some("Inbox".to_string()),
+            entry_icon: None,
+            folder_id: Some(1),
+        });
+        state.display_entry_data.lock().unwrap().push(EntryData {
+            data_id: 3,
+            section_name: Some("Password".to_string()),
+            section_type: Some("password".to_string()),
+            section_data: Some(b"hunter2".to_vec()),
+            entry_id: Some(2),
+        });
+        for block in state.mem_block.lock().unwrap().iter_mut() {
+            block.as_mut().fill(0x5a5a_5a5a_5a5a_5a5a);
+        }
+        *state.page.lock().unwrap() = Page::Passwords;
+        keylight.master_passphrase = vec!["correct".to_string(), "horse".to_string()];
+
+        keylight.vault.folder_id_picked = 1;
+        keylight.vault.entry_id_picked = 2;
+        keylight.vault.breach_counts.insert(2, 7);
+        keylight.vault.breach_dataset_input = "/data/pwned.txt".to_string();
+        keylight.vault.settings.breach_dataset = Some(PathBuf::from("/data/pwned.txt"));
+        keylight.vault.pane_layout_pending = true;
+        keylight.vault.sync.folder_input = "/mnt/sync".to_string();
+        keylight.vault.sync.webdav_input.password = "dav secret".to_string();
+        keylight.vault.history.picked = Some("0123abcd".to_string());
+        let (pane, _) = keylight.vault.panes.iter().next().unwrap();
+        keylight.vault.panes.close(*pane);
+        assert!(!keylight.secrets_cleared());
+        keylight
+    }
+
+    fn assert_locked(keylight: &Keylight) {
+        assert!(keylight.secrets_cleared());
+        let state = &keylight.async_state;
+        assert!(state.master_password.read().unwrap().is_empty());
+        assert!(state
+            .master_password_confirmation
+            .read()
+            .unwrap()
+            .is_empty());
+        assert!(state.display_folders.lock().unwrap().is_empty());
+        assert!(state.display_entries.lock().unwrap().is_empty());
+        assert!(state.display_entry_data.lock().unwrap().is_empty());
+        let mem_block = state.mem_block.lock().unwrap();
+        // Still the full size, the next unlock hashes into it again
+        assert_eq!(
+            mem_block.len(),
+            argon2::Argon2::default().params().block_count()
+        );
+        assert!(mem_block
+            .iter()
+            .all(|block| block.as_ref().iter().all(|word| *word == 0)));
+        assert!(keylight.master_passphrase.is_empty());
+        assert!(matches!(*state.page.lock().unwrap(), Page::LogIn));
+
+        let vault = &keylight.vault;
+        assert_eq!(vault.folder_id_picked, 0);
+        assert_eq!(vault.entry_id_picked, 0);
+        assert!(vault.breach_counts.is_empty());
+        assert!(vault.breach_dataset_input.is_empty());
+        assert!(vault.settings.breach_dataset.is_none());
+        assert!(!vault.pane_layout_pending);
+        assert!(vault.sync.folder_input.is_empty());
+        assert!(vault.sync.webdav_input.password.is_empty());
+        assert!(vault.history.picked.is_none());
+        assert!(keylight.hidden_panes().is_empty());
+        assert_eq!(
+            keylight.vault_pool.connect_options().get_filename(),
+            Path::new(":memory:")
+        );
+    }
+
+    // A clone of the pool taken while unlocked, like the one of a task that is still running, only
+    // reaches an empty in-memory database afterwards
+    async fn assert_not_reopened(old_pool: SqlitePool) {
+        assert_eq!(
+            old_pool.connect_options().get_filename(),
+            Path::new(":memory:")
+        );
+        let mut connection = old_pool.acquire().await.unwrap();
+        let tables: i64 = sqlx::query_scalar("SELECT count(*) FROM sqlite_master;")
+            .fetch_one(connection.as_mut())
+            .await
+            .unwrap();
+        assert_eq!(tables, 0);
+    }
+
+    #[tokio::test]
+    async fn lock_vault_clears_the_unlocked_state() {
+        let mut keylight = unlocked("lock").await;
+        let old_pool = keylight.vault_pool.clone();
+
+        let _ = keylight.lock_vault();
+
+        assert_locked(&keylight);
+        assert_not_reopened(old_pool).await;
+    }
+
+    #[tokio::test]
+    async fn log_out_also_forgets_the_vault() {
+        let mut keylight = unlocked("log_out").await;
+        let old_pool = keylight.vault_pool.clone();
+        keylight
+            .async_state
+            .app_directory
+            .write()
+            .unwrap()
+            .selected_vault = Some(VaultLocation::in_directory(&HOME, "log_out"));
+        keylight.vault_name_input = "log_out".to_string();
+        keylight.vault_path_input = HOME.join("log_out.db").display().to_string();
+        keylight.notifications.push(Notification::info("Unlocked"));
+
+        let _ = keylight.log_out();
+
+        assert_locked(&keylight);
+        assert!(keylight
+            .async_state
+            .app_directory
+            .read()
+            .unwrap()
+            .selected_vault
+            .is_none());
+        assert!(keylight.vault_name_input.is_empty());
+        assert!(keylight.vault_path_input.is_empty());
+        assert!(keylight.notifications.active().is_empty());
+        assert_eq!(keylight.notifications.history().count(), 0);
+        assert_not_reopened(old_pool).await;
+    }
+}
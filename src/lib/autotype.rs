@@ -0,0 +1,1018 @@
+use crate::*;
+use futures::channel::mpsc;
+use futures::SinkExt;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use x11rb::connection::Connection;
+use x11rb::errors::ReplyError;
+use x11rb::protocol::xproto::{
+    AtomEnum, ConnectionExt as _, GrabMode, Keycode, Keysym, ModMask, Window, KEY_PRESS_EVENT,
+    KEY_RELEASE_EVENT,
+};
+use x11rb::protocol::xtest::ConnectionExt as _;
+use x11rb::protocol::{ErrorKind, Event};
+use x11rb::rust_connection::RustConnection;
+use zeroize::Zeroizing;
+
+pub const DEFAULT_SEQUENCE: &str = "{USERNAME}{TAB}{PASSWORD}{ENTER}";
+// Section types holding an entry's own sequence and the window titles it should be typed into
+pub const SEQUENCE_SECTION_TYPE: &str = "autotype";
+pub const WINDOW_SECTION_TYPE: &str = "autotype_window";
+
+// Time to let the user focus the target window when auto-type is started from Keylight itself
+pub const MANUAL_AUTO_TYPE_DELAY: Duration = Duration::from_secs(2);
+// The hotkey's modifiers are still held right after it fires and would change the typed keys
+pub const HOTKEY_AUTO_TYPE_DELAY: Duration = Duration::from_millis(400);
+const KEY_DELAY: Duration = Duration::from_millis(12);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AutoTypeError {
+    Vault(VaultError),
+    Sequence(String),
+    MissingField(String),
+    Unavailable,
+    Backend(String),
+}
+
+impl fmt::Display for AutoTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutoTypeError::Vault(error) => write!(f, "{error}"),
+            AutoTypeError::Sequence(message) => write!(f, "Invalid auto-type sequence: {message}"),
+            AutoTypeError::MissingField(field) => {
+                write!(f, "The entry has no {field} to auto-type")
+            }
+            AutoTypeError::Unavailable => write!(
+                f,
+                "Auto-type needs an X11 display or write access to /dev/uinput"
+            ),
+            AutoTypeError::Backend(message) => write!(f, "Auto-type failed: {message}"),
+        }
+    }
+}
+
+impl<E: std::error::Error> From<E> for AutoTypeError {
+    fn from(error: E) -> Self {
+        println!("[ERROR]: {:?}", error);
+        AutoTypeError::Backend(error.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoTypeCandidate {
+    pub entry_id: i64,
+    pub entry_name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialKey {
+    Tab,
+    Enter,
+    Space,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SequenceToken {
+    Literal(String),
+    Username,
+    Password,
+    Totp,
+    Field(String),
+    Key(SpecialKey, usize),
+    Delay(Duration),
+}
+
+// Parses KeePass style templates, e.g. "{USERNAME}{TAB}{PASSWORD}{DELAY 500}{ENTER}".
+// Braces are typed literally with "{{}" and "{}}"
+pub fn parse_sequence(template: &str) -> Result<Vec<SequenceToken>, AutoTypeError> {
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find(['{', '}']) {
+        literal.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(escaped) = rest.strip_prefix("{{}").or(rest.strip_prefix("{}}")) {
+            literal.push(rest.as_bytes()[1] as char);
+            rest = escaped;
+            continue;
+        }
+        if rest.starts_with('}') {
+            return Err(AutoTypeError::Sequence("unmatched '}'".to_string()));
+        }
+
+        let end = rest
+            .find('}')
+            .ok_or_else(|| AutoTypeError::Sequence("unclosed '{'".to_string()))?;
+        let placeholder = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if !literal.is_empty() {
+            tokens.push(SequenceToken::Literal(std::mem::take(&mut literal)));
+        }
+        tokens.push(parse_placeholder(placeholder)?);
+    }
+
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        tokens.push(SequenceToken::Literal(literal));
+    }
+    Ok(tokens)
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<SequenceToken, AutoTypeError> {
+    if let Some(field) = placeholder.strip_prefix("S:") {
+        return Ok(SequenceToken::Field(field.to_string()));
+    }
+
+    let (name, argument) = match placeholder.split_once(' ') {
+        Some((name, argument)) => (name, Some(argument.trim())),
+        None => (placeholder, None),
+    };
+    let count = |argument: Option<&str>| -> Result<usize, AutoTypeError> {
+        argument.map_or(Ok(1), |count| {
+            count.parse().map_err(|_| {
+                AutoTypeError::Sequence(format!("invalid repeat count in {{{placeholder}}}"))
+            })
+        })
+    };
+
+    let key = |key| Ok(SequenceToken::Key(key, count(argument)?));
+    match name.to_ascii_uppercase().as_str() {
+        "USERNAME" => Ok(SequenceToken::Username),
+        "PASSWORD" => Ok(SequenceToken::Password),
+        "TOTP" => Ok(SequenceToken::Totp),
+        "DELAY" => argument
+            .and_then(|milliseconds| milliseconds.parse().ok())
+            .map(|milliseconds| SequenceToken::Delay(Duration::from_millis(milliseconds)))
+            .ok_or_else(|| {
+                AutoTypeError::Sequence("{DELAY} needs a number of milliseconds".to_string())
+            }),
+        "TAB" => key(SpecialKey::Tab),
+        "ENTER" => key(SpecialKey::Enter),
+        "SPACE" => key(SpecialKey::Space),
+        "BACKSPACE" | "BS" => key(SpecialKey::Backspace),
+        "ESC" => key(SpecialKey::Escape),
+        "UP" => key(SpecialKey::Up),
+        "DOWN" => key(SpecialKey::Down),
+        "LEFT" => key(SpecialKey::Left),
+        "RIGHT" => key(SpecialKey::Right),
+        _ => Err(AutoTypeError::Sequence(format!(
+            "unknown placeholder {{{placeholder}}}"
+        ))),
+    }
+}
+
+pub enum AutoTypeAction {
+    Text(Zeroizing<String>),
+    Key(SpecialKey),
+    Delay(Duration),
+}
+
+fn section_text(section: &EntryData) -> Zeroizing<String> {
+    Zeroizing::new(
+        String::from_utf8_lossy(section.section_data.as_deref().unwrap_or_default()).into_owned(),
+    )
+}
+
+// Replaces every placeholder with the entry's data
+pub fn resolve_sequence(entry_data: &[EntryData]) -> Result<Vec<AutoTypeAction>, AutoTypeError> {
+    let section_of_type = |section_type: &str| {
+        entry_data
+            .iter()
+            .find(|section| section.section_type.as_deref() == Some(section_type))
+    };
+
+    let template = section_of_type(SEQUENCE_SECTION_TYPE)
+        .map(section_text)
+        .unwrap_or_else(|| Zeroizing::new(DEFAULT_SEQUENCE.to_string()));
+
+    let mut actions = Vec::new();
+    for token in parse_sequence(&template)? {
+        match token {
+            SequenceToken::Literal(text) => {
+                actions.push(AutoTypeAction::Text(Zeroizing::new(text)))
+            }
+            SequenceToken::Username => actions.push(AutoTypeAction::Text(section_text(
+                section_of_type("username")
+                    .ok_or_else(|| AutoTypeError::MissingField("username".to_string()))?,
+            ))),
+            SequenceToken::Password => actions.push(AutoTypeAction::Text(section_text(
+                section_of_type("password")
+                    .ok_or_else(|| AutoTypeError::MissingField("password".to_string()))?,
+            ))),
+            SequenceToken::Totp => {
+                let totp = section_of_type("totp")
+                    .and_then(|section| Totp::parse(&section_text(section)))
+                    .ok_or_else(|| AutoTypeError::MissingField("TOTP secret".to_string()))?;
+                actions.push(AutoTypeAction::Text(Zeroizing::new(totp.current_code())));
+            }
+            SequenceToken::Field(name) => actions.push(AutoTypeAction::Text(section_text(
+                entry_data
+                    .iter()
+                    .find(|section| section.section_name.as_deref() == Some(name.as_str()))
+                    .ok_or(AutoTypeError::MissingField(name))?,
+            ))),
+            SequenceToken::Key(key, count) => {
+                actions.extend((0..count).map(|_| AutoTypeAction::Key(key)))
+            }
+            SequenceToken::Delay(delay) => actions.push(AutoTypeAction::Delay(delay)),
+        }
+    }
+    Ok(actions)
+}
+
+// Case insensitive match where '*' stands for any run of characters
+pub fn window_title_matches(pattern: &str, title: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let title = title.to_lowercase();
+    let parts: Vec<&str> = pattern.split('*').collect();
+
+    if parts.len() == 1 {
+        return pattern == title;
+    }
+
+    let mut remaining = title.as_str();
+    for (index, part) in parts.iter().enumerate() {
+        if index == 0 {
+            match remaining.strip_prefix(part) {
+                Some(rest) => remaining = rest,
+                None => return false,
+            }
+        } else if index == parts.len() - 1 {
+            return remaining.ends_with(part);
+        } else {
+            match remaining.find(part) {
+                Some(position) => remaining = &remaining[position + part.len()..],
+                None => return false,
+            }
+        }
+    }
+    true
+}
+
+trait Typist {
+    fn type_text(&mut self, text: &str) -> Result<(), AutoTypeError>;
+    fn press_key(&mut self, key: SpecialKey) -> Result<(), AutoTypeError>;
+}
+
+fn type_actions(typist: &mut dyn Typist, actions: &[AutoTypeAction]) -> Result<(), AutoTypeError> {
+    for action in actions {
+        match action {
+            AutoTypeAction::Text(text) => typist.type_text(text)?,
+            AutoTypeAction::Key(key) => typist.press_key(*key)?,
+            AutoTypeAction::Delay(delay) => std::thread::sleep(*delay),
+        }
+    }
+    Ok(())
+}
+
+const XK_SHIFT_L: Keysym = 0xffe1;
+
+fn special_keysym(key: SpecialKey) -> Keysym {
+    match key {
+        SpecialKey::Tab => 0xff09,
+        SpecialKey::Enter => 0xff0d,
+        SpecialKey::Space => 0x0020,
+        SpecialKey::Backspace => 0xff08,
+        SpecialKey::Escape => 0xff1b,
+        SpecialKey::Left => 0xff51,
+        SpecialKey::Up => 0xff52,
+        SpecialKey::Right => 0xff53,
+        SpecialKey::Down => 0xff54,
+    }
+}
+
+fn char_keysym(character: char) -> Keysym {
+    match character as u32 {
+        codepoint @ (0x20..=0x7e | 0xa0..=0xff) => codepoint,
+        0x0a => 0xff0d,
+        0x09 => 0xff09,
+        codepoint => 0x0100_0000 | codepoint,
+    }
+}
+
+// Types through the XTEST extension, temporarily remapping a spare keycode for
+// characters missing from the current keyboard layout
+struct X11Typist {
+    connection: x11rb::rust_connection::RustConnection,
+    root: Window,
+    min_keycode: Keycode,
+    keysyms_per_keycode: u8,
+    keysyms: Vec<Keysym>,
+    scratch_keycode: Option<Keycode>,
+}
+
+impl X11Typist {
+    fn connect() -> Result<Self, AutoTypeError> {
+        let (connection, screen_number) =
+            x11rb::connect(None).map_err(|_| AutoTypeError::Unavailable)?;
+        let root = connection.setup().roots[screen_number].root;
+        let min_keycode = connection.setup().min_keycode;
+        let max_keycode = connection.setup().max_keycode;
+        let mapping = connection
+            .get_keyboard_mapping(min_keycode, max_keycode - min_keycode + 1)?
+            .reply()?;
+
+        let per_keycode = mapping.keysyms_per_keycode as usize;
+        let scratch_keycode = (min_keycode..=max_keycode).rev().find(|keycode| {
+            let start = (keycode - min_keycode) as usize * per_keycode;
+            mapping.keysyms[start..start + per_keycode]
+                .iter()
+                .all(|keysym| *keysym == 0)
+        });
+
+        Ok(Self {
+            connection,
+            root,
+            min_keycode,
+            keysyms_per_keycode: mapping.keysyms_per_keycode,
+            keysyms: mapping.keysyms,
+            scratch_keycode,
+        })
+    }
+
+    // Returns the keycode and whether shift is needed to reach the keysym
+    fn find_keysym(&self, keysym: Keysym) -> Option<(Keycode, bool)> {
+        let per_keycode = self.keysyms_per_keycode as usize;
+        self.keysyms
+            .chunks(per_keycode)
+            .enumerate()
+            .find_map(|(index, keysyms)| {
+                let keycode = self.min_keycode + index as u8;
+                keysyms
+                    .iter()
+                    .take(2)
+                    .position(|found| *found == keysym)
+                    .map(|level| (keycode, level == 1))
+            })
+    }
+
+    fn tap(&self, keycode: Keycode, shift: bool) -> Result<(), AutoTypeError> {
+        let shift_keycode = self.find_keysym(XK_SHIFT_L).map(|(keycode, _)| keycode);
+
+        if let (true, Some(shift_keycode)) = (shift, shift_keycode) {
+            self.connection.xtest_fake_input(
+                KEY_PRESS_EVENT,
+                shift_keycode,
+                0,
+                self.root,
+                0,
+                0,
+                0,
+            )?;
+        }
+        self.connection
+            .xtest_fake_input(KEY_PRESS_EVENT, keycode, 0, self.root, 0, 0, 0)?;
+        self.connection
+            .xtest_fake_input(KEY_RELEASE_EVENT, keycode, 0, self.root, 0, 0, 0)?;
+        if let (true, Some(shift_keycode)) = (shift, shift_keycode) {
+            self.connection.xtest_fake_input(
+                KEY_RELEASE_EVENT,
+                shift_keycode,
+                0,
+                self.root,
+                0,
+                0,
+                0,
+            )?;
+        }
+        // Round trip so the events are processed before the next key
+        self.connection.get_input_focus()?.reply()?;
+        std::thread::sleep(KEY_DELAY);
+        Ok(())
+    }
+
+    fn tap_keysym(&mut self, keysym: Keysym) -> Result<(), AutoTypeError> {
+        if let Some((keycode, shift)) = self.find_keysym(keysym) {
+            return self.tap(keycode, shift);
+        }
+
+        let scratch_keycode = self.scratch_keycode.ok_or_else(|| {
+            AutoTypeError::Backend("no spare keycode to type this character".to_string())
+        })?;
+        let mapping = vec![keysym; self.keysyms_per_keycode as usize];
+        self.connection.change_keyboard_mapping(
+            1,
+            scratch_keycode,
+            self.keysyms_per_keycode,
+            &mapping,
+        )?;
+        self.connection.get_input_focus()?.reply()?;
+        self.tap(scratch_keycode, false)
+    }
+}
+
+impl Typist for X11Typist {
+    fn type_text(&mut self, text: &str) -> Result<(), AutoTypeError> {
+        for character in text.chars() {
+            self.tap_keysym(char_keysym(character))?;
+        }
+        Ok(())
+    }
+
+    fn press_key(&mut self, key: SpecialKey) -> Result<(), AutoTypeError> {
+        self.tap_keysym(special_keysym(key))
+    }
+}
+
+impl Drop for X11Typist {
+    fn drop(&mut self) {
+        if let Some(scratch_keycode) = self.scratch_keycode {
+            let mapping = vec![0; self.keysyms_per_keycode as usize];
+            let _ = self.connection.change_keyboard_mapping(
+                1,
+                scratch_keycode,
+                self.keysyms_per_keycode,
+                &mapping,
+            );
+            let _ = self.connection.flush();
+        }
+    }
+}
+
+// Wayland has no XTEST equivalent available to regular clients, so a virtual keyboard is
+// created through uinput instead. Keys are sent as positions on a US layout
+struct UinputTypist {
+    device: evdev::uinput::VirtualDevice,
+}
+
+fn us_layout_key(character: char) -> Option<(evdev::Key, bool)> {
+    use evdev::Key;
+
+    const UNSHIFTED: &str = "`1234567890-=qwertyuiop[]\\asdfghjkl;'zxcvbnm,./ ";
+    const SHIFTED: &str = "~!@#$%^&*()_+QWERTYUIOP{}|ASDFGHJKL:\"ZXCVBNM<>? ";
+    const KEYS: [Key; 48] = [
+        Key::KEY_GRAVE,
+        Key::KEY_1,
+        Key::KEY_2,
+        Key::KEY_3,
+        Key::KEY_4,
+        Key::KEY_5,
+        Key::KEY_6,
+        Key::KEY_7,
+        Key::KEY_8,
+        Key::KEY_9,
+        Key::KEY_0,
+        Key::KEY_MINUS,
+        Key::KEY_EQUAL,
+        Key::KEY_Q,
+        Key::KEY_W,
+        Key::KEY_E,
+        Key::KEY_R,
+        Key::KEY_T,
+        Key::KEY_Y,
+        Key::KEY_U,
+        Key::KEY_I,
+        Key::KEY_O,
+        Key::KEY_P,
+        Key::KEY_LEFTBRACE,
+        Key::KEY_RIGHTBRACE,
+        Key::KEY_BACKSLASH,
+        Key::KEY_A,
+        Key::KEY_S,
+        Key::KEY_D,
+        Key::KEY_F,
+        Key::KEY_G,
+        Key::KEY_H,
+        Key::KEY_J,
+        Key::KEY_K,
+        Key::KEY_L,
+        Key::KEY_SEMICOLON,
+        Key::KEY_APOSTROPHE,
+        Key::KEY_Z,
+        Key::KEY_X,
+        Key::KEY_C,
+        Key::KEY_V,
+        Key::KEY_B,
+        Key::KEY_N,
+        Key::KEY_M,
+        Key::KEY_COMMA,
+        Key::KEY_DOT,
+        Key::KEY_SLASH,
+        Key::KEY_SPACE,
+    ];
+
+    if let Some(index) = UNSHIFTED.chars().position(|c| c == character) {
+        return Some((KEYS[index], false));
+    }
+    SHIFTED
+        .chars()
+        .position(|c| c == character)
+        .map(|index| (KEYS[index], true))
+}
+
+fn special_key(key: SpecialKey) -> evdev::Key {
+    use evdev::Key;
+    match key {
+        SpecialKey::Tab => Key::KEY_TAB,
+        SpecialKey::Enter => Key::KEY_ENTER,
+        SpecialKey::Space => Key::KEY_SPACE,
+        SpecialKey::Backspace => Key::KEY_BACKSPACE,
+        SpecialKey::Escape => Key::KEY_ESC,
+        SpecialKey::Up => Key::KEY_UP,
+        SpecialKey::Down => Key::KEY_DOWN,
+        SpecialKey::Left => Key::KEY_LEFT,
+        SpecialKey::Right => Key::KEY_RIGHT,
+    }
+}
+
+impl UinputTypist {
+    fn create() -> Result<Self, AutoTypeError> {
+        let mut keys = evdev::AttributeSet::<evdev::Key>::new();
+        for code in 1..=127 {
+            keys.insert(evdev::Key::new(code));
+        }
+
+        let device = evdev::uinput::VirtualDeviceBuilder::new()
+            .map_err(|_| AutoTypeError::Unavailable)?
+            .name("Keylight auto-type")
+            .with_keys(&keys)?
+            .build()?;
+
+        // The compositor needs a moment to pick up the new input device
+        std::thread::sleep(Duration::from_millis(300));
+        Ok(Self { device })
+    }
+
+    fn tap(&mut self, key: evdev::Key, shift: bool) -> Result<(), AutoTypeError> {
+        use evdev::{EventType, InputEvent, Key};
+
+        let event = |key: Key, value| InputEvent::new(EventType::KEY, key.code(), value);
+        if shift {
+            self.device.emit(&[event(Key::KEY_LEFTSHIFT, 1)])?;
+        }
+        self.device.emit(&[event(key, 1)])?;
+        self.device.emit(&[event(key, 0)])?;
+        if shift {
+            self.device.emit(&[event(Key::KEY_LEFTSHIFT, 0)])?;
+        }
+        std::thread::sleep(KEY_DELAY);
+        Ok(())
+    }
+}
+
+impl Typist for UinputTypist {
+    fn type_text(&mut self, text: &str) -> Result<(), AutoTypeError> {
+        for character in text.chars() {
+            let (key, shift) = match character {
+                '\n' => (special_key(SpecialKey::Enter), false),
+                '\t' => (special_key(SpecialKey::Tab), false),
+                _ => us_layout_key(character).ok_or_else(|| {
+                    AutoTypeError::Backend(
+                        "only US keyboard characters can be typed on Wayland".to_string(),
+                    )
+                })?,
+            };
+            self.tap(key, shift)?;
+        }
+        Ok(())
+    }
+
+    fn press_key(&mut self, key: SpecialKey) -> Result<(), AutoTypeError> {
+        self.tap(special_key(key), false)
+    }
+}
+
+fn create_typist() -> Result<Box<dyn Typist>, AutoTypeError> {
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        match UinputTypist::create() {
+            Ok(typist) => return Ok(Box::new(typist)),
+            // XWayland windows can still be reached through XTEST
+            Err(error) => println!("[ERROR]: uinput unavailable: {error}"),
+        }
+    }
+    if std::env::var_os("DISPLAY").is_none() {
+        return Err(AutoTypeError::Unavailable);
+    }
+    Ok(Box::new(X11Typist::connect()?))
+}
+
+pub async fn auto_type_entry(
+    state: Arc<ApplicationAsyncState>,
+    vault_pool: SqlitePool,
+    entry_id: i64,
+    delay: Duration,
+) -> Result<(), AutoTypeError> {
+    let entry_data = state
+        .get_entry_data(vault_pool, entry_id)
+        .await
+        .map_err(AutoTypeError::Vault)?;
+    let actions = resolve_sequence(&entry_data)?;
+    drop(entry_data);
+
+    tokio::time::sleep(delay).await;
+    tokio::task::spawn_blocking(move || {
+        let mut typist = create_typist()?;
+        type_actions(typist.as_mut(), &actions)
+    })
+    .await?
+}
+
+// Entries with auto-type window patterns match on those, every other entry matches when its
+// name appears in the window title
+pub async fn find_autotype_entries(
+    vault_pool: SqlitePool,
+    window_title: String,
+) -> Result<Vec<AutoTypeCandidate>, VaultError> {
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    let rows: Vec<(i64, Option<String>, Option<Vec<u8>>)> = sqlx::query_as(
+        "SELECT Entries.entry_id, Entries.entry_name, EntryData.section_data FROM Entries
+        LEFT JOIN EntryData ON EntryData.entry_id = Entries.entry_id
+            AND EntryData.section_type = ?
+        WHERE Entries.is_deleted = FALSE ORDER BY Entries.entry_name;",
+    )
+    .bind(WINDOW_SECTION_TYPE)
+    .fetch_all(connection.as_mut())
+    .await?;
+
+    let mut candidates: Vec<AutoTypeCandidate> = Vec::new();
+    for (entry_id, entry_name, window_pattern) in rows {
+        let entry_name = entry_name.unwrap_or_default();
+        let matches = match window_pattern {
+            Some(pattern) => {
+                window_title_matches(&String::from_utf8_lossy(&pattern), &window_title)
+            }
+            None => {
+                !entry_name.is_empty()
+                    && window_title
+                        .to_lowercase()
+                        .contains(&entry_name.to_lowercase())
+            }
+        };
+
+        if matches && !candidates.iter().any(|found| found.entry_id == entry_id) {
+            candidates.push(AutoTypeCandidate {
+                entry_id,
+                entry_name,
+            });
+        }
+    }
+    Ok(candidates)
+}
+
+pub fn active_window_title() -> Option<String> {
+    let (connection, screen_number) = x11rb::connect(None).ok()?;
+    let root = connection.setup().roots[screen_number].root;
+    let intern = |name: &str| {
+        connection
+            .intern_atom(false, name.as_bytes())
+            .ok()?
+            .reply()
+            .ok()
+            .map(|reply| reply.atom)
+    };
+    let active_window_atom = intern("_NET_ACTIVE_WINDOW")?;
+    let name_atom = intern("_NET_WM_NAME")?;
+    let utf8_string = intern("UTF8_STRING")?;
+
+    let window = connection
+        .get_property(false, root, active_window_atom, AtomEnum::WINDOW, 0, 1)
+        .ok()?
+        .reply()
+        .ok()?
+        .value32()?
+        .next()?;
+
+    let name = connection
+        .get_property(false, window, name_atom, utf8_string, 0, 1024)
+        .ok()?
+        .reply()
+        .ok()
+        .filter(|reply| !reply.value.is_empty())
+        .or_else(|| {
+            connection
+                .get_property(false, window, AtomEnum::WM_NAME, AtomEnum::STRING, 0, 1024)
+                .ok()?
+                .reply()
+                .ok()
+        })?;
+
+    Some(String::from_utf8_lossy(&name.value).into_owned())
+}
+
+#[derive(Debug, Clone)]
+pub enum HotkeyEvent {
+    // The title of the window that was focused when the hotkey was pressed
+    Pressed(String),
+    Unavailable(String),
+}
+
+// Ctrl+Alt+A, also with Lock and NumLock on, since X11 grabs match the modifiers exactly
+fn hotkey_modifiers() -> [ModMask; 4] {
+    [
+        ModMask::from(0u16),
+        ModMask::LOCK,
+        ModMask::M2,
+        ModMask::LOCK | ModMask::M2,
+    ]
+    .map(|extra| ModMask::CONTROL | ModMask::M1 | extra)
+}
+
+// Checked one by one, a key another client already grabbed only shows up as an error reply
+fn grab_hotkey(
+    connection: &RustConnection,
+    root: Window,
+    keycode: Keycode,
+) -> Result<(), ReplyError> {
+    for modifiers in hotkey_modifiers() {
+        connection
+            .grab_key(
+                true,
+                root,
+                modifiers,
+                keycode,
+                GrabMode::ASYNC,
+                GrabMode::ASYNC,
+            )?
+            .check()?;
+    }
+    Ok(())
+}
+
+fn ungrab_hotkey(connection: &RustConnection, root: Window, keycode: Keycode) {
+    for modifiers in hotkey_modifiers() {
+        let _ = connection.ungrab_key(keycode, root, modifiers);
+    }
+    let _ = connection.flush();
+}
+
+// Stops the hotkey thread once the subscription is dropped, e.g. when the vault is locked. The
+// thread then releases the grab and closes its connection
+struct HotkeyGuard(Arc<AtomicBool>);
+
+impl Drop for HotkeyGuard {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+fn watch_hotkey(stop: Arc<AtomicBool>, mut output: mpsc::Sender<HotkeyEvent>) {
+    let mut unavailable = |reason: String| {
+        println!("[ERROR]: {reason}");
+        let _ = futures::executor::block_on(output.send(HotkeyEvent::Unavailable(reason)));
+    };
+    let (connection, screen_number) = match x11rb::connect(None) {
+        Ok(connected) => connected,
+        Err(error) => {
+            unavailable(format!(
+                "Could not connect to X11 for the auto-type hotkey: {error}"
+            ));
+            return;
+        }
+    };
+    let root = connection.setup().roots[screen_number].root;
+    let Some(keycode) = X11Typist::connect()
+        .ok()
+        .and_then(|typist| typist.find_keysym(char_keysym('a')))
+        .map(|(keycode, _)| keycode)
+    else {
+        unavailable("The keyboard has no A key for the auto-type hotkey".to_string());
+        return;
+    };
+
+    if let Err(error) = grab_hotkey(&connection, root, keycode) {
+        ungrab_hotkey(&connection, root, keycode);
+        unavailable(match error {
+            ReplyError::X11Error(error) if error.error_kind == ErrorKind::Access => {
+                "Ctrl+Alt+A is taken by another application, the auto-type hotkey is off"
+                    .to_string()
+            }
+            error => format!("Could not grab the auto-type hotkey: {error}"),
+        });
+        return;
+    }
+    println!("[INFO]: Registered the Ctrl+Alt+A auto-type hotkey");
+
+    while !stop.load(Ordering::Acquire) {
+        match connection.poll_for_event() {
+            Ok(Some(Event::KeyPress(_))) => {
+                let title = active_window_title().unwrap_or_default();
+                if futures::executor::block_on(output.send(HotkeyEvent::Pressed(title))).is_err() {
+                    break;
+                }
+            }
+            Ok(Some(_)) => {}
+            Ok(None) => std::thread::sleep(Duration::from_millis(25)),
+            Err(error) => {
+                println!("[ERROR]: Lost the X11 connection: {:?}", error);
+                return;
+            }
+        }
+    }
+    ungrab_hotkey(&connection, root, keycode);
+    println!("[INFO]: Released the auto-type hotkey");
+}
+
+fn hotkey_events() -> impl futures::Stream<Item = HotkeyEvent> {
+    iced::stream::channel(10, |mut output| async move {
+        if std::env::var_os("DISPLAY").is_none() {
+            let _ = output
+                .send(HotkeyEvent::Unavailable(
+                    "The Ctrl+Alt+A auto-type hotkey needs X11, auto-type from the entry instead"
+                        .to_string(),
+                ))
+                .await;
+            return;
+        }
+        // XWayland only sees the keys typed into X11 windows
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            let _ = output
+                .send(HotkeyEvent::Unavailable(
+                    "On Wayland the Ctrl+Alt+A auto-type hotkey only works while an X11 window is focused"
+                        .to_string(),
+                ))
+                .await;
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let _guard = HotkeyGuard(stop.clone());
+        let thread_output = output.clone();
+        std::thread::spawn(move || watch_hotkey(stop, thread_output));
+
+        futures::future::pending::<()>().await;
+    })
+}
+
+impl Keylight {
+    pub(crate) fn auto_type_subscription(&self) -> Subscription<Message> {
+        if !self.async_state.page.lock().unwrap().is_unlocked() {
+            return Subscription::none();
+        }
+
+        Subscription::run(hotkey_events).map(|event| match event {
+            HotkeyEvent::Pressed(window_title) => Message::AutoTypeHotkey(window_title),
+            HotkeyEvent::Unavailable(reason) => Message::AutoTypeHotkeyUnavailable(reason),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(section_name: &str, section_type: &str, data: &str) -> EntryData {
+        EntryData {
+            data_id: 0,
+            section_name: Some(section_name.to_string()),
+            section_type: Some(section_type.to_string()),
+            section_data: Some(data.as_bytes().to_vec()),
+            entry_id: Some(1),
+        }
+    }
+
+    fn login(sequence: Option<&str>) -> Vec<EntryData> {
+        let mut entry_data = vec![
+            section("Username", "username", "alice"),
+            section("Password", "password", "s3cret"),
+            section("PIN", "text", "1234"),
+        ];
+        if let Some(sequence) = sequence {
+            entry_data.push(section("Auto-type", SEQUENCE_SECTION_TYPE, sequence));
+        }
+        entry_data
+    }
+
+    // Actions hold secrets and are neither Debug nor comparable
+    fn describe(actions: &[AutoTypeAction]) -> Vec<String> {
+        actions
+            .iter()
+            .map(|action| match action {
+                AutoTypeAction::Text(text) => format!("text {}", text.as_str()),
+                AutoTypeAction::Key(key) => format!("key {key:?}"),
+                AutoTypeAction::Delay(delay) => format!("delay {}", delay.as_millis()),
+            })
+            .collect()
+    }
+
+    fn sequence_error(template: &str) -> String {
+        match parse_sequence(template) {
+            Err(AutoTypeError::Sequence(message)) => message,
+            other => panic!("{template} parsed as {other:?}"),
+        }
+    }
+
+    #[test]
+    fn templates_parse_into_placeholders_and_text() {
+        assert_eq!(
+            parse_sequence(DEFAULT_SEQUENCE).unwrap(),
+            [
+                SequenceToken::Username,
+                SequenceToken::Key(SpecialKey::Tab, 1),
+                SequenceToken::Password,
+                SequenceToken::Key(SpecialKey::Enter, 1),
+            ]
+        );
+        assert_eq!(
+            parse_sequence("login: {username}{TAB 2}{delay 250}{S:PIN}{bs}{Totp} done").unwrap(),
+            [
+                SequenceToken::Literal("login: ".to_string()),
+                SequenceToken::Username,
+                SequenceToken::Key(SpecialKey::Tab, 2),
+                SequenceToken::Delay(Duration::from_millis(250)),
+                SequenceToken::Field("PIN".to_string()),
+                SequenceToken::Key(SpecialKey::Backspace, 1),
+                SequenceToken::Totp,
+                SequenceToken::Literal(" done".to_string()),
+            ]
+        );
+        // Escaped braces join the text around them
+        assert_eq!(
+            parse_sequence("a{{}b{}}c").unwrap(),
+            [SequenceToken::Literal("a{b}c".to_string())]
+        );
+        assert_eq!(parse_sequence("").unwrap(), []);
+    }
+
+    #[test]
+    fn malformed_templates_are_refused() {
+        assert_eq!(sequence_error("{USERNAME"), "unclosed '{'");
+        assert_eq!(sequence_error("text}"), "unmatched '}'");
+        assert_eq!(sequence_error("{NAME}"), "unknown placeholder {NAME}");
+        assert_eq!(sequence_error("{}"), "unknown placeholder {}");
+        assert_eq!(
+            sequence_error("{TAB twice}"),
+            "invalid repeat count in {TAB twice}"
+        );
+        for delay in ["{DELAY}", "{DELAY soon}", "{DELAY -5}"] {
+            assert_eq!(
+                sequence_error(delay),
+                "{DELAY} needs a number of milliseconds"
+            );
+        }
+    }
+
+    #[test]
+    fn sequences_resolve_to_the_entry_data() {
+        assert_eq!(
+            describe(&resolve_sequence(&login(None)).unwrap()),
+            ["text alice", "key Tab", "text s3cret", "key Enter"]
+        );
+        // The entry's own sequence replaces the default one
+        assert_eq!(
+            describe(&resolve_sequence(&login(Some("{S:PIN}{DELAY 100}{ENTER 2}"))).unwrap()),
+            ["text 1234", "delay 100", "key Enter", "key Enter"]
+        );
+
+        let missing = |sequence, entry_data: Vec<EntryData>| match resolve_sequence(&entry_data) {
+            Err(AutoTypeError::MissingField(field)) => field,
+            Err(error) => panic!("{sequence}: {error}"),
+            Ok(_) => panic!("{sequence} resolved"),
+        };
+        let mut no_password = login(Some("{PASSWORD}"));
+        no_password.remove(1);
+        assert_eq!(missing("{PASSWORD}", no_password), "password");
+        assert_eq!(missing("{S:Notes}", login(Some("{S:Notes}"))), "Notes");
+        assert_eq!(missing("{TOTP}", login(Some("{TOTP}"))), "TOTP secret");
+        assert!(matches!(
+            resolve_sequence(&login(Some("{BROKEN"))),
+            Err(AutoTypeError::Sequence(_))
+        ));
+    }
+
+    #[test]
+    fn totp_placeholders_type_the_current_code() {
+        let mut entry_data = login(Some("{TOTP}{ENTER}"));
+        entry_data.push(section("TOTP", "totp", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"));
+        let actions = describe(&resolve_sequence(&entry_data).unwrap());
+        let code = actions[0].strip_prefix("text ").unwrap();
+        assert!(code.len() == 6 && code.chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(actions[1], "key Enter");
+
+        // A secret that does not parse is as good as none
+        let mut entry_data = login(Some("{TOTP}"));
+        entry_data.push(section("TOTP", "totp", "not a secret!"));
+        assert!(matches!(
+            resolve_sequence(&entry_data),
+            Err(AutoTypeError::MissingField(_))
+        ));
+    }
+
+    #[test]
+    fn window_titles_match_case_insensitive_wildcards() {
+        assert!(window_title_matches("Login - Firefox", "login - firefox"));
+        assert!(!window_title_matches("Login", "Login - Firefox"));
+        assert!(window_title_matches("*", ""));
+        assert!(window_title_matches("*Firefox", "Bank - Mozilla Firefox"));
+        assert!(window_title_matches("Bank*", "BANK - Mozilla Firefox"));
+        assert!(window_title_matches(
+            "*bank*firefox",
+            "My Bank - Mozilla Firefox"
+        ));
+        assert!(window_title_matches("a**b", "ab"));
+        assert!(!window_title_matches("*bank*firefox", "Firefox - Bank"));
+        assert!(!window_title_matches("Bank*", "My Bank"));
+        // Parts do not overlap
+        assert!(!window_title_matches("ab*ba", "aba"));
+    }
+}
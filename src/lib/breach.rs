@@ -0,0 +1,567 @@
+use crate::*;
+use md4::Md4;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+// Offline lookups in the Pwned Passwords dataset (https://haveibeenpwned.com/Passwords), as saved by
+// the official downloader. Three layouts are understood:
+//
+//   a single file of "HASH:COUNT" lines ordered by hash, searched by seeking through it
+//   a directory of range files "ABCDE.txt" holding "SUFFIX:COUNT" lines for every hash prefix
+//   an index written by build_breach_index, fixed size records that are binary searched
+//
+// Both the SHA-1 and the NTLM editions work, the edition is told apart by the hash length.
+
+const INDEX_MAGIC: &[u8; 8] = b"KLHIBP1\0";
+const INDEX_HEADER_LENGTH: u64 = 16;
+const RANGE_PREFIX_LENGTH: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BreachError {
+    Io(io::ErrorKind),
+    Format(String),
+    Vault(VaultError),
+}
+
+impl fmt::Display for BreachError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreachError::Io(kind) => write!(f, "Could not read the breach dataset ({kind})"),
+            BreachError::Format(message) => write!(f, "Unsupported breach dataset: {message}"),
+            BreachError::Vault(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl From<io::Error> for BreachError {
+    fn from(error: io::Error) -> Self {
+        println!("[ERROR]: {:?}", error);
+        BreachError::Io(error.kind())
+    }
+}
+
+impl From<VaultError> for BreachError {
+    fn from(error: VaultError) -> Self {
+        BreachError::Vault(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreachHash {
+    Sha1,
+    Ntlm,
+}
+
+impl BreachHash {
+    fn from_hex_length(length: usize) -> Option<Self> {
+        match length {
+            40 => Some(BreachHash::Sha1),
+            32 => Some(BreachHash::Ntlm),
+            _ => None,
+        }
+    }
+
+    fn byte_length(self) -> usize {
+        match self {
+            BreachHash::Sha1 => 20,
+            BreachHash::Ntlm => 16,
+        }
+    }
+
+    fn index_tag(self) -> u8 {
+        match self {
+            BreachHash::Sha1 => 1,
+            BreachHash::Ntlm => 2,
+        }
+    }
+
+    // NTLM is MD4 over the UTF-16LE encoded password
+    fn digest(self, password: &[u8]) -> Zeroizing<Vec<u8>> {
+        match self {
+            BreachHash::Sha1 => Zeroizing::new(Sha1::digest(password).to_vec()),
+            BreachHash::Ntlm => {
+                let text = Zeroizing::new(String::from_utf8_lossy(password).into_owned());
+                let utf16 = Zeroizing::new(
+                    text.encode_utf16()
+                        .flat_map(u16::to_le_bytes)
+                        .collect::<Vec<u8>>(),
+                );
+                Zeroizing::new(Md4::digest(utf16.as_slice()).to_vec())
+            }
+        }
+    }
+}
+
+enum BreachSource {
+    SortedFile { file: BufReader<File>, length: u64 },
+    RangeDirectory(PathBuf),
+    Index { file: File, records: u64 },
+}
+
+pub struct BreachDatabase {
+    source: BreachSource,
+    hash: BreachHash,
+}
+
+// "HASH:COUNT" -> (hash bytes, count), None for blank or malformed lines
+fn parse_line(line: &str) -> Option<(Vec<u8>, u64)> {
+    let (hash, count) = line.trim_end().split_once(':')?;
+    let hash = data_encoding::HEXUPPER_PERMISSIVE
+        .decode(hash.as_bytes())
+        .ok()?;
+    Some((hash, count.trim().parse().ok()?))
+}
+
+fn first_line(path: &Path) -> Result<String, BreachError> {
+    let mut line = String::new();
+    BufReader::new(File::open(path)?).read_line(&mut line)?;
+    Ok(line)
+}
+
+fn range_file(directory: &Path, prefix: &str) -> PathBuf {
+    directory.join(format!("{prefix}.txt"))
+}
+
+impl BreachDatabase {
+    pub fn open(path: &Path) -> Result<Self, BreachError> {
+        if path.is_dir() {
+            let line = first_line(&range_file(path, "00000"))?;
+            let suffix_length = line.split(':').next().unwrap_or_default().len();
+            let hash = BreachHash::from_hex_length(suffix_length + RANGE_PREFIX_LENGTH)
+                .ok_or_else(|| {
+                    BreachError::Format("range files hold neither SHA-1 nor NTLM".to_string())
+                })?;
+            return Ok(Self {
+                source: BreachSource::RangeDirectory(path.to_path_buf()),
+                hash,
+            });
+        }
+
+        let mut file = File::open(path)?;
+        let length = file.metadata()?.len();
+        let mut header = [0u8; INDEX_HEADER_LENGTH as usize];
+        if length >= INDEX_HEADER_LENGTH
+            && file.read_exact(&mut header).is_ok()
+            && &header[..8] == INDEX_MAGIC
+        {
+            let hash = [BreachHash::Sha1, BreachHash::Ntlm]
+                .into_iter()
+                .find(|hash| hash.index_tag() == header[8])
+                .ok_or_else(|| BreachError::Format("unknown index edition".to_string()))?;
+            let record_length = hash.byte_length() as u64 + 4;
+            return Ok(Self {
+                source: BreachSource::Index {
+                    file,
+                    records: (length - INDEX_HEADER_LENGTH) / record_length,
+                },
+                hash,
+            });
+        }
+
+        let line = first_line(path)?;
+        let hash = parse_line(&line)
+            .and_then(|(hash, _)| BreachHash::from_hex_length(hash.len() * 2))
+            .ok_or_else(|| {
+                BreachError::Format(
+                    "expected lines of a SHA-1 or NTLM hash and a count".to_string(),
+                )
+            })?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Self {
+            source: BreachSource::SortedFile {
+                file: BufReader::new(file),
+                length,
+            },
+            hash,
+        })
+    }
+
+    // How often the password appears in the dataset, 0 when it was never seen
+    pub fn count(&mut self, password: &[u8]) -> Result<u64, BreachError> {
+        let digest = self.hash.digest(password);
+        match &mut self.source {
+            BreachSource::SortedFile { file, length } => sorted_file_count(file, *length, &digest),
+            BreachSource::RangeDirectory(directory) => range_count(directory, &digest),
+            BreachSource::Index { file, records } => {
+                index_count(file, *records, self.hash.byte_length(), &digest)
+            }
+        }
+    }
+}
+
+// The first complete line starting at or after position
+fn line_after(file: &mut BufReader<File>, position: u64) -> Result<Option<String>, BreachError> {
+    file.seek(SeekFrom::Start(position.saturating_sub(1)))?;
+    if position > 0 {
+        file.read_until(b'\n', &mut Vec::new())?;
+    }
+    let mut line = String::new();
+    match file.read_line(&mut line)? {
+        0 => Ok(None),
+        _ => Ok(Some(line)),
+    }
+}
+
+// Binary search over byte positions for the first line whose hash is not below the digest
+fn sorted_file_count(
+    file: &mut BufReader<File>,
+    length: u64,
+    digest: &[u8],
+) -> Result<u64, BreachError> {
+    let (mut low, mut high) = (0, length);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let below = match line_after(file, middle)? {
+            Some(line) => parse_line(&line).is_some_and(|(hash, _)| hash.as_slice() < digest),
+            None => false,
+        };
+        match below {
+            true => low = middle + 1,
+            false => high = middle,
+        }
+    }
+
+    Ok(line_after(file, low)?
+        .and_then(|line| parse_line(&line))
+        .filter(|(hash, _)| hash.as_slice() == digest)
+        .map_or(0, |(_, count)| count))
+}
+
+fn range_count(directory: &Path, digest: &[u8]) -> Result<u64, BreachError> {
+    let hex = Zeroizing::new(data_encoding::HEXUPPER.encode(digest));
+    let (prefix, suffix) = hex.split_at(RANGE_PREFIX_LENGTH);
+    let file = match File::open(range_file(directory, prefix)) {
+        Ok(file) => file,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(error) => return Err(error.into()),
+    };
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if let Some((line_suffix, count)) = line.trim_end().split_once(':') {
+            if line_suffix.eq_ignore_ascii_case(suffix) {
+                return Ok(count.trim().parse().unwrap_or(0));
+            }
+        }
+    }
+    Ok(0)
+}
+
+fn index_count(
+    file: &mut File,
+    records: u64,
+    hash_length: usize,
+    digest: &[u8],
+) -> Result<u64, BreachError> {
+    let record_length = hash_length + 4;
+    let mut record = vec![0u8; record_length];
+    let (mut low, mut high) = (0, records);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        file.seek(SeekFrom::Start(
+            INDEX_HEADER_LENGTH + middle * record_length as u64,
+        ))?;
+        file.read_exact(&mut record)?;
+        match record[..hash_length].cmp(digest) {
+            std::cmp::Ordering::Less => low = middle + 1,
+            std::cmp::Ordering::Greater => high = middle,
+            std::cmp::Ordering::Equal => {
+                let count: [u8; 4] = record[hash_length..].try_into().unwrap();
+                return Ok(u32::from_le_bytes(count) as u64);
+            }
+        }
+    }
+    Ok(0)
+}
+
+// Converts a sorted dataset file or range directory into the index format, returns the number of
+// hashes written. The index is about half the size of the text and needs no parsing to search
+pub fn build_breach_index(source: &Path, output: &Path) -> Result<u64, BreachError> {
+    let database = BreachDatabase::open(source)?;
+    let hash = database.hash;
+    let mut writer = BufWriter::new(File::create(output)?);
+    let mut header = [0u8; INDEX_HEADER_LENGTH as usize];
+    header[..8].copy_from_slice(INDEX_MAGIC);
+    header[8] = hash.index_tag();
+    writer.write_all(&header)?;
+
+    let mut written = 0u64;
+    let mut previous: Option<Vec<u8>> = None;
+    // Scoped, the closure borrows the writer until it is flushed below
+    {
+        let mut write_record = |hash_bytes: Vec<u8>, count: u64| -> Result<(), BreachError> {
+            if hash_bytes.len() != hash.byte_length() {
+                return Err(BreachError::Format("hashes of mixed lengths".to_string()));
+            }
+            // Binary search only works on ordered records
+            if previous
+                .as_ref()
+                .is_some_and(|previous| *previous >= hash_bytes)
+            {
+                return Err(BreachError::Format(
+                    "the dataset is not ordered by hash, download it ordered".to_string(),
+                ));
+            }
+            writer.write_all(&hash_bytes)?;
+            writer.write_all(&(count.min(u32::MAX as u64) as u32).to_le_bytes())?;
+            previous = Some(hash_bytes);
+            written += 1;
+            Ok(())
+        };
+
+        match &database.source {
+            BreachSource::SortedFile { .. } => {
+                for line in BufReader::new(File::open(source)?).lines() {
+                    if let Some((hash_bytes, count)) = parse_line(&line?) {
+                        write_record(hash_bytes, count)?;
+                    }
+                }
+            }
+            BreachSource::RangeDirectory(directory) => {
+                for prefix in 0..(1u32 << 20) {
+                    let prefix = format!("{prefix:05X}");
+                    let file = match File::open(range_file(directory, &prefix)) {
+                        Ok(file) => file,
+                        Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                        Err(error) => return Err(error.into()),
+                    };
+                    for line in BufReader::new(file).lines() {
+                        if let Some((hash_bytes, count)) = parse_line(&format!("{prefix}{}", line?))
+                        {
+                            write_record(hash_bytes, count)?;
+                        }
+                    }
+                }
+            }
+            BreachSource::Index { .. } => {
+                return Err(BreachError::Format(
+                    "the source already is an index".to_string(),
+                ));
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(written)
+}
+
+// Entry id -> times its most exposed password was seen, entries with clean passwords are left out
+pub type BreachCounts = HashMap<i64, u64>;
+
+pub fn count_breached_passwords(
+    dataset: &Path,
+    passwords: &[EntryData],
+) -> Result<BreachCounts, BreachError> {
+    let mut database = BreachDatabase::open(dataset)?;
+    let mut counts = BreachCounts::new();
+    for section in passwords {
+        let (Some(entry_id), Some(password)) = (section.entry_id, section.section_data.as_deref())
+        else {
+            continue;
+        };
+        if password.is_empty() {
+            continue;
+        }
+        let count = database.count(password)?;
+        if count > 0 {
+            let entry_count = counts.entry(entry_id).or_default();
+            *entry_count = (*entry_count).max(count);
+        }
+    }
+    Ok(counts)
+}
+
+// Every password field of the vault, looked up on a blocking thread since a dataset lookup seeks
+// through a file of several gigabytes
+pub async fn check_vault_breaches(
+    state: Arc<ApplicationAsyncState>,
+    vault_pool: SqlitePool,
+    dataset: PathBuf,
+) -> Result<BreachCounts, BreachError> {
+    let passwords = state
+        .get_sections_by_type(vault_pool, "password".to_string())
+        .await?;
+    tokio::task::spawn_blocking(move || count_breached_passwords(&dataset, &passwords))
+        .await
+        .map_err(|_| BreachError::Io(io::ErrorKind::Interrupted))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ScratchDirectory;
+    use std::collections::BTreeMap;
+
+    // A few hundred hashes around a real password, with the lowest and highest possible ones so
+    // the searches run into both ends of the file
+    fn dataset(hash: BreachHash) -> BTreeMap<Vec<u8>, u64> {
+        let mut records: BTreeMap<Vec<u8>, u64> = (0..300u64)
+            .map(|number| {
+                let digest = hash.digest(format!("filler {number}").as_bytes());
+                (digest.to_vec(), number + 1)
+            })
+            .collect();
+        records.insert(hash.digest(b"password").to_vec(), 9_545_824);
+        let mut lowest = vec![0u8; hash.byte_length()];
+        *lowest.last_mut().unwrap() = 1;
+        records.insert(lowest, 7);
+        records.insert(vec![0xff; hash.byte_length()], 8);
+        records
+    }
+
+    fn write_sorted_file(path: &Path, records: &BTreeMap<Vec<u8>, u64>) {
+        let text: String = records
+            .iter()
+            .map(|(hash, count)| format!("{}:{count}\r\n", data_encoding::HEXUPPER.encode(hash)))
+            .collect();
+        fs::write(path, text).unwrap();
+    }
+
+    fn write_range_directory(directory: &Path, records: &BTreeMap<Vec<u8>, u64>) {
+        let mut ranges: BTreeMap<String, String> = BTreeMap::new();
+        for (hash, count) in records {
+            let hex = data_encoding::HEXUPPER.encode(hash);
+            let (prefix, suffix) = hex.split_at(RANGE_PREFIX_LENGTH);
+            ranges
+                .entry(prefix.to_string())
+                .or_default()
+                .push_str(&format!("{suffix}:{count}\r\n"));
+        }
+        fs::create_dir_all(directory).unwrap();
+        for (prefix, text) in ranges {
+            fs::write(range_file(directory, &prefix), text).unwrap();
+        }
+    }
+
+    fn count_digest(database: &mut BreachDatabase, digest: &[u8]) -> u64 {
+        let hash_length = database.hash.byte_length();
+        match &mut database.source {
+            BreachSource::SortedFile { file, length } => {
+                sorted_file_count(file, *length, digest).unwrap()
+            }
+            BreachSource::RangeDirectory(directory) => range_count(directory, digest).unwrap(),
+            BreachSource::Index { file, records } => {
+                index_count(file, *records, hash_length, digest).unwrap()
+            }
+        }
+    }
+
+    // Every hash is found with its count, hashes next to them are not
+    fn assert_finds_all(path: &Path, hash: BreachHash, records: &BTreeMap<Vec<u8>, u64>) {
+        let mut database = BreachDatabase::open(path).unwrap();
+        assert_eq!(database.hash, hash);
+        for (digest, count) in records {
+            assert_eq!(count_digest(&mut database, digest), *count);
+            let mut neighbour = digest.clone();
+            *neighbour.last_mut().unwrap() ^= 0x80;
+            if !records.contains_key(&neighbour) {
+                assert_eq!(count_digest(&mut database, &neighbour), 0);
+            }
+        }
+        assert_eq!(count_digest(&mut database, &vec![0; hash.byte_length()]), 0);
+        assert_eq!(database.count(b"password").unwrap(), 9_545_824);
+        assert_eq!(database.count(b"not in the fixture").unwrap(), 0);
+    }
+
+    #[test]
+    fn digests_match_the_published_editions() {
+        assert_eq!(
+            data_encoding::HEXUPPER.encode(&BreachHash::Sha1.digest(b"password")),
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8"
+        );
+        assert_eq!(
+            data_encoding::HEXUPPER.encode(&BreachHash::Ntlm.digest(b"password")),
+            "8846F7EAEE8FB117AD06BDD830B7586C"
+        );
+    }
+
+    #[test]
+    fn every_layout_finds_every_hash() {
+        let directory = ScratchDirectory::new("breach-layouts");
+        for hash in [BreachHash::Sha1, BreachHash::Ntlm] {
+            let records = dataset(hash);
+            let name = format!("{hash:?}");
+
+            let sorted = directory.0.join(format!("{name}.txt"));
+            write_sorted_file(&sorted, &records);
+            assert_finds_all(&sorted, hash, &records);
+
+            let ranges = directory.0.join(format!("{name}-ranges"));
+            write_range_directory(&ranges, &records);
+            assert_finds_all(&ranges, hash, &records);
+
+            // A file of a single hash, the search starts and ends on it
+            let single = directory.0.join(format!("{name}-single.txt"));
+            let first: BTreeMap<Vec<u8>, u64> = records
+                .iter()
+                .take(1)
+                .map(|(hash, count)| (hash.clone(), *count))
+                .collect();
+            write_sorted_file(&single, &first);
+            let mut database = BreachDatabase::open(&single).unwrap();
+            for digest in records.keys() {
+                let expected = first.get(digest).copied().unwrap_or(0);
+                assert_eq!(count_digest(&mut database, digest), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn indexes_hold_the_same_counts() {
+        let directory = ScratchDirectory::new("breach-index");
+        for hash in [BreachHash::Sha1, BreachHash::Ntlm] {
+            let records = dataset(hash);
+            let name = format!("{hash:?}");
+            let sorted = directory.0.join(format!("{name}.txt"));
+            write_sorted_file(&sorted, &records);
+            let ranges = directory.0.join(format!("{name}-ranges"));
+            write_range_directory(&ranges, &records);
+
+            for (source, index) in [(&sorted, "from-file"), (&ranges, "from-ranges")] {
+                let index = directory.0.join(format!("{name}-{index}.klhibp"));
+                assert_eq!(
+                    build_breach_index(source, &index).unwrap(),
+                    records.len() as u64
+                );
+                assert_eq!(
+                    fs::metadata(&index).unwrap().len(),
+                    INDEX_HEADER_LENGTH + records.len() as u64 * (hash.byte_length() as u64 + 4)
+                );
+                assert_finds_all(&index, hash, &records);
+                assert!(matches!(
+                    build_breach_index(&index, &directory.0.join("again")),
+                    Err(BreachError::Format(_))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn unordered_or_foreign_datasets_are_refused() {
+        let directory = ScratchDirectory::new("breach-refused");
+        let records = dataset(BreachHash::Sha1);
+        let unordered = directory.0.join("unordered.txt");
+        let text: String = records
+            .iter()
+            .rev()
+            .map(|(hash, count)| format!("{}:{count}\n", data_encoding::HEXUPPER.encode(hash)))
+            .collect();
+        fs::write(&unordered, text).unwrap();
+        assert!(matches!(
+            build_breach_index(&unordered, &directory.0.join("index")),
+            Err(BreachError::Format(_))
+        ));
+
+        let foreign = directory.0.join("foreign.txt");
+        fs::write(&foreign, "not a dataset\n").unwrap();
+        assert!(matches!(
+            BreachDatabase::open(&foreign),
+            Err(BreachError::Format(_))
+        ));
+    }
+}
@@ -0,0 +1,392 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ConnectionExt as _, CreateWindowAux, EventMask, PropMode, SelectionNotifyEvent,
+    WindowClass, SELECTION_NOTIFY_EVENT,
+};
+use x11rb::protocol::Event;
+use x11rb::wrapper::ConnectionExt as _;
+use zeroize::Zeroizing;
+
+// Tells KDE's Klipper (and clipboard managers following it) not to keep the value in their history
+const PASSWORD_MANAGER_HINT: &str = "x-kde-passwordManagerHint";
+const PASSWORD_MANAGER_HINT_VALUE: &[u8] = b"secret";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipboardTimeout {
+    Seconds(u64),
+    Never,
+}
+
+impl ClipboardTimeout {
+    pub const ALL: [ClipboardTimeout; 5] = [
+        ClipboardTimeout::Seconds(10),
+        ClipboardTimeout::Seconds(30),
+        ClipboardTimeout::Seconds(60),
+        ClipboardTimeout::Seconds(300),
+        ClipboardTimeout::Never,
+    ];
+
+    pub fn duration(&self) -> Option<Duration> {
+        match self {
+            ClipboardTimeout::Seconds(seconds) => Some(Duration::from_secs(*seconds)),
+            ClipboardTimeout::Never => None,
+        }
+    }
+}
+
+impl fmt::Display for ClipboardTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipboardTimeout::Seconds(seconds) if seconds % 60 == 0 => {
+                write!(f, "Clear clipboard after {} min", seconds / 60)
+            }
+            ClipboardTimeout::Seconds(seconds) => write!(f, "Clear clipboard after {seconds}s"),
+            ClipboardTimeout::Never => write!(f, "Never clear clipboard"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipboardSettings {
+    pub clear_after: ClipboardTimeout,
+    pub use_primary_selection: bool,
+}
+
+impl Default for ClipboardSettings {
+    fn default() -> Self {
+        Self {
+            clear_after: ClipboardTimeout::Seconds(30),
+            use_primary_selection: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardError {
+    Unavailable,
+    Failed(String),
+}
+
+impl fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipboardError::Unavailable => write!(f, "No X11 or Wayland clipboard is available"),
+            ClipboardError::Failed(message) => {
+                write!(f, "Could not access the clipboard: {message}")
+            }
+        }
+    }
+}
+
+impl<E: std::error::Error> From<E> for ClipboardError {
+    fn from(error: E) -> Self {
+        println!("[ERROR]: {:?}", error);
+        ClipboardError::Failed(error.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardOutcome {
+    Cleared,
+    // Something else was copied in the meantime, so it was left alone
+    Replaced,
+    Kept,
+}
+
+enum CopyHandle {
+    Wayland {
+        value: Zeroizing<String>,
+        primary: bool,
+    },
+    X11 {
+        clear_requested: Arc<AtomicBool>,
+        cleared: mpsc::Receiver<bool>,
+    },
+}
+
+impl CopyHandle {
+    fn clear_if_unchanged(self) -> Result<ClipboardOutcome, ClipboardError> {
+        match self {
+            CopyHandle::Wayland { value, primary } => {
+                use std::io::Read;
+                use wl_clipboard_rs::paste;
+
+                let mut cleared = ClipboardOutcome::Replaced;
+                let mut kinds = vec![paste::ClipboardType::Regular];
+                if primary {
+                    kinds.push(paste::ClipboardType::Primary);
+                }
+
+                for kind in kinds {
+                    let mut contents = Zeroizing::new(Vec::new());
+                    if let Ok((mut pipe, _)) =
+                        paste::get_contents(kind, paste::Seat::Unspecified, paste::MimeType::Text)
+                    {
+                        pipe.read_to_end(&mut contents)?;
+                    }
+
+                    if contents.as_slice() == value.as_bytes() {
+                        let kind = match kind {
+                            paste::ClipboardType::Regular => {
+                                wl_clipboard_rs::copy::ClipboardType::Regular
+                            }
+                            paste::ClipboardType::Primary => {
+                                wl_clipboard_rs::copy::ClipboardType::Primary
+                            }
+                        };
+                        wl_clipboard_rs::copy::clear(kind, wl_clipboard_rs::copy::Seat::All)?;
+                        cleared = ClipboardOutcome::Cleared;
+                    }
+                }
+                Ok(cleared)
+            }
+            CopyHandle::X11 {
+                clear_requested,
+                cleared,
+            } => {
+                clear_requested.store(true, Ordering::Release);
+                match cleared.recv_timeout(Duration::from_secs(2)) {
+                    Ok(true) => Ok(ClipboardOutcome::Cleared),
+                    // The serving thread only exits early once every selection was taken over
+                    Ok(false) | Err(_) => Ok(ClipboardOutcome::Replaced),
+                }
+            }
+        }
+    }
+}
+
+fn copy_wayland(value: &Zeroizing<String>, primary: bool) -> Result<CopyHandle, ClipboardError> {
+    use wl_clipboard_rs::copy::{ClipboardType, MimeSource, MimeType, Options, Source};
+
+    let mut options = Options::new();
+    options.clipboard(if primary {
+        ClipboardType::Both
+    } else {
+        ClipboardType::Regular
+    });
+    options.copy_multi(vec![
+        MimeSource {
+            source: Source::Bytes(value.as_bytes().into()),
+            mime_type: MimeType::Text,
+        },
+        MimeSource {
+            source: Source::Bytes(PASSWORD_MANAGER_HINT_VALUE.into()),
+            mime_type: MimeType::Specific(PASSWORD_MANAGER_HINT.to_string()),
+        },
+    ])?;
+
+    Ok(CopyHandle::Wayland {
+        value: value.clone(),
+        primary,
+    })
+}
+
+// Owns the selections from a hidden window and serves them until cleared or taken over
+fn copy_x11(value: Zeroizing<String>, primary: bool) -> Result<CopyHandle, ClipboardError> {
+    let (connection, screen_number) =
+        x11rb::connect(None).map_err(|_| ClipboardError::Unavailable)?;
+    let screen = &connection.setup().roots[screen_number];
+    let window = connection.generate_id()?;
+    connection.create_window(
+        x11rb::COPY_DEPTH_FROM_PARENT,
+        window,
+        screen.root,
+        0,
+        0,
+        1,
+        1,
+        0,
+        WindowClass::INPUT_OUTPUT,
+        0,
+        &CreateWindowAux::new(),
+    )?;
+
+    let intern = |name: &str| -> Result<Atom, ClipboardError> {
+        Ok(connection
+            .intern_atom(false, name.as_bytes())?
+            .reply()?
+            .atom)
+    };
+    let clipboard = intern("CLIPBOARD")?;
+    let targets = intern("TARGETS")?;
+    let utf8_string = intern("UTF8_STRING")?;
+    let text = intern("TEXT")?;
+    let text_plain = intern("text/plain;charset=utf-8")?;
+    let hint = intern(PASSWORD_MANAGER_HINT)?;
+    let string: Atom = AtomEnum::STRING.into();
+
+    let mut selections = vec![clipboard];
+    if primary {
+        selections.push(AtomEnum::PRIMARY.into());
+    }
+    for selection in &selections {
+        connection.set_selection_owner(window, *selection, x11rb::CURRENT_TIME)?;
+        if connection.get_selection_owner(*selection)?.reply()?.owner != window {
+            return Err(ClipboardError::Failed(
+                "could not take ownership of the selection".to_string(),
+            ));
+        }
+    }
+    connection.flush()?;
+
+    let clear_requested = Arc::new(AtomicBool::new(false));
+    let (cleared_sender, cleared) = mpsc::channel();
+    let thread_clear_requested = clear_requested.clone();
+
+    std::thread::spawn(move || {
+        let mut owned = selections;
+        loop {
+            if thread_clear_requested.load(Ordering::Acquire) {
+                let mut cleared_any = false;
+                for selection in &owned {
+                    let still_owner = connection
+                        .get_selection_owner(*selection)
+                        .ok()
+                        .and_then(|cookie| cookie.reply().ok())
+                        .is_some_and(|reply| reply.owner == window);
+                    if still_owner {
+                        let _ = connection.set_selection_owner(
+                            x11rb::NONE,
+                            *selection,
+                            x11rb::CURRENT_TIME,
+                        );
+                        cleared_any = true;
+                    }
+                }
+                let _ = connection.destroy_window(window);
+                let _ = connection.flush();
+                let _ = cleared_sender.send(cleared_any);
+                return;
+            }
+
+            let event = match connection.poll_for_event() {
+                Ok(Some(event)) => event,
+                Ok(None) => {
+                    std::thread::sleep(Duration::from_millis(25));
+                    continue;
+                }
+                Err(error) => {
+                    println!("[ERROR]: Lost the X11 connection: {:?}", error);
+                    let _ = cleared_sender.send(false);
+                    return;
+                }
+            };
+
+            match event {
+                Event::SelectionRequest(request) => {
+                    // Obsolete clients pass no property and expect the target to be used instead
+                    let property = if request.property == x11rb::NONE {
+                        request.target
+                    } else {
+                        request.property
+                    };
+
+                    let served = if request.target == targets {
+                        connection
+                            .change_property32(
+                                PropMode::REPLACE,
+                                request.requestor,
+                                property,
+                                AtomEnum::ATOM,
+                                &[targets, utf8_string, string, text, text_plain, hint],
+                            )
+                            .is_ok()
+                    } else if [utf8_string, string, text, text_plain].contains(&request.target) {
+                        connection
+                            .change_property8(
+                                PropMode::REPLACE,
+                                request.requestor,
+                                property,
+                                request.target,
+                                value.as_bytes(),
+                            )
+                            .is_ok()
+                    } else if request.target == hint {
+                        connection
+                            .change_property8(
+                                PropMode::REPLACE,
+                                request.requestor,
+                                property,
+                                request.target,
+                                PASSWORD_MANAGER_HINT_VALUE,
+                            )
+                            .is_ok()
+                    } else {
+                        false
+                    };
+
+                    let notify = SelectionNotifyEvent {
+                        response_type: SELECTION_NOTIFY_EVENT,
+                        sequence: 0,
+                        time: request.time,
+                        requestor: request.requestor,
+                        selection: request.selection,
+                        target: request.target,
+                        property: if served { property } else { x11rb::NONE },
+                    };
+                    let _ = connection.send_event(
+                        false,
+                        request.requestor,
+                        EventMask::NO_EVENT,
+                        notify,
+                    );
+                    let _ = connection.flush();
+                }
+                Event::SelectionClear(clear) => {
+                    owned.retain(|selection| *selection != clear.selection);
+                    if owned.is_empty() {
+                        let _ = connection.destroy_window(window);
+                        let _ = connection.flush();
+                        let _ = cleared_sender.send(false);
+                        return;
+                    }
+                }
+                _ => {}
+            }
+        }
+    });
+
+    Ok(CopyHandle::X11 {
+        clear_requested,
+        cleared,
+    })
+}
+
+fn copy(value: Zeroizing<String>, primary: bool) -> Result<CopyHandle, ClipboardError> {
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        match copy_wayland(&value, primary) {
+            Ok(handle) => return Ok(handle),
+            // Compositors without the data control protocol still offer the clipboard through XWayland
+            Err(error) => println!("[ERROR]: Wayland clipboard unavailable: {error}"),
+        }
+    }
+
+    if std::env::var_os("DISPLAY").is_none() {
+        return Err(ClipboardError::Unavailable);
+    }
+    copy_x11(value, primary)
+}
+
+// Copies the value and, once the timeout passes, clears it again unless something else was copied
+pub async fn copy_sensitive(
+    value: Zeroizing<String>,
+    primary: bool,
+    clear_after: Option<Duration>,
+) -> Result<ClipboardOutcome, ClipboardError> {
+    let handle = tokio::task::spawn_blocking(move || copy(value, primary)).await??;
+
+    let Some(clear_after) = clear_after else {
+        return Ok(ClipboardOutcome::Kept);
+    };
+    tokio::time::sleep(clear_after).await;
+
+    tokio::task::spawn_blocking(move || handle.clear_if_unchanged()).await?
+}
@@ -0,0 +1,1368 @@
+use crate::*;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use reqwest::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::time::Duration;
+use url::Url;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+// Change sets kept with a cloud storage service, each behind a SyncProvider that maps the
+// SyncStore paths onto its API:
+//
+//   Google Drive   the hidden app data folder, one file per change set named after its path
+//   OneDrive       the app folder, "Apps/<app name>", the paths become folders
+//   Dropbox        the app folder, the same
+//
+// Access is granted through OAuth 2. Google Drive and OneDrive use the device flow: a code is shown
+// that the user enters on the service's page, from any browser. Dropbox has no device flow and
+// uses an authorization code with PKCE, which the user copies back from the page. Every service
+// needs the client id of an app registered with it, Google also hands out a client secret that is
+// not confidential for installed apps. Tokens are kept inside the vault with the other SyncState
+// and refreshed before each sync.
+//
+// Every request goes through an HttpTransport (http.rs) to the urls in CloudEndpoints, so both can
+// be swapped for a local server.
+
+// Refreshed when a sync could still be running as it runs out
+const TOKEN_REFRESH_MARGIN: i64 = 5 * 60;
+const DEVICE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum CloudService {
+    GoogleDrive,
+    OneDrive,
+    Dropbox,
+}
+
+impl CloudService {
+    pub const ALL: [CloudService; 3] = [
+        CloudService::GoogleDrive,
+        CloudService::OneDrive,
+        CloudService::Dropbox,
+    ];
+
+    // The SyncState key its settings are kept under
+    pub fn state_key(self) -> &'static str {
+        match self {
+            CloudService::GoogleDrive => "google_drive",
+            CloudService::OneDrive => "onedrive",
+            CloudService::Dropbox => "dropbox",
+        }
+    }
+
+    pub fn endpoints(self) -> CloudEndpoints {
+        let endpoints = match self {
+            CloudService::GoogleDrive => [
+                "https://oauth2.googleapis.com/device/code",
+                "https://oauth2.googleapis.com/token",
+                "https://www.googleapis.com/",
+                "https://www.googleapis.com/",
+            ],
+            CloudService::OneDrive => [
+                "https://login.microsoftonline.com/common/oauth2/v2.0/devicecode",
+                "https://login.microsoftonline.com/common/oauth2/v2.0/token",
+                "https://graph.microsoft.com/",
+                "https://graph.microsoft.com/",
+            ],
+            CloudService::Dropbox => [
+                "https://www.dropbox.com/oauth2/authorize",
+                "https://api.dropboxapi.com/oauth2/token",
+                "https://api.dropboxapi.com/",
+                "https://content.dropboxapi.com/",
+            ],
+        };
+        let [authorization, token, api, content] = endpoints.map(String::from);
+        CloudEndpoints {
+            authorization,
+            token,
+            api,
+            content,
+        }
+    }
+
+    fn scope(self) -> &'static str {
+        match self {
+            CloudService::GoogleDrive => "https://www.googleapis.com/auth/drive.appdata",
+            CloudService::OneDrive => "Files.ReadWrite.AppFolder offline_access",
+            CloudService::Dropbox => "files.content.read files.content.write",
+        }
+    }
+}
+
+impl fmt::Display for CloudService {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloudService::GoogleDrive => write!(f, "Google Drive"),
+            CloudService::OneDrive => write!(f, "OneDrive"),
+            CloudService::Dropbox => write!(f, "Dropbox"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CloudEndpoints {
+    // The device authorization endpoint, or the page the user is sent to for PKCE
+    pub authorization: String,
+    pub token: String,
+    pub api: String,
+    // Where file contents go, the same as api for most services
+    pub content: String,
+}
+
+// Kept inside the vault like WebDavSettings, the tokens are empty until the service is connected
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct CloudSettings {
+    pub client_id: String,
+    pub client_secret: String,
+    pub access_token: String,
+    pub refresh_token: String,
+    // Unix time the access token runs out
+    pub expires_at: i64,
+    pub enabled: bool,
+}
+
+impl CloudSettings {
+    pub fn is_connected(&self) -> bool {
+        !self.refresh_token.is_empty()
+    }
+
+    fn client_fields(&self) -> Vec<(&str, &str)> {
+        let mut fields = vec![("client_id", self.client_id.as_str())];
+        if !self.client_secret.is_empty() {
+            fields.push(("client_secret", self.client_secret.as_str()));
+        }
+        fields
+    }
+}
+
+// Messages are Debug, the secrets are left out
+impl fmt::Debug for CloudSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CloudSettings")
+            .field("client_id", &self.client_id)
+            .field("connected", &self.is_connected())
+            .field("expires_at", &self.expires_at)
+            .field("enabled", &self.enabled)
+            .finish_non_exhaustive()
+    }
+}
+
+// A login the user still has to complete on the service's page
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct PendingAuthorization {
+    #[zeroize(skip)]
+    pub service: CloudService,
+    // Tells the answer to this login apart from those of earlier ones
+    pub authorization_id: String,
+    // Where the user allows access
+    pub page: String,
+    // The code the user enters on the page, device flow only
+    pub user_code: Option<String>,
+    // device_code for the device flow, code_verifier for PKCE
+    secret: String,
+    interval: u64,
+    expires_at: i64,
+}
+
+impl PendingAuthorization {
+    pub fn needs_code(&self) -> bool {
+        self.user_code.is_none()
+    }
+}
+
+impl fmt::Debug for PendingAuthorization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PendingAuthorization")
+            .field("service", &self.service)
+            .field("page", &self.page)
+            .finish_non_exhaustive()
+    }
+}
+
+// access_token, refresh_token, expires_at
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct CloudTokens(String, String, i64);
+
+impl fmt::Debug for CloudTokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CloudTokens").finish_non_exhaustive()
+    }
+}
+
+impl CloudTokens {
+    pub fn store_in(&self, settings: &mut CloudSettings) {
+        settings.access_token = self.0.clone();
+        // Dropbox and Google keep the refresh token of the first login
+        if !self.1.is_empty() {
+            settings.refresh_token = self.1.clone();
+        }
+        settings.expires_at = self.2;
+    }
+}
+
+fn endpoint_url(base: &str, path: &str) -> Result<Url, SyncError> {
+    Url::parse(base)
+        .and_then(|base| base.join(path))
+        .map_err(|error| SyncError::Remote(format!("Invalid url {base}{path}: {error}")))
+}
+
+fn json_string(value: &serde_json::Value, key: &str) -> Option<String> {
+    value.get(key)?.as_str().map(str::to_string)
+}
+
+fn remote_error(service: &str, response: &HttpResponse, what: &str) -> SyncError {
+    match response.status {
+        StatusCode::UNAUTHORIZED => SyncError::Remote(format!(
+            "{service} did not accept the login anymore, connect it again on the sync page"
+        )),
+        status => SyncError::Remote(format!("{service} answered {status} for {what}")),
+    }
+}
+
+// The token endpoint's answer, or the OAuth error code it gave instead
+async fn request_tokens(
+    transport: &impl HttpTransport,
+    endpoints: &CloudEndpoints,
+    fields: &[(&str, &str)],
+) -> Result<Result<CloudTokens, String>, SyncError> {
+    let url = endpoint_url(&endpoints.token, "")?;
+    let response = transport
+        .send(HttpRequest::new(Method::POST, url).form(fields))
+        .await?;
+    let answer = response.json()?;
+    if !response.status.is_success() {
+        return Ok(Err(
+            json_string(&answer, "error").unwrap_or_else(|| response.status.to_string())
+        ));
+    }
+    let Some(access_token) = json_string(&answer, "access_token") else {
+        return Err(SyncError::Remote(
+            "The login did not return a token".to_string(),
+        ));
+    };
+    let expires_in = answer
+        .get("expires_in")
+        .and_then(serde_json::Value::as_i64)
+        .unwrap_or(3600);
+    Ok(Ok(CloudTokens(
+        access_token,
+        json_string(&answer, "refresh_token").unwrap_or_default(),
+        unix_now() + expires_in,
+    )))
+}
+
+fn random_url_safe(bytes: usize) -> String {
+    let mut random = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut random);
+    data_encoding::BASE64URL_NOPAD.encode(&random)
+}
+
+pub async fn start_authorization(
+    transport: &impl HttpTransport,
+    service: CloudService,
+    endpoints: &CloudEndpoints,
+    settings: &CloudSettings,
+) -> Result<PendingAuthorization, SyncError> {
+    if settings.client_id.trim().is_empty() {
+        return Err(SyncError::Remote(format!(
+            "Enter the client id of an app registered with {service} first"
+        )));
+    }
+
+    if service == CloudService::Dropbox {
+        let verifier = random_url_safe(48);
+        let challenge = data_encoding::BASE64URL_NOPAD.encode(&Sha256::digest(verifier.as_bytes()));
+        let mut page = endpoint_url(&endpoints.authorization, "")?;
+        page.query_pairs_mut()
+            .append_pair("client_id", settings.client_id.trim())
+            .append_pair("response_type", "code")
+            .append_pair("code_challenge", &challenge)
+            .append_pair("code_challenge_method", "S256")
+            .append_pair("token_access_type", "offline")
+            .append_pair("scope", service.scope());
+        return Ok(PendingAuthorization {
+            service,
+            authorization_id: random_url_safe(16),
+            page: page.to_string(),
+            user_code: None,
+            secret: verifier,
+            interval: 0,
+            expires_at: i64::MAX,
+        });
+    }
+
+    let url = endpoint_url(&endpoints.authorization, "")?;
+    let response = transport
+        .send(HttpRequest::new(Method::POST, url).form(&[
+            ("client_id", settings.client_id.trim()),
+            ("scope", service.scope()),
+        ]))
+        .await?;
+    if !response.status.is_success() {
+        return Err(remote_error(
+            &service.to_string(),
+            &response,
+            "the device login",
+        ));
+    }
+    let answer = response.json()?;
+    let field = |key: &str| {
+        json_string(&answer, key)
+            .ok_or_else(|| SyncError::Remote(format!("{service} did not return a {key}")))
+    };
+    let number = |key: &str| answer.get(key).and_then(serde_json::Value::as_i64);
+    Ok(PendingAuthorization {
+        service,
+        authorization_id: random_url_safe(16),
+        // Google still calls it verification_url
+        page: field("verification_uri").or_else(|_| field("verification_url"))?,
+        user_code: Some(field("user_code")?),
+        secret: field("device_code")?,
+        interval: number("interval").map_or(DEVICE_POLL_INTERVAL.as_secs(), |interval| {
+            interval.max(1) as u64
+        }),
+        expires_at: unix_now() + number("expires_in").unwrap_or(900),
+    })
+}
+
+// Waits until the user allowed or denied access on the page, device flow only
+pub async fn finish_device_authorization(
+    transport: &impl HttpTransport,
+    endpoints: &CloudEndpoints,
+    settings: &CloudSettings,
+    pending: PendingAuthorization,
+) -> Result<CloudTokens, SyncError> {
+    let mut interval = Duration::from_secs(pending.interval);
+    let mut fields = settings.client_fields();
+    fields.extend([
+        ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+        ("device_code", pending.secret.as_str()),
+    ]);
+    while unix_now() < pending.expires_at {
+        tokio::time::sleep(interval).await;
+        match request_tokens(transport, endpoints, &fields).await? {
+            Ok(tokens) => return Ok(tokens),
+            Err(error) if error == "authorization_pending" => {}
+            Err(error) if error == "slow_down" => interval += DEVICE_POLL_INTERVAL,
+            Err(error) if error == "access_denied" => {
+                return Err(SyncError::Remote(format!(
+                    "Access to {} was denied",
+                    pending.service
+                )))
+            }
+            Err(error) => {
+                return Err(SyncError::Remote(format!(
+                    "Could not log in to {}: {error}",
+                    pending.service
+                )))
+            }
+        }
+    }
+    Err(SyncError::Remote(format!(
+        "The login code for {} ran out, connect again",
+        pending.service
+    )))
+}
+
+// Trades the code the user copied from the page for tokens, PKCE only
+pub async fn finish_code_authorization(
+    transport: &impl HttpTransport,
+    endpoints: &CloudEndpoints,
+    settings: &CloudSettings,
+    pending: PendingAuthorization,
+    code: &str,
+) -> Result<CloudTokens, SyncError> {
+    let mut fields = settings.client_fields();
+    fields.extend([
+        ("grant_type", "authorization_code"),
+        ("code", code.trim()),
+        ("code_verifier", pending.secret.as_str()),
+    ]);
+    request_tokens(transport, endpoints, &fields)
+        .await?
+        .map_err(|error| {
+            SyncError::Remote(format!("Could not log in to {}: {error}", pending.service))
+        })
+}
+
+async fn refresh_tokens(
+    transport: &impl HttpTransport,
+    service: CloudService,
+    endpoints: &CloudEndpoints,
+    settings: &mut CloudSettings,
+) -> Result<(), SyncError> {
+    let mut fields = settings.client_fields();
+    fields.extend([
+        ("grant_type", "refresh_token"),
+        ("refresh_token", settings.refresh_token.as_str()),
+    ]);
+    let tokens = request_tokens(transport, endpoints, &fields)
+        .await?
+        .map_err(|error| {
+            SyncError::Remote(format!(
+                "{service} did not accept the login anymore ({error}), connect it again on the \
+                sync page"
+            ))
+        })?;
+    tokens.store_in(settings);
+    println!("[INFO]: Refreshed the {service} access token");
+    Ok(())
+}
+
+// How a service stores change sets. Paths are those of SyncStore
+#[allow(async_fn_in_trait)]
+pub trait SyncProvider {
+    fn name(&self) -> &'static str;
+    async fn list(
+        &self,
+        http: &impl HttpTransport,
+        access_token: &str,
+        directory: &str,
+    ) -> Result<Vec<String>, SyncError>;
+    async fn read(
+        &self,
+        http: &impl HttpTransport,
+        access_token: &str,
+        path: &str,
+    ) -> Result<Vec<u8>, SyncError>;
+    // Only ever creates the file, an existing one is left as it is and reported through Ok(false)
+    async fn create(
+        &self,
+        http: &impl HttpTransport,
+        access_token: &str,
+        path: &str,
+        contents: &[u8],
+    ) -> Result<bool, SyncError>;
+}
+
+pub struct GoogleDrive {
+    pub endpoints: CloudEndpoints,
+}
+
+impl GoogleDrive {
+    // Every file of the app data folder named `name`, or all of them, oldest first
+    async fn files(
+        &self,
+        http: &impl HttpTransport,
+        access_token: &str,
+        name: Option<&str>,
+    ) -> Result<Vec<(String, String)>, SyncError> {
+        let mut files = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut url = endpoint_url(&self.endpoints.api, "drive/v3/files")?;
+            {
+                let mut query = url.query_pairs_mut();
+                query
+                    .append_pair("spaces", "appDataFolder")
+                    .append_pair("orderBy", "createdTime")
+                    .append_pair("pageSize", "1000")
+                    .append_pair("fields", "nextPageToken,files(id,name)");
+                if let Some(name) = name {
+                    let name = name.replace('\\', "\\\\").replace('\'', "\\'");
+                    query.append_pair("q", &format!("name = '{name}'"));
+                }
+                if let Some(page_token) = &page_token {
+                    query.append_pair("pageToken", page_token);
+                }
+            }
+            let response = http
+                .send(HttpRequest::new(Method::GET, url).bearer(access_token))
+                .await?;
+            if !response.status.is_success() {
+                return Err(remote_error(self.name(), &response, "the file list"));
+            }
+            let answer = response.json()?;
+            for file in answer["files"].as_array().into_iter().flatten() {
+                if let (Some(id), Some(name)) = (json_string(file, "id"), json_string(file, "name"))
+                {
+                    files.push((id, name));
+                }
+            }
+            page_token = json_string(&answer, "nextPageToken");
+            if page_token.is_none() {
+                return Ok(files);
+            }
+        }
+    }
+}
+
+impl SyncProvider for GoogleDrive {
+    fn name(&self) -> &'static str {
+        "Google Drive"
+    }
+
+    async fn list(
+        &self,
+        http: &impl HttpTransport,
+        access_token: &str,
+        directory: &str,
+    ) -> Result<Vec<String>, SyncError> {
+        let prefix = format!("{directory}/");
+        let mut names: Vec<String> = self
+            .files(http, access_token, None)
+            .await?
+            .into_iter()
+            .map(|(_, name)| name)
+            .filter(|name| name.starts_with(&prefix))
+            .collect();
+        // Drive allows several files of the same name
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    // The oldest file of that name is the one its device wrote first
+    async fn read(
+        &self,
+        http: &impl HttpTransport,
+        access_token: &str,
+        path: &str,
+    ) -> Result<Vec<u8>, SyncError> {
+        let Some((id, _)) = self
+            .files(http, access_token, Some(path))
+            .await?
+            .into_iter()
+            .next()
+        else {
+            return Err(SyncError::Remote(format!(
+                "{path} is missing on Google Drive"
+            )));
+        };
+        let mut url = endpoint_url(&self.endpoints.api, &format!("drive/v3/files/{id}"))?;
+        url.query_pairs_mut().append_pair("alt", "media");
+        let response = http
+            .send(HttpRequest::new(Method::GET, url).bearer(access_token))
+            .await?;
+        if !response.status.is_success() {
+            return Err(remote_error(self.name(), &response, path));
+        }
+        Ok(response.body.clone())
+    }
+
+    // Drive has no create-only upload, a file that shows up at the same time is settled by read
+    async fn create(
+        &self,
+        http: &impl HttpTransport,
+        access_token: &str,
+        path: &str,
+        contents: &[u8],
+    ) -> Result<bool, SyncError> {
+        if !self.files(http, access_token, Some(path)).await?.is_empty() {
+            return Ok(false);
+        }
+        let boundary = random_url_safe(24);
+        let metadata = json!({ "name": path, "parents": ["appDataFolder"] });
+        let mut body = format!(
+            "--{boundary}\r\nContent-Type: application/json; charset=UTF-8\r\n\r\n{metadata}\r\n\
+            --{boundary}\r\nContent-Type: application/octet-stream\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(contents);
+        body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+        let mut url = endpoint_url(&self.endpoints.content, "upload/drive/v3/files")?;
+        url.query_pairs_mut().append_pair("uploadType", "multipart");
+        let response = http
+            .send(
+                HttpRequest::new(Method::POST, url)
+                    .bearer(access_token)
+                    .body(&format!("multipart/related; boundary={boundary}"), body),
+            )
+            .await?;
+        if !response.status.is_success() {
+            return Err(remote_error(self.name(), &response, path));
+        }
+        Ok(true)
+    }
+}
+
+pub struct OneDrive {
+    pub endpoints: CloudEndpoints,
+}
+
+impl OneDrive {
+    fn item_url(&self, path: &str, action: &str) -> Result<Url, SyncError> {
+        endpoint_url(
+            &self.endpoints.api,
+            &format!("v1.0/me/drive/special/approot:/{path}:/{action}"),
+        )
+    }
+}
+
+impl SyncProvider for OneDrive {
+    fn name(&self) -> &'static str {
+        "OneDrive"
+    }
+
+    async fn list(
+        &self,
+        http: &impl HttpTransport,
+        access_token: &str,
+        directory: &str,
+    ) -> Result<Vec<String>, SyncError> {
+        let mut files = Vec::new();
+        let mut pending = vec![directory.to_string()];
+        while let Some(directory) = pending.pop() {
+            let mut next = Some(self.item_url(&directory, "children")?);
+            while let Some(url) = next.take() {
+                let response = http
+                    .send(HttpRequest::new(Method::GET, url).bearer(access_token))
+                    .await?;
+                if response.status == StatusCode::NOT_FOUND {
+                    break;
+                }
+                if !response.status.is_success() {
+                    return Err(remote_error(self.name(), &response, &directory));
+                }
+                let answer = response.json()?;
+                for item in answer["value"].as_array().into_iter().flatten() {
+                    let Some(name) = json_string(item, "name") else {
+                        continue;
+                    };
+                    let path = format!("{directory}/{name}");
+                    match item.get("folder").is_some() {
+                        true => pending.push(path),
+                        false => files.push(path),
+                    }
+                }
+                next =
+                    json_string(&answer, "@odata.nextLink").and_then(|next| Url::parse(&next).ok());
+            }
+        }
+        Ok(files)
+    }
+
+    async fn read(
+        &self,
+        http: &impl HttpTransport,
+        access_token: &str,
+        path: &str,
+    ) -> Result<Vec<u8>, SyncError> {
+        let response = http
+            .send(
+                HttpRequest::new(Method::GET, self.item_url(path, "content")?).bearer(access_token),
+            )
+            .await?;
+        if !response.status.is_success() {
+            return Err(remote_error(self.name(), &response, path));
+        }
+        Ok(response.body.clone())
+    }
+
+    // Missing folders of the path are created along with the file
+    async fn create(
+        &self,
+        http: &impl HttpTransport,
+        access_token: &str,
+        path: &str,
+        contents: &[u8],
+    ) -> Result<bool, SyncError> {
+        let mut url = self.item_url(path, "content")?;
+        url.query_pairs_mut()
+            .append_pair("@microsoft.graph.conflictBehavior", "fail");
+        let response = http
+            .send(
+                HttpRequest::new(Method::PUT, url)
+                    .bearer(access_token)
+                    .body("application/octet-stream", contents.to_vec()),
+            )
+            .await?;
+        match response.status {
+            status if status.is_success() => Ok(true),
+            StatusCode::CONFLICT => Ok(false),
+            _ => Err(remote_error(self.name(), &response, path)),
+        }
+    }
+}
+
+pub struct Dropbox {
+    pub endpoints: CloudEndpoints,
+}
+
+impl Dropbox {
+    async fn list_page(
+        &self,
+        http: &impl HttpTransport,
+        access_token: &str,
+        path: &str,
+        arguments: serde_json::Value,
+    ) -> Result<HttpResponse, SyncError> {
+        http.send(
+            HttpRequest::new(Method::POST, endpoint_url(&self.endpoints.api, path)?)
+                .bearer(access_token)
+                .json(&arguments),
+        )
+        .await
+    }
+}
+
+impl SyncProvider for Dropbox {
+    fn name(&self) -> &'static str {
+        "Dropbox"
+    }
+
+    async fn list(
+        &self,
+        http: &impl HttpTransport,
+        access_token: &str,
+        directory: &str,
+    ) -> Result<Vec<String>, SyncError> {
+        let mut files = Vec::new();
+        let mut response = self
+            .list_page(
+                http,
+                access_token,
+                "2/files/list_folder",
+                json!({ "path": format!("/{directory}"), "recursive": true }),
+            )
+            .await?;
+        loop {
+            let answer = response.json()?;
+            if response.status == StatusCode::CONFLICT
+                && json_string(&answer, "error_summary")
+                    .is_some_and(|error| error.starts_with("path/not_found"))
+            {
+                return Ok(files);
+            }
+            if !response.status.is_success() {
+                return Err(remote_error(self.name(), &response, directory));
+            }
+            for entry in answer["entries"].as_array().into_iter().flatten() {
+                if json_string(entry, ".tag").as_deref() != Some("file") {
+                    continue;
+                }
+                if let Some(path) = json_string(entry, "path_display") {
+                    files.push(path.trim_start_matches('/').to_string());
+                }
+            }
+            match (answer["has_more"].as_bool(), json_string(&answer, "cursor")) {
+                (Some(true), Some(cursor)) => {
+                    response = self
+                        .list_page(
+                            http,
+                            access_token,
+                            "2/files/list_folder/continue",
+                            json!({ "cursor": cursor }),
+                        )
+                        .await?;
+                }
+                _ => return Ok(files),
+            }
+        }
+    }
+
+    async fn read(
+        &self,
+        http: &impl HttpTransport,
+        access_token: &str,
+        path: &str,
+    ) -> Result<Vec<u8>, SyncError> {
+        let response = http
+            .send(
+                HttpRequest::new(
+                    Method::POST,
+                    endpoint_url(&self.endpoints.content, "2/files/download")?,
+                )
+                .bearer(access_token)
+                .header(
+                    "dropbox-api-arg",
+                    json!({ "path": format!("/{path}") }).to_string(),
+                ),
+            )
+            .await?;
+        if !response.status.is_success() {
+            return Err(remote_error(self.name(), &response, path));
+        }
+        Ok(response.body.clone())
+    }
+
+    // Missing folders of the path are created along with the file
+    async fn create(
+        &self,
+        http: &impl HttpTransport,
+        access_token: &str,
+        path: &str,
+        contents: &[u8],
+    ) -> Result<bool, SyncError> {
+        let arguments = json!({
+            "path": format!("/{path}"),
+            "mode": "add",
+            "autorename": false,
+            "mute": true,
+        });
+        let response = http
+            .send(
+                HttpRequest::new(
+                    Method::POST,
+                    endpoint_url(&self.endpoints.content, "2/files/upload")?,
+                )
+                .bearer(access_token)
+                .header("dropbox-api-arg", arguments.to_string())
+                .body("application/octet-stream", contents.to_vec()),
+            )
+            .await?;
+        match response.status {
+            status if status.is_success() => Ok(true),
+            StatusCode::CONFLICT => Ok(false),
+            _ => Err(remote_error(self.name(), &response, path)),
+        }
+    }
+}
+
+// A SyncProvider with a valid access token
+pub struct CloudStore<'a, P: SyncProvider, T: HttpTransport> {
+    pub provider: P,
+    pub transport: &'a T,
+    pub access_token: Zeroizing<String>,
+}
+
+impl<P: SyncProvider, T: HttpTransport> SyncStore for CloudStore<'_, P, T> {
+    async fn list(&self, directory: &str) -> Result<Vec<String>, SyncError> {
+        self.provider
+            .list(self.transport, &self.access_token, directory)
+            .await
+    }
+
+    async fn read(&self, path: &str) -> Result<Vec<u8>, SyncError> {
+        self.provider
+            .read(self.transport, &self.access_token, path)
+            .await
+    }
+
+    // The same as on WebDAV, an existing change set is only fine when it holds the same bytes
+    async fn write(&self, path: &str, contents: &[u8]) -> Result<(), SyncError> {
+        if self
+            .provider
+            .create(self.transport, &self.access_token, path, contents)
+            .await?
+        {
+            return Ok(());
+        }
+        let existing = Zeroizing::new(self.read(path).await?);
+        match existing.as_slice() == contents {
+            true => Ok(()),
+            false => Err(SyncError::Remote(format!(
+                "{path} was already written by another device, \
+                two devices may be sharing one device id"
+            ))),
+        }
+    }
+}
+
+// Refreshes the tokens when needed, keeping the new ones in the vault, then syncs
+pub async fn sync_vault_to_cloud(
+    transport: &impl HttpTransport,
+    service: CloudService,
+    endpoints: CloudEndpoints,
+    mut settings: CloudSettings,
+    vault_pool: SqlitePool,
+    device_id: String,
+) -> Result<SyncSummary, SyncError> {
+    if !settings.is_connected() {
+        return Err(SyncError::Remote(format!("{service} is not connected")));
+    }
+    if settings.expires_at - TOKEN_REFRESH_MARGIN < unix_now() {
+        refresh_tokens(transport, service, &endpoints, &mut settings).await?;
+        set_sync_target_settings(
+            vault_pool.clone(),
+            service.state_key(),
+            Some(settings.clone()),
+        )
+        .await?;
+    }
+
+    let access_token = Zeroizing::new(settings.access_token.clone());
+    match service {
+        CloudService::GoogleDrive => {
+            let provider = GoogleDrive { endpoints };
+            let store = CloudStore {
+                provider,
+                transport,
+                access_token,
+            };
+            sync_vault(&store, vault_pool, device_id).await
+        }
+        CloudService::OneDrive => {
+            let provider = OneDrive { endpoints };
+            let store = CloudStore {
+                provider,
+                transport,
+                access_token,
+            };
+            sync_vault(&store, vault_pool, device_id).await
+        }
+        CloudService::Dropbox => {
+            let provider = Dropbox { endpoints };
+            let store = CloudStore {
+                provider,
+                transport,
+                access_token,
+            };
+            sync_vault(&store, vault_pool, device_id).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::mock::{self, MockResponse};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    // Answers every request with a function and keeps "<METHOD> <url>" of each
+    struct MockTransport<F: Fn(&HttpRequest) -> HttpResponse> {
+        answer: F,
+        requests: Mutex<Vec<String>>,
+    }
+
+    impl<F: Fn(&HttpRequest) -> HttpResponse> MockTransport<F> {
+        fn new(answer: F) -> Self {
+            Self {
+                answer,
+                requests: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn requests(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl<F: Fn(&HttpRequest) -> HttpResponse> HttpTransport for MockTransport<F> {
+        async fn send(&self, request: HttpRequest) -> Result<HttpResponse, SyncError> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", request.method, request.url));
+            Ok((self.answer)(&request))
+        }
+    }
+
+    fn answer(status: u16, body: serde_json::Value) -> HttpResponse {
+        HttpResponse {
+            status: StatusCode::from_u16(status).unwrap(),
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    fn form(request: &HttpRequest) -> HashMap<String, String> {
+        url::form_urlencoded::parse(&request.body)
+            .into_owned()
+            .collect()
+    }
+
+    fn query(url: &Url, name: &str) -> Option<String> {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    }
+
+    fn endpoints() -> CloudEndpoints {
+        CloudEndpoints {
+            authorization: "https://login.test/authorize".to_string(),
+            token: "https://login.test/token".to_string(),
+            api: "https://api.test/".to_string(),
+            content: "https://content.test/".to_string(),
+        }
+    }
+
+    fn settings() -> CloudSettings {
+        let mut settings = CloudSettings::default();
+        settings.client_id = "keylight".to_string();
+        settings.refresh_token = "refresh-1".to_string();
+        settings.enabled = true;
+        settings
+    }
+
+    #[tokio::test]
+    async fn device_flow_polls_until_access_is_allowed() {
+        let polls = AtomicUsize::new(0);
+        let transport = MockTransport::new(|request| match request.url.path() {
+            "/authorize" => {
+                assert_eq!(form(request)["scope"], CloudService::GoogleDrive.scope());
+                answer(
+                    200,
+                    json!({
+                        "device_code": "device-1",
+                        "user_code": "ABCD-EFGH",
+                        "verification_url": "https://login.test/device",
+                        "interval": 1,
+                        "expires_in": 60,
+                    }),
+                )
+            }
+            _ => {
+                let fields = form(request);
+                assert_eq!(
+                    fields["grant_type"],
+                    "urn:ietf:params:oauth:grant-type:device_code"
+                );
+                assert_eq!(fields["device_code"], "device-1");
+                match polls.fetch_add(1, Ordering::SeqCst) {
+                    0 => answer(428, json!({ "error": "authorization_pending" })),
+                    _ => answer(
+                        200,
+                        json!({
+                            "access_token": "access-1",
+                            "refresh_token": "refresh-2",
+                            "expires_in": 3600,
+                        }),
+                    ),
+                }
+            }
+        });
+        let mut settings = settings();
+        let pending = start_authorization(
+            &transport,
+            CloudService::GoogleDrive,
+            &endpoints(),
+            &settings,
+        )
+        .await
+        .unwrap();
+        assert_eq!(pending.page, "https://login.test/device");
+        assert_eq!(pending.user_code.as_deref(), Some("ABCD-EFGH"));
+        assert!(!pending.needs_code());
+
+        let tokens = finish_device_authorization(&transport, &endpoints(), &settings, pending)
+            .await
+            .unwrap();
+        tokens.store_in(&mut settings);
+        assert_eq!(polls.load(Ordering::SeqCst), 2);
+        assert_eq!(settings.access_token, "access-1");
+        assert_eq!(settings.refresh_token, "refresh-2");
+        assert!(settings.expires_at > unix_now() + 3000);
+    }
+
+    #[tokio::test]
+    async fn denied_device_logins_fail() {
+        let transport = MockTransport::new(|request| match request.url.path() {
+            "/authorize" => answer(
+                200,
+                json!({
+                    "device_code": "device-1",
+                    "user_code": "ABCD-EFGH",
+                    "verification_uri": "https://login.test/device",
+                    "interval": 1,
+                }),
+            ),
+            _ => answer(400, json!({ "error": "access_denied" })),
+        });
+        let pending = start_authorization(
+            &transport,
+            CloudService::OneDrive,
+            &endpoints(),
+            &settings(),
+        )
+        .await
+        .unwrap();
+        let denied =
+            finish_device_authorization(&transport, &endpoints(), &settings(), pending).await;
+        assert_eq!(
+            denied.unwrap_err(),
+            SyncError::Remote("Access to OneDrive was denied".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn pkce_codes_are_traded_with_their_verifier() {
+        let challenge = Mutex::new(String::new());
+        let transport = MockTransport::new(|request| {
+            let fields = form(request);
+            assert_eq!(fields["grant_type"], "authorization_code");
+            assert_eq!(fields["code"], "code-1");
+            let verifier = Sha256::digest(fields["code_verifier"].as_bytes());
+            assert_eq!(
+                data_encoding::BASE64URL_NOPAD.encode(&verifier),
+                *challenge.lock().unwrap()
+            );
+            answer(
+                200,
+                json!({
+                    "access_token": "access-1",
+                    "refresh_token": "refresh-2",
+                    "expires_in": 14400,
+                }),
+            )
+        });
+        let mut settings = settings();
+        let pending =
+            start_authorization(&transport, CloudService::Dropbox, &endpoints(), &settings)
+                .await
+                .unwrap();
+        // Dropbox is only asked once the user copied the code back
+        assert!(transport.requests().is_empty());
+        assert!(pending.needs_code());
+        let page = Url::parse(&pending.page).unwrap();
+        assert_eq!(
+            query(&page, "code_challenge_method").as_deref(),
+            Some("S256")
+        );
+        assert_eq!(
+            query(&page, "token_access_type").as_deref(),
+            Some("offline")
+        );
+        *challenge.lock().unwrap() = query(&page, "code_challenge").unwrap();
+
+        finish_code_authorization(&transport, &endpoints(), &settings, pending, " code-1\n")
+            .await
+            .unwrap()
+            .store_in(&mut settings);
+        assert_eq!(settings.access_token, "access-1");
+        assert_eq!(settings.refresh_token, "refresh-2");
+    }
+
+    #[tokio::test]
+    async fn refreshing_keeps_the_refresh_token_it_did_not_replace() {
+        let transport = MockTransport::new(|request| {
+            let fields = form(request);
+            assert_eq!(fields["grant_type"], "refresh_token");
+            match fields["refresh_token"].as_str() {
+                "refresh-1" => answer(200, json!({ "access_token": "access-2" })),
+                _ => answer(400, json!({ "error": "invalid_grant" })),
+            }
+        });
+        let mut settings = settings();
+        refresh_tokens(
+            &transport,
+            CloudService::Dropbox,
+            &endpoints(),
+            &mut settings,
+        )
+        .await
+        .unwrap();
+        assert_eq!(settings.access_token, "access-2");
+        assert_eq!(settings.refresh_token, "refresh-1");
+        assert!(settings.expires_at > unix_now());
+
+        settings.refresh_token = "revoked".to_string();
+        let refreshed = refresh_tokens(
+            &transport,
+            CloudService::Dropbox,
+            &endpoints(),
+            &mut settings,
+        )
+        .await;
+        assert!(
+            matches!(refreshed, Err(SyncError::Remote(error)) if error.contains("invalid_grant"))
+        );
+    }
+
+    #[tokio::test]
+    async fn google_drive_lists_every_page_and_reads_the_oldest_file() {
+        let transport = MockTransport::new(|request| {
+            assert_eq!(
+                request.headers,
+                vec![("authorization", "Bearer access-1".to_string())]
+            );
+            if query(&request.url, "alt").is_some() {
+                return HttpResponse {
+                    status: StatusCode::OK,
+                    body: request.url.path().as_bytes().to_vec(),
+                };
+            }
+            let files = match query(&request.url, "pageToken").as_deref() {
+                None => json!({
+                    "nextPageToken": "page-2",
+                    "files": [
+                        { "id": "1", "name": "vault/b/00000001.klsync" },
+                        { "id": "2", "name": "vault/a/00000001.klsync" },
+                    ],
+                }),
+                _ => json!({
+                    "files": [
+                        { "id": "3", "name": "vault/a/00000001.klsync" },
+                        { "id": "4", "name": "other/00000001.klsync" },
+                    ],
+                }),
+            };
+            answer(200, files)
+        });
+        let drive = GoogleDrive {
+            endpoints: endpoints(),
+        };
+        assert_eq!(
+            drive.list(&transport, "access-1", "vault").await.unwrap(),
+            vec!["vault/a/00000001.klsync", "vault/b/00000001.klsync"]
+        );
+        let read = drive
+            .read(&transport, "access-1", "vault/a/00000001.klsync")
+            .await
+            .unwrap();
+        assert_eq!(read, b"/drive/v3/files/1");
+    }
+
+    #[tokio::test]
+    async fn onedrive_follows_next_links_into_folders() {
+        let transport = MockTransport::new(|request| match request.url.as_str() {
+            "https://api.test/v1.0/me/drive/special/approot:/vault:/children" => answer(
+                200,
+                json!({
+                    "value": [{ "name": "a", "folder": {} }],
+                    "@odata.nextLink": "https://api.test/next",
+                }),
+            ),
+            "https://api.test/next" => {
+                answer(200, json!({ "value": [{ "name": "b", "folder": {} }] }))
+            }
+            "https://api.test/v1.0/me/drive/special/approot:/vault/a:/children" => answer(
+                200,
+                json!({ "value": [{ "name": "00000001.klsync", "file": {} }] }),
+            ),
+            _ => answer(404, json!({})),
+        });
+        let onedrive = OneDrive {
+            endpoints: endpoints(),
+        };
+        assert_eq!(
+            onedrive
+                .list(&transport, "access-1", "vault")
+                .await
+                .unwrap(),
+            vec!["vault/a/00000001.klsync"]
+        );
+        assert!(onedrive
+            .list(&transport, "access-1", "missing")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn dropbox_continues_with_the_cursor() {
+        let transport = MockTransport::new(|request| {
+            let arguments: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            match (request.url.path(), arguments["path"].as_str()) {
+                ("/2/files/list_folder", Some("/vault")) => answer(
+                    200,
+                    json!({
+                        "entries": [
+                            { ".tag": "folder", "path_display": "/vault/a" },
+                            { ".tag": "file", "path_display": "/vault/a/00000001.klsync" },
+                        ],
+                        "has_more": true,
+                        "cursor": "cursor-1",
+                    }),
+                ),
+                ("/2/files/list_folder/continue", _) => {
+                    assert_eq!(arguments["cursor"], "cursor-1");
+                    answer(
+                        200,
+                        json!({
+                            "entries": [
+                                { ".tag": "file", "path_display": "/vault/b/00000001.klsync" },
+                            ],
+                            "has_more": false,
+                        }),
+                    )
+                }
+                _ => answer(409, json!({ "error_summary": "path/not_found/.." })),
+            }
+        });
+        let dropbox = Dropbox {
+            endpoints: endpoints(),
+        };
+        assert_eq!(
+            dropbox.list(&transport, "access-1", "vault").await.unwrap(),
+            vec!["vault/a/00000001.klsync", "vault/b/00000001.klsync"]
+        );
+        assert!(dropbox
+            .list(&transport, "access-1", "missing")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    // Each service reports a change set that is there already, the store then compares the bytes
+    #[tokio::test]
+    async fn existing_change_sets_are_only_accepted_with_the_same_bytes() {
+        let stored = b"first change set".to_vec();
+        let transport = MockTransport::new(|request| {
+            let creates = match request.url.host_str() {
+                Some("content.test") => request.url.path() != "/2/files/download",
+                _ => request.method == Method::PUT,
+            };
+            match creates {
+                true => answer(409, json!({ "error_summary": "path/conflict/file/.." })),
+                false => HttpResponse {
+                    status: StatusCode::OK,
+                    body: stored.clone(),
+                },
+            }
+        });
+        let path = "vault/device/00000001.klsync";
+        let onedrive = CloudStore {
+            provider: OneDrive {
+                endpoints: endpoints(),
+            },
+            transport: &transport,
+            access_token: Zeroizing::new("access-1".to_string()),
+        };
+        onedrive.write(path, &stored).await.unwrap();
+        assert!(onedrive.write(path, b"something else").await.is_err());
+        let dropbox = CloudStore {
+            provider: Dropbox {
+                endpoints: endpoints(),
+            },
+            transport: &transport,
+            access_token: Zeroizing::new("access-1".to_string()),
+        };
+        dropbox.write(path, &stored).await.unwrap();
+        assert!(dropbox.write(path, b"something else").await.is_err());
+        assert!(transport
+            .requests()
+            .iter()
+            .any(|request| request.starts_with("PUT https://api.test/")
+                && request.contains("conflictBehavior=fail")));
+
+        // Drive is asked for files of that name before uploading
+        let listing = MockTransport::new(|request| match query(&request.url, "alt") {
+            Some(_) => HttpResponse {
+                status: StatusCode::OK,
+                body: stored.clone(),
+            },
+            None => answer(200, json!({ "files": [{ "id": "1", "name": path }] })),
+        });
+        let drive = CloudStore {
+            provider: GoogleDrive {
+                endpoints: endpoints(),
+            },
+            transport: &listing,
+            access_token: Zeroizing::new("access-1".to_string()),
+        };
+        drive.write(path, &stored).await.unwrap();
+        assert!(drive.write(path, b"something else").await.is_err());
+        assert!(!listing
+            .requests()
+            .iter()
+            .any(|request| request.starts_with("POST")));
+    }
+
+    #[tokio::test]
+    async fn busy_services_are_asked_again_after_retry_after() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = requests.clone();
+        let url = mock::serve(move |_| match counted.fetch_add(1, Ordering::SeqCst) {
+            0 => MockResponse::new("429 Too Many Requests").header("Retry-After", "2"),
+            _ => MockResponse::new("200 OK").body("{}"),
+        })
+        .await;
+        let transport = ReqwestTransport::new().unwrap();
+        let started = Instant::now();
+        let response = transport
+            .send(HttpRequest::new(Method::GET, Url::parse(&url).unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        // Longer than the first delay of its own, the server's wait was kept
+        assert!(started.elapsed() >= Duration::from_secs(2));
+    }
+}
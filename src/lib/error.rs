@@ -0,0 +1,193 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::io;
+
+// SQLite primary result codes, the extended codes keep these in their lowest byte
+const SQLITE_BUSY: u32 = 5;
+const SQLITE_LOCKED: u32 = 6;
+const SQLITE_CORRUPT: u32 = 11;
+const SQLITE_NOTADB: u32 = 26;
+
+// Also what the unlock agent answers with, so its clients handle an error the same way as when
+// they open the vault themselves
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "detail", rename_all = "snake_case")]
+pub enum VaultError {
+    WrongPassword,
+    NoVaultSelected,
+    CorruptKeyfile,
+    CorruptDatabase,
+    DatabaseLocked,
+    SchemaMismatch {
+        found: i64,
+        supported: i64,
+    },
+    #[serde(with = "error_kind")]
+    Io(io::ErrorKind),
+    Crypto,
+    Database(String),
+    Agent(String),
+}
+
+// io::ErrorKind has no serde support, it is sent by name and unknown names become Other
+mod error_kind {
+    use super::*;
+
+    const KINDS: &[io::ErrorKind] = &[
+        io::ErrorKind::NotFound,
+        io::ErrorKind::PermissionDenied,
+        io::ErrorKind::ConnectionRefused,
+        io::ErrorKind::ConnectionReset,
+        io::ErrorKind::ConnectionAborted,
+        io::ErrorKind::NotConnected,
+        io::ErrorKind::AddrInUse,
+        io::ErrorKind::AddrNotAvailable,
+        io::ErrorKind::BrokenPipe,
+        io::ErrorKind::AlreadyExists,
+        io::ErrorKind::WouldBlock,
+        io::ErrorKind::InvalidInput,
+        io::ErrorKind::InvalidData,
+        io::ErrorKind::TimedOut,
+        io::ErrorKind::WriteZero,
+        io::ErrorKind::Interrupted,
+        io::ErrorKind::Unsupported,
+        io::ErrorKind::UnexpectedEof,
+        io::ErrorKind::OutOfMemory,
+    ];
+
+    pub fn serialize<S: Serializer>(
+        kind: &io::ErrorKind,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{kind:?}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<io::ErrorKind, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(KINDS
+            .iter()
+            .find(|kind| format!("{kind:?}") == name)
+            .copied()
+            .unwrap_or(io::ErrorKind::Other))
+    }
+}
+
+impl VaultError {
+    // Errors that may go away by simply trying the same action again
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, VaultError::DatabaseLocked | VaultError::Io(_))
+    }
+
+    // Errors that can only be resolved through the master sync key or a backup
+    pub fn needs_recovery(&self) -> bool {
+        matches!(
+            self,
+            VaultError::CorruptKeyfile
+                | VaultError::CorruptDatabase
+                | VaultError::SchemaMismatch { .. }
+        )
+    }
+}
+
+impl fmt::Display for VaultError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VaultError::WrongPassword => write!(f, "Wrong password"),
+            VaultError::NoVaultSelected => write!(f, "No vault was selected"),
+            VaultError::CorruptKeyfile => write!(f, "The vault keyfile is malformed"),
+            VaultError::CorruptDatabase => {
+                write!(f, "The vault database is malformed or could not be decrypted")
+            }
+            VaultError::DatabaseLocked => {
+                write!(f, "The vault is in use by another process, please try again")
+            }
+            VaultError::SchemaMismatch { found, supported } => write!(
+                f,
+                "The vault uses schema version {found}, but this version of Keylight only supports up to {supported}"
+            ),
+            VaultError::Io(kind) => write!(f, "Could not access the vault files ({kind})"),
+            VaultError::Crypto => write!(f, "Unexpected Error: a cryptographic operation failed"),
+            VaultError::Database(message) => write!(f, "Unexpected database error: {message}"),
+            VaultError::Agent(message) => write!(f, "The unlock agent failed: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for VaultError {}
+
+impl From<io::Error> for VaultError {
+    fn from(error: io::Error) -> Self {
+        println!("[ERROR]: {:?}", error);
+        VaultError::Io(error.kind())
+    }
+}
+
+impl From<sqlx::Error> for VaultError {
+    fn from(error: sqlx::Error) -> Self {
+        println!("[ERROR]: {:?}", error);
+        match &error {
+            sqlx::Error::Database(database_error) => {
+                let code = database_error
+                    .code()
+                    .and_then(|code| code.parse::<u32>().ok())
+                    .map(|code| code & 0xff);
+
+                match code {
+                    Some(SQLITE_BUSY | SQLITE_LOCKED) => VaultError::DatabaseLocked,
+                    Some(SQLITE_CORRUPT | SQLITE_NOTADB) => VaultError::CorruptDatabase,
+                    _ if database_error.message().starts_with("no such table")
+                        || database_error.message().starts_with("no such column") =>
+                    {
+                        VaultError::SchemaMismatch {
+                            found: 0,
+                            supported: crate::vault::SCHEMA_VERSION,
+                        }
+                    }
+                    _ => VaultError::Database(database_error.message().to_string()),
+                }
+            }
+            sqlx::Error::Io(io_error) => VaultError::Io(io_error.kind()),
+            sqlx::Error::PoolTimedOut => VaultError::DatabaseLocked,
+            _ => VaultError::Database(error.to_string()),
+        }
+    }
+}
+
+impl From<postcard::Error> for VaultError {
+    fn from(error: postcard::Error) -> Self {
+        println!("[ERROR]: {:?}", error);
+        VaultError::CorruptKeyfile
+    }
+}
+
+impl From<chacha20poly1305::Error> for VaultError {
+    fn from(error: chacha20poly1305::Error) -> Self {
+        println!("[ERROR]: {:?}", error);
+        VaultError::Crypto
+    }
+}
+
+// Logs the underlying error and replaces it with the given vault error
+pub trait UiRecovery<T> {
+    fn expect_throw(self, error: VaultError) -> Result<T, VaultError>;
+}
+
+impl<T, E: std::fmt::Debug> UiRecovery<T> for Result<T, E> {
+    fn expect_throw(self, error: VaultError) -> Result<T, VaultError> {
+        self.map_err(|original| {
+            println!("[ERROR]: {:?}", original);
+            error
+        })
+    }
+}
+
+impl<T> UiRecovery<T> for Option<T> {
+    fn expect_throw(self, error: VaultError) -> Result<T, VaultError> {
+        self.ok_or_else(|| {
+            println!("[ERROR]: Failed to unwrap None type");
+            error
+        })
+    }
+}
@@ -0,0 +1,82 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chbs::config::BasicConfig;
+use chbs::probability::Probability;
+use chbs::scheme::ToScheme;
+use zeroize::Zeroizing;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!#$%&()*+,-./:;<=>?@[]^_{|}~";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordOptions {
+    pub length: usize,
+    pub uppercase: bool,
+    pub digits: bool,
+    pub symbols: bool,
+}
+
+impl Default for PasswordOptions {
+    fn default() -> Self {
+        Self {
+            length: 24,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+        }
+    }
+}
+
+// Rejection sampling keeps every character equally likely
+fn random_index(bound: usize) -> usize {
+    let zone = u32::MAX - u32::MAX % bound as u32;
+    loop {
+        let value = OsRng.next_u32();
+        if value < zone {
+            return (value % bound as u32) as usize;
+        }
+    }
+}
+
+// Every enabled character class is guaranteed to appear at least once, passwords missing one are
+// thrown away instead of patched so the result stays uniformly distributed
+pub fn generate_password(options: &PasswordOptions) -> Zeroizing<String> {
+    let mut classes = vec![LOWERCASE];
+    if options.uppercase {
+        classes.push(UPPERCASE);
+    }
+    if options.digits {
+        classes.push(DIGITS);
+    }
+    if options.symbols {
+        classes.push(SYMBOLS);
+    }
+    let alphabet: Vec<char> = classes.concat().chars().collect();
+    let length = options.length.max(classes.len());
+
+    loop {
+        let password: Zeroizing<String> = Zeroizing::new(
+            (0..length)
+                .map(|_| alphabet[random_index(alphabet.len())])
+                .collect(),
+        );
+        if classes
+            .iter()
+            .all(|class| password.chars().any(|c| class.contains(c)))
+        {
+            return password;
+        }
+    }
+}
+
+// The same scheme is used for the master passphrase when creating a vault
+pub fn generate_passphrase(words: usize) -> Zeroizing<String> {
+    let config = BasicConfig {
+        words,
+        capitalize_first: Probability::half(),
+        capitalize_words: Probability::Sometimes(0.2),
+        separator: " ".to_string(),
+    };
+    Zeroizing::new(config.to_scheme().generate())
+}
@@ -0,0 +1,397 @@
+use crate::*;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use zeroize::Zeroizing;
+
+// The security dashboard looks at every password of the vault at once:
+//
+//   weak      the strength estimate (strength.rs) scores it WEAK_SCORE or lower
+//   reused    more than one entry holds the same password
+//   old       it has not changed for longer than the picked PasswordAgeLimit
+//   no 2FA    a login, a password next to a url or username, without a totp section
+//
+// Only entry names and findings leave build_health_report. Passwords are compared through digests
+// salted once per report and are dropped together with the rows they were read from
+
+const WEAK_SCORE: u8 = 2;
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PasswordAgeLimit(pub u64);
+
+impl PasswordAgeLimit {
+    pub const ALL: [PasswordAgeLimit; 4] = [
+        PasswordAgeLimit(90),
+        PasswordAgeLimit(180),
+        PasswordAgeLimit(365),
+        PasswordAgeLimit(730),
+    ];
+}
+
+impl Default for PasswordAgeLimit {
+    fn default() -> Self {
+        PasswordAgeLimit(365)
+    }
+}
+
+impl fmt::Display for PasswordAgeLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            365 => write!(f, "Older than 1 year"),
+            days if days % 365 == 0 => write!(f, "Older than {} years", days / 365),
+            days => write!(f, "Older than {days} days"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReportEntry {
+    pub entry_id: i64,
+    pub entry_name: String,
+    pub folder_id: Option<i64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct WeakPassword {
+    pub entry: ReportEntry,
+    pub score: u8,
+    pub warning: Option<&'static str>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HealthReport {
+    pub password_count: usize,
+    // Every entry holding a password, findings from elsewhere (breach.rs) are shown through these
+    pub password_entries: Vec<ReportEntry>,
+    pub weak: Vec<WeakPassword>,
+    // Each group shares one password, largest group first
+    pub reused: Vec<Vec<ReportEntry>>,
+    // Days since the oldest password of each entry changed, the limit is applied when shown
+    pub password_ages: Vec<(ReportEntry, u64)>,
+    pub missing_totp: Vec<ReportEntry>,
+}
+
+impl HealthReport {
+    pub fn old_passwords(
+        &self,
+        limit: PasswordAgeLimit,
+    ) -> impl Iterator<Item = &(ReportEntry, u64)> {
+        self.password_ages
+            .iter()
+            .filter(move |(_, days)| *days > limit.0)
+    }
+}
+
+// entry_id, entry_name, folder_id, section_type, section_data, modified_at
+type HealthRow = (
+    i64,
+    Option<String>,
+    Option<i64>,
+    String,
+    Option<Vec<u8>>,
+    Option<i64>,
+);
+
+#[derive(Default)]
+struct EntrySections {
+    entry_name: String,
+    folder_id: Option<i64>,
+    passwords: Vec<(Zeroizing<Vec<u8>>, Option<i64>)>,
+    username: Option<Zeroizing<String>>,
+    has_url: bool,
+    has_totp: bool,
+}
+
+impl EntrySections {
+    fn report_entry(&self, entry_id: i64) -> ReportEntry {
+        ReportEntry {
+            entry_id,
+            entry_name: self.entry_name.clone(),
+            folder_id: self.folder_id,
+        }
+    }
+}
+
+fn analyze(entries: BTreeMap<i64, EntrySections>, now: i64) -> HealthReport {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+
+    let mut report = HealthReport::default();
+    let mut groups: HashMap<[u8; 32], Vec<ReportEntry>> = HashMap::new();
+
+    for (entry_id, sections) in &entries {
+        let report_entry = sections.report_entry(*entry_id);
+        let mut weakest: Option<Strength> = None;
+        let mut oldest: Option<i64> = None;
+
+        for (data, modified_at) in &sections.passwords {
+            let password = Zeroizing::new(String::from_utf8_lossy(data).into_owned());
+            if password.is_empty() {
+                continue;
+            }
+            report.password_count += 1;
+            if weakest.is_none() {
+                report.password_entries.push(report_entry.clone());
+            }
+
+            let mut user_inputs = vec![sections.entry_name.as_str()];
+            if let Some(username) = &sections.username {
+                user_inputs.push(username.as_str());
+            }
+            let strength = estimate_strength(&password, &user_inputs);
+            if weakest
+                .as_ref()
+                .is_none_or(|weakest| strength.guesses_log10 < weakest.guesses_log10)
+            {
+                weakest = Some(strength);
+            }
+
+            let mut hasher = Sha256::new();
+            hasher.update(salt);
+            hasher.update(password.as_bytes());
+            let group = groups.entry(hasher.finalize().into()).or_default();
+            if !group.iter().any(|entry| entry.entry_id == *entry_id) {
+                group.push(report_entry.clone());
+            }
+
+            if let Some(modified_at) = modified_at {
+                oldest = Some(oldest.map_or(*modified_at, |oldest| oldest.min(*modified_at)));
+            }
+        }
+
+        if let Some(strength) = weakest.filter(|strength| strength.score <= WEAK_SCORE) {
+            report.weak.push(WeakPassword {
+                entry: report_entry.clone(),
+                score: strength.score,
+                warning: strength.warning,
+            });
+        }
+        if let Some(oldest) = oldest {
+            let days = (now - oldest).max(0) / SECONDS_PER_DAY;
+            report
+                .password_ages
+                .push((report_entry.clone(), days as u64));
+        }
+
+        let is_login = sections.has_url || sections.username.is_some();
+        if is_login && !sections.passwords.is_empty() && !sections.has_totp {
+            report.missing_totp.push(report_entry);
+        }
+    }
+
+    report.reused = groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect();
+    for group in &mut report.reused {
+        group.sort_by(|a, b| a.entry_name.cmp(&b.entry_name));
+    }
+    report.reused.sort_by(|a, b| {
+        b.len()
+            .cmp(&a.len())
+            .then_with(|| a[0].entry_name.cmp(&b[0].entry_name))
+    });
+    report.weak.sort_by(|a, b| {
+        a.score
+            .cmp(&b.score)
+            .then_with(|| a.entry.entry_name.cmp(&b.entry.entry_name))
+    });
+    report
+        .password_ages
+        .sort_by_key(|(_, days)| std::cmp::Reverse(*days));
+    report
+        .missing_totp
+        .sort_by(|a, b| a.entry_name.cmp(&b.entry_name));
+    report
+}
+
+pub async fn build_health_report(vault_pool: SqlitePool) -> Result<HealthReport, VaultError> {
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    // Only passwords and usernames are read, the other sections only count by their type
+    let rows: Vec<HealthRow> = sqlx::query_as(
+        "SELECT Entries.entry_id, Entries.entry_name, Entries.folder_id, EntryData.section_type,
+            CASE WHEN EntryData.section_type IN ('password', 'username')
+                THEN EntryData.section_data END,
+            EntryData.modified_at
+            FROM EntryData JOIN Entries ON Entries.entry_id = EntryData.entry_id
+            WHERE Entries.is_deleted = FALSE AND EntryData.section_type IS NOT NULL;",
+    )
+    .fetch_all(connection.as_mut())
+    .await?;
+    drop(connection);
+
+    let mut entries: BTreeMap<i64, EntrySections> = BTreeMap::new();
+    for (entry_id, entry_name, folder_id, section_type, data, modified_at) in rows {
+        let data = Zeroizing::new(data.unwrap_or_default());
+        let sections = entries.entry(entry_id).or_default();
+        sections.entry_name = entry_name.unwrap_or_else(|| "Unnamed entry".to_string());
+        sections.folder_id = folder_id;
+        match section_type.as_str() {
+            "password" => sections.passwords.push((data, modified_at)),
+            "username" => {
+                sections.username =
+                    Some(Zeroizing::new(String::from_utf8_lossy(&data).into_owned()))
+            }
+            "totp" => sections.has_totp = true,
+            _ => sections.has_url |= UrlMatchMode::from_section_type(&section_type).is_some(),
+        }
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+    // Estimating every password takes a moment on large vaults
+    tokio::task::spawn_blocking(move || analyze(entries, now))
+        .await
+        .map_err(|_| VaultError::Io(std::io::ErrorKind::Interrupted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_750_000_000;
+    const STRONG: &str = "xK9#mQ2$vL7!pR4&";
+
+    fn sections(entry_name: &str, passwords: &[(&str, Option<i64>)]) -> EntrySections {
+        EntrySections {
+            entry_name: entry_name.to_string(),
+            passwords: passwords
+                .iter()
+                .map(|(password, modified_at)| {
+                    (Zeroizing::new(password.as_bytes().to_vec()), *modified_at)
+                })
+                .collect(),
+            ..EntrySections::default()
+        }
+    }
+
+    fn days_ago(days: i64) -> Option<i64> {
+        Some(NOW - days * SECONDS_PER_DAY)
+    }
+
+    fn names(entries: &[ReportEntry]) -> Vec<&str> {
+        entries
+            .iter()
+            .map(|entry| entry.entry_name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn weak_passwords_are_reported_by_the_weakest_one() {
+        let entries = BTreeMap::from([
+            (1, sections("Mail", &[("password", None)])),
+            (2, sections("Bank", &[(STRONG, None)])),
+            (3, sections("Shop", &[(STRONG, None), ("shop2024", None)])),
+            (4, sections("Notes", &[("", None)])),
+        ]);
+        let report = analyze(entries, NOW);
+        assert_eq!(report.password_count, 4);
+        assert_eq!(names(&report.password_entries), ["Mail", "Bank", "Shop"]);
+        let weak: Vec<(&str, u8)> = report
+            .weak
+            .iter()
+            .map(|weak| (weak.entry.entry_name.as_str(), weak.score))
+            .collect();
+        assert_eq!(weak[0], ("Mail", 0));
+        assert_eq!(weak.len(), 2);
+        assert_eq!(weak[1].0, "Shop");
+        assert!(report.weak[0].warning.is_some());
+    }
+
+    #[test]
+    fn reuse_groups_count_entries_not_passwords() {
+        let shared = "Tr0ub4dor&3-horse";
+        let entries = BTreeMap::from([
+            (1, sections("Work", &[(shared, None)])),
+            (2, sections("Forum", &[(shared, None)])),
+            (3, sections("Wiki", &[(shared, None), (STRONG, None)])),
+            // The same password twice in one entry is no reuse
+            (
+                4,
+                sections(
+                    "Router",
+                    &[("only-here-81!", None), ("only-here-81!", None)],
+                ),
+            ),
+            (5, sections("Printer", &[(STRONG, None), (STRONG, None)])),
+        ]);
+        let report = analyze(entries, NOW);
+        let groups: Vec<Vec<&str>> = report.reused.iter().map(|group| names(group)).collect();
+        assert_eq!(
+            groups,
+            [vec!["Forum", "Wiki", "Work"], vec!["Printer", "Wiki"]]
+        );
+    }
+
+    #[test]
+    fn ages_follow_the_oldest_password() {
+        let entries = BTreeMap::from([
+            (
+                1,
+                sections(
+                    "Old",
+                    &[
+                        ("a-Fine-pass-1", days_ago(10)),
+                        ("a-Fine-pass-2", days_ago(400)),
+                    ],
+                ),
+            ),
+            (2, sections("Recent", &[(STRONG, days_ago(30))])),
+            (3, sections("Ancient", &[(STRONG, days_ago(1000))])),
+            (4, sections("Unknown", &[(STRONG, None)])),
+            // A clock that went back does not make ages negative
+            (5, sections("Future", &[(STRONG, Some(NOW + 5000))])),
+        ]);
+        let report = analyze(entries, NOW);
+        let ages: Vec<(&str, u64)> = report
+            .password_ages
+            .iter()
+            .map(|(entry, days)| (entry.entry_name.as_str(), *days))
+            .collect();
+        assert_eq!(
+            ages,
+            [
+                ("Ancient", 1000),
+                ("Old", 400),
+                ("Recent", 30),
+                ("Future", 0)
+            ]
+        );
+
+        let old = |limit| -> Vec<&str> {
+            report
+                .old_passwords(limit)
+                .map(|(entry, _)| entry.entry_name.as_str())
+                .collect()
+        };
+        assert_eq!(old(PasswordAgeLimit(365)), ["Ancient", "Old"]);
+        assert_eq!(old(PasswordAgeLimit(730)), ["Ancient"]);
+    }
+
+    #[test]
+    fn logins_without_totp_are_reported() {
+        let mut site = sections("Site", &[(STRONG, None)]);
+        site.has_url = true;
+        let mut account = sections("Account", &[(STRONG, None)]);
+        account.username = Some(Zeroizing::new("alice".to_string()));
+        let mut protected = sections("Protected", &[(STRONG, None)]);
+        protected.has_url = true;
+        protected.has_totp = true;
+        let mut bookmark = sections("Bookmark", &[]);
+        bookmark.has_url = true;
+        let entries = BTreeMap::from([
+            (1, site),
+            (2, account),
+            (3, protected),
+            (4, bookmark),
+            (5, sections("Wifi", &[(STRONG, None)])),
+        ]);
+        let report = analyze(entries, NOW);
+        assert_eq!(names(&report.missing_totp), ["Account", "Site"]);
+    }
+}
@@ -0,0 +1,1127 @@
+use crate::*;
+use argon2::password_hash::rand_core::OsRng;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use git2::{
+    Commit, Cred, CredentialType, FetchOptions, Oid, PushOptions, RemoteCallbacks, Repository,
+    RepositoryInitOptions, Signature, TreeWalkMode, TreeWalkResult,
+};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+// The vault's history kept as a git repository, a bare one in the data directory under
+// "history/<vault id>". Every snapshot commits the records of sync.rs as files:
+//
+//   folders/<sync id>.klrec   the folder record
+//   entries/<sync id>.klrec   the entry record and one line for each of its sections
+//
+// Every line is "<tag> <base64 of nonce and ciphertext>", the tag an HMAC of the record key so
+// lines keep their place without naming the record. A record that did not change keeps its line,
+// so `git log -p` shows exactly which records changed and nothing else. Names, values and section
+// names are only ever stored encrypted, the keys are derived from the sync key the vault carries.
+//
+// With a remote the history is fetched and pushed like any other repository. Histories that went
+// apart are merged one entry file at a time: a file changed on one side only takes that side,
+// one changed on both sides takes the side committed last and keeps the other version as a sync
+// conflict for review. The records that changed through the merge are then written into the vault.
+
+pub const HISTORY_INTERVAL: Duration = Duration::from_secs(60);
+const HISTORY_BRANCH: &str = "refs/heads/main";
+const REMOTE_BRANCH: &str = "refs/remotes/origin/main";
+const RECORD_EXTENSION: &str = "klrec";
+const AUTHOR_DOMAIN: &str = "@keylight.invalid";
+const HISTORY_LIST_LIMIT: usize = 200;
+
+// Kept inside the vault with the sync settings
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct GitHistorySettings {
+    pub enabled: bool,
+    // Empty keeps the history on this device only
+    pub remote_url: String,
+    pub username: String,
+    // Password or access token for https remotes, ssh remotes use the ssh agent
+    pub password: String,
+}
+
+// Messages are Debug, the password is left out
+impl fmt::Debug for GitHistorySettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GitHistorySettings")
+            .field("enabled", &self.enabled)
+            .field("remote_url", &self.remote_url)
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HistorySummary {
+    pub committed: bool,
+    // Records changed in the vault by merging the remote history
+    pub received: usize,
+    pub conflicts: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct HistoryCommit {
+    pub id: String,
+    pub time: i64,
+    pub summary: String,
+    pub device_id: String,
+}
+
+impl From<git2::Error> for SyncError {
+    fn from(error: git2::Error) -> Self {
+        println!("[ERROR]: {:?}", error);
+        SyncError::Remote(format!("Git: {}", error.message()))
+    }
+}
+
+struct HistoryKeys {
+    cipher: XChaCha20Poly1305,
+    tag_key: Zeroizing<[u8; 32]>,
+}
+
+impl HistoryKeys {
+    fn new(sync_key: &[u8]) -> Result<Self, SyncError> {
+        let hkdf = Hkdf::<Sha256>::new(None, sync_key);
+        let mut cipher_key = Zeroizing::new([0u8; 32]);
+        let mut tag_key = Zeroizing::new([0u8; 32]);
+        hkdf.expand(b"keylight history cipher", cipher_key.as_mut())
+            .and_then(|_| hkdf.expand(b"keylight history tag", tag_key.as_mut()))
+            .map_err(|_| SyncError::Crypto)?;
+        Ok(Self {
+            cipher: XChaCha20Poly1305::new_from_slice(cipher_key.as_slice())
+                .map_err(|_| SyncError::Crypto)?,
+            tag_key,
+        })
+    }
+
+    fn tag(&self, key: &str) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(self.tag_key.as_slice())
+            .expect("HMAC takes keys of any length");
+        mac.update(key.as_bytes());
+        data_encoding::HEXLOWER.encode(&mac.finalize().into_bytes()[..16])
+    }
+
+    // The file and tag are authenticated, a line moved elsewhere does not open
+    fn seal(&self, file: &str, key: &str, payload: &RecordPayload) -> Result<String, SyncError> {
+        let tag = self.tag(key);
+        let plaintext = Zeroizing::new(postcard::to_allocvec(&(key, payload))?);
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher.encrypt(
+            &nonce,
+            Payload {
+                msg: &plaintext,
+                aad: format!("{file} {tag}").as_bytes(),
+            },
+        )?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(format!("{tag} {}", data_encoding::BASE64.encode(&sealed)))
+    }
+
+    fn open(&self, file: &str, line: &str) -> Result<(String, RecordPayload), SyncError> {
+        let unreadable = || SyncError::Format(format!("{file} is not a history record"));
+        let (tag, sealed) = line.split_once(' ').ok_or_else(unreadable)?;
+        let sealed = data_encoding::BASE64
+            .decode(sealed.as_bytes())
+            .map_err(|_| unreadable())?;
+        if sealed.len() < 24 {
+            return Err(unreadable());
+        }
+        let (nonce, ciphertext) = sealed.split_at(24);
+        let plaintext = Zeroizing::new(self.cipher.decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: format!("{file} {tag}").as_bytes(),
+            },
+        )?);
+        Ok(postcard::from_bytes(&plaintext)?)
+    }
+}
+
+// A record as committed, the line is reused as long as the record stays the same
+struct HistoryRecord {
+    line: String,
+    payload: RecordPayload,
+}
+
+// By record key
+type HistoryRecords = BTreeMap<String, HistoryRecord>;
+// By path in the tree
+type HistoryFiles = BTreeMap<String, Oid>;
+
+// A vault record to write, None removes it
+type RecordUpdate = (String, Option<RecordPayload>);
+
+// The version of a record that lost a merge
+struct HistoryConflict {
+    key: String,
+    kept_device: String,
+    other_device: String,
+    other_modified_at: i64,
+    other_payload: Option<RecordPayload>,
+}
+
+// A merge commit, or the remote one when fast-forwarding, that only becomes the branch head once
+// its records are in the vault
+struct PendingMerge {
+    local: Option<Oid>,
+    target: Oid,
+    updates: Vec<RecordUpdate>,
+    conflicts: Vec<HistoryConflict>,
+}
+
+fn record_file(key: &str) -> Option<String> {
+    let mut parts = key.splitn(3, '/');
+    match (parts.next(), parts.next()) {
+        (Some("folder"), Some(sync_id)) => Some(format!("folders/{sync_id}.{RECORD_EXTENSION}")),
+        (Some("entry" | "section"), Some(sync_id)) => {
+            Some(format!("entries/{sync_id}.{RECORD_EXTENSION}"))
+        }
+        _ => None,
+    }
+}
+
+fn history_path(data_directory: &Path, vault_id: &str) -> PathBuf {
+    data_directory.join("history").join(vault_id)
+}
+
+fn open_repository(path: &Path) -> Result<Repository, SyncError> {
+    if let Ok(repository) = Repository::open_bare(path) {
+        return Ok(repository);
+    }
+    std::fs::create_dir_all(path)?;
+    let mut options = RepositoryInitOptions::new();
+    options.bare(true).initial_head("main");
+    println!("[INFO]: Created the vault history at {}", path.display());
+    Ok(Repository::init_opts(path, &options)?)
+}
+
+fn head_commit(repository: &Repository) -> Option<Commit<'_>> {
+    repository
+        .find_reference(HISTORY_BRANCH)
+        .and_then(|reference| reference.peel_to_commit())
+        .ok()
+}
+
+fn commit_device(commit: &Commit) -> String {
+    commit
+        .author()
+        .email()
+        .and_then(|email| email.strip_suffix(AUTHOR_DOMAIN))
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn tree_files(repository: &Repository, commit: Option<&Commit>) -> Result<HistoryFiles, SyncError> {
+    let mut files = HistoryFiles::new();
+    let Some(commit) = commit else {
+        return Ok(files);
+    };
+    repository
+        .find_tree(commit.tree_id())?
+        .walk(TreeWalkMode::PreOrder, |directory, entry| {
+            if entry.kind() == Some(git2::ObjectType::Blob) {
+                if let Some(name) = entry.name() {
+                    files.insert(format!("{directory}{name}"), entry.id());
+                }
+            }
+            TreeWalkResult::Ok
+        })?;
+    Ok(files)
+}
+
+fn read_file(
+    repository: &Repository,
+    keys: &HistoryKeys,
+    path: &str,
+    blob: Oid,
+) -> Result<HistoryRecords, SyncError> {
+    let blob = repository.find_blob(blob)?;
+    let content = std::str::from_utf8(blob.content())
+        .map_err(|_| SyncError::Format(format!("{path} is not a history record")))?;
+    let mut records = HistoryRecords::new();
+    for line in content.lines().filter(|line| !line.is_empty()) {
+        let (key, payload) = keys.open(path, line)?;
+        let line = line.to_string();
+        records.insert(key, HistoryRecord { line, payload });
+    }
+    Ok(records)
+}
+
+fn read_files(
+    repository: &Repository,
+    keys: &HistoryKeys,
+    files: &HistoryFiles,
+) -> Result<HistoryRecords, SyncError> {
+    let mut records = HistoryRecords::new();
+    for (path, blob) in files {
+        records.append(&mut read_file(repository, keys, path, *blob)?);
+    }
+    Ok(records)
+}
+
+// The record files of the vault, with the lines of unchanged records taken over
+fn render_files(
+    repository: &Repository,
+    keys: &HistoryKeys,
+    records: &BTreeMap<String, RecordPayload>,
+    previous: &HistoryRecords,
+) -> Result<HistoryFiles, SyncError> {
+    let mut lines: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    for (key, payload) in records {
+        let Some(file) = record_file(key) else {
+            continue;
+        };
+        let line = match previous.get(key) {
+            Some(previous) if previous.payload == *payload => previous.line.clone(),
+            _ => keys.seal(&file, key, payload)?,
+        };
+        lines.entry(file).or_default().insert(keys.tag(key), line);
+    }
+
+    let mut files = HistoryFiles::new();
+    for (file, lines) in lines {
+        let content: String = lines.into_values().map(|line| line + "\n").collect();
+        files.insert(file, repository.blob(content.as_bytes())?);
+    }
+    Ok(files)
+}
+
+// Record files only ever sit one directory deep
+fn write_tree(repository: &Repository, files: &HistoryFiles) -> Result<Oid, SyncError> {
+    let mut directories = BTreeMap::new();
+    for (path, blob) in files {
+        let Some((directory, name)) = path.split_once('/') else {
+            continue;
+        };
+        let builder = match directories.entry(directory) {
+            Entry::Vacant(vacant) => vacant.insert(repository.treebuilder(None)?),
+            Entry::Occupied(occupied) => occupied.into_mut(),
+        };
+        builder.insert(name, *blob, 0o100644)?;
+    }
+    let mut root = repository.treebuilder(None)?;
+    for (directory, builder) in directories {
+        root.insert(directory, builder.write()?, 0o040000)?;
+    }
+    Ok(root.write()?)
+}
+
+// Device ids are long and random, the start is enough to tell them apart
+fn short_id(id: &str) -> &str {
+    &id[..id.len().min(8)]
+}
+
+fn signature(device_id: &str) -> Result<Signature<'static>, SyncError> {
+    let name = format!("Keylight {}", short_id(device_id));
+    Ok(Signature::now(
+        &name,
+        &format!("{device_id}{AUTHOR_DOMAIN}"),
+    )?)
+}
+
+fn count(count: usize, one: &str, many: &str) -> String {
+    match count {
+        1 => format!("1 {one}"),
+        _ => format!("{count} {many}"),
+    }
+}
+
+// Names nothing but how many entries and folders changed, commit messages are not encrypted
+fn change_message(before: &HistoryFiles, after: &HistoryFiles) -> String {
+    let (mut entries, mut folders) = (0, 0);
+    let paths: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    for path in paths {
+        if before.get(path) != after.get(path) {
+            match path.starts_with("folders/") {
+                true => folders += 1,
+                false => entries += 1,
+            }
+        }
+    }
+    match (entries, folders) {
+        (entries, 0) => format!("Changed {}", count(entries, "entry", "entries")),
+        (0, folders) => format!("Changed {}", count(folders, "folder", "folders")),
+        (entries, folders) => format!(
+            "Changed {} and {}",
+            count(entries, "entry", "entries"),
+            count(folders, "folder", "folders")
+        ),
+    }
+}
+
+// Commits the records unless they are what the branch holds already
+fn commit_records(
+    repository: &Repository,
+    keys: &HistoryKeys,
+    records: &BTreeMap<String, RecordPayload>,
+    device_id: &str,
+    message: Option<&str>,
+) -> Result<bool, SyncError> {
+    let head = head_commit(repository);
+    let previous_files = tree_files(repository, head.as_ref())?;
+    let previous = read_files(repository, keys, &previous_files)?;
+    let files = render_files(repository, keys, records, &previous)?;
+    if files == previous_files {
+        return Ok(false);
+    }
+
+    let message = message.map_or_else(|| change_message(&previous_files, &files), str::to_string);
+    let tree = repository.find_tree(write_tree(repository, &files)?)?;
+    let signature = signature(device_id)?;
+    let parents: Vec<&Commit> = head.iter().collect();
+    repository.commit(
+        Some(HISTORY_BRANCH),
+        &signature,
+        &signature,
+        &message,
+        &tree,
+        &parents,
+    )?;
+    Ok(true)
+}
+
+fn record_updates(before: &HistoryRecords, after: &HistoryRecords) -> Vec<RecordUpdate> {
+    let mut updates = Vec::new();
+    for (key, record) in after {
+        if before.get(key).map(|before| &before.payload) != Some(&record.payload) {
+            updates.push((key.clone(), Some(record.payload.clone())));
+        }
+    }
+    for key in before.keys().filter(|key| !after.contains_key(*key)) {
+        updates.push((key.clone(), None));
+    }
+    updates
+}
+
+fn remote_callbacks(settings: &GitHistorySettings) -> RemoteCallbacks<'_> {
+    let mut callbacks = RemoteCallbacks::new();
+    // libgit2 asks again for as long as the remote refuses
+    let mut attempts = 0;
+    callbacks.credentials(move |_, url_username, allowed| {
+        attempts += 1;
+        if attempts > 3 {
+            return Err(git2::Error::from_str(
+                "the remote did not accept the username or password",
+            ));
+        }
+        let username = match settings.username.as_str() {
+            "" => url_username.unwrap_or("git"),
+            username => username,
+        };
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) && !settings.password.is_empty() {
+            Cred::userpass_plaintext(username, &settings.password)
+        } else if allowed.contains(CredentialType::SSH_KEY) {
+            Cred::ssh_key_from_agent(username)
+        } else if allowed.contains(CredentialType::USERNAME) {
+            Cred::username(username)
+        } else {
+            Cred::default()
+        }
+    });
+    callbacks
+}
+
+// The remote branch head, None while nothing was pushed yet
+fn fetch(repository: &Repository, settings: &GitHistorySettings) -> Result<Option<Oid>, SyncError> {
+    let mut remote = repository.remote_anonymous(settings.remote_url.trim())?;
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(remote_callbacks(settings));
+    remote.fetch(
+        &[format!("+{HISTORY_BRANCH}:{REMOTE_BRANCH}")],
+        Some(&mut options),
+        None,
+    )?;
+    Ok(repository.refname_to_id(REMOTE_BRANCH).ok())
+}
+
+fn push(repository: &Repository, settings: &GitHistorySettings) -> Result<(), SyncError> {
+    let mut remote = repository.remote_anonymous(settings.remote_url.trim())?;
+    let mut refused = None;
+    let mut callbacks = remote_callbacks(settings);
+    callbacks.push_update_reference(|_, status| {
+        refused = status.map(str::to_string);
+        Ok(())
+    });
+    let mut options = PushOptions::new();
+    options.remote_callbacks(callbacks);
+    remote.push(
+        &[format!("{HISTORY_BRANCH}:{HISTORY_BRANCH}")],
+        Some(&mut options),
+    )?;
+    drop(options);
+    match refused {
+        // Someone pushed in between, their history is merged next time
+        Some(status) => Err(SyncError::Remote(format!(
+            "The git remote refused the history: {status}"
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn merge_remote(
+    repository: &Repository,
+    keys: &HistoryKeys,
+    remote: Oid,
+    device_id: &str,
+) -> Result<Option<PendingMerge>, SyncError> {
+    let local = head_commit(repository);
+    let remote = repository.find_commit(remote)?;
+    let local_id = local.as_ref().map(Commit::id);
+    if let Some(local_id) = local_id {
+        if local_id == remote.id() || repository.graph_descendant_of(local_id, remote.id())? {
+            return Ok(None);
+        }
+    }
+
+    let local_files = tree_files(repository, local.as_ref())?;
+    let remote_files = tree_files(repository, Some(&remote))?;
+    let fast_forward = match local_id {
+        Some(local_id) => repository.graph_descendant_of(remote.id(), local_id)?,
+        None => true,
+    };
+    let (target, merged_files, conflicts) = match (fast_forward, &local) {
+        (false, Some(local)) => {
+            let (merged_files, conflicts) = merge_files(
+                repository,
+                keys,
+                local,
+                &remote,
+                &local_files,
+                &remote_files,
+            )?;
+            let tree = repository.find_tree(write_tree(repository, &merged_files)?)?;
+            let signature = signature(device_id)?;
+            let message = format!(
+                "Merged the history of device {}",
+                short_id(&commit_device(&remote))
+            );
+            let target = repository.commit(
+                None,
+                &signature,
+                &signature,
+                &message,
+                &tree,
+                &[local, &remote],
+            )?;
+            (target, merged_files, conflicts)
+        }
+        _ => (remote.id(), remote_files, Vec::new()),
+    };
+
+    let before = read_files(repository, keys, &local_files)?;
+    let after = read_files(repository, keys, &merged_files)?;
+    Ok(Some(PendingMerge {
+        local: local_id,
+        target,
+        updates: record_updates(&before, &after),
+        conflicts,
+    }))
+}
+
+fn merge_files(
+    repository: &Repository,
+    keys: &HistoryKeys,
+    local: &Commit,
+    remote: &Commit,
+    local_files: &HistoryFiles,
+    remote_files: &HistoryFiles,
+) -> Result<(HistoryFiles, Vec<HistoryConflict>), SyncError> {
+    // Unrelated histories, such as two devices that started one each, merge as if from nothing
+    let base = match repository.merge_base(local.id(), remote.id()) {
+        Ok(base) => Some(repository.find_commit(base)?),
+        Err(_) => None,
+    };
+    let base_files = tree_files(repository, base.as_ref())?;
+    // The side committed last wins, the same on every device
+    let remote_wins = (remote.time().seconds(), remote.id().to_string())
+        > (local.time().seconds(), local.id().to_string());
+    let (winner, loser) = match remote_wins {
+        true => (remote, local),
+        false => (local, remote),
+    };
+
+    let mut merged = local_files.clone();
+    let mut conflicts = Vec::new();
+    let paths: BTreeSet<&String> = local_files.keys().chain(remote_files.keys()).collect();
+    for path in paths {
+        let (base, local_blob, remote_blob) = (
+            base_files.get(path),
+            local_files.get(path),
+            remote_files.get(path),
+        );
+        if local_blob == remote_blob || remote_blob == base {
+            continue;
+        }
+        if local_blob == base || remote_wins {
+            match remote_blob {
+                Some(blob) => merged.insert(path.clone(), *blob),
+                None => merged.remove(path),
+            };
+        }
+        if local_blob == base {
+            continue;
+        }
+
+        // Changed on both sides, every record of the losing file that differs is kept for review
+        let records = |blob: Option<&Oid>| match blob {
+            Some(blob) => read_file(repository, keys, path, *blob),
+            None => Ok(HistoryRecords::new()),
+        };
+        let (local_records, remote_records) = (records(local_blob)?, records(remote_blob)?);
+        let (winning, losing) = match remote_wins {
+            true => (&remote_records, &local_records),
+            false => (&local_records, &remote_records),
+        };
+        for (key, other_payload) in record_updates(winning, losing) {
+            conflicts.push(HistoryConflict {
+                key,
+                kept_device: commit_device(winner),
+                other_device: commit_device(loser),
+                other_modified_at: loser.time().seconds(),
+                other_payload,
+            });
+        }
+    }
+    Ok((merged, conflicts))
+}
+
+// Moves the branch to a merge once the vault holds its records, unless a snapshot came first
+fn advance(repository: &Repository, merge: &PendingMerge) -> Result<(), SyncError> {
+    match merge.local {
+        Some(local) => repository.reference_matching(
+            HISTORY_BRANCH,
+            merge.target,
+            true,
+            local,
+            "keylight: merge",
+        )?,
+        None => repository.reference(HISTORY_BRANCH, merge.target, false, "keylight: merge")?,
+    };
+    Ok(())
+}
+
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, SyncError> + Send + 'static,
+) -> Result<T, SyncError> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|_| SyncError::Remote("The vault history task stopped".to_string()))?
+}
+
+async fn vault_records(
+    vault_pool: &SqlitePool,
+) -> Result<(SyncIdentity, BTreeMap<String, RecordPayload>), SyncError> {
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    let identity = sync_identity(connection.as_mut()).await?;
+    let records = load_records(connection.as_mut(), None).await?;
+    Ok((identity, records))
+}
+
+async fn apply_updates(
+    vault_pool: &SqlitePool,
+    updates: &[RecordUpdate],
+    conflicts: &[HistoryConflict],
+) -> Result<(), SyncError> {
+    let mut transaction = vault_pool.begin().await?;
+    for (key, payload) in updates {
+        apply_payload(&mut transaction, key, payload.as_ref(), unix_now()).await?;
+    }
+    for conflict in conflicts {
+        record_conflict(
+            &mut transaction,
+            &conflict.key,
+            &conflict.kept_device,
+            &conflict.other_device,
+            conflict.other_modified_at,
+            conflict.other_payload.as_ref(),
+        )
+        .await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+// Commits the vault when it changed, then merges and pushes the remote history if there is one
+pub async fn sync_history(
+    vault_pool: SqlitePool,
+    data_directory: PathBuf,
+    device_id: String,
+    settings: GitHistorySettings,
+) -> Result<HistorySummary, SyncError> {
+    let (identity, records) = vault_records(&vault_pool).await?;
+    let path = history_path(&data_directory, &identity.vault_id);
+    let has_remote = !settings.remote_url.trim().is_empty();
+
+    let (key, work_path, work_settings) = (identity.key.clone(), path.clone(), settings.clone());
+    let (committed, remote_head, merge) = blocking(move || {
+        let repository = open_repository(&work_path)?;
+        let keys = HistoryKeys::new(&key)?;
+        let committed = commit_records(&repository, &keys, &records, &device_id, None)?;
+        if !has_remote {
+            return Ok((committed, None, None));
+        }
+        let Some(remote_head) = fetch(&repository, &work_settings)? else {
+            return Ok((committed, None, None));
+        };
+        let merge = merge_remote(&repository, &keys, remote_head, &device_id)?;
+        Ok((committed, Some(remote_head), merge))
+    })
+    .await?;
+
+    let mut summary = HistorySummary {
+        committed,
+        ..HistorySummary::default()
+    };
+    if let Some(merge) = &merge {
+        apply_updates(&vault_pool, &merge.updates, &merge.conflicts).await?;
+        summary.received = merge.updates.len();
+        summary.conflicts = merge.conflicts.len();
+    }
+
+    if has_remote {
+        blocking(move || {
+            let repository = open_repository(&path)?;
+            if let Some(merge) = &merge {
+                advance(&repository, merge)?;
+            }
+            match head_commit(&repository).map(|head| head.id()) {
+                Some(head) if Some(head) != remote_head => push(&repository, &settings),
+                _ => Ok(()),
+            }
+        })
+        .await?;
+    }
+    println!(
+        "[INFO]: Updated the history of vault {}, received {} changes with {} conflicts",
+        identity.vault_id, summary.received, summary.conflicts
+    );
+    Ok(summary)
+}
+
+pub async fn list_history(
+    vault_pool: SqlitePool,
+    data_directory: PathBuf,
+) -> Result<Vec<HistoryCommit>, SyncError> {
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    let identity = sync_identity(connection.as_mut()).await?;
+    drop(connection);
+
+    let path = history_path(&data_directory, &identity.vault_id);
+    blocking(move || {
+        let repository = open_repository(&path)?;
+        let Some(head) = head_commit(&repository) else {
+            return Ok(Vec::new());
+        };
+        let mut walk = repository.revwalk()?;
+        walk.push(head.id())?;
+        walk.set_sorting(git2::Sort::TIME)?;
+        let mut commits = Vec::new();
+        for id in walk.take(HISTORY_LIST_LIMIT) {
+            let commit = repository.find_commit(id?)?;
+            commits.push(HistoryCommit {
+                id: commit.id().to_string(),
+                time: commit.time().seconds(),
+                summary: commit.summary().unwrap_or_default().to_string(),
+                device_id: commit_device(&commit),
+            });
+        }
+        Ok(commits)
+    })
+    .await
+}
+
+// Read from the records themselves, the vault may not hold them anymore
+fn change_label(
+    key: &str,
+    before: Option<&RecordPayload>,
+    after: Option<&RecordPayload>,
+    names: &HashMap<String, String>,
+) -> String {
+    let mut parts = key.splitn(3, '/');
+    let (kind, sync_id, section_name) = (parts.next(), parts.next(), parts.next());
+    let name = sync_id
+        .and_then(|sync_id| names.get(sync_id))
+        .map_or("Unnamed", String::as_str);
+    let change = match (before, after) {
+        (None, _) => "added",
+        (_, None) => "removed",
+        (
+            Some(RecordPayload::Entry { deleted: false, .. }),
+            Some(RecordPayload::Entry { deleted: true, .. }),
+        ) => "deleted",
+        _ => "changed",
+    };
+    match (kind, section_name) {
+        (Some("folder"), _) => format!("Folder \"{name}\" {change}"),
+        (_, Some(section_name)) => format!("\"{section_name}\" of \"{name}\" {change}"),
+        _ => format!("Entry \"{name}\" {change}"),
+    }
+}
+
+// What a commit changed compared to its first parent
+pub async fn history_changes(
+    vault_pool: SqlitePool,
+    data_directory: PathBuf,
+    commit_id: String,
+) -> Result<Vec<String>, SyncError> {
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    let identity = sync_identity(connection.as_mut()).await?;
+    drop(connection);
+
+    let path = history_path(&data_directory, &identity.vault_id);
+    blocking(move || {
+        let repository = open_repository(&path)?;
+        let keys = HistoryKeys::new(&identity.key)?;
+        let commit = repository.find_commit(Oid::from_str(&commit_id)?)?;
+        let parent = commit.parent(0).ok();
+        let before = read_files(
+            &repository,
+            &keys,
+            &tree_files(&repository, parent.as_ref())?,
+        )?;
+        let after = read_files(&repository, &keys, &tree_files(&repository, Some(&commit))?)?;
+
+        let mut names = HashMap::new();
+        for (key, record) in before.iter().chain(after.iter()) {
+            if let (
+                Some(sync_id),
+                RecordPayload::Folder {
+                    name: Some(name), ..
+                }
+                | RecordPayload::Entry {
+                    name: Some(name), ..
+                },
+            ) = (key.split('/').nth(1), &record.payload)
+            {
+                names.insert(sync_id.to_string(), name.clone());
+            }
+        }
+        Ok(record_updates(&before, &after)
+            .into_iter()
+            .map(|(key, _)| {
+                let before = before.get(&key).map(|record| &record.payload);
+                let after = after.get(&key).map(|record| &record.payload);
+                change_label(&key, before, after, &names)
+            })
+            .collect())
+    })
+    .await
+}
+
+// Writes the records of a commit back into the vault and commits the result. Entries created since
+// are moved to the trash, their sections are kept
+pub async fn restore_history(
+    vault_pool: SqlitePool,
+    data_directory: PathBuf,
+    device_id: String,
+    commit_id: String,
+) -> Result<usize, SyncError> {
+    let (identity, current) = vault_records(&vault_pool).await?;
+    let path = history_path(&data_directory, &identity.vault_id);
+    let (key, work_path, work_commit_id) = (identity.key.clone(), path.clone(), commit_id.clone());
+    let target = blocking(move || {
+        let repository = open_repository(&work_path)?;
+        let keys = HistoryKeys::new(&key)?;
+        let commit = repository.find_commit(Oid::from_str(&work_commit_id)?)?;
+        let files = tree_files(&repository, Some(&commit))?;
+        let target: BTreeMap<String, RecordPayload> = read_files(&repository, &keys, &files)?
+            .into_iter()
+            .map(|(key, record)| (key, record.payload.clone()))
+            .collect();
+        Ok(target)
+    })
+    .await?;
+
+    let mut updates = Vec::new();
+    for (key, payload) in &target {
+        if current.get(key) != Some(payload) {
+            updates.push((key.clone(), Some(payload.clone())));
+        }
+    }
+    for (key, payload) in &current {
+        if target.contains_key(key) {
+            continue;
+        }
+        match payload {
+            RecordPayload::Entry { deleted: false, .. } => {
+                let mut deleted = payload.clone();
+                if let RecordPayload::Entry { deleted, .. } = &mut deleted {
+                    *deleted = true;
+                }
+                updates.push((key.clone(), Some(deleted)));
+            }
+            RecordPayload::Section { .. } => {
+                let entry = key
+                    .split('/')
+                    .nth(1)
+                    .map(|sync_id| format!("entry/{sync_id}"));
+                if entry.is_some_and(|entry| target.contains_key(&entry)) {
+                    updates.push((key.clone(), None));
+                }
+            }
+            _ => {}
+        }
+    }
+    apply_updates(&vault_pool, &updates, &[]).await?;
+
+    let (_, records) = vault_records(&vault_pool).await?;
+    let restored = updates.len();
+    blocking(move || {
+        let repository = open_repository(&path)?;
+        let keys = HistoryKeys::new(&identity.key)?;
+        let message = format!("Restored the vault to {}", short_id(&commit_id));
+        commit_records(&repository, &keys, &records, &device_id, Some(&message))?;
+        Ok(())
+    })
+    .await?;
+    println!("[INFO]: Restored {restored} records from the vault history");
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ScratchDirectory, ScratchVault};
+
+    // Every device keeps its history in its own data directory and shares the bare remote
+    fn settings(remote: &ScratchDirectory) -> GitHistorySettings {
+        GitHistorySettings {
+            enabled: true,
+            remote_url: remote.0.to_string_lossy().into_owned(),
+            username: String::new(),
+            password: String::new(),
+        }
+    }
+
+    fn bare_remote(name: &str) -> ScratchDirectory {
+        let remote = ScratchDirectory::new(name);
+        Repository::init_bare(&remote.0).unwrap();
+        remote
+    }
+
+    async fn sync(
+        vault: &ScratchVault,
+        device_id: &str,
+        remote: &ScratchDirectory,
+    ) -> HistorySummary {
+        sync_history(
+            vault.vault_pool.clone(),
+            vault.directory.0.clone(),
+            device_id.to_string(),
+            settings(remote),
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn edit(vault: &ScratchVault, entry_id: i64, data: &[u8]) {
+        vault
+            .state
+            .clone()
+            .set_entry_section(
+                vault.vault_pool.clone(),
+                entry_id,
+                "Password".to_string(),
+                "password".to_string(),
+                Zeroizing::new(data.to_vec()),
+            )
+            .await
+            .unwrap();
+    }
+
+    // (entry name, deleted, section name, data), sorted
+    async fn contents(vault: &ScratchVault) -> Vec<(String, bool, String, Vec<u8>)> {
+        sqlx::query_as(
+            "SELECT entry_name, is_deleted, section_name, section_data FROM Entries
+            JOIN EntryData USING (entry_id) ORDER BY entry_name, section_name;",
+        )
+        .fetch_all(&vault.vault_pool)
+        .await
+        .unwrap()
+    }
+
+    async fn password(vault: &ScratchVault, entry_name: &str) -> Vec<u8> {
+        sqlx::query_scalar(
+            "SELECT section_data FROM EntryData JOIN Entries USING (entry_id)
+            WHERE entry_name = ? AND section_name = 'Password';",
+        )
+        .bind(entry_name)
+        .fetch_one(&vault.vault_pool)
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn record_lines_only_open_where_they_were_sealed() {
+        let keys = HistoryKeys::new(b"sync key").unwrap();
+        let payload = RecordPayload::Section {
+            section_type: Some("password".to_string()),
+            data: Some(b"secret".to_vec()),
+        };
+        let file = "entries/abc.klrec";
+        let line = keys.seal(file, "section/abc/Password", &payload).unwrap();
+
+        // Neither the key nor the value shows, the tag stays the same for the same key
+        assert!(!line.contains("secret") && !line.contains("Password"));
+        let (tag, _) = line.split_once(' ').unwrap();
+        assert_eq!(tag, keys.tag("section/abc/Password"));
+        assert_ne!(
+            line,
+            keys.seal(file, "section/abc/Password", &payload).unwrap()
+        );
+        let (key, opened) = keys.open(file, &line).unwrap();
+        assert_eq!(key, "section/abc/Password");
+        assert!(opened == payload);
+
+        // Another file, another key or a damaged line do not open
+        assert!(keys.open("entries/other.klrec", &line).is_err());
+        assert!(HistoryKeys::new(b"other key")
+            .unwrap()
+            .open(file, &line)
+            .is_err());
+        let damaged = line.replacen(tag, &keys.tag("section/abc/Other"), 1);
+        assert!(keys.open(file, &damaged).is_err());
+        assert!(keys.open(file, "no separator").is_err());
+        assert!(keys.open(file, &format!("{tag} c2hvcnQ=")).is_err());
+    }
+
+    #[test]
+    fn record_keys_map_to_one_file_per_folder_and_entry() {
+        assert_eq!(
+            record_file("folder/f1").as_deref(),
+            Some("folders/f1.klrec")
+        );
+        assert_eq!(record_file("entry/e1").as_deref(), Some("entries/e1.klrec"));
+        assert_eq!(
+            record_file("section/e1/Password/with/slashes").as_deref(),
+            Some("entries/e1.klrec")
+        );
+        assert_eq!(record_file("tag/e1"), None);
+        assert_eq!(record_file("entry"), None);
+    }
+
+    #[tokio::test]
+    async fn diverged_histories_merge_through_the_remote() {
+        let remote = bare_remote("history-merge-remote");
+        let a = ScratchVault::new("history-merge-a").await;
+        let mail = a.entry("Mail", &[("Password", "password", b"mail")]).await;
+        let shared = a
+            .entry("Shared", &[("Password", "password", b"shared")])
+            .await;
+        let b = a.copy("history-merge-b").await;
+
+        // Two histories of their own, joined by b's merge
+        assert!(sync(&a, "device-a", &remote).await.committed);
+        let joined = sync(&b, "device-b", &remote).await;
+        assert_eq!((joined.received, joined.conflicts), (0, 0));
+        assert_eq!(sync(&a, "device-a", &remote).await.received, 0);
+
+        // a and b go apart, both change the shared entry
+        edit(&a, mail, b"mail from a").await;
+        edit(&a, shared, b"shared from a").await;
+        b.entry("Bank", &[("Password", "password", b"bank")]).await;
+        edit(&b, shared, b"shared from b").await;
+        sync(&a, "device-a", &remote).await;
+        let merged = sync(&b, "device-b", &remote).await;
+        assert_eq!(merged.conflicts, 1);
+        assert!(merged.received >= 1);
+        assert_eq!(password(&b, "Mail").await, b"mail from a");
+
+        // a takes b's merge as it is and nothing is left to exchange
+        let received = sync(&a, "device-a", &remote).await;
+        assert_eq!(received.conflicts, 0);
+        assert_eq!(password(&a, "Bank").await, b"bank");
+        assert_eq!(contents(&a).await, contents(&b).await);
+        // One of the shared versions won, the other is kept on b for review
+        assert_eq!(
+            get_sync_conflicts(b.vault_pool.clone())
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(sync(&b, "device-b", &remote).await.received, 0);
+        assert_eq!(sync(&a, "device-a", &remote).await.received, 0);
+    }
+
+    #[tokio::test]
+    async fn restoring_a_commit_brings_its_records_back() {
+        let remote = bare_remote("history-restore-remote");
+        let a = ScratchVault::new("history-restore-a").await;
+        let mail = a.entry("Mail", &[("Password", "password", b"first")]).await;
+        let b = a.copy("history-restore-b").await;
+        sync(&a, "device-a", &remote).await;
+        let history = list_history(a.vault_pool.clone(), a.directory.0.clone())
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].device_id, "device-a");
+        let first = history[0].id.clone();
+
+        edit(&a, mail, b"second").await;
+        a.entry("Bank", &[("Password", "password", b"bank")]).await;
+        sync(&a, "device-a", &remote).await;
+
+        // The password goes back, the entry made since goes to the trash with its sections
+        let restored = restore_history(
+            a.vault_pool.clone(),
+            a.directory.0.clone(),
+            "device-a".to_string(),
+            first.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(restored, 2);
+        assert_eq!(password(&a, "Mail").await, b"first");
+        let bank: bool =
+            sqlx::query_scalar("SELECT is_deleted FROM Entries WHERE entry_name = 'Bank';")
+                .fetch_one(&a.vault_pool)
+                .await
+                .unwrap();
+        assert!(bank);
+        assert_eq!(password(&a, "Bank").await, b"bank");
+
+        // The restore is a commit of its own, naming what it changed
+        let history = list_history(a.vault_pool.clone(), a.directory.0.clone())
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 3);
+        let restore = history
+            .iter()
+            .find(|commit| commit.summary.starts_with("Restored the vault to"))
+            .unwrap();
+        let mut changes = history_changes(
+            a.vault_pool.clone(),
+            a.directory.0.clone(),
+            restore.id.clone(),
+        )
+        .await
+        .unwrap();
+        changes.sort();
+        assert_eq!(
+            changes,
+            ["\"Password\" of \"Mail\" changed", "Entry \"Bank\" deleted"]
+        );
+
+        // Other devices receive the restored vault like any other change
+        sync(&a, "device-a", &remote).await;
+        sync(&b, "device-b", &remote).await;
+        assert_eq!(contents(&b).await, contents(&a).await);
+    }
+}
@@ -0,0 +1,293 @@
+use crate::*;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use std::time::Duration;
+use url::Url;
+use zeroize::Zeroize;
+
+// What the remote sync stores (webdav.rs, s3.rs, cloud.rs) share. Requests failing on the way or
+// answered with 429 or 5xx are sent again after 1, 2, 4 and 8 seconds, or after the Retry-After the
+// server asked for.
+//
+// The cloud providers send everything through an HttpTransport instead of reqwest directly, so
+// they can be pointed at anything that answers like their APIs.
+
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+impl From<reqwest::Error> for SyncError {
+    fn from(error: reqwest::Error) -> Self {
+        println!("[ERROR]: {:?}", error);
+        SyncError::Remote(error.without_url().to_string())
+    }
+}
+
+pub fn http_client() -> Result<reqwest::Client, SyncError> {
+    Ok(reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?)
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+// Sends the request built by `build` until it arrives and the server is not busy. It is built
+// again for every attempt, so signatures and dates stay fresh
+pub async fn send_with_retries(build: impl Fn() -> RequestBuilder) -> Result<Response, SyncError> {
+    let mut delay = FIRST_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        let retry_after = match build().send().await {
+            Ok(response) if is_transient(response.status()) && attempt < MAX_ATTEMPTS => {
+                println!(
+                    "[INFO]: The sync server answered {}, trying again",
+                    response.status()
+                );
+                response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse().ok())
+                    .map(|seconds| Duration::from_secs(seconds).min(MAX_RETRY_DELAY))
+            }
+            Ok(response) => return Ok(response),
+            Err(error)
+                if (error.is_connect() || error.is_timeout() || error.is_request())
+                    && attempt < MAX_ATTEMPTS =>
+            {
+                println!("[INFO]: Could not reach the sync server, trying again: {error}");
+                None
+            }
+            Err(error) => return Err(error.into()),
+        };
+        tokio::time::sleep(retry_after.unwrap_or(delay)).await;
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+        attempt += 1;
+    }
+}
+
+// Bodies carry tokens and change sets, both are cleared once sent or read
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: Url,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn new(method: Method, url: Url) -> Self {
+        Self {
+            method,
+            url,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    pub fn bearer(self, access_token: &str) -> Self {
+        self.header("authorization", format!("Bearer {access_token}"))
+    }
+
+    pub fn body(mut self, content_type: &str, body: Vec<u8>) -> Self {
+        self.body = body;
+        self.header("content-type", content_type)
+    }
+
+    pub fn form(self, fields: &[(&str, &str)]) -> Self {
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(fields)
+            .finish();
+        self.body("application/x-www-form-urlencoded", body.into_bytes())
+    }
+
+    pub fn json(self, value: &serde_json::Value) -> Self {
+        self.body("application/json", value.to_string().into_bytes())
+    }
+}
+
+impl Drop for HttpRequest {
+    fn drop(&mut self) {
+        self.body.zeroize();
+        for (_, value) in &mut self.headers {
+            value.zeroize();
+        }
+    }
+}
+
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn json(&self) -> Result<serde_json::Value, SyncError> {
+        serde_json::from_slice(&self.body)
+            .map_err(|error| SyncError::Remote(format!("Unreadable answer: {error}")))
+    }
+}
+
+impl Drop for HttpResponse {
+    fn drop(&mut self) {
+        self.body.zeroize();
+    }
+}
+
+#[allow(async_fn_in_trait)]
+pub trait HttpTransport {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, SyncError>;
+}
+
+// The transport the app uses, with the retries described above. Redirects are followed, OneDrive
+// answers downloads with one
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    pub fn new() -> Result<Self, SyncError> {
+        Ok(Self {
+            client: http_client()?,
+        })
+    }
+}
+
+impl HttpTransport for ReqwestTransport {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, SyncError> {
+        let response = send_with_retries(|| {
+            let mut builder = self
+                .client
+                .request(request.method.clone(), request.url.clone())
+                .body(request.body.clone());
+            for (name, value) in &request.headers {
+                builder = builder.header(*name, value);
+            }
+            builder
+        })
+        .await?;
+        Ok(HttpResponse {
+            status: response.status(),
+            body: response.bytes().await?.to_vec(),
+        })
+    }
+}
+
+// A local HTTP server for the tests of the sync stores, answering every request with a function
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    pub struct MockRequest {
+        pub method: String,
+        // The path and query as sent
+        pub target: String,
+        // Lowercase names
+        pub headers: HashMap<String, String>,
+        pub body: Vec<u8>,
+    }
+
+    pub struct MockResponse {
+        pub status: &'static str,
+        pub headers: Vec<(&'static str, String)>,
+        pub body: Vec<u8>,
+    }
+
+    impl MockResponse {
+        pub fn new(status: &'static str) -> Self {
+            Self {
+                status,
+                headers: Vec::new(),
+                body: Vec::new(),
+            }
+        }
+
+        pub fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+            self.headers.push((name, value.into()));
+            self
+        }
+
+        pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+            self.body = body.into();
+            self
+        }
+    }
+
+    // The server's url, "http://127.0.0.1:<port>/". It runs until the test's runtime stops
+    pub async fn serve(
+        handler: impl Fn(MockRequest) -> MockResponse + Send + Sync + 'static,
+    ) -> String {
+        let handler = Arc::new(handler);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    if let Some(request) = read_request(&mut BufReader::new(stream)).await {
+                        let (request, stream) = request;
+                        let _ = write_response(stream, handler(request)).await;
+                    }
+                });
+            }
+        });
+        url
+    }
+
+    // One request per connection, every answer closes it
+    async fn read_request(
+        stream: &mut BufReader<TcpStream>,
+    ) -> Option<(MockRequest, &mut TcpStream)> {
+        let mut request_line = String::new();
+        stream.read_line(&mut request_line).await.ok()?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next()?.to_string();
+        let target = parts.next()?.to_string();
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line).await.ok()? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+            }
+        }
+        let length = headers
+            .get("content-length")
+            .and_then(|length| length.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await.ok()?;
+        let request = MockRequest {
+            method,
+            target,
+            headers,
+            body,
+        };
+        Some((request, stream.get_mut()))
+    }
+
+    async fn write_response(stream: &mut TcpStream, response: MockResponse) -> std::io::Result<()> {
+        let mut head = format!("HTTP/1.1 {}\r\n", response.status);
+        for (name, value) in &response.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str(&format!(
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            response.body.len()
+        ));
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&response.body).await?;
+        stream.shutdown().await
+    }
+}
@@ -0,0 +1,339 @@
+use crate::*;
+use iced::widget::pane_grid::{Axis, Configuration, Node};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+// The panes of the passwords page are saved with the vault settings as the tree of splits
+// pane_grid keeps, with the panes named by what they show. Hidden panes are simply missing from
+// the tree. Resizing sends an event for every mouse movement, so it is saved at most once per
+// PANE_LAYOUT_SAVE_DELAY while everything else is saved right away
+
+pub const PANE_LAYOUT_SAVE_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaneState {
+    Folders,
+    Entries,
+    Data,
+}
+
+impl PaneState {
+    // Left to right in the default layout, the order of the variants
+    pub const ALL: [PaneState; 3] = [PaneState::Folders, PaneState::Entries, PaneState::Data];
+}
+
+impl fmt::Display for PaneState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaneState::Folders => write!(f, "Folders"),
+            PaneState::Entries => write!(f, "Entries"),
+            PaneState::Data => write!(f, "Details"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaneLayout {
+    Split {
+        vertical: bool,
+        ratio: f32,
+        a: Box<PaneLayout>,
+        b: Box<PaneLayout>,
+    },
+    Pane(PaneState),
+}
+
+impl PaneLayout {
+    fn from_node(panes: &pane_grid::State<PaneState>, node: &Node) -> Self {
+        match node {
+            Node::Split {
+                axis, ratio, a, b, ..
+            } => PaneLayout::Split {
+                vertical: *axis == Axis::Vertical,
+                ratio: *ratio,
+                a: Box::new(Self::from_node(panes, a)),
+                b: Box::new(Self::from_node(panes, b)),
+            },
+            Node::Pane(pane) => PaneLayout::Pane(
+                *panes
+                    .get(*pane)
+                    .expect("every pane of the layout has a state"),
+            ),
+        }
+    }
+
+    fn collect_panes(&self, found: &mut Vec<PaneState>) -> bool {
+        match self {
+            PaneLayout::Split { ratio, a, b, .. } => {
+                (0.0..=1.0).contains(ratio) && a.collect_panes(found) && b.collect_panes(found)
+            }
+            PaneLayout::Pane(state) if found.contains(state) => false,
+            PaneLayout::Pane(state) => {
+                found.push(*state);
+                true
+            }
+        }
+    }
+
+    fn configuration(&self) -> Configuration<PaneState> {
+        match self {
+            PaneLayout::Split {
+                vertical,
+                ratio,
+                a,
+                b,
+            } => Configuration::Split {
+                axis: match vertical {
+                    true => Axis::Vertical,
+                    false => Axis::Horizontal,
+                },
+                ratio: *ratio,
+                a: Box::new(a.configuration()),
+                b: Box::new(b.configuration()),
+            },
+            PaneLayout::Pane(state) => Configuration::Pane(*state),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedPanes {
+    pub layout: PaneLayout,
+    pub maximized: Option<PaneState>,
+}
+
+impl SavedPanes {
+    pub fn capture(panes: &pane_grid::State<PaneState>) -> Self {
+        Self {
+            layout: PaneLayout::from_node(panes, panes.layout()),
+            maximized: panes.maximized().and_then(|pane| panes.get(pane)).copied(),
+        }
+    }
+
+    // None for a layout naming a pane twice or with a ratio out of range, it did not come from here
+    pub fn restore(&self) -> Option<pane_grid::State<PaneState>> {
+        if !self.layout.collect_panes(&mut Vec::new()) {
+            println!("[ERROR]: The saved pane layout is invalid, using the default one");
+            return None;
+        }
+        let mut panes = pane_grid::State::with_configuration(self.layout.configuration());
+        let maximized = panes
+            .iter()
+            .find(|(_, state)| Some(**state) == self.maximized)
+            .map(|(pane, _)| *pane);
+        if let Some(pane) = maximized {
+            panes.maximize(pane);
+        }
+        Some(panes)
+    }
+}
+
+// Folders, entries and the picked entry's details side by side, the details taking half
+pub fn default_panes() -> pane_grid::State<PaneState> {
+    let (mut panes, pane) = pane_grid::State::new(PaneState::Folders);
+    panes.split(Axis::Vertical, pane, PaneState::Data);
+    panes.split(Axis::Vertical, pane, PaneState::Entries);
+    panes
+}
+
+pub fn hidden_panes(panes: &pane_grid::State<PaneState>) -> Vec<PaneState> {
+    PaneState::ALL
+        .into_iter()
+        .filter(|state| !panes.iter().any(|(_, shown)| shown == state))
+        .collect()
+}
+
+// Placed next to the closest pane it is shown beside by default, after it if there is one before
+pub fn show_pane(panes: &mut pane_grid::State<PaneState>, state: PaneState) {
+    if !hidden_panes(panes).contains(&state) {
+        return;
+    }
+    let shown: Vec<(pane_grid::Pane, PaneState)> =
+        panes.iter().map(|(pane, shown)| (*pane, *shown)).collect();
+    let before = shown
+        .iter()
+        .filter(|(_, other)| (*other as usize) < state as usize)
+        .max_by_key(|(_, other)| *other as usize);
+    let after = shown
+        .iter()
+        .filter(|(_, other)| *other as usize > state as usize)
+        .min_by_key(|(_, other)| *other as usize);
+
+    panes.restore();
+    match (before, after) {
+        (Some((pane, _)), _) => {
+            panes.split(Axis::Vertical, *pane, state);
+        }
+        (None, Some((pane, _))) => {
+            if let Some((shown, _)) = panes.split(Axis::Vertical, *pane, state) {
+                panes.swap(*pane, shown);
+            }
+        }
+        (None, None) => {}
+    }
+}
+
+impl Keylight {
+    pub(crate) fn hidden_panes(&self) -> Vec<PaneState> {
+        hidden_panes(&self.vault.panes)
+    }
+
+    pub(crate) fn show_pane(&mut self, state: PaneState) {
+        show_pane(&mut self.vault.panes, state);
+    }
+
+    pub(crate) fn save_pane_layout(&mut self) -> Task<Message> {
+        self.vault.pane_layout_pending = false;
+        let saved = SavedPanes::capture(&self.vault.panes);
+        if self.vault.settings.panes.as_ref() == Some(&saved) {
+            return Task::none();
+        }
+        self.vault.settings.panes = Some(saved);
+        self.save_vault_settings()
+    }
+
+    pub(crate) fn pane_layout_subscription(&self) -> Subscription<Message> {
+        if !self.vault.pane_layout_pending {
+            return Subscription::none();
+        }
+        iced::time::every(PANE_LAYOUT_SAVE_DELAY).map(|_| Message::SavePaneLayout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pane(panes: &pane_grid::State<PaneState>, state: PaneState) -> pane_grid::Pane {
+        panes
+            .iter()
+            .find(|(_, shown)| **shown == state)
+            .map(|(pane, _)| *pane)
+            .unwrap()
+    }
+
+    fn hide(panes: &mut pane_grid::State<PaneState>, state: PaneState) {
+        let hidden = pane(panes, state);
+        panes.close(hidden);
+    }
+
+    // Left to right
+    fn order(layout: &PaneLayout) -> Vec<PaneState> {
+        match layout {
+            PaneLayout::Split { a, b, .. } => [order(a), order(b)].concat(),
+            PaneLayout::Pane(state) => vec![*state],
+        }
+    }
+
+    fn split(ratio: f32, a: PaneLayout, b: PaneLayout) -> PaneLayout {
+        PaneLayout::Split {
+            vertical: true,
+            ratio,
+            a: Box::new(a),
+            b: Box::new(b),
+        }
+    }
+
+    #[test]
+    fn the_default_layout_survives_a_save() {
+        let saved = SavedPanes::capture(&default_panes());
+        assert_eq!(
+            saved.layout,
+            split(
+                0.5,
+                split(
+                    0.5,
+                    PaneLayout::Pane(PaneState::Folders),
+                    PaneLayout::Pane(PaneState::Entries)
+                ),
+                PaneLayout::Pane(PaneState::Data)
+            )
+        );
+        assert_eq!(saved.maximized, None);
+
+        // Stored as JSON with the vault settings
+        let stored: SavedPanes =
+            serde_json::from_str(&serde_json::to_string(&saved).unwrap()).unwrap();
+        let restored = stored.restore().unwrap();
+        assert_eq!(SavedPanes::capture(&restored), saved);
+        assert!(hidden_panes(&restored).is_empty());
+    }
+
+    #[test]
+    fn hidden_and_maximized_panes_survive_a_save() {
+        let mut panes = default_panes();
+        hide(&mut panes, PaneState::Entries);
+        let divider = *panes.layout().splits().next().unwrap();
+        panes.resize(divider, 0.3);
+        panes.maximize(pane(&panes, PaneState::Data));
+        let saved = SavedPanes::capture(&panes);
+        assert_eq!(order(&saved.layout), [PaneState::Folders, PaneState::Data]);
+        assert_eq!(saved.maximized, Some(PaneState::Data));
+
+        let restored = saved.restore().unwrap();
+        assert_eq!(hidden_panes(&restored), [PaneState::Entries]);
+        assert_eq!(restored.maximized(), Some(pane(&restored, PaneState::Data)));
+        assert_eq!(SavedPanes::capture(&restored), saved);
+    }
+
+    #[test]
+    fn layouts_that_were_not_saved_here_are_refused() {
+        let folders = || PaneLayout::Pane(PaneState::Folders);
+        let data = || PaneLayout::Pane(PaneState::Data);
+        let restore = |layout| {
+            SavedPanes {
+                layout,
+                maximized: None,
+            }
+            .restore()
+        };
+        assert!(restore(split(0.5, folders(), folders())).is_none());
+        assert!(restore(split(0.4, split(0.5, folders(), data()), data())).is_none());
+        for ratio in [-0.1, 1.5, f32::NAN] {
+            assert!(restore(split(ratio, folders(), data())).is_none());
+        }
+        // One pane is enough, a maximized pane that is not shown is ignored
+        let single = SavedPanes {
+            layout: data(),
+            maximized: Some(PaneState::Folders),
+        }
+        .restore()
+        .unwrap();
+        assert_eq!(single.len(), 1);
+        assert_eq!(single.maximized(), None);
+    }
+
+    #[test]
+    fn shown_panes_return_to_their_place() {
+        let order_of =
+            |panes: &pane_grid::State<PaneState>| order(&SavedPanes::capture(panes).layout);
+        for state in PaneState::ALL {
+            let mut panes = default_panes();
+            hide(&mut panes, state);
+            show_pane(&mut panes, state);
+            assert_eq!(order_of(&panes), PaneState::ALL, "{state}");
+        }
+
+        let mut panes = default_panes();
+        hide(&mut panes, PaneState::Folders);
+        hide(&mut panes, PaneState::Entries);
+        show_pane(&mut panes, PaneState::Entries);
+        assert_eq!(order_of(&panes), [PaneState::Entries, PaneState::Data]);
+        show_pane(&mut panes, PaneState::Folders);
+        assert_eq!(order_of(&panes), PaneState::ALL);
+
+        // A shown pane stays where it is, showing one ends maximizing
+        let mut panes = default_panes();
+        hide(&mut panes, PaneState::Data);
+        panes.maximize(pane(&panes, PaneState::Folders));
+        show_pane(&mut panes, PaneState::Folders);
+        assert_eq!(panes.len(), 2);
+        assert!(panes.maximized().is_some());
+        show_pane(&mut panes, PaneState::Data);
+        assert_eq!(order_of(&panes), PaneState::ALL);
+        assert_eq!(panes.maximized(), None);
+    }
+}
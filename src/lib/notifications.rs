@@ -0,0 +1,235 @@
+use crate::*;
+use iced::widget::column;
+use std::time::Duration;
+
+const HISTORY_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationLevel {
+    Info,
+    Warning,
+    Error,
+}
+
+impl NotificationLevel {
+    pub fn color(&self) -> Color {
+        match self {
+            NotificationLevel::Info => Color::from_rgb8(120, 180, 255),
+            NotificationLevel::Warning => Color::from_rgb8(255, 190, 60),
+            NotificationLevel::Error => Color::from_rgb8(255, 0, 0),
+        }
+    }
+
+    // Errors stay on screen until they are dismissed
+    pub fn default_timeout(&self) -> Option<Duration> {
+        match self {
+            NotificationLevel::Info => Some(Duration::from_secs(5)),
+            NotificationLevel::Warning => Some(Duration::from_secs(10)),
+            NotificationLevel::Error => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub id: u64,
+    pub level: NotificationLevel,
+    pub message: String,
+    pub action: Option<(String, Message)>,
+    pub created_at: Instant,
+    pub timeout: Option<Duration>,
+}
+
+impl Notification {
+    pub fn new(level: NotificationLevel, message: impl Into<String>) -> Self {
+        Self {
+            id: 0,
+            level,
+            message: message.into(),
+            action: None,
+            created_at: Instant::now(),
+            timeout: level.default_timeout(),
+        }
+    }
+
+    pub fn info(message: impl Into<String>) -> Self {
+        Self::new(NotificationLevel::Info, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(NotificationLevel::Warning, message)
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(NotificationLevel::Error, message)
+    }
+
+    // Turns a vault error into a notification offering the matching way out
+    pub fn from_vault_error(error: &VaultError, retry: Message) -> Self {
+        if error.is_retryable() {
+            Self::warning(error.to_string()).with_action("Retry", retry)
+        } else if error.needs_recovery() {
+            Self::error(format!(
+                "{error}. Restore the vault from a backup or recreate it with your master sync key"
+            ))
+        } else {
+            Self::error(error.to_string())
+        }
+    }
+
+    pub fn with_action(mut self, label: impl Into<String>, message: Message) -> Self {
+        self.action = Some((label.into(), message));
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.timeout
+            .is_some_and(|timeout| now.duration_since(self.created_at) >= timeout)
+    }
+}
+
+#[derive(Default)]
+pub struct NotificationCenter {
+    active: Vec<Notification>,
+    history: VecDeque<Notification>,
+    next_id: u64,
+    pub show_history: bool,
+}
+
+impl NotificationCenter {
+    pub fn push(&mut self, mut notification: Notification) -> u64 {
+        self.next_id += 1;
+        notification.id = self.next_id;
+
+        match notification.level {
+            NotificationLevel::Error => println!("[ERROR]: {}", notification.message),
+            _ => println!("[INFO]: {}", notification.message),
+        }
+
+        if self.history.len() == HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+        self.history.push_back(notification.clone());
+        self.active.push(notification);
+        self.next_id
+    }
+
+    pub fn dismiss(&mut self, id: u64) -> Option<Notification> {
+        let index = self.active.iter().position(|active| active.id == id)?;
+        Some(self.active.remove(index))
+    }
+
+    pub fn dismiss_all(&mut self) {
+        self.active.clear();
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        self.active
+            .retain(|notification| !notification.is_expired(now));
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    pub fn active(&self) -> &[Notification] {
+        &self.active
+    }
+
+    pub fn history(&self) -> impl DoubleEndedIterator<Item = &Notification> {
+        self.history.iter()
+    }
+
+    // Only tick while something on screen can actually time out
+    pub fn needs_tick(&self) -> bool {
+        self.active
+            .iter()
+            .any(|notification| notification.timeout.is_some())
+    }
+}
+
+impl Keylight {
+    pub(crate) fn notification_subscription(&self) -> Subscription<Message> {
+        if self.notifications.needs_tick() {
+            iced::time::every(Duration::from_millis(500)).map(Message::NotificationTick)
+        } else {
+            Subscription::none()
+        }
+    }
+
+    pub(crate) fn notifications_overlay(&self) -> Element<Message> {
+        let mut toasts: Column<'_, Message> = column![].spacing(5).width(350);
+
+        for notification in self.notifications.active() {
+            let mut toast = row![text(notification.message.as_str())
+                .color(notification.level.color())
+                .width(Fill)]
+            .spacing(5)
+            .align_y(Center);
+
+            if let Some((label, _)) = &notification.action {
+                toast = toast.push(
+                    button(text(label.as_str()))
+                        .on_press(Message::NotificationActionPressed(notification.id)),
+                );
+            }
+            toast = toast.push(button("x").on_press(Message::DismissNotification(notification.id)));
+
+            toasts = toasts.push(container(toast).padding(10).style(container::rounded_box));
+        }
+
+        let history_count = self.notifications.history.len();
+        let mut panel: Column<'_, Message> =
+            column![row![button(text!("Notifications ({history_count})"))
+                .on_press(Message::ToggleNotificationHistory)]]
+            .spacing(5)
+            .align_x(iced::alignment::Horizontal::Right);
+
+        if self.notifications.show_history {
+            let now = Instant::now();
+            let mut history: Column<'_, Message> = column![].spacing(5);
+            for notification in self.notifications.history().rev() {
+                let elapsed = now.duration_since(notification.created_at).as_secs();
+                history = history.push(
+                    row![
+                        text!("{}m {}s ago", elapsed / 60, elapsed % 60).size(12),
+                        text(notification.message.as_str()).color(notification.level.color())
+                    ]
+                    .spacing(10),
+                );
+            }
+
+            panel = panel.push(
+                container(
+                    column![
+                        row![
+                            text!("History").width(Fill),
+                            button("Clear").on_press(Message::ClearNotificationHistory)
+                        ]
+                        .align_y(Center),
+                        scrollable(history).height(250)
+                    ]
+                    .spacing(5),
+                )
+                .padding(10)
+                .width(350)
+                .style(container::rounded_box),
+            );
+        }
+
+        container(
+            column![panel, iced::widget::vertical_space(), toasts]
+                .align_x(iced::alignment::Horizontal::Right),
+        )
+        .padding(10)
+        .width(Fill)
+        .height(Fill)
+        .align_x(iced::alignment::Horizontal::Right)
+        .into()
+    }
+}
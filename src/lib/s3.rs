@@ -0,0 +1,613 @@
+use crate::*;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use url::Url;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+// Change sets kept in an S3 compatible bucket (AWS, MinIO, Backblaze B2, ...). Requests are signed
+// with AWS Signature Version 4 and address the bucket by path, "<endpoint>/<bucket>/<key>", which
+// every one of them understands.
+//
+// Like on WebDAV (webdav.rs) uploads are conditional writes with "If-None-Match: *" and never
+// replace a change set, and reads take the latest version in a single request. Buckets that ignore
+// the condition let a second writer replace a change set. With object versioning turned on in the
+// bucket settings the replaced version is kept, S3Store::versions lists them and
+// S3Store::rollback makes a chosen one the latest again.
+
+const SIGNING_ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const DEFAULT_REGION: &str = "us-east-1";
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+// Kept inside the vault like WebDavSettings
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct S3Settings {
+    // https://s3.eu-central-1.amazonaws.com, http://localhost:9000, ...
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub enabled: bool,
+}
+
+// Messages are Debug, the secret key is left out
+impl fmt::Debug for S3Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Settings")
+            .field("endpoint", &self.endpoint)
+            .field("region", &self.region)
+            .field("bucket", &self.bucket)
+            .field("access_key_id", &self.access_key_id)
+            .field("enabled", &self.enabled)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectVersion {
+    pub version_id: String,
+    // As the server sent it, such as "2024-05-01T12:00:00.000Z"
+    pub last_modified: String,
+    pub is_latest: bool,
+}
+
+pub struct S3Store {
+    client: reqwest::Client,
+    endpoint: Url,
+    region: String,
+    settings: S3Settings,
+}
+
+// Percent encodes everything but the unreserved characters, as the canonical request expects
+fn uri_encode(value: &str, keep_slashes: bool) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if keep_slashes => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn hmac_sha256(key: &[u8], message: &str) -> Vec<u8> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(message.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// "20130524T000000Z" for the given unix time, the date alone is its first 8 characters
+fn amz_date(unix_time: i64) -> String {
+    let (days, seconds) = (
+        unix_time.div_euclid(SECONDS_PER_DAY),
+        unix_time.rem_euclid(SECONDS_PER_DAY),
+    );
+    // Days since 1970-01-01 to the civil date, counted in 400 year eras starting at March 1st
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+impl S3Store {
+    pub fn new(settings: S3Settings) -> Result<Self, SyncError> {
+        let endpoint = Url::parse(settings.endpoint.trim())
+            .map_err(|error| SyncError::Remote(format!("Invalid S3 endpoint: {error}")))?;
+        if !matches!(endpoint.scheme(), "http" | "https") || endpoint.host_str().is_none() {
+            return Err(SyncError::Remote(
+                "The S3 endpoint has to start with https:// or http://".to_string(),
+            ));
+        }
+        if settings.bucket.trim().is_empty() || settings.bucket.contains('/') {
+            return Err(SyncError::Remote("Invalid S3 bucket name".to_string()));
+        }
+        let region = match settings.region.trim() {
+            "" => DEFAULT_REGION.to_string(),
+            region => region.to_string(),
+        };
+        Ok(Self {
+            client: http_client()?,
+            endpoint,
+            region,
+            settings,
+        })
+    }
+
+    // The url of a key, or of the bucket for None, with the query encoded the canonical way
+    fn s3_url(&self, key: Option<&str>, query: &[(&str, &str)]) -> Url {
+        let mut url = self.endpoint.clone();
+        let mut path = format!(
+            "{}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            uri_encode(self.settings.bucket.trim(), false)
+        );
+        if let Some(key) = key {
+            path = format!("{path}/{}", uri_encode(key, true));
+        }
+        url.set_path(&path);
+
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (uri_encode(name, false), uri_encode(value, false)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("&");
+        url.set_query((!query.is_empty()).then_some(query.as_str()));
+        url
+    }
+
+    // The Authorization header for a request, `headers` are the lowercase names and values of every
+    // header that is signed, host and the x-amz ones included
+    fn authorization(
+        &self,
+        method: &Method,
+        url: &Url,
+        headers: &[(&str, String)],
+        payload_hash: &str,
+        time: &str,
+    ) -> String {
+        let (date, region) = (&time[..8], self.region.as_str());
+        let mut headers = headers.to_vec();
+        headers.sort();
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        // The query is built already encoded and sorted by s3_url
+        let canonical_request = format!(
+            "{method}\n{}\n{}\n{canonical_headers}\n{signed_headers}\n{payload_hash}",
+            url.path(),
+            url.query().unwrap_or_default(),
+        );
+
+        let scope = format!("{date}/{region}/s3/aws4_request");
+        let string_to_sign = format!(
+            "{SIGNING_ALGORITHM}\n{time}\n{scope}\n{}",
+            data_encoding::HEXLOWER.encode(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let secret = Zeroizing::new(format!("AWS4{}", self.settings.secret_access_key));
+        let mut key = Zeroizing::new(hmac_sha256(secret.as_bytes(), date));
+        for part in [region, "s3", "aws4_request"] {
+            key = Zeroizing::new(hmac_sha256(&key, part));
+        }
+        let signature = data_encoding::HEXLOWER.encode(&hmac_sha256(&key, &string_to_sign));
+        format!(
+            "{SIGNING_ALGORITHM} Credential={}/{scope}, SignedHeaders={signed_headers}, \
+            Signature={signature}",
+            self.settings.access_key_id
+        )
+    }
+
+    // A signed request, `extra` are further headers to sign and send, with lowercase names
+    fn request(
+        &self,
+        method: Method,
+        url: &Url,
+        body: &[u8],
+        extra: &[(&'static str, String)],
+    ) -> RequestBuilder {
+        let time = amz_date(unix_now());
+        let payload_hash = data_encoding::HEXLOWER.encode(&Sha256::digest(body));
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", time.clone()),
+        ];
+        headers.extend_from_slice(extra);
+        let authorization = self.authorization(&method, url, &headers, &payload_hash, &time);
+
+        let mut request = self
+            .client
+            .request(method, url.clone())
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", time)
+            .header("authorization", authorization)
+            .body(body.to_vec());
+        for (name, value) in extra {
+            request = request.header(*name, value);
+        }
+        request
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        url: Url,
+        body: &[u8],
+        extra: &[(&'static str, String)],
+    ) -> Result<Response, SyncError> {
+        send_with_retries(|| self.request(method.clone(), &url, body, extra)).await
+    }
+
+    // Every version of a key, oldest first. Buckets without versioning list the one there is
+    pub async fn versions(&self, key: &str) -> Result<Vec<ObjectVersion>, SyncError> {
+        let mut versions = Vec::new();
+        let mut marker: Option<(String, String)> = None;
+        loop {
+            let mut query = vec![("versions", ""), ("prefix", key)];
+            if let Some((key_marker, version_marker)) = &marker {
+                query.push(("key-marker", key_marker.as_str()));
+                query.push(("version-id-marker", version_marker.as_str()));
+            }
+            let url = self.s3_url(None, &query);
+            let response = self.send(Method::GET, url, &[], &[]).await?;
+            if !response.status().is_success() {
+                return Err(status_error(&response, key));
+            }
+            let body = response.text().await?;
+            let document = parse_xml(&body)?;
+            let result = document.root_element();
+            // Delete markers are left out, they have no contents to go back to
+            versions.extend(
+                result
+                    .children()
+                    .filter(|node| node.tag_name().name() == "Version")
+                    .filter(|version| child_text(version, "Key").as_deref() == Some(key))
+                    .filter_map(|version| {
+                        Some(ObjectVersion {
+                            version_id: child_text(&version, "VersionId")?,
+                            last_modified: child_text(&version, "LastModified").unwrap_or_default(),
+                            is_latest: child_text(&version, "IsLatest").as_deref() == Some("true"),
+                        })
+                    }),
+            );
+            marker = match child_text(&result, "IsTruncated").as_deref() {
+                Some("true") => child_text(&result, "NextKeyMarker")
+                    .zip(child_text(&result, "NextVersionIdMarker")),
+                _ => None,
+            };
+            if marker.is_none() {
+                // Listed newest first
+                versions.reverse();
+                return Ok(versions);
+            }
+        }
+    }
+
+    // Makes a version of a key the latest one again by copying it over the key on the server, the
+    // versions in between are kept
+    pub async fn rollback(&self, key: &str, version_id: &str) -> Result<(), SyncError> {
+        let source = format!(
+            "/{}/{}?versionId={}",
+            uri_encode(self.settings.bucket.trim(), false),
+            uri_encode(key, true),
+            uri_encode(version_id, false)
+        );
+        let url = self.s3_url(Some(key), &[]);
+        let response = self
+            .send(Method::PUT, url, &[], &[("x-amz-copy-source", source)])
+            .await?;
+        if !response.status().is_success() {
+            return Err(status_error(&response, key));
+        }
+        // A copy can fail after the server started answering, the error is then in the body
+        let body = response.text().await?;
+        if parse_xml(&body)?.root_element().tag_name().name() == "Error" {
+            return Err(SyncError::Remote(format!(
+                "The S3 server could not restore version {version_id} of {key}"
+            )));
+        }
+        println!("[INFO]: Restored version {version_id} of {key}");
+        Ok(())
+    }
+}
+
+fn parse_xml(body: &str) -> Result<roxmltree::Document<'_>, SyncError> {
+    roxmltree::Document::parse(body)
+        .map_err(|error| SyncError::Remote(format!("Unreadable S3 answer: {error}")))
+}
+
+fn child_text(node: &roxmltree::Node, name: &str) -> Option<String> {
+    node.children()
+        .find(|child| child.tag_name().name() == name)?
+        .text()
+        .map(str::to_string)
+}
+
+fn status_error(response: &Response, key: &str) -> SyncError {
+    match response.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => SyncError::Remote(
+            "The S3 server did not accept the access key, or it may not use the bucket".to_string(),
+        ),
+        status => SyncError::Remote(format!("The S3 server answered {status} for {key}")),
+    }
+}
+
+impl SyncStore for S3Store {
+    async fn list(&self, directory: &str) -> Result<Vec<String>, SyncError> {
+        let prefix = format!("{directory}/");
+        let mut files = Vec::new();
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix.as_str())];
+            if let Some(continuation) = &continuation {
+                query.push(("continuation-token", continuation.as_str()));
+            }
+            let url = self.s3_url(None, &query);
+            let response = self.send(Method::GET, url, &[], &[]).await?;
+            if !response.status().is_success() {
+                return Err(status_error(&response, directory));
+            }
+            let body = response.text().await?;
+            let document = parse_xml(&body)?;
+            let result = document.root_element();
+            files.extend(
+                result
+                    .children()
+                    .filter(|node| node.tag_name().name() == "Contents")
+                    .filter_map(|contents| child_text(&contents, "Key")),
+            );
+            continuation = match child_text(&result, "IsTruncated").as_deref() {
+                Some("true") => child_text(&result, "NextContinuationToken"),
+                _ => None,
+            };
+            if continuation.is_none() {
+                return Ok(files);
+            }
+        }
+    }
+
+    async fn read(&self, path: &str) -> Result<Vec<u8>, SyncError> {
+        let url = self.s3_url(Some(path), &[]);
+        let response = self.send(Method::GET, url, &[], &[]).await?;
+        if !response.status().is_success() {
+            return Err(status_error(&response, path));
+        }
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    async fn write(&self, path: &str, contents: &[u8]) -> Result<(), SyncError> {
+        let url = self.s3_url(Some(path), &[]);
+        let conditional = [("if-none-match", "*".to_string())];
+        let mut response = self
+            .send(Method::PUT, url.clone(), contents, &conditional)
+            .await?;
+        // Another conditional write to the same key was in flight, it has finished by now
+        if response.status() == StatusCode::CONFLICT {
+            response = self.send(Method::PUT, url, contents, &conditional).await?;
+        }
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::PRECONDITION_FAILED => {
+                let existing = Zeroizing::new(self.read(path).await?);
+                match existing.as_slice() == contents {
+                    true => Ok(()),
+                    false => Err(SyncError::Remote(format!(
+                        "{path} was already written by another device, \
+                        two devices may be sharing one device id"
+                    ))),
+                }
+            }
+            _ => Err(status_error(&response, path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::mock::{self, MockResponse};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // Set with the others below to run the tests against a real bucket, such as one of
+    // `minio server /tmp/minio` made with `mc mb --with-versioning local/keylight-test`
+    const ENDPOINT_VARIABLE: &str = "KEYLIGHT_TEST_S3_ENDPOINT";
+    const BUCKET_VARIABLE: &str = "KEYLIGHT_TEST_S3_BUCKET";
+    const ACCESS_KEY_ID_VARIABLE: &str = "KEYLIGHT_TEST_S3_ACCESS_KEY_ID";
+    const SECRET_ACCESS_KEY_VARIABLE: &str = "KEYLIGHT_TEST_S3_SECRET_ACCESS_KEY";
+
+    fn store(endpoint: &str, bucket: &str) -> S3Store {
+        S3Store::new(S3Settings {
+            endpoint: endpoint.to_string(),
+            region: String::new(),
+            bucket: bucket.to_string(),
+            access_key_id: std::env::var(ACCESS_KEY_ID_VARIABLE).unwrap_or("keylight".into()),
+            secret_access_key: std::env::var(SECRET_ACCESS_KEY_VARIABLE)
+                .unwrap_or("keylight-secret".into()),
+            enabled: true,
+        })
+        .unwrap()
+    }
+
+    fn signed_headers(request: &mock::MockRequest) -> Vec<String> {
+        let authorization = &request.headers["authorization"];
+        let (_, signed) = authorization.split_once("SignedHeaders=").unwrap();
+        let (signed, _) = signed.split_once(',').unwrap();
+        signed.split(';').map(str::to_string).collect()
+    }
+
+    fn version(key: &str, version_id: &str, is_latest: bool) -> String {
+        format!(
+            "<Version><Key>{key}</Key><VersionId>{version_id}</VersionId>\
+            <IsLatest>{is_latest}</IsLatest><LastModified>2024-05-01T12:00:00.000Z</LastModified>\
+            </Version>"
+        )
+    }
+
+    #[tokio::test]
+    async fn versions_are_listed_across_pages() {
+        let key = "device/00000001.klsync";
+        let url = mock::serve(move |request| {
+            assert!(request.target.starts_with("/bucket?"));
+            let page = match request.target.contains("version-id-marker=v2") {
+                false => format!(
+                    "<IsTruncated>true</IsTruncated><NextKeyMarker>{key}</NextKeyMarker>\
+                    <NextVersionIdMarker>v2</NextVersionIdMarker>{}{}\
+                    <DeleteMarker><Key>{key}</Key><VersionId>d1</VersionId></DeleteMarker>",
+                    version(key, "v3", true),
+                    version(key, "v2", false)
+                ),
+                true => format!(
+                    "<IsTruncated>false</IsTruncated>{}{}",
+                    version(key, "v1", false),
+                    version(&format!("{key}.old"), "o1", true)
+                ),
+            };
+            MockResponse::new("200 OK").body(format!(
+                "<ListVersionsResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+                {page}</ListVersionsResult>"
+            ))
+        })
+        .await;
+
+        let versions = store(&url, "bucket").versions(key).await.unwrap();
+        let ids: Vec<&str> = versions
+            .iter()
+            .map(|version| version.version_id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["v1", "v2", "v3"]);
+        assert!(versions[2].is_latest && !versions[0].is_latest);
+        assert_eq!(versions[0].last_modified, "2024-05-01T12:00:00.000Z");
+    }
+
+    #[tokio::test]
+    async fn reads_take_a_single_request() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = requests.clone();
+        let url = mock::serve(move |request| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            assert_eq!(request.method, "GET");
+            assert_eq!(request.target, "/bucket/device/00000001.klsync");
+            MockResponse::new("200 OK").body("change set")
+        })
+        .await;
+        let read = store(&url, "bucket")
+            .read("device/00000001.klsync")
+            .await
+            .unwrap();
+        assert_eq!(read, b"change set");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn uploads_never_replace_a_change_set() {
+        let url = mock::serve(|request| match request.method.as_str() {
+            "PUT" => {
+                assert_eq!(request.headers["if-none-match"], "*");
+                assert!(signed_headers(&request).contains(&"if-none-match".to_string()));
+                MockResponse::new("412 Precondition Failed")
+            }
+            _ => MockResponse::new("200 OK").body("first change set"),
+        })
+        .await;
+        let store = store(&url, "bucket");
+        store
+            .write("device/00000001.klsync", b"first change set")
+            .await
+            .unwrap();
+        assert!(matches!(
+            store
+                .write("device/00000001.klsync", b"something else")
+                .await,
+            Err(SyncError::Remote(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rollback_copies_the_chosen_version() {
+        let url = mock::serve(|request| {
+            assert_eq!(request.method, "PUT");
+            assert_eq!(request.target, "/bucket/device/00000001.klsync");
+            assert!(signed_headers(&request).contains(&"x-amz-copy-source".to_string()));
+            match request.headers["x-amz-copy-source"].as_str() {
+                "/bucket/device/00000001.klsync?versionId=v1" => MockResponse::new("200 OK")
+                    .body("<CopyObjectResult><ETag>\"1\"</ETag></CopyObjectResult>"),
+                _ => MockResponse::new("200 OK")
+                    .header("content-type", "application/xml")
+                    .body("<Error><Code>NoSuchVersion</Code></Error>"),
+            }
+        })
+        .await;
+        let store = store(&url, "bucket");
+        store
+            .rollback("device/00000001.klsync", "v1")
+            .await
+            .unwrap();
+        assert!(store
+            .rollback("device/00000001.klsync", "v9")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn change_sets_survive_on_a_real_bucket() {
+        let (Ok(endpoint), Ok(bucket)) = (
+            std::env::var(ENDPOINT_VARIABLE),
+            std::env::var(BUCKET_VARIABLE),
+        ) else {
+            println!("[INFO]: {ENDPOINT_VARIABLE} is not set, skipping the S3 server test");
+            return;
+        };
+        let store = store(&endpoint, &bucket);
+        let directory = format!("keylight-test-{}-{}", std::process::id(), unix_now());
+        let first = format!("{directory}/device/00000001.klsync");
+        let second = format!("{directory}/device/00000002.klsync");
+        store.write(&first, b"first change set").await.unwrap();
+        store.write(&second, b"second change set").await.unwrap();
+        store.write(&first, b"first change set").await.unwrap();
+        assert!(store.write(&first, b"something else").await.is_err());
+        assert_eq!(store.read(&first).await.unwrap(), b"first change set");
+        let mut listed = store.list(&directory).await.unwrap();
+        listed.sort();
+        assert_eq!(listed, vec![first.clone(), second]);
+
+        // What a bucket ignoring the condition would let another writer do
+        let url = store.s3_url(Some(&first), &[]);
+        let response = store
+            .send(Method::PUT, url, b"replaced", &[])
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        let versions = store.versions(&first).await.unwrap();
+        if versions.len() < 2 {
+            println!("[INFO]: {bucket} does not keep versions, skipping the rollback");
+            return;
+        }
+        store
+            .rollback(&first, &versions[0].version_id)
+            .await
+            .unwrap();
+        assert_eq!(store.read(&first).await.unwrap(), b"first change set");
+        assert_eq!(
+            store.versions(&first).await.unwrap().len(),
+            versions.len() + 1
+        );
+    }
+}
@@ -0,0 +1,1991 @@
+use crate::ssh_agent::askpass_confirm;
+use crate::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock, Weak};
+use std::time::Duration;
+
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use futures::StreamExt;
+use hkdf::Hkdf;
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::AbortHandle;
+use zbus::message::Header;
+use zbus::names::BusName;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Type, Value};
+use zeroize::{Zeroize, Zeroizing};
+
+// Keylight as the org.freedesktop.secrets provider of the session: every folder of the unlocked
+// vault is a collection, every entry an item whose secret is its password section and whose
+// lookup attributes are kept in EntryTags as "secret-service:<name>=<value>".
+//
+// Any client sees which items there are, but the vault counts as locked for it until the user
+// allowed it through the Unlock prompt. Only then can it read or change secrets, and creating a
+// collection or deleting an item is confirmed every time. What the clients see is read from the
+// vault again whenever one opens a session or searches, so entries edited in Keylight show up
+
+pub const SECRET_SERVICE_NAME: &str = "org.freedesktop.secrets";
+const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+const COLLECTION_PREFIX: &str = "/org/freedesktop/secrets/collection";
+const ALIAS_PREFIX: &str = "/org/freedesktop/secrets/aliases";
+const SESSION_PREFIX: &str = "/org/freedesktop/secrets/session";
+const PROMPT_PREFIX: &str = "/org/freedesktop/secrets/prompt";
+const ATTRIBUTE_TAG_PREFIX: &str = "secret-service:";
+const SECRET_CONTENT_TYPE: &str = "text/plain; charset=utf8";
+
+const LABEL_PROPERTY: &str = "org.freedesktop.Secret.Item.Label";
+const ATTRIBUTES_PROPERTY: &str = "org.freedesktop.Secret.Item.Attributes";
+const COLLECTION_LABEL_PROPERTY: &str = "org.freedesktop.Secret.Collection.Label";
+
+// Second Oakley group from RFC 2409, the group the dh-ietf1024 algorithm is defined over
+const MODP_1024_PRIME: &[u8] = b"FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD1\
+29024E088A67CC74020BBEA63B139B22514A08798E3404DD\
+EF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245\
+E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE65381\
+FFFFFFFFFFFFFFFF";
+const DH_ALGORITHM: &str = "dh-ietf1024-sha256-aes128-cbc-pkcs7";
+// Unanswered prompts count as dismissed
+pub const SECRET_SERVICE_CONFIRM_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, zbus::DBusError)]
+#[zbus(prefix = "org.freedesktop.Secret.Error")]
+enum SecretError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    NoSuchObject(String),
+    NoSession(String),
+    IsLocked(String),
+}
+
+impl From<VaultError> for SecretError {
+    fn from(error: VaultError) -> Self {
+        println!("[ERROR]: {:?}", error);
+        SecretError::ZBus(zbus::Error::Failure(error.to_string()))
+    }
+}
+
+impl From<zbus::fdo::Error> for SecretError {
+    fn from(error: zbus::fdo::Error) -> Self {
+        SecretError::ZBus(error.into())
+    }
+}
+
+impl From<zbus::zvariant::Error> for SecretError {
+    fn from(error: zbus::zvariant::Error) -> Self {
+        SecretError::ZBus(error.into())
+    }
+}
+
+#[derive(Debug)]
+pub enum SecretServiceError {
+    Vault(VaultError),
+    Bus(zbus::Error),
+}
+
+impl fmt::Display for SecretServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretServiceError::Vault(error) => write!(f, "{error}"),
+            SecretServiceError::Bus(zbus::Error::NameTaken) => write!(
+                f,
+                "Another Secret Service provider (e.g. GNOME Keyring or KWallet) is already running"
+            ),
+            SecretServiceError::Bus(error) => {
+                write!(f, "Could not provide the Secret Service: {error}")
+            }
+        }
+    }
+}
+
+impl From<VaultError> for SecretServiceError {
+    fn from(error: VaultError) -> Self {
+        SecretServiceError::Vault(error)
+    }
+}
+
+impl From<zbus::Error> for SecretServiceError {
+    fn from(error: zbus::Error) -> Self {
+        println!("[ERROR]: {:?}", error);
+        SecretServiceError::Bus(error)
+    }
+}
+
+// A prompt waiting for the user, answered from wherever the provider runs (GUI or ssh-askpass)
+#[derive(Debug, Clone)]
+pub struct SecretServiceConfirmation {
+    pub question: String,
+    reply: Arc<Mutex<Option<oneshot::Sender<bool>>>>,
+}
+
+impl SecretServiceConfirmation {
+    pub fn answer(&self, allowed: bool) {
+        if let Some(reply) = self.reply.lock().unwrap().take() {
+            let _ = reply.send(allowed);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Type)]
+struct Secret {
+    session: OwnedObjectPath,
+    parameters: Vec<u8>,
+    value: Vec<u8>,
+    content_type: String,
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+enum SessionKey {
+    Plain,
+    Aes128(Zeroizing<[u8; 16]>),
+}
+
+// Only the connection that opened a session may use or close it
+struct OpenSession {
+    owner: String,
+    key: SessionKey,
+}
+
+type Aes128CbcEncryptor = cbc::Encryptor<aes::Aes128>;
+type Aes128CbcDecryptor = cbc::Decryptor<aes::Aes128>;
+
+impl SessionKey {
+    fn encrypt(&self, session: OwnedObjectPath, value: &[u8]) -> Secret {
+        let (parameters, value) = match self {
+            SessionKey::Plain => (Vec::new(), value.to_vec()),
+            SessionKey::Aes128(key) => {
+                let mut iv = [0u8; 16];
+                OsRng.fill_bytes(&mut iv);
+                let encrypted = Aes128CbcEncryptor::new((&**key).into(), &iv.into())
+                    .encrypt_padded_vec_mut::<Pkcs7>(value);
+                (iv.to_vec(), encrypted)
+            }
+        };
+
+        Secret {
+            session,
+            parameters,
+            value,
+            content_type: SECRET_CONTENT_TYPE.to_string(),
+        }
+    }
+
+    fn decrypt(&self, secret: &Secret) -> Result<Zeroizing<Vec<u8>>, SecretError> {
+        match self {
+            SessionKey::Plain => Ok(Zeroizing::new(secret.value.clone())),
+            SessionKey::Aes128(key) => {
+                Aes128CbcDecryptor::new_from_slices(key.as_ref(), &secret.parameters)
+                    .ok()
+                    .and_then(|decryptor| {
+                        decryptor
+                            .decrypt_padded_vec_mut::<Pkcs7>(&secret.value)
+                            .ok()
+                    })
+                    .map(Zeroizing::new)
+                    .ok_or_else(|| {
+                        zbus::fdo::Error::InvalidArgs(
+                            "The secret could not be decrypted".to_string(),
+                        )
+                        .into()
+                    })
+            }
+        }
+    }
+}
+
+// Returns our public key and the AES key both sides derive from the shared secret with HKDF-SHA256
+fn negotiate_dh(client_public: &[u8]) -> Result<(Vec<u8>, Zeroizing<[u8; 16]>), SecretError> {
+    let prime = BigUint::parse_bytes(MODP_1024_PRIME, 16).expect("the prime is valid hex");
+    let client_public = BigUint::from_bytes_be(client_public);
+    if client_public <= BigUint::from(1u32) || client_public >= &prime - 1u32 {
+        return Err(zbus::fdo::Error::InvalidArgs("Invalid public key".to_string()).into());
+    }
+
+    // BigUint cannot be wiped, the byte buffers around it are
+    let mut private_bytes = Zeroizing::new([0u8; 128]);
+    OsRng.fill_bytes(private_bytes.as_mut());
+    let private = BigUint::from_bytes_be(private_bytes.as_ref());
+    let public = BigUint::from(2u32).modpow(&private, &prime);
+    let shared = client_public.modpow(&private, &prime);
+
+    // The shared secret is used as a fixed size big endian number
+    let mut shared_bytes = Zeroizing::new([0u8; 128]);
+    let shared_be = Zeroizing::new(shared.to_bytes_be());
+    shared_bytes[128 - shared_be.len()..].copy_from_slice(&shared_be);
+
+    let mut key = Zeroizing::new([0u8; 16]);
+    Hkdf::<sha2::Sha256>::new(None, shared_bytes.as_ref())
+        .expand(&[], key.as_mut())
+        .expect("16 bytes is a valid HKDF-SHA256 output length");
+
+    Ok((public.to_bytes_be(), key))
+}
+
+fn object_path(path: String) -> OwnedObjectPath {
+    OwnedObjectPath::try_from(path).expect("generated object paths are valid")
+}
+
+fn collection_path(folder_id: i64) -> OwnedObjectPath {
+    object_path(format!("{COLLECTION_PREFIX}/f{folder_id}"))
+}
+
+fn alias_path(alias: &str) -> OwnedObjectPath {
+    object_path(format!("{ALIAS_PREFIX}/{alias}"))
+}
+
+fn item_path(folder_id: i64, entry_id: i64) -> OwnedObjectPath {
+    object_path(format!("{COLLECTION_PREFIX}/f{folder_id}/e{entry_id}"))
+}
+
+fn entry_id_of(path: &ObjectPath<'_>) -> Option<i64> {
+    path.as_str()
+        .strip_prefix(COLLECTION_PREFIX)?
+        .rsplit('/')
+        .next()?
+        .strip_prefix('e')?
+        .parse()
+        .ok()
+}
+
+fn attributes_from_tags(tags: &[String]) -> HashMap<String, String> {
+    tags.iter()
+        .filter_map(|tag| tag.strip_prefix(ATTRIBUTE_TAG_PREFIX)?.split_once('='))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+fn property<T: TryFrom<OwnedValue>>(
+    properties: &HashMap<String, OwnedValue>,
+    name: &str,
+) -> Option<T> {
+    T::try_from(properties.get(name)?.try_clone().ok()?).ok()
+}
+
+struct ItemInfo {
+    folder_id: i64,
+    label: String,
+    attributes: HashMap<String, String>,
+    created: u64,
+    modified: u64,
+}
+
+impl ItemInfo {
+    fn matches(&self, attributes: &HashMap<String, String>) -> bool {
+        attributes
+            .iter()
+            .all(|(name, value)| self.attributes.get(name) == Some(value))
+    }
+}
+
+// Folders by id with their names, items by entry id
+type Index = (BTreeMap<i64, String>, HashMap<i64, ItemInfo>);
+// entry_id, folder_id, entry_name, created, modified
+type ItemRow = (i64, i64, Option<String>, Option<i64>, Option<i64>);
+
+// Entries keep no creation time, the oldest recorded change to their sections stands in for it
+async fn load_index(vault_pool: &SqlitePool) -> Result<Index, VaultError> {
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    let folders: Vec<(i64, Option<String>)> =
+        sqlx::query_as("SELECT folder_id, folder_name FROM Folders;")
+            .fetch_all(connection.as_mut())
+            .await?;
+    let entries: Vec<ItemRow> = sqlx::query_as(
+        "SELECT entry_id, folder_id, entry_name,
+            MIN(COALESCE(first_change, first_replaced), COALESCE(first_replaced, first_change)),
+            MAX(COALESCE(last_change, last_replaced), COALESCE(last_replaced, last_change))
+        FROM (SELECT entry_id, folder_id, entry_name,
+            (SELECT MIN(modified_at) FROM EntryData WHERE EntryData.entry_id = Entries.entry_id) AS first_change,
+            (SELECT MAX(modified_at) FROM EntryData WHERE EntryData.entry_id = Entries.entry_id) AS last_change,
+            (SELECT MIN(replaced_at) FROM SectionHistory WHERE SectionHistory.entry_id = Entries.entry_id) AS first_replaced,
+            (SELECT MAX(replaced_at) FROM SectionHistory WHERE SectionHistory.entry_id = Entries.entry_id) AS last_replaced
+            FROM Entries WHERE is_deleted = FALSE AND folder_id IS NOT NULL);",
+    )
+    .fetch_all(connection.as_mut())
+    .await?;
+    let tags: Vec<(i64, String)> = sqlx::query_as(
+        "SELECT entry_id, tag_name FROM EntryTags
+        WHERE substr(tag_name, 1, length(?1)) = ?1 ORDER BY tag_id;",
+    )
+    .bind(ATTRIBUTE_TAG_PREFIX)
+    .fetch_all(connection.as_mut())
+    .await?;
+
+    let mut entry_tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for (entry_id, tag) in tags {
+        entry_tags.entry(entry_id).or_default().push(tag);
+    }
+    let folders: BTreeMap<i64, String> = folders
+        .into_iter()
+        .map(|(folder_id, folder_name)| (folder_id, folder_name.unwrap_or_default()))
+        .collect();
+    let items = entries
+        .into_iter()
+        .filter(|(_, folder_id, ..)| folders.contains_key(folder_id))
+        .map(|(entry_id, folder_id, entry_name, created, modified)| {
+            let item = ItemInfo {
+                folder_id,
+                label: entry_name.unwrap_or_default(),
+                attributes: attributes_from_tags(
+                    entry_tags.get(&entry_id).map_or(&[], Vec::as_slice),
+                ),
+                created: created.unwrap_or_default().max(0) as u64,
+                modified: modified.unwrap_or_default().max(0) as u64,
+            };
+            (entry_id, item)
+        })
+        .collect();
+    Ok((folders, items))
+}
+
+// The program behind a bus name as the user knows it, the bus name itself when that is unknown
+async fn client_name(connection: &zbus::Connection, client: &str) -> String {
+    let Ok(bus) = zbus::fdo::DBusProxy::new(connection).await else {
+        return client.to_string();
+    };
+    let Ok(name) = BusName::try_from(client) else {
+        return client.to_string();
+    };
+    match bus.get_connection_unix_process_id(name).await {
+        Ok(pid) => fs::read_to_string(format!("/proc/{pid}/comm"))
+            .map(|program| format!("{} (process {pid})", program.trim()))
+            .unwrap_or_else(|_| format!("Process {pid}")),
+        Err(_) => client.to_string(),
+    }
+}
+
+fn sender_of(header: &Header<'_>) -> String {
+    header
+        .sender()
+        .map(|sender| sender.to_string())
+        .unwrap_or_default()
+}
+
+enum PromptAction {
+    Unlock { objects: Vec<OwnedObjectPath> },
+    CreateCollection { label: String, alias: String },
+    DeleteItem { entry_id: i64 },
+}
+
+struct Provider {
+    state: Arc<ApplicationAsyncState>,
+    vault_pool: SqlitePool,
+    sessions: Mutex<HashMap<OwnedObjectPath, OpenSession>>,
+    // Sessions of clients that left the bus, unexported with the next refresh
+    departed_sessions: Mutex<Vec<OwnedObjectPath>>,
+    folders: Mutex<BTreeMap<i64, String>>,
+    items: Mutex<HashMap<i64, ItemInfo>>,
+    aliases: Mutex<HashMap<String, i64>>,
+    // Unique bus names of the clients the user let in, these are never reused on a bus
+    allowed_clients: Mutex<HashSet<String>>,
+    // Without a channel the user is asked through $SSH_ASKPASS
+    confirmations: Option<mpsc::Sender<SecretServiceConfirmation>>,
+    next_object_id: AtomicU64,
+    // Watches for clients leaving the bus, for as long as the provider exists
+    departures: OnceLock<AbortHandle>,
+}
+
+impl Drop for Provider {
+    fn drop(&mut self) {
+        if let Some(departures) = self.departures.get() {
+            departures.abort();
+        }
+    }
+}
+
+impl Provider {
+    fn is_allowed(&self, header: Option<&Header<'_>>) -> bool {
+        header.and_then(Header::sender).is_some_and(|sender| {
+            self.allowed_clients
+                .lock()
+                .unwrap()
+                .contains(sender.as_str())
+        })
+    }
+
+    fn check_allowed(&self, header: Option<&Header<'_>>) -> Result<(), SecretError> {
+        match self.is_allowed(header) {
+            true => Ok(()),
+            false => Err(SecretError::IsLocked(
+                "Unlock the items first, Keylight asks whether to allow this application"
+                    .to_string(),
+            )),
+        }
+    }
+
+    async fn confirm(&self, question: String) -> bool {
+        let Some(confirmations) = &self.confirmations else {
+            return askpass_confirm(&question, SECRET_SERVICE_CONFIRM_TIMEOUT).await;
+        };
+
+        let (reply, answer) = oneshot::channel();
+        let confirmation = SecretServiceConfirmation {
+            question,
+            reply: Arc::new(Mutex::new(Some(reply))),
+        };
+        if confirmations.send(confirmation).await.is_err() {
+            return false;
+        }
+        matches!(
+            tokio::time::timeout(SECRET_SERVICE_CONFIRM_TIMEOUT, answer).await,
+            Ok(Ok(true))
+        )
+    }
+
+    // Prompts belong to the client that caused them, only it is let in by an Unlock prompt
+    async fn prompt(
+        self: &Arc<Self>,
+        object_server: &zbus::ObjectServer,
+        header: &Header<'_>,
+        action: PromptAction,
+    ) -> Result<OwnedObjectPath, SecretError> {
+        let prompt = self.next_path(PROMPT_PREFIX);
+        object_server
+            .at(
+                &prompt,
+                PromptObject {
+                    provider: self.clone(),
+                    client: sender_of(header),
+                    action: Mutex::new(Some(action)),
+                    completed: Arc::new(AtomicBool::new(false)),
+                },
+            )
+            .await?;
+        Ok(prompt)
+    }
+
+    async fn refresh(
+        self: &Arc<Self>,
+        object_server: &zbus::ObjectServer,
+    ) -> Result<(), SecretError> {
+        let index = load_index(&self.vault_pool).await?;
+        Ok(self.export(index, object_server).await?)
+    }
+
+    // Takes over the index and exports what was added to the vault since the last one, removing
+    // what is gone. Exporting an object that is already there changes nothing
+    async fn export(
+        self: &Arc<Self>,
+        (folders, items): Index,
+        object_server: &zbus::ObjectServer,
+    ) -> zbus::Result<()> {
+        let departed_sessions = std::mem::take(&mut *self.departed_sessions.lock().unwrap());
+        for path in departed_sessions {
+            let _ = object_server.remove::<SessionObject, _>(&path).await;
+        }
+        let gone_items: Vec<OwnedObjectPath> = self
+            .items
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(entry_id, item)| {
+                items
+                    .get(entry_id)
+                    .is_none_or(|current| current.folder_id != item.folder_id)
+            })
+            .map(|(entry_id, item)| item_path(item.folder_id, *entry_id))
+            .collect();
+        let gone_folders: Vec<OwnedObjectPath> = self
+            .folders
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|folder_id| !folders.contains_key(folder_id))
+            .map(|folder_id| collection_path(*folder_id))
+            .collect();
+        let (old_aliases, aliases) = {
+            let mut aliases = self.aliases.lock().unwrap();
+            let old_aliases = aliases.clone();
+            aliases.retain(|_, folder_id| folders.contains_key(folder_id));
+            // Clients store their passwords in the default collection unless told otherwise
+            if let (false, Some(folder_id)) =
+                (aliases.contains_key("default"), folders.keys().next())
+            {
+                aliases.insert("default".to_string(), *folder_id);
+            }
+            (old_aliases, aliases.clone())
+        };
+
+        let exported_items: Vec<(i64, i64)> = items
+            .iter()
+            .map(|(entry_id, item)| (item.folder_id, *entry_id))
+            .collect();
+        let exported_folders: Vec<i64> = folders.keys().copied().collect();
+        *self.folders.lock().unwrap() = folders;
+        *self.items.lock().unwrap() = items;
+
+        for path in gone_items {
+            let _ = object_server.remove::<ItemObject, _>(&path).await;
+        }
+        for path in gone_folders {
+            let _ = object_server.remove::<CollectionObject, _>(&path).await;
+        }
+        for (alias, folder_id) in &old_aliases {
+            if aliases.get(alias) != Some(folder_id) {
+                let _ = object_server
+                    .remove::<CollectionObject, _>(alias_path(alias))
+                    .await;
+            }
+        }
+
+        for folder_id in exported_folders {
+            object_server
+                .at(
+                    collection_path(folder_id),
+                    CollectionObject {
+                        provider: self.clone(),
+                        folder_id,
+                    },
+                )
+                .await?;
+        }
+        for (alias, folder_id) in aliases {
+            object_server
+                .at(
+                    alias_path(&alias),
+                    CollectionObject {
+                        provider: self.clone(),
+                        folder_id,
+                    },
+                )
+                .await?;
+        }
+        for (folder_id, entry_id) in exported_items {
+            object_server
+                .at(
+                    item_path(folder_id, entry_id),
+                    ItemObject {
+                        provider: self.clone(),
+                        folder_id,
+                        entry_id,
+                    },
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn next_path(&self, prefix: &str) -> OwnedObjectPath {
+        let id = self.next_object_id.fetch_add(1, Ordering::Relaxed);
+        object_path(format!("{prefix}/{id}"))
+    }
+
+    fn collection_paths(&self) -> Vec<OwnedObjectPath> {
+        self.folders
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|folder_id| collection_path(*folder_id))
+            .collect()
+    }
+
+    fn search(
+        &self,
+        folder_id: Option<i64>,
+        attributes: &HashMap<String, String>,
+    ) -> Vec<OwnedObjectPath> {
+        let mut found: Vec<(i64, i64)> = self
+            .items
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, item)| folder_id.is_none_or(|folder_id| item.folder_id == folder_id))
+            .filter(|(_, item)| item.matches(attributes))
+            .map(|(entry_id, item)| (item.folder_id, *entry_id))
+            .collect();
+        found.sort();
+        found
+            .into_iter()
+            .map(|(folder_id, entry_id)| item_path(folder_id, entry_id))
+            .collect()
+    }
+
+    fn item(&self, path: &ObjectPath<'_>) -> Result<(i64, i64), SecretError> {
+        entry_id_of(path)
+            .and_then(|entry_id| {
+                let items = self.items.lock().unwrap();
+                items.get(&entry_id).map(|item| (item.folder_id, entry_id))
+            })
+            .ok_or_else(|| SecretError::NoSuchObject(path.to_string()))
+    }
+
+    // The sessions of other clients are as unknown to a client as closed ones
+    fn check_session(
+        sessions: &HashMap<OwnedObjectPath, OpenSession>,
+        session: &ObjectPath<'_>,
+        header: &Header<'_>,
+    ) -> Result<(), SecretError> {
+        match sessions.get(&OwnedObjectPath::from(session.to_owned())) {
+            Some(open) if open.owner == sender_of(header) => Ok(()),
+            _ => Err(SecretError::NoSession(session.to_string())),
+        }
+    }
+
+    fn encrypt_for(
+        &self,
+        session: &ObjectPath<'_>,
+        header: &Header<'_>,
+        value: &[u8],
+    ) -> Result<Secret, SecretError> {
+        let sessions = self.sessions.lock().unwrap();
+        Self::check_session(&sessions, session, header)?;
+        let open = &sessions[&OwnedObjectPath::from(session.to_owned())];
+        Ok(open.key.encrypt(session.to_owned().into(), value))
+    }
+
+    fn decrypt(
+        &self,
+        secret: &Secret,
+        header: &Header<'_>,
+    ) -> Result<Zeroizing<Vec<u8>>, SecretError> {
+        let sessions = self.sessions.lock().unwrap();
+        Self::check_session(&sessions, &secret.session, header)?;
+        sessions[&secret.session].key.decrypt(secret)
+    }
+
+    // Unique names are never reused, what was granted to one goes with it
+    fn client_left(&self, client: &str) {
+        let mut departed = Vec::new();
+        self.sessions.lock().unwrap().retain(|path, open| {
+            if open.owner == client {
+                departed.push(path.clone());
+            }
+            open.owner != client
+        });
+        self.allowed_clients.lock().unwrap().remove(client);
+        if !departed.is_empty() {
+            println!(
+                "[INFO]: Closed {} Secret Service sessions of {client}, it left the bus",
+                departed.len()
+            );
+            self.departed_sessions.lock().unwrap().extend(departed);
+        }
+    }
+
+    async fn read_secret(&self, entry_id: i64) -> Result<Zeroizing<Vec<u8>>, SecretError> {
+        let entry_data = self
+            .state
+            .clone()
+            .get_entry_data(self.vault_pool.clone(), entry_id)
+            .await?;
+        Ok(Zeroizing::new(
+            entry_data
+                .iter()
+                .find(|section| section.section_type.as_deref() == Some("password"))
+                .and_then(|section| section.section_data.clone())
+                .unwrap_or_default(),
+        ))
+    }
+
+    async fn write_secret(
+        &self,
+        entry_id: i64,
+        value: Zeroizing<Vec<u8>>,
+    ) -> Result<(), SecretError> {
+        self.state
+            .clone()
+            .set_entry_section(
+                self.vault_pool.clone(),
+                entry_id,
+                "password".to_string(),
+                "password".to_string(),
+                value,
+            )
+            .await?;
+        if let Some(item) = self.items.lock().unwrap().get_mut(&entry_id) {
+            item.modified = unix_now().max(0) as u64;
+        }
+        Ok(())
+    }
+
+    // Attribute tags are replaced while any other tag of the entry is kept
+    async fn write_attributes(
+        &self,
+        entry_id: i64,
+        attributes: &HashMap<String, String>,
+    ) -> Result<(), SecretError> {
+        let mut tags: Vec<String> = self
+            .state
+            .clone()
+            .get_entry_tags(self.vault_pool.clone(), entry_id)
+            .await?
+            .into_iter()
+            .filter(|tag| !tag.starts_with(ATTRIBUTE_TAG_PREFIX))
+            .collect();
+        tags.extend(
+            attributes
+                .iter()
+                .map(|(name, value)| format!("{ATTRIBUTE_TAG_PREFIX}{name}={value}")),
+        );
+        self.state
+            .clone()
+            .set_entry_tags(self.vault_pool.clone(), entry_id, tags)
+            .await?;
+        Ok(())
+    }
+}
+
+struct ServiceObject {
+    provider: Arc<Provider>,
+}
+
+#[zbus::interface(name = "org.freedesktop.Secret.Service")]
+impl ServiceObject {
+    async fn open_session(
+        &self,
+        algorithm: &str,
+        input: OwnedValue,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(object_server)] object_server: &zbus::ObjectServer,
+    ) -> Result<(OwnedValue, OwnedObjectPath), SecretError> {
+        let (output, key) = match algorithm {
+            "plain" => (OwnedValue::try_from(Value::from(""))?, SessionKey::Plain),
+            DH_ALGORITHM => {
+                let client_public = Vec::<u8>::try_from(input)?;
+                let (public, key) = negotiate_dh(&client_public)?;
+                (
+                    OwnedValue::try_from(Value::from(public))?,
+                    SessionKey::Aes128(key),
+                )
+            }
+            _ => {
+                return Err(zbus::fdo::Error::NotSupported(format!(
+                    "Unsupported algorithm {algorithm}"
+                ))
+                .into())
+            }
+        };
+
+        self.provider.refresh(object_server).await?;
+        let path = self.provider.next_path(SESSION_PREFIX);
+        let owner = sender_of(&header);
+        self.provider
+            .sessions
+            .lock()
+            .unwrap()
+            .insert(path.clone(), OpenSession { owner, key });
+        object_server
+            .at(
+                &path,
+                SessionObject {
+                    provider: self.provider.clone(),
+                },
+            )
+            .await?;
+        Ok((output, path))
+    }
+
+    // Collections are folders, creating one goes through a prompt like in other providers
+    async fn create_collection(
+        &self,
+        properties: HashMap<String, OwnedValue>,
+        alias: String,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(object_server)] object_server: &zbus::ObjectServer,
+    ) -> Result<(OwnedObjectPath, OwnedObjectPath), SecretError> {
+        if let Some(folder_id) = self.provider.aliases.lock().unwrap().get(&alias) {
+            return Ok((collection_path(*folder_id), object_path("/".to_string())));
+        }
+
+        let label: String = property(&properties, COLLECTION_LABEL_PROPERTY)
+            .unwrap_or_else(|| "Secret Service".to_string());
+        let action = PromptAction::CreateCollection { label, alias };
+        let prompt = self.provider.prompt(object_server, &header, action).await?;
+        Ok((object_path("/".to_string()), prompt))
+    }
+
+    // Items are listed as locked to clients the user did not let in yet
+    #[zbus(out_args("unlocked", "locked"))]
+    async fn search_items(
+        &self,
+        attributes: HashMap<String, String>,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(object_server)] object_server: &zbus::ObjectServer,
+    ) -> Result<(Vec<OwnedObjectPath>, Vec<OwnedObjectPath>), SecretError> {
+        self.provider.refresh(object_server).await?;
+        let found = self.provider.search(None, &attributes);
+        Ok(match self.provider.is_allowed(Some(&header)) {
+            true => (found, Vec::new()),
+            false => (Vec::new(), found),
+        })
+    }
+
+    // The vault itself is unlocked while the provider runs, unlocking asks the user to let the
+    // client in
+    #[zbus(out_args("unlocked", "prompt"))]
+    async fn unlock(
+        &self,
+        objects: Vec<OwnedObjectPath>,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(object_server)] object_server: &zbus::ObjectServer,
+    ) -> Result<(Vec<OwnedObjectPath>, OwnedObjectPath), SecretError> {
+        if self.provider.is_allowed(Some(&header)) {
+            return Ok((objects, object_path("/".to_string())));
+        }
+        let action = PromptAction::Unlock { objects };
+        let prompt = self.provider.prompt(object_server, &header, action).await?;
+        Ok((Vec::new(), prompt))
+    }
+
+    // Locking the vault is done from Keylight itself, a client can only give up its own access
+    #[zbus(out_args("locked", "Prompt"))]
+    fn lock(
+        &self,
+        objects: Vec<OwnedObjectPath>,
+        #[zbus(header)] header: Header<'_>,
+    ) -> (Vec<OwnedObjectPath>, OwnedObjectPath) {
+        self.provider
+            .allowed_clients
+            .lock()
+            .unwrap()
+            .remove(&sender_of(&header));
+        (objects, object_path("/".to_string()))
+    }
+
+    async fn get_secrets(
+        &self,
+        items: Vec<OwnedObjectPath>,
+        session: ObjectPath<'_>,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(object_server)] object_server: &zbus::ObjectServer,
+    ) -> Result<HashMap<OwnedObjectPath, Secret>, SecretError> {
+        self.provider.check_allowed(Some(&header))?;
+        self.provider.refresh(object_server).await?;
+        let mut secrets = HashMap::new();
+        for item in items {
+            // Unknown items are skipped as the specification asks
+            let Ok((_, entry_id)) = self.provider.item(&item) else {
+                continue;
+            };
+            let value = self.provider.read_secret(entry_id).await?;
+            secrets.insert(item, self.provider.encrypt_for(&session, &header, &value)?);
+        }
+        Ok(secrets)
+    }
+
+    fn read_alias(&self, name: &str) -> OwnedObjectPath {
+        match self.provider.aliases.lock().unwrap().get(name) {
+            Some(folder_id) => collection_path(*folder_id),
+            None => object_path("/".to_string()),
+        }
+    }
+
+    async fn set_alias(
+        &self,
+        name: String,
+        collection: ObjectPath<'_>,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(object_server)] object_server: &zbus::ObjectServer,
+    ) -> Result<(), SecretError> {
+        self.provider.check_allowed(Some(&header))?;
+        let alias = alias_path(&name);
+        object_server.remove::<CollectionObject, _>(&alias).await?;
+
+        if collection.as_str() == "/" {
+            self.provider.aliases.lock().unwrap().remove(&name);
+            return Ok(());
+        }
+
+        let folder_id = collection
+            .as_str()
+            .strip_prefix(COLLECTION_PREFIX)
+            .and_then(|folder| folder.strip_prefix("/f"))
+            .and_then(|folder_id| folder_id.parse().ok())
+            .filter(|folder_id| {
+                self.provider
+                    .folders
+                    .lock()
+                    .unwrap()
+                    .contains_key(folder_id)
+            })
+            .ok_or_else(|| SecretError::NoSuchObject(collection.to_string()))?;
+
+        self.provider
+            .aliases
+            .lock()
+            .unwrap()
+            .insert(name, folder_id);
+        object_server
+            .at(
+                &alias,
+                CollectionObject {
+                    provider: self.provider.clone(),
+                    folder_id,
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn collections(&self) -> Vec<OwnedObjectPath> {
+        self.provider.collection_paths()
+    }
+
+    #[zbus(signal)]
+    async fn collection_created(
+        emitter: &SignalEmitter<'_>,
+        collection: ObjectPath<'_>,
+    ) -> zbus::Result<()>;
+}
+
+struct CollectionObject {
+    provider: Arc<Provider>,
+    folder_id: i64,
+}
+
+#[zbus::interface(name = "org.freedesktop.Secret.Collection")]
+impl CollectionObject {
+    // Removing a folder would take every Keylight entry in it along, that is left to Keylight itself
+    fn delete(&self) -> Result<OwnedObjectPath, SecretError> {
+        Err(zbus::fdo::Error::AccessDenied(
+            "Keylight folders can only be deleted from Keylight".to_string(),
+        )
+        .into())
+    }
+
+    async fn search_items(
+        &self,
+        attributes: HashMap<String, String>,
+        #[zbus(object_server)] object_server: &zbus::ObjectServer,
+    ) -> Result<Vec<OwnedObjectPath>, SecretError> {
+        self.provider.refresh(object_server).await?;
+        Ok(self.provider.search(Some(self.folder_id), &attributes))
+    }
+
+    #[zbus(out_args("item", "prompt"))]
+    async fn create_item(
+        &self,
+        properties: HashMap<String, OwnedValue>,
+        secret: Secret,
+        replace: bool,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(object_server)] object_server: &zbus::ObjectServer,
+        #[zbus(connection)] connection: &zbus::Connection,
+    ) -> Result<(OwnedObjectPath, OwnedObjectPath), SecretError> {
+        self.provider.check_allowed(Some(&header))?;
+        self.provider.refresh(object_server).await?;
+        let value = self.provider.decrypt(&secret, &header)?;
+        let label: String = property(&properties, LABEL_PROPERTY).unwrap_or_default();
+        let attributes: HashMap<String, String> =
+            property(&properties, ATTRIBUTES_PROPERTY).unwrap_or_default();
+        let collection = collection_path(self.folder_id);
+
+        let existing = if replace {
+            self.provider
+                .items
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(_, item)| item.folder_id == self.folder_id && item.attributes == attributes)
+                .map(|(entry_id, _)| *entry_id)
+        } else {
+            None
+        };
+
+        if let Some(entry_id) = existing {
+            self.provider.write_secret(entry_id, value).await?;
+            self.provider
+                .state
+                .clone()
+                .rename_entry(self.provider.vault_pool.clone(), entry_id, label.clone())
+                .await?;
+            if let Some(item) = self.provider.items.lock().unwrap().get_mut(&entry_id) {
+                item.label = label;
+            }
+
+            let path = item_path(self.folder_id, entry_id);
+            Self::item_changed(&SignalEmitter::new(connection, &collection)?, path.as_ref())
+                .await?;
+            return Ok((path, object_path("/".to_string())));
+        }
+
+        let entry_id = self
+            .provider
+            .state
+            .clone()
+            .create_entry(
+                self.provider.vault_pool.clone(),
+                self.folder_id,
+                label.clone(),
+            )
+            .await?;
+        self.provider.write_secret(entry_id, value).await?;
+        self.provider
+            .write_attributes(entry_id, &attributes)
+            .await?;
+        let now = unix_now().max(0) as u64;
+        self.provider.items.lock().unwrap().insert(
+            entry_id,
+            ItemInfo {
+                folder_id: self.folder_id,
+                label,
+                attributes,
+                created: now,
+                modified: now,
+            },
+        );
+
+        let path = item_path(self.folder_id, entry_id);
+        object_server
+            .at(
+                &path,
+                ItemObject {
+                    provider: self.provider.clone(),
+                    folder_id: self.folder_id,
+                    entry_id,
+                },
+            )
+            .await?;
+        Self::item_created(&SignalEmitter::new(connection, &collection)?, path.as_ref()).await?;
+        println!("[INFO]: Stored a secret for a Secret Service client");
+        Ok((path, object_path("/".to_string())))
+    }
+
+    #[zbus(property)]
+    fn items(&self) -> Vec<OwnedObjectPath> {
+        self.provider.search(Some(self.folder_id), &HashMap::new())
+    }
+
+    #[zbus(property)]
+    fn label(&self) -> String {
+        self.provider
+            .folders
+            .lock()
+            .unwrap()
+            .get(&self.folder_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    #[zbus(property)]
+    async fn set_label(
+        &mut self,
+        label: String,
+        #[zbus(header)] header: Option<Header<'_>>,
+    ) -> zbus::fdo::Result<()> {
+        self.provider
+            .check_allowed(header.as_ref())
+            .map_err(|_| zbus::fdo::Error::AccessDenied("The vault is locked".to_string()))?;
+        self.provider
+            .state
+            .clone()
+            .rename_folder(
+                self.provider.vault_pool.clone(),
+                self.folder_id,
+                label.clone(),
+            )
+            .await
+            .map_err(|error| zbus::fdo::Error::Failed(error.to_string()))?;
+        self.provider
+            .folders
+            .lock()
+            .unwrap()
+            .insert(self.folder_id, label);
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn locked(&self, #[zbus(header)] header: Option<Header<'_>>) -> bool {
+        !self.provider.is_allowed(header.as_ref())
+    }
+
+    // Empty collections have no time to go by
+    #[zbus(property)]
+    fn created(&self) -> u64 {
+        self.provider
+            .items
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|item| item.folder_id == self.folder_id)
+            .map(|item| item.created)
+            .min()
+            .unwrap_or_default()
+    }
+
+    #[zbus(property)]
+    fn modified(&self) -> u64 {
+        self.provider
+            .items
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|item| item.folder_id == self.folder_id)
+            .map(|item| item.modified)
+            .max()
+            .unwrap_or_default()
+    }
+
+    #[zbus(signal)]
+    async fn item_created(emitter: &SignalEmitter<'_>, item: ObjectPath<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn item_deleted(emitter: &SignalEmitter<'_>, item: ObjectPath<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn item_changed(emitter: &SignalEmitter<'_>, item: ObjectPath<'_>) -> zbus::Result<()>;
+}
+
+struct ItemObject {
+    provider: Arc<Provider>,
+    folder_id: i64,
+    entry_id: i64,
+}
+
+#[zbus::interface(name = "org.freedesktop.Secret.Item")]
+impl ItemObject {
+    async fn delete(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(object_server)] object_server: &zbus::ObjectServer,
+    ) -> Result<OwnedObjectPath, SecretError> {
+        let action = PromptAction::DeleteItem {
+            entry_id: self.entry_id,
+        };
+        self.provider.prompt(object_server, &header, action).await
+    }
+
+    async fn get_secret(
+        &self,
+        session: ObjectPath<'_>,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<Secret, SecretError> {
+        self.provider.check_allowed(Some(&header))?;
+        let value = self.provider.read_secret(self.entry_id).await?;
+        self.provider.encrypt_for(&session, &header, &value)
+    }
+
+    async fn set_secret(
+        &self,
+        secret: Secret,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+    ) -> Result<(), SecretError> {
+        self.provider.check_allowed(Some(&header))?;
+        let value = self.provider.decrypt(&secret, &header)?;
+        self.provider.write_secret(self.entry_id, value).await?;
+        self.changed(connection).await
+    }
+
+    #[zbus(property)]
+    fn locked(&self, #[zbus(header)] header: Option<Header<'_>>) -> bool {
+        !self.provider.is_allowed(header.as_ref())
+    }
+
+    #[zbus(property)]
+    fn attributes(&self) -> HashMap<String, String> {
+        self.provider
+            .items
+            .lock()
+            .unwrap()
+            .get(&self.entry_id)
+            .map(|item| item.attributes.clone())
+            .unwrap_or_default()
+    }
+
+    #[zbus(property)]
+    async fn set_attributes(
+        &mut self,
+        attributes: HashMap<String, String>,
+        #[zbus(header)] header: Option<Header<'_>>,
+    ) -> zbus::fdo::Result<()> {
+        self.provider
+            .check_allowed(header.as_ref())
+            .map_err(|_| zbus::fdo::Error::AccessDenied("The vault is locked".to_string()))?;
+        self.provider
+            .write_attributes(self.entry_id, &attributes)
+            .await
+            .map_err(|error| zbus::fdo::Error::Failed(format!("{error:?}")))?;
+        if let Some(item) = self.provider.items.lock().unwrap().get_mut(&self.entry_id) {
+            item.attributes = attributes;
+        }
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn label(&self) -> String {
+        self.provider
+            .items
+            .lock()
+            .unwrap()
+            .get(&self.entry_id)
+            .map(|item| item.label.clone())
+            .unwrap_or_default()
+    }
+
+    #[zbus(property)]
+    async fn set_label(
+        &mut self,
+        label: String,
+        #[zbus(header)] header: Option<Header<'_>>,
+    ) -> zbus::fdo::Result<()> {
+        self.provider
+            .check_allowed(header.as_ref())
+            .map_err(|_| zbus::fdo::Error::AccessDenied("The vault is locked".to_string()))?;
+        self.provider
+            .state
+            .clone()
+            .rename_entry(
+                self.provider.vault_pool.clone(),
+                self.entry_id,
+                label.clone(),
+            )
+            .await
+            .map_err(|error| zbus::fdo::Error::Failed(error.to_string()))?;
+        if let Some(item) = self.provider.items.lock().unwrap().get_mut(&self.entry_id) {
+            item.label = label;
+        }
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn created(&self) -> u64 {
+        self.provider
+            .items
+            .lock()
+            .unwrap()
+            .get(&self.entry_id)
+            .map_or(0, |item| item.created)
+    }
+
+    #[zbus(property)]
+    fn modified(&self) -> u64 {
+        self.provider
+            .items
+            .lock()
+            .unwrap()
+            .get(&self.entry_id)
+            .map_or(0, |item| item.modified)
+    }
+}
+
+impl ItemObject {
+    async fn changed(&self, connection: &zbus::Connection) -> Result<(), SecretError> {
+        let collection = collection_path(self.folder_id);
+        let path = item_path(self.folder_id, self.entry_id);
+        CollectionObject::item_changed(
+            &SignalEmitter::new(connection, &collection)?,
+            path.as_ref(),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+struct SessionObject {
+    provider: Arc<Provider>,
+}
+
+#[zbus::interface(name = "org.freedesktop.Secret.Session")]
+impl SessionObject {
+    async fn close(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+    ) -> Result<(), SecretError> {
+        let Some(path) = header
+            .path()
+            .map(|path| OwnedObjectPath::from(path.to_owned()))
+        else {
+            return Ok(());
+        };
+        {
+            let mut sessions = self.provider.sessions.lock().unwrap();
+            Provider::check_session(&sessions, &path, &header)?;
+            sessions.remove(&path);
+        }
+
+        // The object cannot remove itself while its own method call is still being handled
+        let connection = connection.clone();
+        tokio::spawn(async move {
+            let _ = connection
+                .object_server()
+                .remove::<SessionObject, _>(&path)
+                .await;
+        });
+        Ok(())
+    }
+}
+
+struct PromptObject {
+    provider: Arc<Provider>,
+    client: String,
+    action: Mutex<Option<PromptAction>>,
+    // Set by whichever of the answer and Dismiss comes first, only that one completes the prompt
+    completed: Arc<AtomicBool>,
+}
+
+impl PromptAction {
+    fn question(&self, provider: &Provider, client: &str) -> String {
+        match self {
+            PromptAction::Unlock { .. } => {
+                format!("{client} wants to read and change passwords of the vault")
+            }
+            PromptAction::CreateCollection { label, .. } => {
+                format!("{client} wants to create the folder \"{label}\"")
+            }
+            PromptAction::DeleteItem { entry_id } => {
+                let label = provider
+                    .items
+                    .lock()
+                    .unwrap()
+                    .get(entry_id)
+                    .map(|item| item.label.clone())
+                    .unwrap_or_default();
+                format!("{client} wants to delete \"{label}\"")
+            }
+        }
+    }
+
+    async fn run(
+        self,
+        provider: &Arc<Provider>,
+        client: String,
+        connection: &zbus::Connection,
+    ) -> Result<Value<'static>, SecretError> {
+        Ok(match self {
+            PromptAction::Unlock { objects } => {
+                provider.allowed_clients.lock().unwrap().insert(client);
+                Value::from(objects)
+            }
+            PromptAction::CreateCollection { label, alias } => {
+                let folder_id = provider
+                    .state
+                    .clone()
+                    .create_folder(provider.vault_pool.clone(), label.clone())
+                    .await?;
+                provider.folders.lock().unwrap().insert(folder_id, label);
+
+                let path = collection_path(folder_id);
+                let object_server = connection.object_server();
+                object_server
+                    .at(
+                        &path,
+                        CollectionObject {
+                            provider: provider.clone(),
+                            folder_id,
+                        },
+                    )
+                    .await?;
+                if !alias.is_empty() {
+                    provider
+                        .aliases
+                        .lock()
+                        .unwrap()
+                        .insert(alias.clone(), folder_id);
+                    object_server
+                        .at(
+                            alias_path(&alias),
+                            CollectionObject {
+                                provider: provider.clone(),
+                                folder_id,
+                            },
+                        )
+                        .await?;
+                }
+                ServiceObject::collection_created(
+                    &SignalEmitter::new(connection, SERVICE_PATH)?,
+                    path.as_ref(),
+                )
+                .await?;
+                Value::from(path)
+            }
+            PromptAction::DeleteItem { entry_id } => {
+                provider
+                    .state
+                    .clone()
+                    .delete_entry(provider.vault_pool.clone(), entry_id)
+                    .await?;
+                let removed = provider.items.lock().unwrap().remove(&entry_id);
+                if let Some(item) = removed {
+                    let path = item_path(item.folder_id, entry_id);
+                    connection
+                        .object_server()
+                        .remove::<ItemObject, _>(&path)
+                        .await?;
+                    CollectionObject::item_deleted(
+                        &SignalEmitter::new(connection, collection_path(item.folder_id))?,
+                        path.as_ref(),
+                    )
+                    .await?;
+                }
+                Value::from("")
+            }
+        })
+    }
+}
+
+#[zbus::interface(name = "org.freedesktop.Secret.Prompt")]
+impl PromptObject {
+    // Returns right away, the client waits for Completed while the user makes up their mind
+    async fn prompt(
+        &self,
+        _window_id: &str,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+    ) -> Result<(), SecretError> {
+        let Some(action) = self.action.lock().unwrap().take() else {
+            return Ok(());
+        };
+
+        let provider = self.provider.clone();
+        let client = self.client.clone();
+        let completed = self.completed.clone();
+        let connection = connection.clone();
+        let path: OwnedObjectPath = emitter.path().to_owned().into();
+        tokio::spawn(async move {
+            let client_name = client_name(&connection, &client).await;
+            let allowed = provider
+                .confirm(action.question(&provider, &client_name))
+                .await;
+            if completed.swap(true, Ordering::SeqCst) {
+                return;
+            }
+
+            let result = match allowed {
+                true => action.run(&provider, client, &connection).await.map(Some),
+                false => {
+                    println!("[INFO]: A Secret Service prompt of {client_name} was denied");
+                    Ok(None)
+                }
+            };
+            let (dismissed, result) = match result {
+                Ok(Some(result)) => (false, result),
+                Ok(None) => (true, Value::from("")),
+                Err(error) => {
+                    println!("[ERROR]: {:?}", error);
+                    (true, Value::from(""))
+                }
+            };
+            if let Ok(emitter) = SignalEmitter::new(&connection, path.clone()) {
+                let _ = Self::completed(&emitter, dismissed, result).await;
+            }
+            Self::finish(path, &connection);
+        });
+        Ok(())
+    }
+
+    async fn dismiss(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+    ) -> Result<(), SecretError> {
+        self.action.lock().unwrap().take();
+        if !self.completed.swap(true, Ordering::SeqCst) {
+            Self::completed(&emitter, true, Value::from("")).await?;
+        }
+        Self::finish(emitter.path().to_owned().into(), connection);
+        Ok(())
+    }
+
+    #[zbus(signal)]
+    async fn completed(
+        emitter: &SignalEmitter<'_>,
+        dismissed: bool,
+        result: Value<'_>,
+    ) -> zbus::Result<()>;
+}
+
+impl PromptObject {
+    // Prompts are single use and disappear once completed or dismissed
+    fn finish(path: OwnedObjectPath, connection: &zbus::Connection) {
+        let connection = connection.clone();
+        tokio::spawn(async move {
+            let _ = connection
+                .object_server()
+                .remove::<PromptObject, _>(&path)
+                .await;
+        });
+    }
+}
+
+// Unique names such as ":1.42" lose their owner when the client's connection closes
+async fn watch_departures(
+    mut departures: zbus::fdo::NameOwnerChangedStream,
+    provider: Weak<Provider>,
+) {
+    while let Some(signal) = departures.next().await {
+        let Ok(args) = signal.args() else {
+            continue;
+        };
+        if args.new_owner().is_some() || !args.name().starts_with(':') {
+            continue;
+        }
+        match provider.upgrade() {
+            Some(provider) => provider.client_left(args.name()),
+            None => break,
+        }
+    }
+}
+
+// Exports the unlocked vault on the session bus (or the bus at the given address, e.g. a private
+// test bus) and claims org.freedesktop.secrets. The service stops when the connection is dropped.
+// Prompts are sent to the confirmation channel, without one they go through $SSH_ASKPASS
+pub async fn serve_secret_service(
+    state: Arc<ApplicationAsyncState>,
+    vault_pool: SqlitePool,
+    bus_address: Option<String>,
+    confirmations: Option<mpsc::Sender<SecretServiceConfirmation>>,
+) -> Result<zbus::Connection, SecretServiceError> {
+    let index = load_index(&vault_pool).await?;
+    let provider = Arc::new(Provider {
+        state,
+        vault_pool,
+        sessions: Mutex::new(HashMap::new()),
+        departed_sessions: Mutex::new(Vec::new()),
+        folders: Mutex::new(BTreeMap::new()),
+        items: Mutex::new(HashMap::new()),
+        aliases: Mutex::new(HashMap::new()),
+        allowed_clients: Mutex::new(HashSet::new()),
+        confirmations,
+        next_object_id: AtomicU64::new(1),
+        departures: OnceLock::new(),
+    });
+
+    let builder = || match &bus_address {
+        Some(address) => zbus::connection::Builder::address(address.as_str()),
+        None => zbus::connection::Builder::session(),
+    };
+    // Watched from a connection of its own, a signal stream would keep the service's connection
+    // and with it the service alive after it was dropped
+    let watcher = builder()?.build().await?;
+    let departures = zbus::fdo::DBusProxy::new(&watcher)
+        .await?
+        .receive_name_owner_changed()
+        .await?;
+    let watch = tokio::spawn(watch_departures(departures, Arc::downgrade(&provider)));
+    let _ = provider.departures.set(watch.abort_handle());
+
+    let connection = builder()?
+        .serve_at(
+            SERVICE_PATH,
+            ServiceObject {
+                provider: provider.clone(),
+            },
+        )?
+        .build()
+        .await?;
+
+    // Everything is exported before the name is claimed, so no client sees a half filled service
+    provider.export(index, connection.object_server()).await?;
+    connection.request_name(SECRET_SERVICE_NAME).await?;
+    println!("[INFO]: Providing the Secret Service as {SECRET_SERVICE_NAME}");
+    Ok(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{PrivateBus, ScratchVault};
+
+    const ITEM_PROPERTY_INTERFACE: &str = "org.freedesktop.Secret.Item";
+
+    // What a client such as libsecret does: the dh-ietf1024 exchange, then AES-128-CBC
+    struct ClientSession {
+        path: OwnedObjectPath,
+        key: Zeroizing<[u8; 16]>,
+    }
+
+    impl ClientSession {
+        async fn open(service: &zbus::Proxy<'_>) -> Self {
+            let prime = BigUint::parse_bytes(MODP_1024_PRIME, 16).unwrap();
+            let mut private_bytes = [0u8; 128];
+            OsRng.fill_bytes(&mut private_bytes);
+            let private = BigUint::from_bytes_be(&private_bytes);
+            let public = BigUint::from(2u32).modpow(&private, &prime);
+
+            let (output, path): (OwnedValue, OwnedObjectPath) = service
+                .call(
+                    "OpenSession",
+                    &(DH_ALGORITHM, Value::from(public.to_bytes_be())),
+                )
+                .await
+                .unwrap();
+            let server_public = BigUint::from_bytes_be(&Vec::<u8>::try_from(output).unwrap());
+            let shared = server_public.modpow(&private, &prime).to_bytes_be();
+            let mut shared_bytes = [0u8; 128];
+            shared_bytes[128 - shared.len()..].copy_from_slice(&shared);
+            let mut key = Zeroizing::new([0u8; 16]);
+            Hkdf::<sha2::Sha256>::new(None, &shared_bytes)
+                .expand(&[], key.as_mut())
+                .unwrap();
+            Self { path, key }
+        }
+
+        fn encrypt(&self, value: &[u8]) -> Secret {
+            SessionKey::Aes128(self.key.clone()).encrypt(self.path.clone(), value)
+        }
+
+        fn decrypt(&self, secret: &Secret) -> Vec<u8> {
+            SessionKey::Aes128(self.key.clone())
+                .decrypt(secret)
+                .unwrap()
+                .to_vec()
+        }
+    }
+
+    async fn proxy<'a>(
+        client: &zbus::Connection,
+        path: impl TryInto<ObjectPath<'a>, Error = zbus::zvariant::Error>,
+        interface: &'a str,
+    ) -> zbus::Proxy<'a> {
+        zbus::Proxy::new(client, SECRET_SERVICE_NAME, path, interface)
+            .await
+            .unwrap()
+    }
+
+    fn assert_error<T>(result: zbus::Result<T>, expected: &str) {
+        match result {
+            Err(zbus::Error::MethodError(name, _, _)) => assert_eq!(name.as_str(), expected),
+            Err(error) => panic!("expected {expected}, got {error:?}"),
+            Ok(_) => panic!("expected {expected}, the call succeeded"),
+        }
+    }
+
+    fn assert_locked_error<T>(result: zbus::Result<T>) {
+        assert_error(result, "org.freedesktop.Secret.Error.IsLocked");
+    }
+
+    fn assert_no_session<T>(result: zbus::Result<T>) {
+        assert_error(result, "org.freedesktop.Secret.Error.NoSession");
+    }
+
+    async fn connect(address: &str) -> zbus::Connection {
+        zbus::connection::Builder::address(address)
+            .unwrap()
+            .build()
+            .await
+            .unwrap()
+    }
+
+    // Lets the client in through the prompt Unlock raises
+    async fn let_in(
+        client: &zbus::Connection,
+        service: &zbus::Proxy<'_>,
+        collection: &OwnedObjectPath,
+        requests: &mut mpsc::Receiver<SecretServiceConfirmation>,
+    ) {
+        let (_, prompt): (Vec<OwnedObjectPath>, OwnedObjectPath) = service
+            .call("Unlock", &(vec![collection.clone()],))
+            .await
+            .unwrap();
+        let (dismissed, _, _) = answer_prompt(client, &prompt, requests, true).await;
+        assert!(!dismissed);
+    }
+
+    // Calls Prompt, answers the question it raises and returns what Completed reported
+    async fn answer_prompt(
+        client: &zbus::Connection,
+        prompt: &OwnedObjectPath,
+        requests: &mut mpsc::Receiver<SecretServiceConfirmation>,
+        allowed: bool,
+    ) -> (bool, OwnedValue, String) {
+        let prompt = proxy(client, prompt.as_str(), "org.freedesktop.Secret.Prompt").await;
+        let mut completed = prompt.receive_signal("Completed").await.unwrap();
+        let () = prompt.call("Prompt", &("",)).await.unwrap();
+        let confirmation = requests.recv().await.unwrap();
+        confirmation.answer(allowed);
+        let signal = completed.next().await.unwrap();
+        let (dismissed, result): (bool, OwnedValue) = signal.body().deserialize().unwrap();
+        (dismissed, result, confirmation.question)
+    }
+
+    fn item_properties(label: &str, attributes: &[(&str, &str)]) -> HashMap<String, OwnedValue> {
+        let attributes: HashMap<String, String> = attributes
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        HashMap::from([
+            (
+                LABEL_PROPERTY.to_string(),
+                OwnedValue::try_from(Value::from(label)).unwrap(),
+            ),
+            (
+                ATTRIBUTES_PROPERTY.to_string(),
+                OwnedValue::try_from(Value::from(attributes)).unwrap(),
+            ),
+        ])
+    }
+
+    #[tokio::test]
+    async fn clients_need_consent_and_see_entries_made_in_keylight() {
+        let Some(bus) = PrivateBus::start() else {
+            println!("[INFO]: dbus-daemon is not installed, skipping the Secret Service test");
+            return;
+        };
+        let address = bus.address.clone();
+        let vault = ScratchVault::new("secret-service").await;
+        let (state, vault_pool) = (vault.state.clone(), vault.vault_pool.clone());
+        let (confirmations, mut requests) = mpsc::channel(10);
+        let _service = serve_secret_service(
+            state.clone(),
+            vault_pool.clone(),
+            Some(address.clone()),
+            Some(confirmations),
+        )
+        .await
+        .unwrap();
+
+        let client = zbus::connection::Builder::address(address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let service = proxy(&client, SERVICE_PATH, "org.freedesktop.Secret.Service").await;
+        let session = ClientSession::open(&service).await;
+        let collection: OwnedObjectPath = service.call("ReadAlias", &("default",)).await.unwrap();
+        let collection_proxy = proxy(
+            &client,
+            collection.as_str(),
+            "org.freedesktop.Secret.Collection",
+        )
+        .await;
+
+        // Nothing can be stored before the user let the client in
+        let properties = item_properties("Mail", &[("service", "imap"), ("user", "ann")]);
+        let stored: zbus::Result<(OwnedObjectPath, OwnedObjectPath)> = collection_proxy
+            .call(
+                "CreateItem",
+                &(&properties, session.encrypt(b"hunter2"), false),
+            )
+            .await;
+        assert_locked_error(stored);
+
+        let (unlocked, prompt): (Vec<OwnedObjectPath>, OwnedObjectPath) = service
+            .call("Unlock", &(vec![collection.clone()],))
+            .await
+            .unwrap();
+        assert!(unlocked.is_empty());
+        let (dismissed, result, question) =
+            answer_prompt(&client, &prompt, &mut requests, true).await;
+        assert!(!dismissed);
+        assert!(question.contains("wants to read and change passwords"));
+        assert_eq!(
+            Vec::<OwnedObjectPath>::try_from(result).unwrap(),
+            vec![collection.clone()]
+        );
+
+        let (item, prompt): (OwnedObjectPath, OwnedObjectPath) = collection_proxy
+            .call(
+                "CreateItem",
+                &(&properties, session.encrypt(b"hunter2"), false),
+            )
+            .await
+            .unwrap();
+        assert_eq!(prompt.as_str(), "/");
+
+        let attributes = HashMap::from([("service", "imap")]);
+        let (unlocked, locked): (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) =
+            service.call("SearchItems", &(&attributes,)).await.unwrap();
+        assert_eq!(unlocked, vec![item.clone()]);
+        assert!(locked.is_empty());
+
+        let secrets: HashMap<OwnedObjectPath, Secret> = service
+            .call("GetSecrets", &(vec![item.clone()], &session.path))
+            .await
+            .unwrap();
+        assert_eq!(session.decrypt(&secrets[&item]), b"hunter2");
+        let item_proxy = proxy(&client, item.as_str(), ITEM_PROPERTY_INTERFACE).await;
+        assert!(item_proxy.get_property::<u64>("Created").await.unwrap() > 0);
+        assert!(!item_proxy.get_property::<bool>("Locked").await.unwrap());
+
+        // An entry saved in Keylight meanwhile is found by the next search
+        let folder_id = vault.folder_id().await;
+        let entry_id = vault
+            .entry("Calendar", &[("password", "password", b"correct horse")])
+            .await;
+        state
+            .clone()
+            .set_entry_tags(
+                vault_pool.clone(),
+                entry_id,
+                vec![format!("{ATTRIBUTE_TAG_PREFIX}service=caldav")],
+            )
+            .await
+            .unwrap();
+        let attributes = HashMap::from([("service", "caldav")]);
+        let (unlocked, _): (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) =
+            service.call("SearchItems", &(&attributes,)).await.unwrap();
+        assert_eq!(unlocked, vec![item_path(folder_id, entry_id)]);
+        let secrets: HashMap<OwnedObjectPath, Secret> = service
+            .call("GetSecrets", &(unlocked.clone(), &session.path))
+            .await
+            .unwrap();
+        assert_eq!(session.decrypt(&secrets[&unlocked[0]]), b"correct horse");
+
+        // Another client is still a stranger, and stays one when the user says no
+        let stranger = zbus::connection::Builder::address(address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let stranger_service =
+            proxy(&stranger, SERVICE_PATH, "org.freedesktop.Secret.Service").await;
+        let stranger_session = ClientSession::open(&stranger_service).await;
+        let attributes = HashMap::from([("service", "imap")]);
+        let (unlocked, locked): (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) = stranger_service
+            .call("SearchItems", &(&attributes,))
+            .await
+            .unwrap();
+        assert!(unlocked.is_empty());
+        assert_eq!(locked, vec![item.clone()]);
+        let secrets: zbus::Result<HashMap<OwnedObjectPath, Secret>> = stranger_service
+            .call("GetSecrets", &(vec![item.clone()], &stranger_session.path))
+            .await;
+        assert_locked_error(secrets);
+
+        let (_, prompt): (Vec<OwnedObjectPath>, OwnedObjectPath) = stranger_service
+            .call("Unlock", &(vec![item.clone()],))
+            .await
+            .unwrap();
+        let (dismissed, _, _) = answer_prompt(&stranger, &prompt, &mut requests, false).await;
+        assert!(dismissed);
+        let secrets: zbus::Result<HashMap<OwnedObjectPath, Secret>> = stranger_service
+            .call("GetSecrets", &(vec![item.clone()], &stranger_session.path))
+            .await;
+        assert_locked_error(secrets);
+
+        // Deleting asks every time, even a client that was let in
+        let item_proxy = proxy(&client, item.as_str(), ITEM_PROPERTY_INTERFACE).await;
+        let prompt: OwnedObjectPath = item_proxy.call("Delete", &()).await.unwrap();
+        let (dismissed, _, question) = answer_prompt(&client, &prompt, &mut requests, true).await;
+        assert!(!dismissed);
+        assert!(question.contains("wants to delete \"Mail\""));
+        let attributes = HashMap::from([("service", "imap")]);
+        let (unlocked, locked): (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) =
+            service.call("SearchItems", &(&attributes,)).await.unwrap();
+        assert!(unlocked.is_empty() && locked.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sessions_belong_to_the_client_that_opened_them() {
+        let Some(bus) = PrivateBus::start() else {
+            println!("[INFO]: dbus-daemon is not installed, skipping the Secret Service test");
+            return;
+        };
+        let address = bus.address.clone();
+        let vault = ScratchVault::new("secret-service-sessions").await;
+        let (confirmations, mut requests) = mpsc::channel(10);
+        let service_connection = serve_secret_service(
+            vault.state.clone(),
+            vault.vault_pool.clone(),
+            Some(address.clone()),
+            Some(confirmations),
+        )
+        .await
+        .unwrap();
+
+        // Both clients are let in, only the sessions differ
+        let owner = connect(&address).await;
+        let owner_service = proxy(&owner, SERVICE_PATH, "org.freedesktop.Secret.Service").await;
+        let collection: OwnedObjectPath = owner_service
+            .call("ReadAlias", &("default",))
+            .await
+            .unwrap();
+        let_in(&owner, &owner_service, &collection, &mut requests).await;
+        let session = ClientSession::open(&owner_service).await;
+        let other = connect(&address).await;
+        let other_service = proxy(&other, SERVICE_PATH, "org.freedesktop.Secret.Service").await;
+        let_in(&other, &other_service, &collection, &mut requests).await;
+
+        let owner_collection = proxy(
+            &owner,
+            collection.as_str(),
+            "org.freedesktop.Secret.Collection",
+        )
+        .await;
+        let properties = item_properties("Mail", &[("service", "imap")]);
+        let (item, _): (OwnedObjectPath, OwnedObjectPath) = owner_collection
+            .call(
+                "CreateItem",
+                &(&properties, session.encrypt(b"hunter2"), false),
+            )
+            .await
+            .unwrap();
+
+        // The other client can neither read through the session, store through it nor close it
+        let secrets: zbus::Result<HashMap<OwnedObjectPath, Secret>> = other_service
+            .call("GetSecrets", &(vec![item.clone()], &session.path))
+            .await;
+        assert_no_session(secrets);
+        let other_collection = proxy(
+            &other,
+            collection.as_str(),
+            "org.freedesktop.Secret.Collection",
+        )
+        .await;
+        let properties = item_properties("Bank", &[("service", "bank")]);
+        let stored: zbus::Result<(OwnedObjectPath, OwnedObjectPath)> = other_collection
+            .call(
+                "CreateItem",
+                &(&properties, session.encrypt(b"stolen"), false),
+            )
+            .await;
+        assert_no_session(stored);
+        let other_item = proxy(&other, item.as_str(), ITEM_PROPERTY_INTERFACE).await;
+        let secret: zbus::Result<Secret> = other_item.call("GetSecret", &(&session.path,)).await;
+        assert_no_session(secret);
+        let other_session = proxy(
+            &other,
+            session.path.as_str(),
+            "org.freedesktop.Secret.Session",
+        )
+        .await;
+        assert_no_session(other_session.call::<_, _, ()>("Close", &()).await);
+
+        let secrets: HashMap<OwnedObjectPath, Secret> = owner_service
+            .call("GetSecrets", &(vec![item.clone()], &session.path))
+            .await
+            .unwrap();
+        assert_eq!(session.decrypt(&secrets[&item]), b"hunter2");
+
+        // Once the owner leaves the bus its session goes, with the next refresh also from the bus
+        drop((owner_collection, owner_service, owner));
+        let attributes = HashMap::from([("service", "imap")]);
+        let mut closed = false;
+        for _ in 0..200 {
+            let _: (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) = other_service
+                .call("SearchItems", &(&attributes,))
+                .await
+                .unwrap();
+            if other_session
+                .call::<_, _, ()>("Close", &())
+                .await
+                .is_err_and(|error| {
+                    matches!(&error, zbus::Error::MethodError(name, _, _)
+                    if name.as_str() == "org.freedesktop.DBus.Error.UnknownObject")
+                })
+            {
+                closed = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(closed);
+
+        // Watching for departures does not keep the service running
+        drop(service_connection);
+        let mut stopped = false;
+        for _ in 0..200 {
+            let bus = zbus::fdo::DBusProxy::new(&other).await.unwrap();
+            let name = BusName::try_from(SECRET_SERVICE_NAME).unwrap();
+            if !bus.name_has_owner(name).await.unwrap() {
+                stopped = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(stopped);
+    }
+}
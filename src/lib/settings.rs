@@ -0,0 +1,501 @@
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Settings are kept in two places:
+//
+//   settings.toml in the config directory   what holds for every vault and is not secret
+//   the Settings table of the vault         what belongs to one vault, encrypted with it
+//
+// Both carry a version and are upgraded one version at a time like the vault schema. Values that
+// are missing take their defaults, so a file only needs what differs from them. Settings written by
+// a newer Keylight are read as far as they are understood but never written back
+
+const SETTINGS_FILE: &str = "settings.toml";
+const VAULT_SETTINGS_KEY: &str = "vault";
+
+// Each migration upgrades the settings by one version, the first one from version 1 to 2. Files
+// from before versioning carry no version and are treated as version 1
+const APP_MIGRATIONS: &[fn(&mut toml::Table)] = &[];
+const VAULT_MIGRATIONS: &[fn(&mut serde_json::Map<String, serde_json::Value>)] = &[];
+
+pub const APP_SETTINGS_VERSION: u32 = 1 + APP_MIGRATIONS.len() as u32;
+pub const VAULT_SETTINGS_VERSION: u32 = 1 + VAULT_MIGRATIONS.len() as u32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SettingsError {
+    Io(io::ErrorKind),
+    Format(String),
+    Newer { found: u32, supported: u32 },
+    Vault(VaultError),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::Io(kind) => write!(f, "Could not access the settings ({kind})"),
+            SettingsError::Format(message) => write!(f, "The settings are malformed: {message}"),
+            SettingsError::Newer { found, supported } => write!(
+                f,
+                "The settings were saved by a newer Keylight (version {found}, this one supports up to {supported}), changes are only kept until Keylight closes"
+            ),
+            SettingsError::Vault(error) => error.fmt(f),
+        }
+    }
+}
+
+impl From<io::Error> for SettingsError {
+    fn from(error: io::Error) -> Self {
+        println!("[ERROR]: {:?}", error);
+        SettingsError::Io(error.kind())
+    }
+}
+
+impl From<VaultError> for SettingsError {
+    fn from(error: VaultError) -> Self {
+        SettingsError::Vault(error)
+    }
+}
+
+impl From<sqlx::Error> for SettingsError {
+    fn from(error: sqlx::Error) -> Self {
+        SettingsError::Vault(error.into())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppSettings {
+    pub version: u32,
+    // Turned on again on the next unlock, a failure to start them only turns them off until then
+    pub secret_service: bool,
+    pub ssh_agent: bool,
+    pub master_password_minimum: MinimumScore,
+    pub theme: ThemeChoice,
+    pub auto_lock: AutoLock,
+    pub clipboard: ClipboardSettings,
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            version: APP_SETTINGS_VERSION,
+            secret_service: false,
+            ssh_agent: false,
+            master_password_minimum: MinimumScore::default(),
+            theme: ThemeChoice::default(),
+            auto_lock: AutoLock::default(),
+            clipboard: ClipboardSettings::default(),
+        }
+    }
+}
+
+impl AppSettings {
+    // Starts over from the defaults, a file from a newer Keylight stays protected
+    pub fn reset(&mut self) {
+        let version = self.version.max(APP_SETTINGS_VERSION);
+        *self = Self::default();
+        self.version = version;
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VaultSettings {
+    pub version: u32,
+    pub password_age_limit: PasswordAgeLimit,
+    pub breach_dataset: Option<PathBuf>,
+    // The layout of the passwords page, None for the default one
+    pub panes: Option<SavedPanes>,
+}
+
+impl Default for VaultSettings {
+    fn default() -> Self {
+        Self {
+            version: VAULT_SETTINGS_VERSION,
+            password_age_limit: PasswordAgeLimit::default(),
+            breach_dataset: None,
+            panes: None,
+        }
+    }
+}
+
+impl VaultSettings {
+    pub fn reset(&mut self) {
+        let version = self.version.max(VAULT_SETTINGS_VERSION);
+        *self = Self::default();
+        self.version = version;
+    }
+}
+
+fn settings_version(found: Option<i64>, supported: u32) -> u32 {
+    let found = found.unwrap_or(1).clamp(1, u32::MAX as i64) as u32;
+    if found > supported {
+        println!(
+            "[ERROR]: The settings have version {found}, this version of Keylight supports up to {supported}"
+        );
+    }
+    found
+}
+
+fn upgrade_app_settings(table: &mut toml::Table, migrations: &[fn(&mut toml::Table)]) {
+    let supported = 1 + migrations.len() as u32;
+    let found = table.get("version").and_then(toml::Value::as_integer);
+    let version = settings_version(found, supported);
+    for version in version..supported {
+        println!(
+            "[INFO]: Upgrading the settings from version {version} to {}",
+            version + 1
+        );
+        migrations[version as usize - 1](table);
+    }
+    let version = version.max(supported);
+    table.insert("version".into(), toml::Value::Integer(version.into()));
+}
+
+fn upgrade_vault_settings(
+    object: &mut serde_json::Map<String, serde_json::Value>,
+    migrations: &[fn(&mut serde_json::Map<String, serde_json::Value>)],
+) {
+    let supported = 1 + migrations.len() as u32;
+    let found = object.get("version").and_then(serde_json::Value::as_i64);
+    let version = settings_version(found, supported);
+    for version in version..supported {
+        println!(
+            "[INFO]: Upgrading the vault settings from version {version} to {}",
+            version + 1
+        );
+        migrations[version as usize - 1](object);
+    }
+    object.insert("version".into(), version.max(supported).into());
+}
+
+pub fn settings_path(config_directory: &Path) -> PathBuf {
+    config_directory.join(SETTINGS_FILE)
+}
+
+// A missing file gives the defaults. One that cannot be parsed is moved aside rather than being
+// overwritten by the next change, the error says where it went
+pub fn load_app_settings(config_directory: &Path) -> Result<AppSettings, SettingsError> {
+    let path = settings_path(config_directory);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(AppSettings::default()),
+        Err(error) => return Err(error.into()),
+    };
+
+    let parsed = contents.parse::<toml::Table>().and_then(|mut table| {
+        upgrade_app_settings(&mut table, APP_MIGRATIONS);
+        table.try_into::<AppSettings>()
+    });
+    parsed.or_else(|error| {
+        println!("[ERROR]: {:?}", error);
+        let unreadable = path.with_extension("toml.invalid");
+        fs::rename(&path, &unreadable)?;
+        Err(SettingsError::Format(format!(
+            "{}, the file was moved to {}",
+            error.message(),
+            unreadable.display()
+        )))
+    })
+}
+
+// Written next to the old file and renamed over it, a crash never leaves half a file behind
+pub fn save_app_settings(
+    config_directory: &Path,
+    settings: &AppSettings,
+) -> Result<(), SettingsError> {
+    if settings.version > APP_SETTINGS_VERSION {
+        return Err(SettingsError::Newer {
+            found: settings.version,
+            supported: APP_SETTINGS_VERSION,
+        });
+    }
+    let contents = toml::to_string_pretty(settings)
+        .map_err(|error| SettingsError::Format(error.to_string()))?;
+    fs::create_dir_all(config_directory)?;
+    let path = settings_path(config_directory);
+    let partial = path.with_extension("toml.partial");
+    fs::write(&partial, contents)?;
+    fs::rename(&partial, &path)?;
+    Ok(())
+}
+
+pub async fn load_vault_settings(vault_pool: SqlitePool) -> Result<VaultSettings, SettingsError> {
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    let stored: Option<String> =
+        sqlx::query_scalar("SELECT setting_value FROM Settings WHERE setting_key = ?;")
+            .bind(VAULT_SETTINGS_KEY)
+            .fetch_optional(connection.as_mut())
+            .await?;
+    let Some(stored) = stored else {
+        return Ok(VaultSettings::default());
+    };
+
+    let format = |error: serde_json::Error| {
+        println!("[ERROR]: {:?}", error);
+        SettingsError::Format(error.to_string())
+    };
+    let mut object: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(&stored).map_err(format)?;
+    upgrade_vault_settings(&mut object, VAULT_MIGRATIONS);
+    serde_json::from_value(object.into()).map_err(format)
+}
+
+pub async fn save_vault_settings(
+    vault_pool: SqlitePool,
+    settings: VaultSettings,
+) -> Result<(), SettingsError> {
+    if settings.version > VAULT_SETTINGS_VERSION {
+        return Err(SettingsError::Newer {
+            found: settings.version,
+            supported: VAULT_SETTINGS_VERSION,
+        });
+    }
+    let stored = serde_json::to_string(&settings)
+        .map_err(|error| SettingsError::Format(error.to_string()))?;
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    sqlx::query("INSERT OR REPLACE INTO Settings (setting_key, setting_value) VALUES (?, ?);")
+        .bind(VAULT_SETTINGS_KEY)
+        .bind(stored)
+        .execute(connection.as_mut())
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keylight2::testing::{ScratchDirectory, ScratchVault};
+
+    fn write_settings(directory: &ScratchDirectory, contents: &str) {
+        fs::write(settings_path(&directory.0), contents).unwrap();
+    }
+
+    fn is_default(settings: &AppSettings) -> bool {
+        let default = AppSettings::default();
+        settings.secret_service == default.secret_service
+            && settings.ssh_agent == default.ssh_agent
+            && settings.master_password_minimum == default.master_password_minimum
+            && settings.theme == default.theme
+            && settings.auto_lock.idle_timeout == default.auto_lock.idle_timeout
+            && settings.auto_lock.lock_on_sleep == default.auto_lock.lock_on_sleep
+            && settings.clipboard.clear_after == default.clipboard.clear_after
+            && settings.clipboard.use_primary_selection == default.clipboard.use_primary_selection
+    }
+
+    async fn store_vault_settings(vault: &ScratchVault, stored: &str) {
+        sqlx::query("INSERT OR REPLACE INTO Settings (setting_key, setting_value) VALUES (?, ?);")
+            .bind(VAULT_SETTINGS_KEY)
+            .bind(stored)
+            .execute(&vault.vault_pool)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn settings_survive_a_save_and_load() {
+        let directory = ScratchDirectory::new("settings-round-trip");
+        let loaded = load_app_settings(&directory.0).unwrap();
+        assert_eq!(loaded.version, APP_SETTINGS_VERSION);
+        assert!(is_default(&loaded));
+
+        let mut settings = AppSettings {
+            ssh_agent: true,
+            master_password_minimum: MinimumScore(4),
+            theme: ThemeChoice::Custom("Solarized".to_string()),
+            ..AppSettings::default()
+        };
+        settings.auto_lock.idle_timeout = IdleTimeout::Never;
+        settings.clipboard.clear_after = ClipboardTimeout::Seconds(90);
+        // The directory is created when missing
+        let nested = directory.0.join("keylight");
+        save_app_settings(&nested, &settings).unwrap();
+        assert!(!settings_path(&nested)
+            .with_extension("toml.partial")
+            .exists());
+
+        let loaded = load_app_settings(&nested).unwrap();
+        assert!(loaded.ssh_agent && !loaded.secret_service);
+        assert_eq!(loaded.master_password_minimum, MinimumScore(4));
+        assert_eq!(loaded.theme, ThemeChoice::Custom("Solarized".to_string()));
+        assert_eq!(loaded.auto_lock.idle_timeout, IdleTimeout::Never);
+        assert_eq!(loaded.clipboard.clear_after, ClipboardTimeout::Seconds(90));
+    }
+
+    #[test]
+    fn missing_keys_take_their_defaults() {
+        let directory = ScratchDirectory::new("settings-partial");
+        // From before versioning, with a table that is only partly there
+        write_settings(
+            &directory,
+            "ssh_agent = true\n\n[clipboard]\nuse_primary_selection = true\n",
+        );
+        let loaded = load_app_settings(&directory.0).unwrap();
+        assert_eq!(loaded.version, APP_SETTINGS_VERSION);
+        assert!(loaded.ssh_agent && loaded.clipboard.use_primary_selection);
+        assert_eq!(
+            loaded.clipboard.clear_after,
+            ClipboardSettings::default().clear_after
+        );
+        assert_eq!(loaded.theme, ThemeChoice::default());
+        assert_eq!(loaded.auto_lock.idle_timeout, IdleTimeout::Minutes(5));
+    }
+
+    #[test]
+    fn settings_of_a_newer_keylight_are_never_overwritten() {
+        let directory = ScratchDirectory::new("settings-newer");
+        let newer = APP_SETTINGS_VERSION + 1;
+        let contents = format!("version = {newer}\nssh_agent = true\nfuture_option = 3\n");
+        write_settings(&directory, &contents);
+
+        let mut loaded = load_app_settings(&directory.0).unwrap();
+        assert_eq!(loaded.version, newer);
+        assert!(loaded.ssh_agent);
+        assert_eq!(
+            save_app_settings(&directory.0, &loaded),
+            Err(SettingsError::Newer {
+                found: newer,
+                supported: APP_SETTINGS_VERSION
+            })
+        );
+        // Not even starting over lifts the protection
+        loaded.reset();
+        assert_eq!(loaded.version, newer);
+        assert!(save_app_settings(&directory.0, &loaded).is_err());
+        assert_eq!(
+            fs::read_to_string(settings_path(&directory.0)).unwrap(),
+            contents
+        );
+    }
+
+    #[test]
+    fn unparsable_settings_are_moved_aside() {
+        for (name, contents) in [
+            ("syntax", "ssh_agent = [true\n"),
+            ("type", "ssh_agent = \"yes\"\n"),
+        ] {
+            let directory = ScratchDirectory::new(&format!("settings-unparsable-{name}"));
+            write_settings(&directory, contents);
+            let unreadable = directory.0.join("settings.toml.invalid");
+            match load_app_settings(&directory.0) {
+                Err(SettingsError::Format(message)) => {
+                    assert!(
+                        message.ends_with(&unreadable.display().to_string()),
+                        "{message}"
+                    )
+                }
+                other => panic!("{name}: {:?}", other.err()),
+            }
+            assert_eq!(fs::read_to_string(&unreadable).unwrap(), contents);
+
+            // The next start begins from the defaults and may save again
+            let loaded = load_app_settings(&directory.0).unwrap();
+            assert!(is_default(&loaded));
+            save_app_settings(&directory.0, &loaded).unwrap();
+        }
+    }
+
+    #[test]
+    fn migrations_run_from_the_version_found() {
+        fn rename_lock(table: &mut toml::Table) {
+            if let Some(value) = table.remove("lock") {
+                table.insert("lock_on_sleep".into(), value);
+            }
+        }
+        fn mark(table: &mut toml::Table) {
+            let steps = table.get("steps").and_then(toml::Value::as_integer);
+            table.insert("steps".into(), (steps.unwrap_or(0) + 1).into());
+        }
+        let migrations: &[fn(&mut toml::Table)] = &[rename_lock, mark, mark];
+
+        // Unversioned files are version 1 and take every step
+        let mut table: toml::Table = "lock = false".parse().unwrap();
+        upgrade_app_settings(&mut table, migrations);
+        assert_eq!(
+            table,
+            "lock_on_sleep = false\nsteps = 2\nversion = 4"
+                .parse()
+                .unwrap()
+        );
+
+        let mut table: toml::Table = "version = 3\nlock = false".parse().unwrap();
+        upgrade_app_settings(&mut table, migrations);
+        assert_eq!(
+            table,
+            "lock = false\nsteps = 1\nversion = 4".parse().unwrap()
+        );
+
+        // Newer and nonsensical versions are left alone
+        for version in ["7", "0", "-2"] {
+            let mut table: toml::Table = format!("version = {version}").parse().unwrap();
+            upgrade_app_settings(&mut table, migrations);
+            let expected = if version == "7" { 7 } else { 4 };
+            assert_eq!(table["version"].as_integer(), Some(expected));
+            assert_eq!(table.get("steps").is_some(), version != "7");
+        }
+
+        fn migrated(object: &mut serde_json::Map<String, serde_json::Value>) {
+            object.insert("migrated".into(), true.into());
+        }
+        let mut object = serde_json::Map::new();
+        let migrations: &[fn(&mut serde_json::Map<String, serde_json::Value>)] = &[migrated];
+        upgrade_vault_settings(&mut object, migrations);
+        assert_eq!(
+            serde_json::Value::Object(object),
+            serde_json::json!({"migrated": true, "version": 2})
+        );
+    }
+
+    #[tokio::test]
+    async fn vault_settings_are_kept_in_the_vault() {
+        let vault = ScratchVault::new("settings-vault").await;
+        let loaded = load_vault_settings(vault.vault_pool.clone()).await.unwrap();
+        assert_eq!(loaded.version, VAULT_SETTINGS_VERSION);
+        assert_eq!(loaded.password_age_limit, PasswordAgeLimit::default());
+
+        let settings = VaultSettings {
+            password_age_limit: PasswordAgeLimit(730),
+            breach_dataset: Some(PathBuf::from("/data/pwned.txt")),
+            ..VaultSettings::default()
+        };
+        save_vault_settings(vault.vault_pool.clone(), settings)
+            .await
+            .unwrap();
+        let loaded = load_vault_settings(vault.vault_pool.clone()).await.unwrap();
+        assert_eq!(loaded.password_age_limit, PasswordAgeLimit(730));
+        assert_eq!(
+            loaded.breach_dataset.as_deref(),
+            Some(Path::new("/data/pwned.txt"))
+        );
+        assert_eq!(loaded.panes, None);
+
+        // Missing keys take their defaults here too
+        store_vault_settings(&vault, r#"{"breach_dataset": "/other"}"#).await;
+        let loaded = load_vault_settings(vault.vault_pool.clone()).await.unwrap();
+        assert_eq!(loaded.version, VAULT_SETTINGS_VERSION);
+        assert_eq!(loaded.password_age_limit, PasswordAgeLimit::default());
+
+        let newer = VAULT_SETTINGS_VERSION + 1;
+        store_vault_settings(&vault, &format!(r#"{{"version": {newer}}}"#)).await;
+        let mut loaded = load_vault_settings(vault.vault_pool.clone()).await.unwrap();
+        loaded.reset();
+        assert_eq!(
+            save_vault_settings(vault.vault_pool.clone(), loaded).await,
+            Err(SettingsError::Newer {
+                found: newer,
+                supported: VAULT_SETTINGS_VERSION
+            })
+        );
+
+        store_vault_settings(&vault, "not json").await;
+        assert!(matches!(
+            load_vault_settings(vault.vault_pool.clone()).await,
+            Err(SettingsError::Format(_))
+        ));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::*;
+use std::sync::atomic::AtomicU8;
+use std::sync::{Mutex, RwLock};
+
+use argon2::{Argon2, Block};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+// State shared by the GUI and the keylight CLI, everything here is reachable through VaultManagement
+
+#[derive(Zeroize, ZeroizeOnDrop, Serialize, Deserialize)]
+pub struct Folder {
+    pub folder_id: i64,
+    pub folder_name: Option<String>,
+    pub folder_icon: Option<String>,
+}
+
+#[derive(Zeroize, ZeroizeOnDrop, sqlx::FromRow, Serialize, Deserialize)]
+pub struct Entry {
+    pub entry_id: i64,
+    pub entry_name: Option<String>,
+    pub entry_icon: Option<String>,
+    pub folder_id: Option<i64>,
+}
+
+#[derive(Zeroize, ZeroizeOnDrop, sqlx::FromRow, Serialize, Deserialize)]
+pub struct EntryData {
+    pub data_id: i64,
+    pub section_name: Option<String>,
+    pub section_type: Option<String>,
+    pub section_data: Option<Vec<u8>>,
+    pub entry_id: Option<i64>,
+}
+
+pub enum Page {
+    LogIn,
+    Passwords,
+    NewVault,
+    Security,
+    Sync,
+    History,
+    Settings,
+}
+
+impl Page {
+    // Pages only reachable with the vault open
+    pub fn is_unlocked(&self) -> bool {
+        matches!(
+            self,
+            Page::Passwords | Page::Security | Page::Sync | Page::History | Page::Settings
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct KeyfileContents {
+    pub hashed_password: String,
+    pub derivation_salt: String,
+    pub nonce: Vec<u8>,
+    pub encrypted_master_passphrase: Vec<u8>,
+}
+
+pub struct StorageServices {
+    pub directory_handler: ProjectDirs,
+    pub vaults: Vec<VaultLocation>,
+    pub selected_vault: Option<VaultLocation>,
+    // The vault being created, it only becomes the selected one once its files exist
+    pub new_vault: Option<VaultLocation>,
+}
+
+pub struct ApplicationAsyncState {
+    pub master_password: RwLock<String>,
+    pub master_password_confirmation: RwLock<String>,
+    pub app_directory: RwLock<StorageServices>,
+    pub page: Mutex<Page>,
+    pub mem_block: Mutex<Vec<argon2::Block>>,
+    pub display_folders: Mutex<Vec<Folder>>,
+    pub display_entries: Mutex<Vec<Entry>>,
+    pub display_entry_data: Mutex<Vec<EntryData>>,
+    pub vault_creation_state: AtomicU8,
+}
+
+impl ApplicationAsyncState {
+    pub fn new(app_directory: StorageServices) -> Self {
+        Self {
+            master_password: RwLock::new(String::default()),
+            master_password_confirmation: RwLock::new(String::default()),
+            page: Mutex::new(Page::LogIn),
+            display_folders: Mutex::new(Vec::new()),
+            display_entries: Mutex::new(Vec::new()),
+            display_entry_data: Mutex::new(Vec::new()),
+            mem_block: Mutex::new(vec![
+                Block::default();
+                Argon2::default().params().block_count()
+            ]),
+            app_directory: RwLock::new(app_directory),
+            vault_creation_state: AtomicU8::new(0),
+        }
+    }
+
+    // Same as zeroize, but usable through the Arc shared with async tasks
+    pub fn clear_secrets(&self) {
+        self.master_password.write().unwrap().zeroize();
+        self.master_password_confirmation.write().unwrap().zeroize();
+        self.display_folders.lock().unwrap().zeroize();
+        self.display_entries.lock().unwrap().zeroize();
+        self.display_entry_data.lock().unwrap().zeroize();
+
+        // Zeroizing the Vec also truncates it, the next login still needs the full block count
+        let mut mem_block = self.mem_block.lock().unwrap();
+        mem_block.zeroize();
+        mem_block.resize(Argon2::default().params().block_count(), Block::default());
+    }
+}
+
+impl Zeroize for ApplicationAsyncState {
+    fn zeroize(&mut self) {
+        self.clear_secrets();
+    }
+}
+
+impl Drop for ApplicationAsyncState {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
@@ -0,0 +1,724 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::LazyLock;
+
+// Password strength estimation in the spirit of zxcvbn: the password is split into the pieces an
+// attacker would guess separately (common passwords and words, keyboard rows, sequences, repeats,
+// dates), each piece gets a guess count, and the cheapest split decides the estimate. Whatever no
+// pattern covers is brute forced at 10 guesses per character.
+
+// Longer passwords are estimated on their first characters, the rest only adds strength
+const MAX_ESTIMATED_LENGTH: usize = 100;
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+const MIN_PATTERN_GUESSES: f64 = 50.0;
+// What an attacker with a few GPUs manages against a slow hash like the Argon2 of the keyfile
+pub const SLOW_HASH_GUESSES_PER_SECOND: f64 = 1e4;
+// The years people put into passwords cluster around the present, the current year is close
+// enough from the clock without calendar arithmetic
+static REFERENCE_YEAR: LazyLock<i64> = LazyLock::new(|| {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    1970 + (seconds / 31_556_952) as i64
+});
+const MIN_YEAR_SPACE: i64 = 20;
+const KEYBOARD_ROWS: &[&str] = &[
+    "`1234567890-=",
+    "qwertyuiop[]\\",
+    "asdfghjkl;'",
+    "zxcvbnm,./",
+    "1qaz",
+    "2wsx",
+    "3edc",
+    "4rfv",
+    "5tgb",
+    "6yhn",
+    "7ujm",
+    "8ik,",
+    "9ol.",
+    "0p;/",
+    "azertyuiop",
+    "qsdfghjklm",
+    "wxcvbn",
+    "qwertzuiop",
+    "yxcvbnm",
+];
+const LEET_SUBSTITUTIONS: &[(char, char)] = &[
+    ('4', 'a'),
+    ('@', 'a'),
+    ('8', 'b'),
+    ('(', 'c'),
+    ('3', 'e'),
+    ('6', 'g'),
+    ('1', 'i'),
+    ('!', 'i'),
+    ('|', 'l'),
+    ('0', 'o'),
+    ('$', 's'),
+    ('5', 's'),
+    ('7', 't'),
+    ('+', 't'),
+    ('2', 'z'),
+];
+
+// Both lists are ordered by frequency and ranked on their own, a word keeps its better rank
+static DICTIONARY: LazyLock<HashMap<&'static str, usize>> = LazyLock::new(|| {
+    let mut dictionary = HashMap::new();
+    let passwords = include_str!("../../assets/common_passwords.txt").lines();
+    let words = include_str!("../../assets/common_words.txt").lines();
+    for (rank, word) in passwords.enumerate().chain(words.enumerate()) {
+        let best = dictionary.entry(word).or_insert(rank + 1);
+        *best = (*best).min(rank + 1);
+    }
+    dictionary
+});
+
+static COMMON_PASSWORDS: LazyLock<HashSet<&'static str>> = LazyLock::new(|| {
+    include_str!("../../assets/common_passwords.txt")
+        .lines()
+        .collect()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pattern {
+    Dictionary,
+    Sequence,
+    Repeat,
+    Keyboard,
+    Date,
+    Bruteforce,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Strength {
+    // log10 of the estimated number of guesses
+    pub guesses_log10: f64,
+    // 0 (guessed almost at once) to 4 (out of reach of offline attacks)
+    pub score: u8,
+    pub warning: Option<&'static str>,
+    pub suggestions: Vec<&'static str>,
+}
+
+impl Strength {
+    pub fn label(&self) -> &'static str {
+        match self.score {
+            0 => "Very weak",
+            1 => "Weak",
+            2 => "Fair",
+            3 => "Strong",
+            _ => "Very strong",
+        }
+    }
+
+    // Time to go through half of the estimated guesses
+    pub fn crack_time(&self, guesses_per_second: f64) -> String {
+        let seconds = 10f64.powf(self.guesses_log10) / 2.0 / guesses_per_second;
+        let units = [
+            (60.0 * 60.0 * 24.0 * 365.0, "year"),
+            (60.0 * 60.0 * 24.0 * 30.0, "month"),
+            (60.0 * 60.0 * 24.0, "day"),
+            (60.0 * 60.0, "hour"),
+            (60.0, "minute"),
+            (1.0, "second"),
+        ];
+        if seconds < 1.0 {
+            return "less than a second".to_string();
+        }
+        if seconds >= 100.0 * units[0].0 {
+            return "centuries".to_string();
+        }
+        let (length, unit) = units
+            .into_iter()
+            .find(|(length, _)| seconds >= *length)
+            .unwrap_or(units[5]);
+        match (seconds / length).round() as u64 {
+            1 => format!("1 {unit}"),
+            count => format!("{count} {unit}s"),
+        }
+    }
+}
+
+// The weakest score a password is accepted with, where a policy applies
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MinimumScore(pub u8);
+
+impl MinimumScore {
+    pub const ALL: [MinimumScore; 3] = [MinimumScore(2), MinimumScore(3), MinimumScore(4)];
+
+    pub fn allows(self, strength: &Strength) -> bool {
+        strength.score >= self.0
+    }
+}
+
+impl Default for MinimumScore {
+    fn default() -> Self {
+        MinimumScore(3)
+    }
+}
+
+impl fmt::Display for MinimumScore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            4 => write!(f, "Require very strong"),
+            3 => write!(f, "Require strong or better"),
+            _ => write!(f, "Require fair or better"),
+        }
+    }
+}
+
+// Exact matches only, variations are left to the estimate
+pub fn is_common_password(password: &str) -> bool {
+    COMMON_PASSWORDS.contains(password.to_lowercase().as_str())
+}
+
+struct Match {
+    start: usize,
+    end: usize,
+    guesses: f64,
+    pattern: Pattern,
+}
+
+fn uppercase_variations(word: &[char]) -> f64 {
+    let uppercase = word.iter().filter(|c| c.is_uppercase()).count();
+    if uppercase == 0 {
+        return 1.0;
+    }
+    let lowercase = word.iter().filter(|c| c.is_lowercase()).count();
+    // Capitalized, all caps and a trailing capital are the first variations tried
+    if lowercase == 0
+        || (uppercase == 1 && (word[0].is_uppercase() || word[word.len() - 1].is_uppercase()))
+    {
+        return 2.0;
+    }
+    2f64.powi(uppercase.min(lowercase) as i32 + 1)
+}
+
+fn dictionary_matches(
+    chars: &[char],
+    lower: &[char],
+    user_inputs: &HashMap<String, usize>,
+) -> Vec<Match> {
+    let mut matches = Vec::new();
+    // Every leet character read as the letter it stands in for
+    let unleeted: Vec<char> = lower
+        .iter()
+        .map(|c| {
+            LEET_SUBSTITUTIONS
+                .iter()
+                .find(|(leet, _)| leet == c)
+                .map_or(*c, |(_, letter)| *letter)
+        })
+        .collect();
+
+    let rank_of = |word: &str| -> Option<usize> {
+        let user_rank = user_inputs.get(word).copied();
+        let rank = DICTIONARY.get(word).copied();
+        match (user_rank, rank) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    };
+
+    for start in 0..chars.len() {
+        for end in (start + 3)..=chars.len() {
+            let word = &chars[start..end];
+            let variants = [
+                (lower[start..end].iter().collect::<String>(), 1.0),
+                (lower[start..end].iter().rev().collect::<String>(), 2.0),
+                (unleeted[start..end].iter().collect::<String>(), {
+                    let substituted = lower[start..end]
+                        .iter()
+                        .zip(&unleeted[start..end])
+                        .filter(|(a, b)| a != b)
+                        .count();
+                    match substituted {
+                        0 => f64::INFINITY,
+                        n => 2f64.powi(n as i32),
+                    }
+                }),
+            ];
+            for (candidate, multiplier) in variants {
+                if !multiplier.is_finite() {
+                    continue;
+                }
+                if let Some(rank) = rank_of(&candidate) {
+                    matches.push(Match {
+                        start,
+                        end,
+                        guesses: rank as f64 * uppercase_variations(word) * multiplier,
+                        pattern: Pattern::Dictionary,
+                    });
+                }
+            }
+        }
+    }
+    matches
+}
+
+// Straight runs along a keyboard row or column, "qwerty", "asdf", "1qaz", backwards too
+fn keyboard_matches(lower: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    for start in 0..lower.len() {
+        for end in (start + 4)..=lower.len() {
+            let run: String = lower[start..end].iter().collect();
+            let reversed: String = lower[start..end].iter().rev().collect();
+            if KEYBOARD_ROWS
+                .iter()
+                .any(|row| row.contains(&run) || row.contains(&reversed))
+            {
+                matches.push(Match {
+                    start,
+                    end,
+                    // Starting key, direction and length
+                    guesses: 47.0 * 2.0 * (end - start) as f64,
+                    pattern: Pattern::Keyboard,
+                });
+            }
+        }
+    }
+    matches
+}
+
+// Runs with a constant step between characters, "abcd", "9753"
+fn sequence_matches(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while start + 2 < chars.len() {
+        let step = chars[start + 1] as i64 - chars[start] as i64;
+        let mut end = start + 2;
+        while end < chars.len() && chars[end] as i64 - chars[end - 1] as i64 == step {
+            end += 1;
+        }
+        if end - start >= 3 && step != 0 && step.abs() <= 5 {
+            let first = chars[start];
+            let base = if matches!(first, 'a' | 'A' | 'z' | 'Z' | '0' | '1' | '9') {
+                4.0
+            } else if first.is_ascii_digit() {
+                10.0
+            } else {
+                26.0
+            };
+            let direction = if step > 0 { 1.0 } else { 2.0 };
+            matches.push(Match {
+                start,
+                end,
+                guesses: base * (end - start) as f64 * direction,
+                pattern: Pattern::Sequence,
+            });
+            start = end - 1;
+        } else {
+            start += 1;
+        }
+    }
+    matches
+}
+
+// A block written several times in a row, "aaaa", "abcabcabc"
+fn repeat_matches(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    // The same block shows up at many offsets of a long repetition
+    let mut block_estimates: HashMap<&[char], f64> = HashMap::new();
+    for start in 0..chars.len() {
+        for block in 1..=(chars.len() - start) / 2 {
+            let mut repeats = 1;
+            while start + (repeats + 1) * block <= chars.len()
+                && chars[start..start + block]
+                    == chars[start + repeats * block..start + (repeats + 1) * block]
+            {
+                repeats += 1;
+            }
+            if repeats < 2 || (block == 1 && repeats < 3) {
+                continue;
+            }
+            let block_chars = &chars[start..start + block];
+            // "abab" repeated is already found as "ab" repeated
+            let doubled = [block_chars, block_chars].concat();
+            if doubled[1..doubled.len() - 1]
+                .windows(block)
+                .any(|window| window == block_chars)
+            {
+                continue;
+            }
+            let block_guesses = *block_estimates.entry(block_chars).or_insert_with(|| {
+                estimate(block_chars, &HashMap::new())
+                    .0
+                    .max(BRUTEFORCE_CARDINALITY)
+            });
+            matches.push(Match {
+                start,
+                end: start + repeats * block,
+                guesses: block_guesses * repeats as f64,
+                pattern: Pattern::Repeat,
+            });
+        }
+    }
+    matches
+}
+
+fn year_guesses(year: i64) -> Option<f64> {
+    (1900..=2099)
+        .contains(&year)
+        .then(|| (year - *REFERENCE_YEAR).abs().max(MIN_YEAR_SPACE) as f64)
+}
+
+fn parse_year(text: &str) -> Option<i64> {
+    let year: i64 = text.parse().ok()?;
+    match text.len() {
+        // Two digit years are read as the closest century, "89" is 1989 and "12" is 2012
+        2 if year < 50 => Some(2000 + year),
+        2 => Some(1900 + year),
+        4 => Some(year),
+        _ => None,
+    }
+}
+
+// Guesses for a day and month in either order plus a year
+fn date_guesses(first: &str, second: &str, year: &str) -> Option<f64> {
+    let (first, second): (i64, i64) = (first.parse().ok()?, second.parse().ok()?);
+    let day_month = (1..=31).contains(&first) && (1..=12).contains(&second);
+    let month_day = (1..=12).contains(&first) && (1..=31).contains(&second);
+    if !day_month && !month_day {
+        return None;
+    }
+    Some(year_guesses(parse_year(year)?)? * 365.0)
+}
+
+// Years on their own and day, month and year in the usual orders, with or without separators
+fn date_matches(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    for start in 0..chars.len() {
+        for end in (start + 4)..=(start + 10).min(chars.len()) {
+            let text: String = chars[start..end].iter().collect();
+            let guesses = if text.chars().all(|c| c.is_ascii_digit()) {
+                match text.len() {
+                    4 => text.parse().ok().and_then(year_guesses),
+                    6 | 8 => {
+                        let year = text.len() - 4;
+                        date_guesses(&text[..2], &text[2..4], &text[4..]).or_else(|| {
+                            date_guesses(&text[year + 2..], &text[year..year + 2], &text[..year])
+                        })
+                    }
+                    _ => None,
+                }
+            } else {
+                let Some(separator) = text.chars().find(|c| !c.is_ascii_digit()) else {
+                    continue;
+                };
+                let parts: Vec<&str> = text.split(separator).collect();
+                match parts.as_slice() {
+                    [a, b, c]
+                        if parts.iter().all(|part| {
+                            !part.is_empty() && part.chars().all(|c| c.is_ascii_digit())
+                        }) =>
+                    {
+                        date_guesses(a, b, c)
+                            .or_else(|| date_guesses(c, b, a))
+                            .map(|guesses| guesses * 4.0)
+                    }
+                    _ => None,
+                }
+            };
+
+            if let Some(guesses) = guesses {
+                matches.push(Match {
+                    start,
+                    end,
+                    guesses,
+                    pattern: Pattern::Date,
+                });
+            }
+        }
+    }
+    matches
+}
+
+// (guesses, the patterns of the cheapest split in order)
+fn estimate(chars: &[char], user_inputs: &HashMap<String, usize>) -> (f64, Vec<Pattern>) {
+    let length = chars.len();
+    if length == 0 {
+        return (1.0, Vec::new());
+    }
+    let lower: Vec<char> = chars.iter().flat_map(|c| c.to_lowercase()).collect();
+    // Lowercasing can change the length for a few scripts, such passwords skip the pattern search
+    let mut matches = Vec::new();
+    if lower.len() == length {
+        matches.extend(dictionary_matches(chars, &lower, user_inputs));
+        matches.extend(keyboard_matches(&lower));
+    }
+    matches.extend(sequence_matches(chars));
+    if length > 1 {
+        matches.extend(repeat_matches(chars));
+    }
+    matches.extend(date_matches(chars));
+
+    // best[end] = (log10 of the guesses for chars[..end], number of pieces, start of the last
+    // piece, its pattern)
+    let mut best: Vec<Option<(f64, usize, usize, Pattern)>> = vec![None; length + 1];
+    best[0] = Some((0.0, 0, 0, Pattern::Bruteforce));
+    for end in 1..=length {
+        let bruteforce = (0..end).map(|start| {
+            (
+                start,
+                (end - start) as f64 * BRUTEFORCE_CARDINALITY.log10(),
+                Pattern::Bruteforce,
+            )
+        });
+        let found = matches
+            .iter()
+            .filter(|found| found.end == end)
+            .map(|found| {
+                (
+                    found.start,
+                    found.guesses.max(MIN_PATTERN_GUESSES).log10(),
+                    found.pattern,
+                )
+            });
+
+        for (start, guesses_log10, pattern) in bruteforce.chain(found) {
+            let Some((before_log10, pieces, _, previous)) = best[start] else {
+                continue;
+            };
+            // Bruteforce next to bruteforce is one longer piece, never two
+            if pattern == Pattern::Bruteforce && previous == Pattern::Bruteforce && start > 0 {
+                continue;
+            }
+            let pieces = pieces + 1;
+            // An attacker also has to guess how many pieces there are and in which order
+            let total = before_log10 + guesses_log10 + (pieces as f64).log10();
+            if best[end].is_none_or(|(current, _, _, _)| total < current) {
+                best[end] = Some((total, pieces, start, pattern));
+            }
+        }
+    }
+
+    let mut patterns = Vec::new();
+    let mut end = length;
+    while end > 0 {
+        let Some((_, _, start, pattern)) = best[end] else {
+            break;
+        };
+        patterns.push(pattern);
+        end = start;
+    }
+    patterns.reverse();
+    let guesses_log10 = best[length].map_or(0.0, |(total, _, _, _)| total);
+    (10f64.powf(guesses_log10), patterns)
+}
+
+fn score(guesses_log10: f64) -> u8 {
+    match guesses_log10 {
+        g if g < 3.0 => 0,
+        g if g < 6.0 => 1,
+        g if g < 8.0 => 2,
+        g if g < 10.0 => 3,
+        _ => 4,
+    }
+}
+
+// user_inputs are words an attacker targeting this password would try first, like the entry name,
+// username or site
+pub fn estimate_strength(password: &str, user_inputs: &[&str]) -> Strength {
+    let chars: Vec<char> = password.chars().collect();
+    let user_inputs: HashMap<String, usize> = user_inputs
+        .iter()
+        .flat_map(|input| input.split(|c: char| !c.is_alphanumeric()))
+        .filter(|word| word.chars().count() >= 3)
+        .enumerate()
+        .map(|(rank, word)| (word.to_lowercase(), rank + 1))
+        .collect();
+
+    let estimated = chars.len().min(MAX_ESTIMATED_LENGTH);
+    let (guesses, patterns) = estimate(&chars[..estimated], &user_inputs);
+    let guesses_log10 =
+        guesses.log10() + (chars.len() - estimated) as f64 * BRUTEFORCE_CARDINALITY.log10();
+    let score = score(guesses_log10);
+
+    let warning = match score {
+        3.. => None,
+        _ => patterns
+            .iter()
+            .find(|pattern| **pattern != Pattern::Bruteforce)
+            .map(|pattern| match pattern {
+                Pattern::Dictionary if patterns.len() == 1 => "This is a very common password",
+                Pattern::Dictionary => "Common words are easy to guess, even with symbols added",
+                Pattern::Sequence => "Sequences like \"abc\" or \"6543\" are easy to guess",
+                Pattern::Repeat => "Repeats like \"aaa\" or \"abcabc\" are easy to guess",
+                Pattern::Keyboard => "Straight rows of keys are easy to guess",
+                Pattern::Date => "Dates and years are easy to guess",
+                Pattern::Bruteforce => unreachable!(),
+            })
+            .or(Some("This password is too short")),
+    };
+    let mut suggestions = Vec::new();
+    if score < 3 {
+        suggestions.push("Add another word or two, uncommon words are better");
+        if patterns.contains(&Pattern::Dictionary) {
+            suggestions.push("Predictable substitutions like \"@\" for \"a\" do not help much");
+        }
+    }
+
+    Strength {
+        guesses_log10,
+        score,
+        warning,
+        suggestions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    fn spans(matches: &[Match], pattern: Pattern) -> Vec<(usize, usize)> {
+        matches
+            .iter()
+            .filter(|found| found.pattern == pattern)
+            .map(|found| (found.start, found.end))
+            .collect()
+    }
+
+    fn strength(guesses_log10: f64) -> Strength {
+        Strength {
+            guesses_log10,
+            score: score(guesses_log10),
+            warning: None,
+            suggestions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn the_bundled_lists_cover_common_passwords() {
+        assert!(COMMON_PASSWORDS.len() > 10_000);
+        for password in [
+            "123456",
+            "Password",
+            "michael1",
+            "jessica123",
+            "1990",
+            "qwerty123",
+        ] {
+            assert!(is_common_password(password), "{password}");
+        }
+        assert!(!is_common_password("correct horse battery staple"));
+        assert_eq!(DICTIONARY["password"], 2);
+        // Words rank by their own list, not behind every common password
+        assert_eq!(DICTIONARY["the"], 1);
+    }
+
+    #[test]
+    fn dictionary_words_are_found_with_their_variations() {
+        let find = |text: &str| {
+            let (chars, lower) = (chars(text), chars(&text.to_lowercase()));
+            dictionary_matches(&chars, &lower, &HashMap::new())
+                .into_iter()
+                .filter(|found| found.start == 0 && found.end == chars.len())
+                .map(|found| found.guesses)
+                .fold(f64::INFINITY, f64::min)
+        };
+        assert_eq!(find("password"), 2.0);
+        assert_eq!(find("Password"), 4.0);
+        assert_eq!(find("PaSsword"), 16.0);
+        // Reversed and leet spellings cost a little more
+        assert_eq!(find("drowssap"), 4.0);
+        assert_eq!(find("p@ssw0rd"), 8.0);
+        assert_eq!(find("xqzvjk"), f64::INFINITY);
+
+        let user_inputs = HashMap::from([("xqzvjk".to_string(), 1)]);
+        let text = chars("xqzvjk");
+        assert_eq!(dictionary_matches(&text, &text, &user_inputs).len(), 1);
+    }
+
+    #[test]
+    fn keyboard_sequence_and_repeat_patterns() {
+        assert!(spans(&keyboard_matches(&chars("xwertyu")), Pattern::Keyboard).contains(&(1, 7)));
+        assert!(spans(&keyboard_matches(&chars("lkjh")), Pattern::Keyboard).contains(&(0, 4)));
+        assert!(spans(&keyboard_matches(&chars("1qaz")), Pattern::Keyboard).contains(&(0, 4)));
+        assert!(keyboard_matches(&chars("qwdf")).is_empty());
+
+        let sequences = sequence_matches(&chars("abcdef-9753"));
+        assert_eq!(spans(&sequences, Pattern::Sequence), [(0, 6), (7, 11)]);
+        assert_eq!(sequences[0].guesses, 4.0 * 6.0);
+        // Counting down is the second direction tried
+        assert_eq!(sequences[1].guesses, 4.0 * 4.0 * 2.0);
+        assert!(sequence_matches(&chars("aaaa")).is_empty());
+        assert!(sequence_matches(&chars("axbz")).is_empty());
+
+        let repeats = repeat_matches(&chars("abcabcabc"));
+        assert!(spans(&repeats, Pattern::Repeat).contains(&(0, 9)));
+        assert!(spans(&repeat_matches(&chars("zzzz")), Pattern::Repeat).contains(&(0, 4)));
+        // Two of a character are no pattern yet
+        assert!(repeat_matches(&chars("zz")).is_empty());
+    }
+
+    #[test]
+    fn dates_and_years() {
+        let year = *REFERENCE_YEAR;
+        assert!((2025..2100).contains(&year));
+        assert_eq!(year_guesses(year), Some(MIN_YEAR_SPACE as f64));
+        assert_eq!(year_guesses(year - 50), Some(50.0));
+        assert_eq!(year_guesses(1899), None);
+        assert_eq!(parse_year("89"), Some(1989));
+        assert_eq!(parse_year("12"), Some(2012));
+
+        for date in ["13/04/1989", "1989-04-13", "04.13.89", "13041989", "890413"] {
+            let text = chars(date);
+            assert!(
+                spans(&date_matches(&text), Pattern::Date).contains(&(0, text.len())),
+                "{date}"
+            );
+        }
+        assert!(spans(&date_matches(&chars("45/45/1989")), Pattern::Date)
+            .iter()
+            .all(|span| span.1 - span.0 == 4));
+    }
+
+    #[test]
+    fn scores_follow_the_guess_thresholds() {
+        assert_eq!(
+            [2.9, 3.0, 5.9, 6.0, 7.9, 8.0, 9.9, 10.0].map(score),
+            [0, 1, 1, 2, 2, 3, 3, 4]
+        );
+
+        let common = estimate_strength("password", &[]);
+        assert_eq!(common.score, 0);
+        assert_eq!(common.warning, Some("This is a very common password"));
+        assert!(estimate_strength("P@ssw0rd!", &[]).score <= 1);
+        assert!(estimate_strength("qwertyuiop", &[]).score <= 1);
+        assert!(estimate_strength("11111111111111", &[]).score <= 1);
+
+        let random = estimate_strength("xK9#mQ2$vL7!pR4&", &[]);
+        assert_eq!(random.score, 4);
+        assert_eq!(random.warning, None);
+        assert!(random.suggestions.is_empty());
+
+        // The site name is among the first guesses
+        let alone = estimate_strength("Zorblatt77", &[]);
+        let targeted = estimate_strength("Zorblatt77", &["zorblatt.example"]);
+        assert!(targeted.guesses_log10 < alone.guesses_log10);
+
+        // Whatever is cut off past the estimated length still counts as brute force
+        let long = "a".repeat(MAX_ESTIMATED_LENGTH) + "xyz";
+        assert!(estimate_strength(&long, &[]).guesses_log10 >= 3.0);
+    }
+
+    #[test]
+    fn crack_times_read_in_the_largest_unit() {
+        let time = |seconds: f64| strength((2.0 * seconds).log10()).crack_time(1.0);
+        assert_eq!(time(0.4), "less than a second");
+        assert_eq!(time(1.0), "1 second");
+        assert_eq!(time(100.0), "2 minutes");
+        assert_eq!(time(3.0 * 60.0 * 60.0), "3 hours");
+        assert_eq!(time(40.0 * 24.0 * 60.0 * 60.0), "1 month");
+        assert_eq!(time(5.0 * 365.0 * 24.0 * 60.0 * 60.0), "5 years");
+        assert_eq!(time(1e12), "centuries");
+        assert_eq!(
+            strength(4.0).crack_time(SLOW_HASH_GUESSES_PER_SECOND),
+            "less than a second"
+        );
+    }
+}
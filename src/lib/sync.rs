@@ -0,0 +1,1378 @@
+use crate::*;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+// Vault sync through change sets. The vault is cut into records, every folder, every entry (with its
+// tags) and every section, each known on all devices by the sync ids handed out in migration 4.
+// Syncing
+//
+//   1. compares every record with SyncRecords, the state last sent or received, and gives each
+//      changed one a new random revision id that names the revision it replaced as its parent.
+//      The change is stamped with the time of the edit, but always later than every revision of
+//      the record this device has seen, so an edit made on a device whose clock is behind still
+//      replaces the versions it was made on
+//   2. writes those changes as one encrypted file "<vault id>/<device id>/<sequence>.klsync" to a
+//      SyncStore. Devices only ever add files to their own directory, so any file-sync tool or
+//      dumb storage can carry them without locking
+//   3. applies the files of other devices that were not applied yet. Of all revisions of a record
+//      the one with the latest stamp wins, ties broken by device id and revision id. That only
+//      depends on which revisions a device has seen, not on the order they arrived in, so every
+//      device settles on the same version. A losing version that was not replaced knowingly, by
+//      an edit made on top of it, is kept in SyncConflicts for review
+//
+// The change sets are sealed with a key that is created together with the vault id and never
+// leaves the encrypted database. Other devices join by opening a copy of the vault, the copy
+// carries both. Both come from migration 4, so the copy has to be made after the vault was
+// upgraded to it: copies from before that are upgraded on their own get a vault id and key of
+// their own, write to a directory nobody else reads and can never be merged with the rest.
+
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const CHANGE_SET_MAGIC: &[u8; 8] = b"KLSYNC1\0";
+const CHANGE_SET_EXTENSION: &str = "klsync";
+const DEVICE_ID_FILE: &str = "device_id";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncError {
+    Io(io::ErrorKind),
+    Format(String),
+    Crypto,
+    Vault(VaultError),
+    Remote(String),
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Io(kind) => write!(f, "Could not access the sync folder ({kind})"),
+            SyncError::Format(message) => write!(f, "Unreadable change set: {message}"),
+            SyncError::Crypto => write!(f, "A change set could not be decrypted"),
+            SyncError::Vault(error) => write!(f, "{error}"),
+            SyncError::Remote(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl From<io::Error> for SyncError {
+    fn from(error: io::Error) -> Self {
+        println!("[ERROR]: {:?}", error);
+        SyncError::Io(error.kind())
+    }
+}
+
+impl From<VaultError> for SyncError {
+    fn from(error: VaultError) -> Self {
+        SyncError::Vault(error)
+    }
+}
+
+impl From<sqlx::Error> for SyncError {
+    fn from(error: sqlx::Error) -> Self {
+        SyncError::Vault(error.into())
+    }
+}
+
+impl From<postcard::Error> for SyncError {
+    fn from(error: postcard::Error) -> Self {
+        SyncError::Format(error.to_string())
+    }
+}
+
+impl From<chacha20poly1305::Error> for SyncError {
+    fn from(_: chacha20poly1305::Error) -> Self {
+        SyncError::Crypto
+    }
+}
+
+// Where change sets are kept, paths are relative and separated by "/"
+#[allow(async_fn_in_trait)]
+pub trait SyncStore {
+    // Every file below the directory, a missing directory is empty
+    async fn list(&self, directory: &str) -> Result<Vec<String>, SyncError>;
+    async fn read(&self, path: &str) -> Result<Vec<u8>, SyncError>;
+    // Files are written once and never changed afterwards
+    async fn write(&self, path: &str, contents: &[u8]) -> Result<(), SyncError>;
+}
+
+// A local or mounted network directory, possibly replicated by another tool
+pub struct FolderStore {
+    pub root: PathBuf,
+}
+
+impl SyncStore for FolderStore {
+    async fn list(&self, directory: &str) -> Result<Vec<String>, SyncError> {
+        let mut files = Vec::new();
+        let mut pending = vec![directory.to_string()];
+        while let Some(directory) = pending.pop() {
+            let mut entries = match tokio::fs::read_dir(self.root.join(&directory)).await {
+                Ok(entries) => entries,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(error.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                    continue;
+                };
+                let path = format!("{directory}/{name}");
+                if entry.file_type().await?.is_dir() {
+                    pending.push(path);
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    async fn read(&self, path: &str) -> Result<Vec<u8>, SyncError> {
+        Ok(tokio::fs::read(self.root.join(path)).await?)
+    }
+
+    // Written under a temporary name first, so a half written file is never picked up
+    async fn write(&self, path: &str, contents: &[u8]) -> Result<(), SyncError> {
+        let path = self.root.join(path);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let temporary = path.with_extension("tmp");
+        tokio::fs::write(&temporary, contents).await?;
+        tokio::fs::rename(&temporary, &path).await?;
+        Ok(())
+    }
+}
+
+// Where this vault syncs to. Every device of a vault has to use the same one, change sets are
+// not carried over when switching
+#[derive(Clone)]
+pub enum SyncTarget {
+    Folder(PathBuf),
+    WebDav(WebDavSettings),
+    S3(S3Settings),
+    Cloud(CloudService, CloudSettings),
+}
+
+// Identifies this installation, kept outside the vault so copies of it on other devices differ
+pub fn sync_device_id(data_directory: &Path) -> io::Result<String> {
+    let path = data_directory.join(DEVICE_ID_FILE);
+    match std::fs::read_to_string(&path) {
+        Ok(device_id) if !device_id.trim().is_empty() => return Ok(device_id.trim().to_string()),
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+        _ => {}
+    }
+    let device_id = random_id();
+    std::fs::create_dir_all(data_directory)?;
+    std::fs::write(&path, &device_id)?;
+    println!("[INFO]: Created sync device id {device_id}");
+    Ok(device_id)
+}
+
+fn random_id() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    data_encoding::HEXLOWER.encode(&bytes)
+}
+
+pub fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub enum RecordPayload {
+    Folder {
+        name: Option<String>,
+        icon: Option<String>,
+    },
+    Entry {
+        name: Option<String>,
+        icon: Option<String>,
+        folder: Option<String>,
+        deleted: bool,
+        tags: Vec<String>,
+    },
+    Section {
+        section_type: Option<String>,
+        data: Option<Vec<u8>>,
+    },
+}
+
+// A removed record travels as None
+fn payload_digest(payload: Option<&RecordPayload>) -> Result<Vec<u8>, SyncError> {
+    let encoded = Zeroizing::new(postcard::to_allocvec(&payload)?);
+    Ok(Sha256::digest(encoded.as_slice()).to_vec())
+}
+
+fn folder_key(sync_id: &str) -> String {
+    format!("folder/{sync_id}")
+}
+
+fn entry_key(sync_id: &str) -> String {
+    format!("entry/{sync_id}")
+}
+
+fn section_key(entry_sync_id: &str, section_name: &str) -> String {
+    format!("section/{entry_sync_id}/{section_name}")
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordChange {
+    key: String,
+    revision: String,
+    parent: Option<String>,
+    modified_at: i64,
+    payload: Option<RecordPayload>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChangeSet {
+    vault_id: String,
+    device_id: String,
+    sequence: i64,
+    changes: Vec<RecordChange>,
+}
+
+// sync_id, entry_name, entry_icon, folder sync_id, is_deleted
+type EntryRow = (String, Option<String>, Option<String>, Option<String>, bool);
+// entry sync_id, section_name, section_type, section_data
+type SectionRow = (String, String, Option<String>, Option<Vec<u8>>);
+// record_key, modified_at
+type EditRow = (String, Option<i64>);
+// record_key, revision, modified_at, device_id, digest
+type KnownRow = (String, Option<String>, i64, String, Vec<u8>);
+
+// The state of a record as last sent or received
+struct KnownRecord {
+    // None for placeholders created while applying a record that refers to one not seen yet
+    revision: Option<String>,
+    modified_at: i64,
+    device_id: String,
+    digest: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SyncSummary {
+    pub sent: usize,
+    pub received: usize,
+    pub conflicts: usize,
+    // Files of other devices that could not be read, they are tried again next time
+    pub unreadable: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncConflict {
+    pub conflict_id: i64,
+    pub label: String,
+    pub kept_device: String,
+    pub other_device: String,
+    pub other_modified_at: i64,
+}
+
+pub struct SyncIdentity {
+    pub vault_id: String,
+    pub key: Zeroizing<Vec<u8>>,
+    pub sequence: i64,
+}
+
+pub async fn sync_identity(connection: &mut SqliteConnection) -> Result<SyncIdentity, SyncError> {
+    let vault_id: String =
+        sqlx::query_scalar("SELECT state_value FROM SyncState WHERE state_key = 'vault_id';")
+            .fetch_one(&mut *connection)
+            .await?;
+    let key: Vec<u8> =
+        sqlx::query_scalar("SELECT state_value FROM SyncState WHERE state_key = 'key';")
+            .fetch_one(&mut *connection)
+            .await?;
+    let sequence: i64 =
+        sqlx::query_scalar("SELECT state_value FROM SyncState WHERE state_key = 'sequence';")
+            .fetch_one(&mut *connection)
+            .await?;
+    Ok(SyncIdentity {
+        vault_id,
+        key: Zeroizing::new(key),
+        sequence,
+    })
+}
+
+pub async fn get_sync_folder(vault_pool: SqlitePool) -> Result<Option<PathBuf>, VaultError> {
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    let folder: Option<String> =
+        sqlx::query_scalar("SELECT state_value FROM SyncState WHERE state_key = 'folder';")
+            .fetch_optional(connection.as_mut())
+            .await?;
+    Ok(folder.map(PathBuf::from))
+}
+
+pub async fn set_sync_folder(
+    vault_pool: SqlitePool,
+    folder: Option<PathBuf>,
+) -> Result<(), VaultError> {
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    match folder {
+        Some(folder) => {
+            sqlx::query(
+                "INSERT OR REPLACE INTO SyncState (state_key, state_value) VALUES ('folder', ?);",
+            )
+            .bind(folder.to_string_lossy().into_owned())
+            .execute(connection.as_mut())
+            .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM SyncState WHERE state_key = 'folder';")
+                .execute(connection.as_mut())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+// Settings of a remote sync target, WebDavSettings or S3Settings. They hold credentials and are
+// only ever kept inside the vault
+pub async fn get_sync_target_settings<T: DeserializeOwned>(
+    vault_pool: SqlitePool,
+    state_key: &str,
+) -> Result<Option<T>, VaultError> {
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    let settings: Option<Vec<u8>> =
+        sqlx::query_scalar("SELECT state_value FROM SyncState WHERE state_key = ?;")
+            .bind(state_key)
+            .fetch_optional(connection.as_mut())
+            .await?;
+    let Some(settings) = settings.map(Zeroizing::new) else {
+        return Ok(None);
+    };
+    Ok(Some(
+        postcard::from_bytes(&settings).map_err(|_| VaultError::CorruptDatabase)?,
+    ))
+}
+
+pub async fn set_sync_target_settings<T: Serialize>(
+    vault_pool: SqlitePool,
+    state_key: &'static str,
+    settings: Option<T>,
+) -> Result<(), VaultError> {
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    match settings {
+        Some(settings) => {
+            let settings =
+                Zeroizing::new(postcard::to_allocvec(&settings).map_err(|_| VaultError::Crypto)?);
+            sqlx::query("INSERT OR REPLACE INTO SyncState (state_key, state_value) VALUES (?, ?);")
+                .bind(state_key)
+                .bind(settings.as_slice())
+                .execute(connection.as_mut())
+                .await?;
+        }
+        None => {
+            sqlx::query("DELETE FROM SyncState WHERE state_key = ?;")
+                .bind(state_key)
+                .execute(connection.as_mut())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+// Every record of the vault, or only those of one folder or entry (with its sections)
+pub async fn load_records(
+    connection: &mut SqliteConnection,
+    sync_id: Option<&str>,
+) -> Result<BTreeMap<String, RecordPayload>, SyncError> {
+    let mut records = BTreeMap::new();
+
+    let folders: Vec<(String, Option<String>, Option<String>)> = sqlx::query_as(
+        "SELECT SyncIds.sync_id, Folders.folder_name, Folders.folder_icon FROM Folders
+        JOIN SyncIds ON SyncIds.record_table = 'folder' AND SyncIds.local_id = Folders.folder_id
+        WHERE ? IS NULL OR SyncIds.sync_id = ?;",
+    )
+    .bind(sync_id)
+    .bind(sync_id)
+    .fetch_all(&mut *connection)
+    .await?;
+    for (folder_id, name, icon) in folders {
+        records.insert(folder_key(&folder_id), RecordPayload::Folder { name, icon });
+    }
+
+    let entries: Vec<EntryRow> = sqlx::query_as(
+        "SELECT EntryIds.sync_id, Entries.entry_name, Entries.entry_icon, FolderIds.sync_id,
+            Entries.is_deleted FROM Entries
+            JOIN SyncIds AS EntryIds
+                ON EntryIds.record_table = 'entry' AND EntryIds.local_id = Entries.entry_id
+            LEFT JOIN SyncIds AS FolderIds
+                ON FolderIds.record_table = 'folder' AND FolderIds.local_id = Entries.folder_id
+            WHERE ? IS NULL OR EntryIds.sync_id = ?;",
+    )
+    .bind(sync_id)
+    .bind(sync_id)
+    .fetch_all(&mut *connection)
+    .await?;
+    let tags: Vec<(String, String)> = sqlx::query_as(
+        "SELECT SyncIds.sync_id, EntryTags.tag_name FROM EntryTags
+        JOIN SyncIds ON SyncIds.record_table = 'entry' AND SyncIds.local_id = EntryTags.entry_id
+        WHERE EntryTags.tag_name IS NOT NULL AND (? IS NULL OR SyncIds.sync_id = ?)
+        ORDER BY EntryTags.tag_id;",
+    )
+    .bind(sync_id)
+    .bind(sync_id)
+    .fetch_all(&mut *connection)
+    .await?;
+    let mut entry_tags: HashMap<String, Vec<String>> = HashMap::new();
+    for (entry_id, tag) in tags {
+        entry_tags.entry(entry_id).or_default().push(tag);
+    }
+    for (entry_id, name, icon, folder, deleted) in entries {
+        let tags = entry_tags.remove(&entry_id).unwrap_or_default();
+        records.insert(
+            entry_key(&entry_id),
+            RecordPayload::Entry {
+                name,
+                icon,
+                folder,
+                deleted,
+                tags,
+            },
+        );
+    }
+
+    let sections: Vec<SectionRow> = sqlx::query_as(
+        "SELECT SyncIds.sync_id, EntryData.section_name, EntryData.section_type,
+        EntryData.section_data FROM EntryData
+        JOIN SyncIds ON SyncIds.record_table = 'entry' AND SyncIds.local_id = EntryData.entry_id
+        WHERE EntryData.section_name IS NOT NULL AND (? IS NULL OR SyncIds.sync_id = ?);",
+    )
+    .bind(sync_id)
+    .bind(sync_id)
+    .fetch_all(&mut *connection)
+    .await?;
+    for (entry_id, section_name, section_type, data) in sections {
+        records.insert(
+            section_key(&entry_id, &section_name),
+            RecordPayload::Section { section_type, data },
+        );
+    }
+
+    Ok(records)
+}
+
+// When each record was last changed. Removed sections count from their removal, which is when
+// their last value went to SectionHistory
+async fn edit_times(connection: &mut SqliteConnection) -> Result<HashMap<String, i64>, SyncError> {
+    let rows: Vec<EditRow> = sqlx::query_as(
+        "SELECT 'folder/' || SyncIds.sync_id, Folders.modified_at FROM Folders
+        JOIN SyncIds ON SyncIds.record_table = 'folder' AND SyncIds.local_id = Folders.folder_id
+        UNION ALL
+        SELECT 'entry/' || SyncIds.sync_id, Entries.modified_at FROM Entries
+        JOIN SyncIds ON SyncIds.record_table = 'entry' AND SyncIds.local_id = Entries.entry_id
+        UNION ALL
+        SELECT 'section/' || SyncIds.sync_id || '/' || SectionHistory.section_name,
+            MAX(SectionHistory.replaced_at) FROM SectionHistory
+        JOIN SyncIds ON SyncIds.record_table = 'entry' AND SyncIds.local_id = SectionHistory.entry_id
+        WHERE SectionHistory.section_name IS NOT NULL
+        GROUP BY SyncIds.sync_id, SectionHistory.section_name
+        UNION ALL
+        SELECT 'section/' || SyncIds.sync_id || '/' || EntryData.section_name, EntryData.modified_at
+        FROM EntryData
+        JOIN SyncIds ON SyncIds.record_table = 'entry' AND SyncIds.local_id = EntryData.entry_id
+        WHERE EntryData.section_name IS NOT NULL;",
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+    let mut edited: HashMap<String, i64> = HashMap::new();
+    for (key, modified_at) in rows {
+        if let Some(modified_at) = modified_at {
+            let edited_at = edited.entry(key).or_insert(modified_at);
+            *edited_at = (*edited_at).max(modified_at);
+        }
+    }
+    Ok(edited)
+}
+
+async fn load_record(
+    connection: &mut SqliteConnection,
+    key: &str,
+) -> Result<Option<RecordPayload>, SyncError> {
+    let Some(sync_id) = key.split('/').nth(1) else {
+        return Ok(None);
+    };
+    Ok(load_records(connection, Some(sync_id)).await?.remove(key))
+}
+
+async fn known_records(
+    connection: &mut SqliteConnection,
+) -> Result<HashMap<String, KnownRecord>, SyncError> {
+    let rows: Vec<KnownRow> = sqlx::query_as(
+        "SELECT record_key, revision, modified_at, device_id, digest FROM SyncRecords;",
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(key, revision, modified_at, device_id, digest)| {
+            (
+                key,
+                KnownRecord {
+                    revision,
+                    modified_at,
+                    device_id,
+                    digest,
+                },
+            )
+        })
+        .collect())
+}
+
+async fn remember_record(
+    connection: &mut SqliteConnection,
+    key: &str,
+    known: &KnownRecord,
+) -> Result<(), SyncError> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO SyncRecords (record_key, revision, modified_at, device_id, digest)
+        VALUES (?, ?, ?, ?, ?);",
+    )
+    .bind(key)
+    .bind(&known.revision)
+    .bind(known.modified_at)
+    .bind(&known.device_id)
+    .bind(&known.digest)
+    .execute(&mut *connection)
+    .await?;
+    if let Some(revision) = &known.revision {
+        sqlx::query("INSERT OR IGNORE INTO SyncSeenRevisions (revision) VALUES (?);")
+            .bind(revision)
+            .execute(&mut *connection)
+            .await?;
+    }
+    Ok(())
+}
+
+// Local id of a folder or entry, one that was not seen yet is created empty until its own record
+// arrives. The placeholder is remembered as already synced so it is never sent back
+async fn local_id(
+    connection: &mut SqliteConnection,
+    record_table: &str,
+    sync_id: &str,
+) -> Result<i64, SyncError> {
+    let found: Option<i64> =
+        sqlx::query_scalar("SELECT local_id FROM SyncIds WHERE record_table = ? AND sync_id = ?;")
+            .bind(record_table)
+            .bind(sync_id)
+            .fetch_optional(&mut *connection)
+            .await?;
+    if let Some(found) = found {
+        return Ok(found);
+    }
+
+    let (insert, key, placeholder) = match record_table {
+        "folder" => (
+            "INSERT INTO Folders (folder_name, folder_icon) VALUES (NULL, NULL);",
+            folder_key(sync_id),
+            RecordPayload::Folder {
+                name: None,
+                icon: None,
+            },
+        ),
+        _ => (
+            "INSERT INTO Entries (entry_name, entry_icon) VALUES (NULL, NULL);",
+            entry_key(sync_id),
+            RecordPayload::Entry {
+                name: None,
+                icon: None,
+                folder: None,
+                deleted: false,
+                tags: Vec::new(),
+            },
+        ),
+    };
+    let local_id = sqlx::query(insert)
+        .execute(&mut *connection)
+        .await?
+        .last_insert_rowid();
+    // The insert trigger handed out a fresh id, the record keeps the one it has everywhere else
+    sqlx::query("UPDATE SyncIds SET sync_id = ? WHERE record_table = ? AND local_id = ?;")
+        .bind(sync_id)
+        .bind(record_table)
+        .bind(local_id)
+        .execute(&mut *connection)
+        .await?;
+    remember_record(
+        connection,
+        &key,
+        &KnownRecord {
+            revision: None,
+            modified_at: 0,
+            device_id: String::new(),
+            digest: payload_digest(Some(&placeholder))?,
+        },
+    )
+    .await?;
+    Ok(local_id)
+}
+
+// Writes a record into the vault tables the way a local edit made at modified_at would
+pub async fn apply_payload(
+    connection: &mut SqliteConnection,
+    key: &str,
+    payload: Option<&RecordPayload>,
+    modified_at: i64,
+) -> Result<(), SyncError> {
+    let mut parts = key.splitn(3, '/');
+    match (parts.next(), parts.next(), parts.next(), payload) {
+        (Some("folder"), Some(sync_id), None, Some(RecordPayload::Folder { name, icon })) => {
+            let folder_id = local_id(connection, "folder", sync_id).await?;
+            // Cleared first, so the FolderModified trigger sees modified_at being set and keeps it
+            sqlx::query("UPDATE Folders SET modified_at = NULL WHERE folder_id = ?;")
+                .bind(folder_id)
+                .execute(&mut *connection)
+                .await?;
+            sqlx::query(
+                "UPDATE Folders SET folder_name = ?, folder_icon = ?, modified_at = ?
+                WHERE folder_id = ?;",
+            )
+            .bind(name)
+            .bind(icon)
+            .bind(modified_at)
+            .bind(folder_id)
+            .execute(&mut *connection)
+            .await?;
+        }
+        (
+            Some("entry"),
+            Some(sync_id),
+            None,
+            Some(RecordPayload::Entry {
+                name,
+                icon,
+                folder,
+                deleted,
+                tags,
+            }),
+        ) => {
+            let entry_id = local_id(connection, "entry", sync_id).await?;
+            let folder_id = match folder {
+                Some(folder) => Some(local_id(connection, "folder", folder).await?),
+                None => None,
+            };
+            // Tags first, their triggers stamp the entry with the current time
+            sqlx::query("DELETE FROM EntryTags WHERE entry_id = ?;")
+                .bind(entry_id)
+                .execute(&mut *connection)
+                .await?;
+            for tag in tags {
+                sqlx::query("INSERT INTO EntryTags (tag_name, entry_id) VALUES (?, ?);")
+                    .bind(tag)
+                    .bind(entry_id)
+                    .execute(&mut *connection)
+                    .await?;
+            }
+            sqlx::query("UPDATE Entries SET modified_at = NULL WHERE entry_id = ?;")
+                .bind(entry_id)
+                .execute(&mut *connection)
+                .await?;
+            sqlx::query(
+                "UPDATE Entries SET entry_name = ?, entry_icon = ?, folder_id = ?, is_deleted = ?,
+                modified_at = ? WHERE entry_id = ?;",
+            )
+            .bind(name)
+            .bind(icon)
+            .bind(folder_id)
+            .bind(deleted)
+            .bind(modified_at)
+            .bind(entry_id)
+            .execute(&mut *connection)
+            .await?;
+        }
+        (Some("section"), Some(sync_id), Some(section_name), payload) => {
+            let entry_id = local_id(connection, "entry", sync_id).await?;
+            // Same bookkeeping as set_entry_section and remove_entry_section
+            sqlx::query(
+                "INSERT INTO SectionHistory
+                (entry_id, section_name, section_type, section_data, replaced_at)
+                SELECT entry_id, section_name, section_type, section_data, ? FROM EntryData
+                WHERE entry_id = ? AND section_name = ?;",
+            )
+            .bind(modified_at)
+            .bind(entry_id)
+            .bind(section_name)
+            .execute(&mut *connection)
+            .await?;
+            sqlx::query("DELETE FROM EntryData WHERE entry_id = ? AND section_name = ?;")
+                .bind(entry_id)
+                .bind(section_name)
+                .execute(&mut *connection)
+                .await?;
+            if let Some(RecordPayload::Section { section_type, data }) = payload {
+                sqlx::query(
+                    "INSERT INTO EntryData
+                    (section_name, section_type, section_data, entry_id, modified_at)
+                    VALUES (?, ?, ?, ?, ?);",
+                )
+                .bind(section_name)
+                .bind(section_type)
+                .bind(data)
+                .bind(entry_id)
+                .bind(modified_at)
+                .execute(&mut *connection)
+                .await?;
+            }
+        }
+        // Folders and entries are never removed, only marked deleted
+        (Some("folder" | "entry"), _, _, None) => {}
+        _ => println!("[ERROR]: Ignoring the malformed sync record {key}"),
+    }
+    Ok(())
+}
+
+// What the conflict review shows instead of the record key
+async fn record_label(connection: &mut SqliteConnection, key: &str) -> Result<String, SyncError> {
+    let mut parts = key.splitn(3, '/');
+    let (kind, sync_id, section_name) = (parts.next(), parts.next(), parts.next());
+    let name: Option<String> = match kind {
+        Some("folder") => {
+            sqlx::query_scalar(
+                "SELECT Folders.folder_name FROM Folders JOIN SyncIds
+                ON SyncIds.record_table = 'folder' AND SyncIds.local_id = Folders.folder_id
+                WHERE SyncIds.sync_id = ?;",
+            )
+            .bind(sync_id)
+            .fetch_optional(&mut *connection)
+            .await?
+        }
+        _ => {
+            sqlx::query_scalar(
+                "SELECT Entries.entry_name FROM Entries JOIN SyncIds
+                ON SyncIds.record_table = 'entry' AND SyncIds.local_id = Entries.entry_id
+                WHERE SyncIds.sync_id = ?;",
+            )
+            .bind(sync_id)
+            .fetch_optional(&mut *connection)
+            .await?
+        }
+    };
+    let name = name.unwrap_or_else(|| "Unnamed".to_string());
+    Ok(match (kind, section_name) {
+        (Some("folder"), _) => format!("Folder \"{name}\""),
+        (_, Some(section_name)) => format!("\"{section_name}\" of \"{name}\""),
+        _ => format!("Entry \"{name}\""),
+    })
+}
+
+fn seal(identity: &SyncIdentity, path: &str, change_set: &ChangeSet) -> Result<Vec<u8>, SyncError> {
+    let plaintext = Zeroizing::new(postcard::to_allocvec(change_set)?);
+    let cipher = XChaCha20Poly1305::new_from_slice(&identity.key).map_err(|_| SyncError::Crypto)?;
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    // The path is authenticated, a file moved to another device's directory does not open
+    let ciphertext = cipher.encrypt(
+        &nonce,
+        Payload {
+            msg: &plaintext,
+            aad: path.as_bytes(),
+        },
+    )?;
+
+    let mut sealed = CHANGE_SET_MAGIC.to_vec();
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+fn open(identity: &SyncIdentity, path: &str, sealed: &[u8]) -> Result<ChangeSet, SyncError> {
+    let Some(sealed) = sealed.strip_prefix(CHANGE_SET_MAGIC.as_slice()) else {
+        return Err(SyncError::Format(format!("{path} is not a change set")));
+    };
+    if sealed.len() < 24 {
+        return Err(SyncError::Format(format!("{path} is truncated")));
+    }
+    let (nonce, ciphertext) = sealed.split_at(24);
+    let cipher = XChaCha20Poly1305::new_from_slice(&identity.key).map_err(|_| SyncError::Crypto)?;
+    let plaintext = Zeroizing::new(cipher.decrypt(
+        XNonce::from_slice(nonce),
+        Payload {
+            msg: ciphertext,
+            aad: path.as_bytes(),
+        },
+    )?);
+    Ok(postcard::from_bytes(&plaintext)?)
+}
+
+// Parses "<vault id>/<device id>/<sequence>.klsync"
+fn change_set_path(path: &str) -> Option<(&str, i64)> {
+    let mut parts = path.split('/');
+    let (_, device_id, file_name) = (parts.next()?, parts.next()?, parts.next()?);
+    let sequence = file_name
+        .strip_suffix(CHANGE_SET_EXTENSION)?
+        .strip_suffix('.')?
+        .parse()
+        .ok()?;
+    parts.next().is_none().then_some((device_id, sequence))
+}
+
+async fn send_changes(
+    store: &impl SyncStore,
+    vault_pool: &SqlitePool,
+    identity: &SyncIdentity,
+    device_id: &str,
+) -> Result<usize, SyncError> {
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    let records = load_records(connection.as_mut(), None).await?;
+    let known = known_records(connection.as_mut()).await?;
+    let edited = edit_times(connection.as_mut()).await?;
+    drop(connection);
+
+    // Later than the revision it replaces, which is the latest this device has seen of the record
+    let now = unix_now();
+    let stamp = |key: &str, parent: Option<&KnownRecord>| {
+        let edited_at = edited.get(key).copied().unwrap_or(now);
+        parent.map_or(edited_at, |parent| edited_at.max(parent.modified_at + 1))
+    };
+    let mut changes = Vec::new();
+    for (key, payload) in &records {
+        let digest = payload_digest(Some(payload))?;
+        let parent = known.get(key);
+        if parent.is_some_and(|parent| parent.digest == digest) {
+            continue;
+        }
+        changes.push((
+            RecordChange {
+                key: key.clone(),
+                revision: random_id(),
+                parent: parent.and_then(|parent| parent.revision.clone()),
+                modified_at: stamp(key, parent),
+                payload: Some(payload.clone()),
+            },
+            digest,
+        ));
+    }
+    let removed_digest = payload_digest(None)?;
+    for (key, parent) in &known {
+        if records.contains_key(key) || parent.digest == removed_digest {
+            continue;
+        }
+        changes.push((
+            RecordChange {
+                key: key.clone(),
+                revision: random_id(),
+                parent: parent.revision.clone(),
+                modified_at: stamp(key, Some(parent)),
+                payload: None,
+            },
+            removed_digest.clone(),
+        ));
+    }
+    if changes.is_empty() {
+        return Ok(0);
+    }
+
+    let sequence = identity.sequence + 1;
+    let path = format!(
+        "{}/{device_id}/{sequence:012}.{CHANGE_SET_EXTENSION}",
+        identity.vault_id
+    );
+    let (changes, digests): (Vec<RecordChange>, Vec<Vec<u8>>) = changes.into_iter().unzip();
+    let change_set = ChangeSet {
+        vault_id: identity.vault_id.clone(),
+        device_id: device_id.to_string(),
+        sequence,
+        changes,
+    };
+    store
+        .write(&path, &seal(identity, &path, &change_set)?)
+        .await?;
+
+    // Edits made while the file was written differ from the digests kept here and go out next time
+    let mut transaction = vault_pool.begin().await?;
+    for (change, digest) in change_set.changes.iter().zip(digests) {
+        remember_record(
+            &mut transaction,
+            &change.key,
+            &KnownRecord {
+                revision: Some(change.revision.clone()),
+                modified_at: change.modified_at,
+                device_id: device_id.to_string(),
+                digest,
+            },
+        )
+        .await?;
+    }
+    sqlx::query("UPDATE SyncState SET state_value = ? WHERE state_key = 'sequence';")
+        .bind(sequence)
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(change_set.changes.len())
+}
+
+async fn receive_changes(
+    store: &impl SyncStore,
+    vault_pool: &SqlitePool,
+    identity: &SyncIdentity,
+    device_id: &str,
+    summary: &mut SyncSummary,
+) -> Result<(), SyncError> {
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    let applied: HashSet<String> = sqlx::query_scalar("SELECT file_path FROM SyncAppliedFiles;")
+        .fetch_all(connection.as_mut())
+        .await?
+        .into_iter()
+        .collect();
+    drop(connection);
+
+    let mut change_sets = Vec::new();
+    for path in store.list(&identity.vault_id).await? {
+        let Some((file_device, sequence)) = change_set_path(&path) else {
+            continue;
+        };
+        if file_device == device_id || applied.contains(&path) {
+            continue;
+        }
+        let opened = store
+            .read(&path)
+            .await
+            .and_then(|sealed| open(identity, &path, &sealed));
+        match opened {
+            Ok(change_set)
+                if change_set.vault_id == identity.vault_id
+                    && change_set.device_id == file_device
+                    && change_set.sequence == sequence =>
+            {
+                change_sets.push((path, change_set));
+            }
+            Ok(_) => {
+                println!("[ERROR]: The change set {path} does not match its location");
+                summary.unreadable += 1;
+            }
+            Err(error) => {
+                println!("[ERROR]: Could not open the change set {path}: {error}");
+                summary.unreadable += 1;
+            }
+        }
+    }
+    if change_sets.is_empty() {
+        return Ok(());
+    }
+
+    // The outcome does not depend on the order, this one replaces every version at most once in
+    // the usual case where each change set holds newer revisions than the ones before it
+    let mut changes: Vec<(&str, i64, usize, &RecordChange)> = change_sets
+        .iter()
+        .flat_map(|(_, change_set)| {
+            change_set
+                .changes
+                .iter()
+                .enumerate()
+                .map(|(position, change)| {
+                    (
+                        change_set.device_id.as_str(),
+                        change_set.sequence,
+                        position,
+                        change,
+                    )
+                })
+        })
+        .collect();
+    changes.sort_by(|a, b| (a.3.modified_at, a.0, a.1, a.2).cmp(&(b.3.modified_at, b.0, b.1, b.2)));
+
+    let mut transaction = vault_pool.begin().await?;
+    let mut known = known_records(&mut transaction).await?;
+    for (change_device, _, _, change) in changes {
+        let seen: Option<String> =
+            sqlx::query_scalar("SELECT revision FROM SyncSeenRevisions WHERE revision = ?;")
+                .bind(&change.revision)
+                .fetch_optional(&mut *transaction)
+                .await?;
+        if seen.is_some() {
+            continue;
+        }
+
+        let digest = payload_digest(change.payload.as_ref())?;
+        let incoming = KnownRecord {
+            revision: Some(change.revision.clone()),
+            modified_at: change.modified_at,
+            device_id: change_device.to_string(),
+            digest,
+        };
+        let local = known.get(&change.key);
+        let incoming_wins = match local {
+            Some(local) if local.revision.is_some() => {
+                (
+                    incoming.modified_at,
+                    &incoming.device_id,
+                    &incoming.revision,
+                ) > (local.modified_at, &local.device_id, &local.revision)
+            }
+            _ => true,
+        };
+        // A change made on top of the local revision replaced it knowingly
+        let conflicting = local.is_some_and(|local| {
+            local.revision.is_some()
+                && local.revision != change.parent
+                && local.digest != incoming.digest
+        });
+
+        if incoming_wins {
+            let replaced = match conflicting {
+                true => Some(load_record(&mut transaction, &change.key).await?),
+                false => None,
+            };
+            apply_payload(
+                &mut transaction,
+                &change.key,
+                change.payload.as_ref(),
+                change.modified_at,
+            )
+            .await?;
+            if let (Some(replaced), Some(local)) = (replaced, local) {
+                record_conflict(
+                    &mut transaction,
+                    &change.key,
+                    &incoming.device_id,
+                    &local.device_id,
+                    local.modified_at,
+                    replaced.as_ref(),
+                )
+                .await?;
+                summary.conflicts += 1;
+            }
+            remember_record(&mut transaction, &change.key, &incoming).await?;
+            known.insert(change.key.clone(), incoming);
+        } else {
+            if conflicting {
+                let kept_device = local
+                    .map(|local| local.device_id.clone())
+                    .unwrap_or_default();
+                record_conflict(
+                    &mut transaction,
+                    &change.key,
+                    &kept_device,
+                    &incoming.device_id,
+                    incoming.modified_at,
+                    change.payload.as_ref(),
+                )
+                .await?;
+                summary.conflicts += 1;
+            }
+            sqlx::query("INSERT OR IGNORE INTO SyncSeenRevisions (revision) VALUES (?);")
+                .bind(&change.revision)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        summary.received += 1;
+    }
+    for (path, _) in &change_sets {
+        sqlx::query("INSERT OR IGNORE INTO SyncAppliedFiles (file_path) VALUES (?);")
+            .bind(path)
+            .execute(&mut *transaction)
+            .await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+pub async fn record_conflict(
+    connection: &mut SqliteConnection,
+    key: &str,
+    kept_device: &str,
+    other_device: &str,
+    other_modified_at: i64,
+    other_payload: Option<&RecordPayload>,
+) -> Result<(), SyncError> {
+    let label = record_label(connection, key).await?;
+    let other_payload = Zeroizing::new(postcard::to_allocvec(&other_payload)?);
+    sqlx::query(
+        "INSERT INTO SyncConflicts
+        (record_key, label, kept_device, other_device, other_modified_at, other_payload)
+        VALUES (?, ?, ?, ?, ?, ?);",
+    )
+    .bind(key)
+    .bind(label)
+    .bind(kept_device)
+    .bind(other_device)
+    .bind(other_modified_at)
+    .bind(other_payload.as_slice())
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+// Sends the local changes, then merges those of every other device
+pub async fn sync_vault(
+    store: &impl SyncStore,
+    vault_pool: SqlitePool,
+    device_id: String,
+) -> Result<SyncSummary, SyncError> {
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    let identity = sync_identity(connection.as_mut()).await?;
+    drop(connection);
+
+    let mut summary = SyncSummary {
+        sent: send_changes(store, &vault_pool, &identity, &device_id).await?,
+        ..SyncSummary::default()
+    };
+    receive_changes(store, &vault_pool, &identity, &device_id, &mut summary).await?;
+    println!(
+        "[INFO]: Synced vault {}, sent {} and received {} changes with {} conflicts",
+        identity.vault_id, summary.sent, summary.received, summary.conflicts
+    );
+    Ok(summary)
+}
+
+pub async fn sync_vault_to(
+    target: SyncTarget,
+    vault_pool: SqlitePool,
+    device_id: String,
+) -> Result<SyncSummary, SyncError> {
+    match target {
+        SyncTarget::Folder(root) => sync_vault(&FolderStore { root }, vault_pool, device_id).await,
+        SyncTarget::WebDav(settings) => {
+            sync_vault(&WebDavStore::new(settings)?, vault_pool, device_id).await
+        }
+        SyncTarget::S3(settings) => {
+            sync_vault(&S3Store::new(settings)?, vault_pool, device_id).await
+        }
+        SyncTarget::Cloud(service, settings) => {
+            let transport = ReqwestTransport::new()?;
+            let endpoints = service.endpoints();
+            sync_vault_to_cloud(
+                &transport, service, endpoints, settings, vault_pool, device_id,
+            )
+            .await
+        }
+    }
+}
+
+pub async fn get_sync_conflicts(vault_pool: SqlitePool) -> Result<Vec<SyncConflict>, VaultError> {
+    let mut connection = vault_pool.acquire().await?;
+    connection.close_on_drop();
+    let rows: Vec<(i64, String, String, String, i64)> = sqlx::query_as(
+        "SELECT conflict_id, label, kept_device, other_device, other_modified_at
+        FROM SyncConflicts ORDER BY conflict_id;",
+    )
+    .fetch_all(connection.as_mut())
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(
+            |(conflict_id, label, kept_device, other_device, other_modified_at)| SyncConflict {
+                conflict_id,
+                label,
+                kept_device,
+                other_device,
+                other_modified_at,
+            },
+        )
+        .collect())
+}
+
+// Restoring the other version is a local edit, the next sync sends it to every device
+pub async fn resolve_sync_conflict(
+    vault_pool: SqlitePool,
+    conflict_id: i64,
+    restore_other: bool,
+) -> Result<(), SyncError> {
+    let mut transaction = vault_pool.begin().await?;
+    if restore_other {
+        let (key, other_payload): (String, Vec<u8>) = sqlx::query_as(
+            "SELECT record_key, other_payload FROM SyncConflicts WHERE conflict_id = ?;",
+        )
+        .bind(conflict_id)
+        .fetch_one(&mut *transaction)
+        .await?;
+        let other_payload = Zeroizing::new(other_payload);
+        let payload: Option<RecordPayload> = postcard::from_bytes(&other_payload)?;
+        apply_payload(&mut transaction, &key, payload.as_ref(), unix_now()).await?;
+    }
+    sqlx::query("DELETE FROM SyncConflicts WHERE conflict_id = ?;")
+        .bind(conflict_id)
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ScratchDirectory, ScratchVault};
+
+    // Copies of a vault holding the entry "Mail" with the password "first", made after the first
+    // copy synced it. Device i syncs as the i-th letter
+    async fn devices(name: &str, count: usize) -> (ScratchDirectory, Vec<ScratchVault>, i64) {
+        let store = ScratchDirectory::new(&format!("sync-{name}"));
+        let first = ScratchVault::new(&format!("sync-{name}-a")).await;
+        let entry_id = first
+            .entry("Mail", &[("Password", "password", b"first")])
+            .await;
+        sync(&store, &first, 0).await;
+        let mut vaults = vec![first];
+        for device in 1..count {
+            let copy = vaults[0].copy(&format!("sync-{name}-{device}")).await;
+            vaults.push(copy);
+        }
+        (store, vaults, entry_id)
+    }
+
+    async fn sync(store: &ScratchDirectory, vault: &ScratchVault, device: usize) -> SyncSummary {
+        let device_id = ["a", "b", "c"][device].to_string();
+        let store = FolderStore {
+            root: store.0.clone(),
+        };
+        sync_vault(&store, vault.vault_pool.clone(), device_id)
+            .await
+            .unwrap()
+    }
+
+    // A local edit made when the device's clock said edited_at
+    async fn edit(vault: &ScratchVault, entry_id: i64, data: &[u8], edited_at: i64) {
+        vault
+            .state
+            .clone()
+            .set_entry_section(
+                vault.vault_pool.clone(),
+                entry_id,
+                "Password".to_string(),
+                "password".to_string(),
+                Zeroizing::new(data.to_vec()),
+            )
+            .await
+            .unwrap();
+        sqlx::query("UPDATE EntryData SET modified_at = ? WHERE section_name = 'Password';")
+            .bind(edited_at)
+            .execute(&vault.vault_pool)
+            .await
+            .unwrap();
+    }
+
+    async fn remove(vault: &ScratchVault, entry_id: i64, removed_at: i64) {
+        vault
+            .state
+            .clone()
+            .remove_entry_section(vault.vault_pool.clone(), entry_id, "Password".to_string())
+            .await
+            .unwrap();
+        sqlx::query(
+            "UPDATE SectionHistory SET replaced_at = ?
+            WHERE history_id = (SELECT MAX(history_id) FROM SectionHistory);",
+        )
+        .bind(removed_at)
+        .execute(&vault.vault_pool)
+        .await
+        .unwrap();
+    }
+
+    async fn password(vault: &ScratchVault) -> Option<Vec<u8>> {
+        sqlx::query_scalar("SELECT section_data FROM EntryData WHERE section_name = 'Password';")
+            .fetch_optional(&vault.vault_pool)
+            .await
+            .unwrap()
+    }
+
+    async fn conflicts(vault: &ScratchVault) -> usize {
+        get_sync_conflicts(vault.vault_pool.clone())
+            .await
+            .unwrap()
+            .len()
+    }
+
+    #[tokio::test]
+    async fn concurrent_edits_settle_on_the_later_edit() {
+        let (store, vaults, entry_id) = devices("concurrent", 3).await;
+        let now = unix_now();
+        edit(&vaults[0], entry_id, b"from a", now + 20).await;
+        edit(&vaults[1], entry_id, b"from b", now + 10).await;
+
+        // b syncs last, yet a's later edit wins everywhere, whichever version a device saw first
+        sync(&store, &vaults[0], 0).await;
+        sync(&store, &vaults[2], 2).await;
+        sync(&store, &vaults[1], 1).await;
+        sync(&store, &vaults[0], 0).await;
+        sync(&store, &vaults[2], 2).await;
+        for vault in &vaults {
+            assert_eq!(password(vault).await.as_deref(), Some(b"from a".as_slice()));
+        }
+        // Every copy keeps b's version for review
+        for vault in &vaults {
+            assert_eq!(conflicts(vault).await, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn edits_on_a_device_that_is_behind_replace_what_they_were_made_on() {
+        let (store, vaults, entry_id) = devices("skewed", 3).await;
+        let now = unix_now();
+        // a's clock is an hour ahead, b's is right
+        edit(&vaults[0], entry_id, b"second", now + 3600).await;
+        sync(&store, &vaults[0], 0).await;
+        sync(&store, &vaults[1], 1).await;
+        assert_eq!(
+            password(&vaults[1]).await.as_deref(),
+            Some(b"second".as_slice())
+        );
+        edit(&vaults[1], entry_id, b"third", now).await;
+        sync(&store, &vaults[1], 1).await;
+
+        // c gets both revisions at once, a only the newer one
+        assert_eq!(sync(&store, &vaults[2], 2).await.received, 2);
+        sync(&store, &vaults[0], 0).await;
+        for vault in &vaults {
+            assert_eq!(password(vault).await.as_deref(), Some(b"third".as_slice()));
+            assert_eq!(conflicts(vault).await, 0);
+        }
+
+        // Nobody sends anything back, the copies agree
+        for (device, vault) in vaults.iter().enumerate() {
+            assert_eq!(sync(&store, vault, device).await.sent, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn removals_compete_like_edits() {
+        let (store, vaults, entry_id) = devices("removals", 3).await;
+        let now = unix_now();
+        remove(&vaults[0], entry_id, now + 10).await;
+        edit(&vaults[1], entry_id, b"kept", now + 20).await;
+        sync(&store, &vaults[0], 0).await;
+        sync(&store, &vaults[1], 1).await;
+        sync(&store, &vaults[0], 0).await;
+        sync(&store, &vaults[2], 2).await;
+        for vault in &vaults {
+            assert_eq!(password(vault).await.as_deref(), Some(b"kept".as_slice()));
+        }
+
+        // A later removal wins the same way, as does deleting the entry
+        remove(&vaults[2], entry_id, now + 30).await;
+        vaults[2]
+            .state
+            .clone()
+            .delete_entry(vaults[2].vault_pool.clone(), entry_id)
+            .await
+            .unwrap();
+        sync(&store, &vaults[2], 2).await;
+        sync(&store, &vaults[0], 0).await;
+        sync(&store, &vaults[1], 1).await;
+        for vault in &vaults {
+            assert_eq!(password(vault).await, None);
+            let deleted: bool =
+                sqlx::query_scalar("SELECT is_deleted FROM Entries WHERE entry_id = ?;")
+                    .bind(entry_id)
+                    .fetch_one(&vault.vault_pool)
+                    .await
+                    .unwrap();
+            assert!(deleted);
+        }
+    }
+}
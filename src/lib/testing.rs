@@ -0,0 +1,193 @@
+use crate::*;
+use sqlx::sqlite::SqliteConnectOptions;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+// Fixtures for the tests of the library and of the helpers in src/bin, Keylight itself never uses
+// them. Vaults made here are plain SQLite files without a key, in a directory of their own below
+// the temporary directory
+
+// A directory that is removed again with the value
+pub struct ScratchDirectory(pub PathBuf);
+
+impl ScratchDirectory {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "keylight-test-{name}-{}-{}",
+            std::process::id(),
+            unix_now()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for ScratchDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+pub struct ScratchVault {
+    pub state: Arc<ApplicationAsyncState>,
+    pub vault_pool: SqlitePool,
+    pub location: VaultLocation,
+    // Last, the pool is closed before its files go
+    pub directory: ScratchDirectory,
+}
+
+impl ScratchVault {
+    // An empty vault of the current schema with one folder, "Online"
+    pub async fn new(name: &str) -> Self {
+        let directory = ScratchDirectory::new(name);
+        let location = VaultLocation::in_directory(&directory.0, name);
+        let vault_pool = SqlitePool::connect_with(
+            SqliteConnectOptions::new()
+                .filename(&location.database_path)
+                .create_if_missing(true),
+        )
+        .await
+        .unwrap();
+        let mut connection = vault_pool.acquire().await.unwrap();
+        sqlx::query(
+            "CREATE TABLE Folders ( folder_id INTEGER PRIMARY KEY AUTOINCREMENT, folder_name VARCHAR(255), folder_icon VARCHAR(255) );
+            CREATE TABLE Entries ( entry_id INTEGER PRIMARY KEY AUTOINCREMENT, entry_name VARCHAR(255), entry_icon VARCHAR(255), is_deleted BOOLEAN DEFAULT FALSE);
+            CREATE TABLE EntryTags (tag_id INTEGER PRIMARY KEY AUTOINCREMENT, tag_name VARCHAR(255), entry_id int);
+            CREATE TABLE EntryData (data_id INTEGER PRIMARY KEY AUTOINCREMENT, section_name VARCHAR(255), section_type VARCHAR(255), section_data BLOB, entry_id int);
+            INSERT INTO Folders (folder_name, folder_icon) VALUES ('Online', 'default');
+            PRAGMA user_version = 1;",
+        )
+        .execute(connection.as_mut())
+        .await
+        .unwrap();
+        migrate_schema(connection.as_mut()).await.unwrap();
+        drop(connection);
+        fs::write(&location.keyfile_path, b"").unwrap();
+
+        let state = Arc::new(ApplicationAsyncState::new(StorageServices::new()));
+        Self {
+            state,
+            vault_pool,
+            location,
+            directory,
+        }
+    }
+
+    // Another device's copy of the vault, it shares the vault's sync id and key
+    pub async fn copy(&self, name: &str) -> Self {
+        let directory = ScratchDirectory::new(name);
+        let location = VaultLocation::in_directory(&directory.0, name);
+        sqlx::query("VACUUM INTO ?;")
+            .bind(location.database_path.to_string_lossy().into_owned())
+            .execute(&self.vault_pool)
+            .await
+            .unwrap();
+        let vault_pool =
+            SqlitePool::connect_with(SqliteConnectOptions::new().filename(&location.database_path))
+                .await
+                .unwrap();
+        fs::write(&location.keyfile_path, b"").unwrap();
+
+        let state = Arc::new(ApplicationAsyncState::new(StorageServices::new()));
+        Self {
+            state,
+            vault_pool,
+            location,
+            directory,
+        }
+    }
+
+    pub async fn folder_id(&self) -> i64 {
+        self.state
+            .clone()
+            .get_folders(self.vault_pool.clone())
+            .await
+            .unwrap()[0]
+            .folder_id
+    }
+
+    // An entry in the first folder with the given sections, each (name, type, data)
+    pub async fn entry(&self, entry_name: &str, sections: &[(&str, &str, &[u8])]) -> i64 {
+        let entry_id = self
+            .state
+            .clone()
+            .create_entry(
+                self.vault_pool.clone(),
+                self.folder_id().await,
+                entry_name.to_string(),
+            )
+            .await
+            .unwrap();
+        for (section_name, section_type, section_data) in sections {
+            self.state
+                .clone()
+                .set_entry_section(
+                    self.vault_pool.clone(),
+                    entry_id,
+                    section_name.to_string(),
+                    section_type.to_string(),
+                    Zeroizing::new(section_data.to_vec()),
+                )
+                .await
+                .unwrap();
+        }
+        entry_id
+    }
+}
+
+impl ScratchVault {
+    // Serves the vault like `keylight agent` and connects to it. Locking through the client stops
+    // the agent and removes its socket
+    pub async fn agent(&self) -> AgentClient {
+        let (state, vault_pool, location) = (
+            self.state.clone(),
+            self.vault_pool.clone(),
+            self.location.clone(),
+        );
+        tokio::spawn(async move { agent::serve_agent(state, vault_pool, &location, None).await });
+        for _ in 0..500 {
+            if let Some(client) = AgentClient::connect(&self.location).await {
+                return client;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("the agent did not start");
+    }
+}
+
+// A D-Bus daemon of its own, so no desktop service is touched
+pub struct PrivateBus {
+    daemon: Child,
+    pub address: String,
+}
+
+impl PrivateBus {
+    // None where dbus-daemon is not installed
+    pub fn start() -> Option<Self> {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--print-address", "--nofork"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let mut address = String::new();
+        std::io::BufReader::new(daemon.stdout.take()?)
+            .read_line(&mut address)
+            .ok()?;
+        Some(Self {
+            daemon,
+            address: address.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for PrivateBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
@@ -0,0 +1,410 @@
+use crate::*;
+use futures::{SinkExt, StreamExt};
+use iced::theme::Palette;
+use iced::Theme;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use zbus::zvariant::Value;
+
+// Custom themes are read from themes.toml next to settings.toml, one table per theme:
+//
+//   [Nord]
+//   base = "dark"
+//   background = "#2e3440"
+//   text = "#eceff4"
+//   primary = "#88c0d0"
+//   success = "#a3be8c"
+//   danger = "#bf616a"
+//
+// Colors that are left out come from the built-in light or dark palette picked by base, dark if
+// there is none. The file is checked for changes while it is in use, so edits show up right away
+
+const THEMES_FILE: &str = "themes.toml";
+pub const THEMES_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+const APPEARANCE_NAMESPACE: &str = "org.freedesktop.appearance";
+const COLOR_SCHEME_KEY: &str = "color-scheme";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThemeChoice {
+    // Light or Dark, following the desktop's preference
+    System,
+    // One of iced's themes, by name
+    BuiltIn(String),
+    // One of the themes in themes.toml, by name
+    Custom(String),
+}
+
+impl Default for ThemeChoice {
+    fn default() -> Self {
+        ThemeChoice::BuiltIn(Theme::Dark.to_string())
+    }
+}
+
+impl fmt::Display for ThemeChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThemeChoice::System => write!(f, "Follow the system"),
+            ThemeChoice::BuiltIn(name) => write!(f, "{name}"),
+            ThemeChoice::Custom(name) => write!(f, "{name} ({THEMES_FILE})"),
+        }
+    }
+}
+
+// The freedesktop appearance preference
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorScheme {
+    #[default]
+    NoPreference,
+    Dark,
+    Light,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PaletteBase {
+    Dark,
+    Light,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PaletteFile {
+    base: Option<PaletteBase>,
+    background: Option<String>,
+    text: Option<String>,
+    primary: Option<String>,
+    success: Option<String>,
+    danger: Option<String>,
+}
+
+impl PaletteFile {
+    fn palette(&self, name: &str) -> Result<Palette, String> {
+        let base = match self.base {
+            Some(PaletteBase::Light) => Palette::LIGHT,
+            Some(PaletteBase::Dark) | None => Palette::DARK,
+        };
+        let color = |value: &Option<String>, fallback| match value {
+            Some(value) => Color::parse(value.trim())
+                .ok_or_else(|| format!("\"{value}\" of {name} is not a color like #2e3440")),
+            None => Ok(fallback),
+        };
+        Ok(Palette {
+            background: color(&self.background, base.background)?,
+            text: color(&self.text, base.text)?,
+            primary: color(&self.primary, base.primary)?,
+            success: color(&self.success, base.success)?,
+            danger: color(&self.danger, base.danger)?,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct CustomThemes {
+    pub themes: Vec<Theme>,
+    // Why the file was not read, the themes of the last readable version are kept meanwhile
+    pub error: Option<String>,
+    modified: Option<SystemTime>,
+}
+
+pub fn themes_path(config_directory: &Path) -> PathBuf {
+    config_directory.join(THEMES_FILE)
+}
+
+fn read_themes(path: &Path) -> Result<Vec<Theme>, String> {
+    let contents = fs::read_to_string(path).map_err(|error| error.to_string())?;
+    let palettes: BTreeMap<String, PaletteFile> =
+        toml::from_str(&contents).map_err(|error| error.message().to_string())?;
+    palettes
+        .iter()
+        .map(|(name, palette)| Ok(Theme::custom(name.clone(), palette.palette(name)?)))
+        .collect()
+}
+
+impl CustomThemes {
+    // Returns whether the themes were read again, only when the file changed since the last time
+    pub fn reload(&mut self, config_directory: &Path) -> bool {
+        let path = themes_path(config_directory);
+        let modified = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+
+        if modified.is_none() {
+            self.themes.clear();
+            self.error = None;
+            return true;
+        }
+        match read_themes(&path) {
+            Ok(themes) => {
+                println!(
+                    "[INFO]: Read {} themes from {}",
+                    themes.len(),
+                    path.display()
+                );
+                self.themes = themes;
+                self.error = None;
+            }
+            Err(error) => {
+                println!("[ERROR]: Could not read {}: {error}", path.display());
+                self.error = Some(format!("Could not read {}: {error}", path.display()));
+            }
+        }
+        true
+    }
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.portal.Settings",
+    default_service = "org.freedesktop.portal.Desktop",
+    default_path = "/org/freedesktop/portal/desktop"
+)]
+trait PortalSettings {
+    fn read_one(&self, namespace: &str, key: &str) -> zbus::Result<zbus::zvariant::OwnedValue>;
+    fn read(&self, namespace: &str, key: &str) -> zbus::Result<zbus::zvariant::OwnedValue>;
+
+    #[zbus(signal)]
+    fn setting_changed(&self, namespace: &str, key: &str, value: Value<'_>) -> zbus::Result<()>;
+}
+
+fn color_scheme(value: &Value) -> Option<ColorScheme> {
+    match value {
+        Value::U32(0) => Some(ColorScheme::NoPreference),
+        Value::U32(1) => Some(ColorScheme::Dark),
+        Value::U32(2) => Some(ColorScheme::Light),
+        // The deprecated Read wraps the value in one more variant
+        Value::Value(value) => color_scheme(value),
+        _ => None,
+    }
+}
+
+// Sends the desktop's color scheme and every change to it, silently gives up without a portal
+fn portal_color_scheme() -> impl futures::Stream<Item = ColorScheme> {
+    iced::stream::channel(10, |mut output| async move {
+        let connection = match zbus::Connection::session().await {
+            Ok(connection) => connection,
+            Err(error) => {
+                println!("[ERROR]: Could not connect to the session bus: {:?}", error);
+                return;
+            }
+        };
+        let Ok(portal) = PortalSettingsProxy::new(&connection).await else {
+            return;
+        };
+        // Subscribed before reading, so a change in between is not missed
+        let Ok(mut changes) = portal.receive_setting_changed().await else {
+            println!("[ERROR]: Could not subscribe to desktop portal settings");
+            return;
+        };
+
+        let current = match portal
+            .read_one(APPEARANCE_NAMESPACE, COLOR_SCHEME_KEY)
+            .await
+        {
+            Ok(value) => Ok(value),
+            Err(_) => portal.read(APPEARANCE_NAMESPACE, COLOR_SCHEME_KEY).await,
+        };
+        match current {
+            Ok(value) => {
+                if let Some(scheme) = color_scheme(&value) {
+                    let _ = output.send(scheme).await;
+                }
+            }
+            Err(error) => {
+                println!(
+                    "[ERROR]: Could not read the color scheme from the desktop portal: {:?}",
+                    error
+                );
+            }
+        }
+
+        println!("[INFO]: Following the desktop color scheme");
+        while let Some(signal) = changes.next().await {
+            let Ok(args) = signal.args() else {
+                continue;
+            };
+            if args.namespace != APPEARANCE_NAMESPACE || args.key != COLOR_SCHEME_KEY {
+                continue;
+            }
+            if let Some(scheme) = color_scheme(&args.value) {
+                if output.send(scheme).await.is_err() {
+                    break;
+                }
+            }
+        }
+    })
+}
+
+impl Keylight {
+    // A theme that is gone, such as a custom one removed from the file, falls back to Dark
+    pub(crate) fn theme(&self) -> Theme {
+        match &self.settings.theme {
+            ThemeChoice::System => match self.color_scheme {
+                ColorScheme::Light => Theme::Light,
+                ColorScheme::Dark | ColorScheme::NoPreference => Theme::Dark,
+            },
+            ThemeChoice::BuiltIn(name) => Theme::ALL
+                .iter()
+                .find(|theme| theme.to_string() == *name)
+                .cloned()
+                .unwrap_or(Theme::Dark),
+            ThemeChoice::Custom(name) => self
+                .custom_themes
+                .themes
+                .iter()
+                .find(|theme| theme.to_string() == *name)
+                .cloned()
+                .unwrap_or(Theme::Dark),
+        }
+    }
+
+    pub(crate) fn theme_choices(&self) -> Vec<ThemeChoice> {
+        let built_in = Theme::ALL
+            .iter()
+            .map(|theme| ThemeChoice::BuiltIn(theme.to_string()));
+        let custom = self
+            .custom_themes
+            .themes
+            .iter()
+            .map(|theme| ThemeChoice::Custom(theme.to_string()));
+        std::iter::once(ThemeChoice::System)
+            .chain(built_in)
+            .chain(custom)
+            .collect()
+    }
+
+    pub(crate) fn theme_subscription(&self) -> Subscription<Message> {
+        let system = match self.settings.theme {
+            ThemeChoice::System => {
+                Subscription::run(portal_color_scheme).map(Message::ColorSchemeChanged)
+            }
+            _ => Subscription::none(),
+        };
+        // Only watched while a custom theme is shown or could be picked
+        let watch = matches!(self.settings.theme, ThemeChoice::Custom(_))
+            || matches!(*self.async_state.page.lock().unwrap(), Page::Settings);
+        let file_check = match watch {
+            true => iced::time::every(THEMES_CHECK_INTERVAL).map(|_| Message::ThemesFileCheck),
+            false => Subscription::none(),
+        };
+        Subscription::batch([system, file_check])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keylight2::testing::ScratchDirectory;
+
+    fn palette(toml: &str) -> Result<Palette, String> {
+        let palette: PaletteFile = toml::from_str(toml).map_err(|error| error.to_string())?;
+        palette.palette("Test")
+    }
+
+    // Each write gets a time of its own, the file system may not tell writes in one tick apart
+    fn write_themes(directory: &ScratchDirectory, contents: &str, second: u64) {
+        let path = themes_path(&directory.0);
+        fs::write(&path, contents).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + second))
+            .unwrap();
+    }
+
+    fn names(themes: &CustomThemes) -> Vec<String> {
+        themes.themes.iter().map(Theme::to_string).collect()
+    }
+
+    #[test]
+    fn left_out_colors_come_from_the_base() {
+        assert_eq!(palette("").unwrap(), Palette::DARK);
+        assert_eq!(palette("base = \"light\"").unwrap(), Palette::LIGHT);
+
+        let nord = palette(
+            "base = \"light\"\nbackground = \"#2e3440\"\nprimary = \" #88C0D0 \"\ndanger = \"#bf616a80\"",
+        )
+        .unwrap();
+        assert_eq!(nord.background, Color::from_rgb8(0x2e, 0x34, 0x40));
+        assert_eq!(nord.primary, Color::from_rgb8(0x88, 0xc0, 0xd0));
+        assert_eq!(
+            nord.danger,
+            Color::from_rgba8(0xbf, 0x61, 0x6a, 0x80 as f32 / 255.0)
+        );
+        assert_eq!(nord.text, Palette::LIGHT.text);
+        assert_eq!(nord.success, Palette::LIGHT.success);
+    }
+
+    #[test]
+    fn malformed_palettes_are_refused() {
+        assert_eq!(
+            palette("text = \"white\"").unwrap_err(),
+            "\"white\" of Test is not a color like #2e3440"
+        );
+        assert!(palette("primary = \"#12345\"").is_err());
+        assert!(palette("base = \"blue\"").is_err());
+        assert!(palette("accent = \"#ffffff\"").is_err());
+        assert!(palette("background = 3").is_err());
+    }
+
+    #[test]
+    fn themes_follow_the_file() {
+        let directory = ScratchDirectory::new("themes");
+        let mut themes = CustomThemes::default();
+        // No file and nothing changed
+        assert!(!themes.reload(&directory.0));
+        assert!(themes.themes.is_empty());
+
+        write_themes(
+            &directory,
+            "[Nord]\nbackground = \"#2e3440\"\n\n[Paper]\nbase = \"light\"\n",
+            1,
+        );
+        assert!(themes.reload(&directory.0));
+        assert_eq!(names(&themes), ["Nord", "Paper"]);
+        assert_eq!(themes.themes[1].palette(), Palette::LIGHT);
+        assert!(!themes.reload(&directory.0));
+
+        // A broken edit keeps the themes read last until it is fixed
+        write_themes(&directory, "[Nord]\nbackground = \"dark grey\"\n", 2);
+        assert!(themes.reload(&directory.0));
+        assert_eq!(names(&themes), ["Nord", "Paper"]);
+        assert!(themes
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("\"dark grey\" of Nord"));
+        write_themes(&directory, "[Nord\n", 3);
+        assert!(themes.reload(&directory.0));
+        assert!(themes.error.is_some());
+
+        write_themes(&directory, "[Solarized]\ntext = \"#839496\"\n", 4);
+        assert!(themes.reload(&directory.0));
+        assert_eq!(names(&themes), ["Solarized"]);
+        assert_eq!(themes.error, None);
+
+        fs::remove_file(themes_path(&directory.0)).unwrap();
+        assert!(themes.reload(&directory.0));
+        assert!(themes.themes.is_empty());
+    }
+
+    #[test]
+    fn color_schemes_are_read_from_either_portal_method() {
+        assert_eq!(color_scheme(&Value::U32(1)), Some(ColorScheme::Dark));
+        assert_eq!(color_scheme(&Value::U32(2)), Some(ColorScheme::Light));
+        assert_eq!(
+            color_scheme(&Value::Value(Box::new(Value::U32(0)))),
+            Some(ColorScheme::NoPreference)
+        );
+        assert_eq!(color_scheme(&Value::U32(3)), None);
+        assert_eq!(color_scheme(&Value::Str("dark".into())), None);
+    }
+}
@@ -0,0 +1,555 @@
+use publicsuffix::{List, Psl};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+// Entries are matched to web pages through their url sections. The section type says how the stored
+// pattern is compared, so one entry can carry several URLs with different rules:
+//
+//   url              same registrable domain ("login.example.co.uk" fills on "www.example.co.uk")
+//   url_host         same host and port
+//   url_starts_with  the page URL starts with the pattern
+//   url_regex        the page URL matches the regular expression
+//   url_never        kept for reference, never matches
+//
+// An equivalent_domains section anywhere in the vault lists domains that share logins, on top of the
+// built-in groups below.
+
+pub const EQUIVALENT_DOMAINS_SECTION_TYPE: &str = "equivalent_domains";
+// Longer expressions are more likely a paste accident than a rule
+const MAX_REGEX_LENGTH: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UrlMatchMode {
+    BaseDomain,
+    Host,
+    StartsWith,
+    Regex,
+    Never,
+}
+
+impl UrlMatchMode {
+    pub const ALL: [UrlMatchMode; 5] = [
+        UrlMatchMode::BaseDomain,
+        UrlMatchMode::Host,
+        UrlMatchMode::StartsWith,
+        UrlMatchMode::Regex,
+        UrlMatchMode::Never,
+    ];
+
+    pub fn section_type(self) -> &'static str {
+        match self {
+            UrlMatchMode::BaseDomain => "url",
+            UrlMatchMode::Host => "url_host",
+            UrlMatchMode::StartsWith => "url_starts_with",
+            UrlMatchMode::Regex => "url_regex",
+            UrlMatchMode::Never => "url_never",
+        }
+    }
+
+    pub fn from_section_type(section_type: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.section_type() == section_type)
+    }
+}
+
+impl std::fmt::Display for UrlMatchMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                UrlMatchMode::BaseDomain => "Base domain",
+                UrlMatchMode::Host => "Exact host",
+                UrlMatchMode::StartsWith => "Starts with",
+                UrlMatchMode::Regex => "Regular expression",
+                UrlMatchMode::Never => "Never",
+            }
+        )
+    }
+}
+
+// How closely an entry matched the page, better matches compare greater
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchLevel {
+    EquivalentDomain,
+    BaseDomain,
+    Host,
+    StartsWith,
+    Exact,
+}
+
+// One url section of an entry
+pub struct EntryUrl {
+    pub entry_id: i64,
+    pub entry_name: Option<String>,
+    pub folder_id: Option<i64>,
+    pub mode: UrlMatchMode,
+    pub pattern: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UrlCandidate {
+    pub entry_id: i64,
+    pub entry_name: Option<String>,
+    pub folder_id: Option<i64>,
+    pub level: MatchLevel,
+    // Length of the stored path or prefix the page falls under, breaks ties within a level
+    pub specificity: usize,
+}
+
+static PUBLIC_SUFFIXES: LazyLock<List> = LazyLock::new(|| {
+    include_str!("../../assets/public_suffix_list.dat")
+        .parse()
+        .expect("The bundled public suffix list parses")
+});
+
+// Sites known to sign in with the same account
+const EQUIVALENT_DOMAINS: &[&[&str]] = &[
+    &[
+        "google.com",
+        "youtube.com",
+        "gmail.com",
+        "google.co.uk",
+        "google.de",
+    ],
+    &["apple.com", "icloud.com"],
+    &[
+        "microsoft.com",
+        "live.com",
+        "outlook.com",
+        "office.com",
+        "microsoftonline.com",
+        "hotmail.com",
+        "xbox.com",
+        "skype.com",
+    ],
+    &[
+        "amazon.com",
+        "amazon.co.uk",
+        "amazon.de",
+        "amazon.fr",
+        "amazon.ca",
+        "amazon.com.au",
+    ],
+    &[
+        "atlassian.com",
+        "atlassian.net",
+        "bitbucket.org",
+        "trello.com",
+    ],
+    &["steampowered.com", "steamcommunity.com"],
+    &["paypal.com", "paypal.me"],
+    &["ebay.com", "ebay.co.uk", "ebay.de"],
+];
+
+// Registrable domain of a host, "mail.example.co.uk" -> "example.co.uk". IP addresses and single
+// labels like "localhost" are their own base domain
+pub fn base_domain(host: &str) -> String {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    if host.starts_with('[') || host.parse::<std::net::IpAddr>().is_ok() {
+        return host;
+    }
+    PUBLIC_SUFFIXES
+        .domain(host.as_bytes())
+        .and_then(|domain| {
+            std::str::from_utf8(domain.as_bytes())
+                .ok()
+                .map(str::to_string)
+        })
+        .unwrap_or(host)
+}
+
+// Stored patterns and requested URLs may leave out the scheme, "example.com/login" reads as https
+pub fn parse_url(text: &str) -> Option<url::Url> {
+    let text = text.trim();
+    let url = match url::Url::parse(text) {
+        Ok(url) if url.has_host() => url,
+        _ => url::Url::parse(&format!("https://{text}")).ok()?,
+    };
+    url.has_host().then_some(url)
+}
+
+// A login saved for https is never offered to the same site over plain http
+fn scheme_allows(stored: &url::Url, page: &url::Url) -> bool {
+    stored.scheme() == page.scheme() || (stored.scheme() == "http" && page.scheme() == "https")
+}
+
+// Length of the stored path when the page lies under it, whole path segments only
+fn path_specificity(stored: &url::Url, page: &url::Url) -> usize {
+    let stored_path = stored.path().trim_end_matches('/');
+    let page_path = page.path().trim_end_matches('/');
+    match page_path.strip_prefix(stored_path) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => stored_path.len(),
+        _ => 0,
+    }
+}
+
+pub struct UrlMatcher {
+    groups: Vec<Vec<String>>,
+}
+
+impl Default for UrlMatcher {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl UrlMatcher {
+    pub fn new(custom_groups: impl IntoIterator<Item = Vec<String>>) -> Self {
+        let mut groups: Vec<Vec<String>> = EQUIVALENT_DOMAINS
+            .iter()
+            .map(|group| group.iter().map(|domain| domain.to_string()).collect())
+            .collect();
+        groups.extend(custom_groups.into_iter().filter(|group| group.len() > 1));
+        Self { groups }
+    }
+
+    // The data of an equivalent_domains section, domains separated by commas or whitespace
+    pub fn parse_group(data: &[u8]) -> Vec<String> {
+        String::from_utf8_lossy(data)
+            .split(|character: char| character == ',' || character.is_whitespace())
+            .filter(|domain| !domain.is_empty())
+            .map(base_domain)
+            .collect()
+    }
+
+    fn equivalent(&self, a: &str, b: &str) -> bool {
+        self.groups.iter().any(|group| {
+            group.iter().any(|domain| domain == a) && group.iter().any(|domain| domain == b)
+        })
+    }
+
+    // None when the pattern does not apply to the page at all
+    pub fn match_level(
+        &self,
+        mode: UrlMatchMode,
+        pattern: &str,
+        page: &url::Url,
+    ) -> Option<(MatchLevel, usize)> {
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return None;
+        }
+
+        match mode {
+            UrlMatchMode::Never => None,
+            UrlMatchMode::Regex => {
+                if pattern.len() > MAX_REGEX_LENGTH {
+                    return None;
+                }
+                let regex = regex::RegexBuilder::new(pattern)
+                    .size_limit(1 << 20)
+                    .build()
+                    .ok()?;
+                regex
+                    .is_match(page.as_str())
+                    .then_some((MatchLevel::Host, 0))
+            }
+            UrlMatchMode::StartsWith => {
+                let stored = parse_url(pattern)?;
+                if page.as_str() == stored.as_str() {
+                    Some((MatchLevel::Exact, stored.as_str().len()))
+                } else {
+                    page.as_str()
+                        .starts_with(stored.as_str())
+                        .then_some((MatchLevel::StartsWith, stored.as_str().len()))
+                }
+            }
+            UrlMatchMode::Host | UrlMatchMode::BaseDomain => {
+                let stored = parse_url(pattern)?;
+                if !scheme_allows(&stored, page) {
+                    return None;
+                }
+                let stored_host = stored.host_str()?;
+                let page_host = page.host_str()?;
+                let specificity = path_specificity(&stored, page);
+
+                let same_host = stored_host == page_host
+                    && stored.port_or_known_default() == page.port_or_known_default();
+                if same_host {
+                    let same_path =
+                        stored.path().trim_end_matches('/') == page.path().trim_end_matches('/');
+                    let level = match same_path && stored.path() != "/" {
+                        true => MatchLevel::Exact,
+                        false => MatchLevel::Host,
+                    };
+                    return Some((level, specificity));
+                }
+                if mode == UrlMatchMode::Host {
+                    return None;
+                }
+
+                let stored_domain = base_domain(stored_host);
+                let page_domain = base_domain(page_host);
+                if stored_domain == page_domain {
+                    Some((MatchLevel::BaseDomain, specificity))
+                } else if self.equivalent(&stored_domain, &page_domain) {
+                    Some((MatchLevel::EquivalentDomain, 0))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    // Every entry with a URL applying to the page, best match first. An entry with several URLs is
+    // ranked by its best one
+    pub fn rank(
+        &self,
+        page: &url::Url,
+        urls: impl IntoIterator<Item = EntryUrl>,
+    ) -> Vec<UrlCandidate> {
+        let mut candidates: HashMap<i64, UrlCandidate> = HashMap::new();
+        for url in urls {
+            let Some((level, specificity)) = self.match_level(url.mode, &url.pattern, page) else {
+                continue;
+            };
+            let candidate = candidates.entry(url.entry_id).or_insert(UrlCandidate {
+                entry_id: url.entry_id,
+                entry_name: url.entry_name,
+                folder_id: url.folder_id,
+                level,
+                specificity,
+            });
+            if (level, specificity) > (candidate.level, candidate.specificity) {
+                candidate.level = level;
+                candidate.specificity = specificity;
+            }
+        }
+
+        let mut candidates: Vec<UrlCandidate> = candidates.into_values().collect();
+        candidates.sort_by(|a, b| {
+            (b.level, b.specificity)
+                .cmp(&(a.level, a.specificity))
+                .then_with(|| a.entry_name.cmp(&b.entry_name))
+                .then_with(|| a.entry_id.cmp(&b.entry_id))
+        });
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(text: &str) -> url::Url {
+        parse_url(text).unwrap()
+    }
+
+    fn level(mode: UrlMatchMode, pattern: &str, page_url: &str) -> Option<MatchLevel> {
+        UrlMatcher::default()
+            .match_level(mode, pattern, &page(page_url))
+            .map(|(level, _)| level)
+    }
+
+    fn url(entry_id: i64, entry_name: &str, mode: UrlMatchMode, pattern: &str) -> EntryUrl {
+        EntryUrl {
+            entry_id,
+            entry_name: Some(entry_name.to_string()),
+            folder_id: None,
+            mode,
+            pattern: pattern.to_string(),
+        }
+    }
+
+    #[test]
+    fn base_domains_follow_the_public_suffix_list() {
+        assert_eq!(base_domain("mail.example.co.uk"), "example.co.uk");
+        assert_eq!(base_domain("Login.Example.COM."), "example.com");
+        assert_eq!(base_domain("a.b.example.com.au"), "example.com.au");
+        // Private suffixes count too, one GitHub Pages site is no other's
+        assert_eq!(base_domain("alice.github.io"), "alice.github.io");
+        assert_eq!(base_domain("192.168.1.10"), "192.168.1.10");
+        assert_eq!(base_domain("[::1]"), "[::1]");
+        assert_eq!(base_domain("localhost"), "localhost");
+    }
+
+    #[test]
+    fn base_domain_mode_matches_sibling_hosts_only() {
+        use UrlMatchMode::BaseDomain;
+        assert_eq!(
+            level(
+                BaseDomain,
+                "login.example.co.uk",
+                "https://www.example.co.uk/"
+            ),
+            Some(MatchLevel::BaseDomain)
+        );
+        assert_eq!(
+            level(BaseDomain, "example.co.uk", "https://other.co.uk/"),
+            None
+        );
+        assert_eq!(
+            level(
+                BaseDomain,
+                "https://example.com/login",
+                "https://example.com/login/"
+            ),
+            Some(MatchLevel::Exact)
+        );
+        assert_eq!(
+            level(BaseDomain, "example.com", "https://example.com/account"),
+            Some(MatchLevel::Host)
+        );
+        // A login saved for https stays off plain http, the other way round is fine but the
+        // default ports differ, so it is not the same host
+        assert_eq!(
+            level(BaseDomain, "https://example.com", "http://example.com/"),
+            None
+        );
+        assert_eq!(
+            level(BaseDomain, "http://example.com", "https://example.com/"),
+            Some(MatchLevel::BaseDomain)
+        );
+        assert_eq!(level(BaseDomain, "10.0.0.1", "https://10.0.0.2/"), None);
+    }
+
+    #[test]
+    fn host_mode_compares_host_and_port() {
+        use UrlMatchMode::Host;
+        assert_eq!(
+            level(Host, "example.com", "https://example.com:443/"),
+            Some(MatchLevel::Host)
+        );
+        assert_eq!(
+            level(Host, "example.com", "https://example.com:8443/"),
+            None
+        );
+        assert_eq!(
+            level(Host, "localhost:8080", "http://localhost:8080/admin"),
+            None
+        );
+        assert_eq!(
+            level(Host, "http://localhost:8080", "http://localhost:8080/admin"),
+            Some(MatchLevel::Host)
+        );
+        assert_eq!(level(Host, "example.com", "https://www.example.com/"), None);
+    }
+
+    #[test]
+    fn starts_with_regex_and_never() {
+        use UrlMatchMode::{Never, Regex, StartsWith};
+        assert_eq!(
+            level(
+                StartsWith,
+                "https://example.com/app",
+                "https://example.com/app/login"
+            ),
+            Some(MatchLevel::StartsWith)
+        );
+        assert_eq!(
+            level(
+                StartsWith,
+                "https://example.com/app",
+                "https://example.com/app"
+            ),
+            Some(MatchLevel::Exact)
+        );
+        assert_eq!(
+            level(
+                StartsWith,
+                "https://example.com/app",
+                "https://example.com/other"
+            ),
+            None
+        );
+        assert_eq!(
+            level(
+                Regex,
+                r"^https://[a-z]+\.example\.com/",
+                "https://shop.example.com/"
+            ),
+            Some(MatchLevel::Host)
+        );
+        assert_eq!(level(Regex, "(unclosed", "https://example.com/"), None);
+        assert_eq!(
+            level(
+                Regex,
+                &"a".repeat(MAX_REGEX_LENGTH + 1),
+                "https://example.com/"
+            ),
+            None
+        );
+        assert_eq!(level(Never, "example.com", "https://example.com/"), None);
+        assert_eq!(
+            level(UrlMatchMode::BaseDomain, "  ", "https://example.com/"),
+            None
+        );
+    }
+
+    #[test]
+    fn equivalent_domains_share_logins() {
+        assert_eq!(
+            level(
+                UrlMatchMode::BaseDomain,
+                "google.com",
+                "https://mail.gmail.com/"
+            ),
+            Some(MatchLevel::EquivalentDomain)
+        );
+        assert_eq!(
+            level(UrlMatchMode::Host, "google.com", "https://gmail.com/"),
+            None
+        );
+
+        let group = UrlMatcher::parse_group(b"example.com, www.example.org\nexample.net");
+        assert_eq!(group, ["example.com", "example.org", "example.net"]);
+        let matcher = UrlMatcher::new([group]);
+        assert_eq!(
+            matcher
+                .match_level(
+                    UrlMatchMode::BaseDomain,
+                    "example.org",
+                    &page("https://login.example.net/")
+                )
+                .map(|(level, _)| level),
+            Some(MatchLevel::EquivalentDomain)
+        );
+        // A group of one domain relates nothing
+        assert_eq!(
+            UrlMatcher::new([vec!["a.com".to_string()]]).groups.len(),
+            EQUIVALENT_DOMAINS.len()
+        );
+    }
+
+    #[test]
+    fn ranking_puts_the_closest_match_first() {
+        use UrlMatchMode::{BaseDomain, Host, StartsWith};
+        let urls = vec![
+            url(1, "Google", BaseDomain, "gmail.com"),
+            url(2, "Shop", BaseDomain, "shop.example.com"),
+            url(
+                3,
+                "Login page",
+                BaseDomain,
+                "https://example.com/account/login",
+            ),
+            url(4, "Account", BaseDomain, "https://example.com/account"),
+            url(5, "Unrelated", Host, "example.org"),
+            // Ranked by its better url
+            url(6, "Admin", BaseDomain, "admin.example.com"),
+            url(6, "Admin", StartsWith, "https://example.com/account/login"),
+            url(7, "Same name", Host, "example.com"),
+            url(8, "Same name", Host, "example.com"),
+        ];
+        let ranked = UrlMatcher::default().rank(&page("https://example.com/account/login"), urls);
+        let order: Vec<i64> = ranked.iter().map(|candidate| candidate.entry_id).collect();
+        assert_eq!(order, [6, 3, 4, 7, 8, 2]);
+        assert_eq!(ranked[0].level, MatchLevel::Exact);
+        assert_eq!(ranked[5].level, MatchLevel::BaseDomain);
+
+        // Entries with the same name keep their order whatever order they come in
+        let urls = vec![
+            url(9, "Same name", Host, "example.com"),
+            url(8, "Same name", Host, "example.com"),
+            url(7, "Same name", Host, "example.com"),
+        ];
+        let ranked = UrlMatcher::default().rank(&page("https://example.com/"), urls);
+        let order: Vec<i64> = ranked.iter().map(|candidate| candidate.entry_id).collect();
+        assert_eq!(order, [7, 8, 9]);
+    }
+}
@@ -1,55 +1,495 @@
 use crate::*;
+use zeroize::Zeroizing;
 // Change this later, but the warning is very annoying
 #[allow(async_fn_in_trait)]
 pub trait VaultManagement {
-    async fn get_folders(
-        self,
-        vault_pool: SqlitePool,
-        errors: Arc<Mutex<VecDeque<String>>>,
-    ) -> Result<Vec<Folder>, u8>;
+    async fn get_folders(self, vault_pool: SqlitePool) -> Result<Vec<Folder>, VaultError>;
     async fn initialize_vault_files(
         self,
         master_passphrase: String,
         vault_pool: SqlitePool,
-        errors: Arc<Mutex<VecDeque<String>>>,
-    ) -> Result<u8, u8>;
-    async fn login_to_vault(
+    ) -> Result<(), VaultError>;
+    async fn login_to_vault(self, vault_pool: SqlitePool) -> Result<(), VaultError>;
+    async fn get_entries(
+        self,
+        vault_pool: SqlitePool,
+        folder_id: i64,
+    ) -> Result<Vec<Entry>, VaultError>;
+    async fn get_entry_data(
+        self,
+        vault_pool: SqlitePool,
+        entry_id: i64,
+    ) -> Result<Vec<EntryData>, VaultError>;
+    async fn create_folder(
+        self,
+        vault_pool: SqlitePool,
+        folder_name: String,
+    ) -> Result<i64, VaultError>;
+    async fn rename_folder(
+        self,
+        vault_pool: SqlitePool,
+        folder_id: i64,
+        folder_name: String,
+    ) -> Result<(), VaultError>;
+    async fn create_entry(
+        self,
+        vault_pool: SqlitePool,
+        folder_id: i64,
+        entry_name: String,
+    ) -> Result<i64, VaultError>;
+    async fn rename_entry(
+        self,
+        vault_pool: SqlitePool,
+        entry_id: i64,
+        entry_name: String,
+    ) -> Result<(), VaultError>;
+    async fn delete_entry(self, vault_pool: SqlitePool, entry_id: i64) -> Result<(), VaultError>;
+    async fn set_entry_section(
+        self,
+        vault_pool: SqlitePool,
+        entry_id: i64,
+        section_name: String,
+        section_type: String,
+        section_data: Zeroizing<Vec<u8>>,
+    ) -> Result<(), VaultError>;
+    async fn remove_entry_section(
+        self,
+        vault_pool: SqlitePool,
+        entry_id: i64,
+        section_name: String,
+    ) -> Result<(), VaultError>;
+    async fn get_entry_tags(
+        self,
+        vault_pool: SqlitePool,
+        entry_id: i64,
+    ) -> Result<Vec<String>, VaultError>;
+    async fn set_entry_tags(
         self,
         vault_pool: SqlitePool,
-        errors: Arc<Mutex<VecDeque<String>>>,
-    ) -> Result<(), u8>;
+        entry_id: i64,
+        tags: Vec<String>,
+    ) -> Result<(), VaultError>;
+    async fn get_url_candidates(
+        self,
+        vault_pool: SqlitePool,
+        url: String,
+    ) -> Result<Vec<UrlCandidate>, VaultError>;
+    async fn get_sections_by_type(
+        self,
+        vault_pool: SqlitePool,
+        section_type: String,
+    ) -> Result<Vec<EntryData>, VaultError>;
+}
+
+// Each migration upgrades the schema by one version, the first one takes a vault from version 1 to 2.
+// initialize_vault_files always creates the version 1 tables, vaults created before versioning was
+// introduced report 0 and are treated as version 1
+const MIGRATIONS: &[&str] = &[
+    // 2: Entries belong to a folder, the welcome letter was inserted for a nonexistent entry 0
+    "ALTER TABLE Entries ADD COLUMN folder_id INTEGER REFERENCES Folders(folder_id);
+    UPDATE Entries SET folder_id = (SELECT MIN(folder_id) FROM Folders) WHERE folder_id IS NULL;
+    UPDATE EntryData SET entry_id = (SELECT MIN(entry_id) FROM Entries) WHERE entry_id = 0;",
+    // 3: Sections remember when they last changed and overwritten values are kept. Nothing was
+    // recorded before, so ages of existing sections count from the upgrade
+    "ALTER TABLE EntryData ADD COLUMN modified_at INTEGER;
+    UPDATE EntryData SET modified_at = CAST(strftime('%s', 'now') AS INTEGER);
+    CREATE TABLE IF NOT EXISTS SectionHistory (history_id INTEGER PRIMARY KEY AUTOINCREMENT, entry_id int, section_name VARCHAR(255), section_type VARCHAR(255), section_data BLOB, replaced_at INTEGER);",
+    // 4: Bookkeeping for sync.rs. Identifiers are handed out here and by triggers rather than on the
+    // first sync, so copies of the vault made after this upgrade agree on them. Copies that were
+    // upgraded separately each draw their own vault id, key and sync ids and never sync with each
+    // other, they have to be replaced by a copy of the one upgraded vault
+    "CREATE TABLE IF NOT EXISTS SyncState (state_key TEXT PRIMARY KEY, state_value BLOB);
+    CREATE TABLE IF NOT EXISTS SyncIds (record_table TEXT, local_id INTEGER, sync_id TEXT, PRIMARY KEY (record_table, local_id));
+    CREATE TABLE IF NOT EXISTS SyncRecords (record_key TEXT PRIMARY KEY, revision TEXT, modified_at INTEGER, device_id TEXT, digest BLOB);
+    CREATE TABLE IF NOT EXISTS SyncSeenRevisions (revision TEXT PRIMARY KEY);
+    CREATE TABLE IF NOT EXISTS SyncAppliedFiles (file_path TEXT PRIMARY KEY);
+    CREATE TABLE IF NOT EXISTS SyncConflicts (conflict_id INTEGER PRIMARY KEY AUTOINCREMENT, record_key TEXT, label TEXT, kept_device TEXT, other_device TEXT, other_modified_at INTEGER, other_payload BLOB);
+    INSERT INTO SyncState (state_key, state_value) VALUES
+        ('vault_id', lower(hex(randomblob(16)))), ('key', randomblob(32)), ('sequence', 0);
+    INSERT INTO SyncIds (record_table, local_id, sync_id)
+        SELECT 'folder', folder_id, lower(hex(randomblob(16))) FROM Folders;
+    INSERT INTO SyncIds (record_table, local_id, sync_id)
+        SELECT 'entry', entry_id, lower(hex(randomblob(16))) FROM Entries;
+    CREATE TRIGGER IF NOT EXISTS FolderSyncId AFTER INSERT ON Folders BEGIN
+        INSERT INTO SyncIds (record_table, local_id, sync_id)
+        VALUES ('folder', NEW.folder_id, lower(hex(randomblob(16))));
+    END;
+    CREATE TRIGGER IF NOT EXISTS EntrySyncId AFTER INSERT ON Entries BEGIN
+        INSERT INTO SyncIds (record_table, local_id, sync_id)
+        VALUES ('entry', NEW.entry_id, lower(hex(randomblob(16))));
+    END;",
+    // 5: Settings that belong to the vault, settings.rs keeps them as versioned JSON documents
+    "CREATE TABLE IF NOT EXISTS Settings (setting_key TEXT PRIMARY KEY, setting_value TEXT);",
+    // 6: Folders and entries remember when they last changed, for sync.rs. The triggers keep it
+    // current for every writer, an update that sets modified_at itself (a synced change) is left
+    // alone. Nothing was recorded before, so existing ones count from the upgrade
+    "ALTER TABLE Folders ADD COLUMN modified_at INTEGER;
+    ALTER TABLE Entries ADD COLUMN modified_at INTEGER;
+    UPDATE Folders SET modified_at = CAST(strftime('%s', 'now') AS INTEGER);
+    UPDATE Entries SET modified_at = CAST(strftime('%s', 'now') AS INTEGER);
+    CREATE TRIGGER IF NOT EXISTS FolderCreated AFTER INSERT ON Folders WHEN NEW.modified_at IS NULL BEGIN
+        UPDATE Folders SET modified_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE folder_id = NEW.folder_id;
+    END;
+    CREATE TRIGGER IF NOT EXISTS FolderModified AFTER UPDATE OF folder_name, folder_icon ON Folders
+    WHEN NEW.modified_at IS OLD.modified_at BEGIN
+        UPDATE Folders SET modified_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE folder_id = NEW.folder_id;
+    END;
+    CREATE TRIGGER IF NOT EXISTS EntryCreated AFTER INSERT ON Entries WHEN NEW.modified_at IS NULL BEGIN
+        UPDATE Entries SET modified_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE entry_id = NEW.entry_id;
+    END;
+    CREATE TRIGGER IF NOT EXISTS EntryModified AFTER UPDATE OF entry_name, entry_icon, folder_id, is_deleted ON Entries
+    WHEN NEW.modified_at IS OLD.modified_at BEGIN
+        UPDATE Entries SET modified_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE entry_id = NEW.entry_id;
+    END;
+    CREATE TRIGGER IF NOT EXISTS EntryTagAdded AFTER INSERT ON EntryTags BEGIN
+        UPDATE Entries SET modified_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE entry_id = NEW.entry_id;
+    END;
+    CREATE TRIGGER IF NOT EXISTS EntryTagRemoved AFTER DELETE ON EntryTags BEGIN
+        UPDATE Entries SET modified_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE entry_id = OLD.entry_id;
+    END;",
+];
+
+pub const SCHEMA_VERSION: i64 = 1 + MIGRATIONS.len() as i64;
+
+pub async fn migrate_schema(connection: &mut SqliteConnection) -> Result<(), VaultError> {
+    let found: i64 = sqlx::query_scalar("PRAGMA user_version;")
+        .fetch_one(&mut *connection)
+        .await?;
+
+    if found > SCHEMA_VERSION {
+        return Err(VaultError::SchemaMismatch {
+            found,
+            supported: SCHEMA_VERSION,
+        });
+    }
+
+    for version in found.max(1)..SCHEMA_VERSION {
+        println!(
+            "[INFO]: Upgrading vault schema from version {version} to {}",
+            version + 1
+        );
+        let migration = format!(
+            "BEGIN;
+            {}
+            PRAGMA user_version = {};
+            COMMIT;",
+            MIGRATIONS[version as usize - 1],
+            version + 1
+        );
+        sqlx::query(&migration).execute(&mut *connection).await?;
+    }
+
+    Ok(())
 }
 
 // TODO: Make functions more modular
 // Database functions
 impl VaultManagement for Arc<ApplicationAsyncState> {
-    async fn get_folders(
-        self,
-        vault_pool: SqlitePool,
-        error_notifications: Arc<Mutex<VecDeque<String>>>,
-    ) -> Result<Vec<Folder>, u8> {
-        let mut connection = vault_pool.acquire().await.expect_throw(
-            error_notifications.clone(),
-            "Unexpected Error: Could not fetch vault connection",
-        )?;
+    async fn get_folders(self, vault_pool: SqlitePool) -> Result<Vec<Folder>, VaultError> {
+        let mut connection = vault_pool.acquire().await?;
         connection.close_on_drop();
-        Ok::<Vec<Folder>, u8>(
+        Ok::<Vec<Folder>, VaultError>(
             sqlx::query_as!(Folder, "SELECT * FROM Folders;")
                 .fetch_all(connection.as_mut())
-                .await
-                .expect_throw(
-                    error_notifications.clone(),
-                    "Unexpected Error: Malformed Database, Could not fetch folders",
-                )?,
+                .await?,
+        )
+    }
+
+    async fn get_entries(
+        self,
+        vault_pool: SqlitePool,
+        folder_id: i64,
+    ) -> Result<Vec<Entry>, VaultError> {
+        let mut connection = vault_pool.acquire().await?;
+        connection.close_on_drop();
+        Ok(sqlx::query_as::<_, Entry>(
+            "SELECT entry_id, entry_name, entry_icon, folder_id FROM Entries
+            WHERE folder_id = ? AND is_deleted = FALSE ORDER BY entry_name;",
+        )
+        .bind(folder_id)
+        .fetch_all(connection.as_mut())
+        .await?)
+    }
+
+    async fn get_entry_data(
+        self,
+        vault_pool: SqlitePool,
+        entry_id: i64,
+    ) -> Result<Vec<EntryData>, VaultError> {
+        let mut connection = vault_pool.acquire().await?;
+        connection.close_on_drop();
+        Ok(sqlx::query_as::<_, EntryData>(
+            "SELECT data_id, section_name, section_type, section_data, entry_id FROM EntryData
+            WHERE entry_id = ? ORDER BY data_id;",
+        )
+        .bind(entry_id)
+        .fetch_all(connection.as_mut())
+        .await?)
+    }
+
+    async fn create_folder(
+        self,
+        vault_pool: SqlitePool,
+        folder_name: String,
+    ) -> Result<i64, VaultError> {
+        let mut connection = vault_pool.acquire().await?;
+        connection.close_on_drop();
+        Ok(
+            sqlx::query("INSERT INTO Folders (folder_name, folder_icon) VALUES (?, 'default');")
+                .bind(folder_name)
+                .execute(connection.as_mut())
+                .await?
+                .last_insert_rowid(),
+        )
+    }
+
+    async fn rename_folder(
+        self,
+        vault_pool: SqlitePool,
+        folder_id: i64,
+        folder_name: String,
+    ) -> Result<(), VaultError> {
+        let mut connection = vault_pool.acquire().await?;
+        connection.close_on_drop();
+        sqlx::query("UPDATE Folders SET folder_name = ? WHERE folder_id = ?;")
+            .bind(folder_name)
+            .bind(folder_id)
+            .execute(connection.as_mut())
+            .await?;
+        Ok(())
+    }
+
+    async fn create_entry(
+        self,
+        vault_pool: SqlitePool,
+        folder_id: i64,
+        entry_name: String,
+    ) -> Result<i64, VaultError> {
+        let mut connection = vault_pool.acquire().await?;
+        connection.close_on_drop();
+        Ok(sqlx::query(
+            "INSERT INTO Entries (entry_name, entry_icon, folder_id) VALUES (?, 'default', ?);",
+        )
+        .bind(entry_name)
+        .bind(folder_id)
+        .execute(connection.as_mut())
+        .await?
+        .last_insert_rowid())
+    }
+
+    async fn rename_entry(
+        self,
+        vault_pool: SqlitePool,
+        entry_id: i64,
+        entry_name: String,
+    ) -> Result<(), VaultError> {
+        let mut connection = vault_pool.acquire().await?;
+        connection.close_on_drop();
+        sqlx::query("UPDATE Entries SET entry_name = ? WHERE entry_id = ?;")
+            .bind(entry_name)
+            .bind(entry_id)
+            .execute(connection.as_mut())
+            .await?;
+        Ok(())
+    }
+
+    // Entries are only flagged, their data stays in the vault until it is purged
+    async fn delete_entry(self, vault_pool: SqlitePool, entry_id: i64) -> Result<(), VaultError> {
+        let mut connection = vault_pool.acquire().await?;
+        connection.close_on_drop();
+        sqlx::query("UPDATE Entries SET is_deleted = TRUE WHERE entry_id = ?;")
+            .bind(entry_id)
+            .execute(connection.as_mut())
+            .await?;
+        Ok(())
+    }
+
+    // Sections are identified by their name within an entry, an existing one is overwritten. The
+    // replaced value moves to SectionHistory and modified_at only changes when the data does
+    async fn set_entry_section(
+        self,
+        vault_pool: SqlitePool,
+        entry_id: i64,
+        section_name: String,
+        section_type: String,
+        section_data: Zeroizing<Vec<u8>>,
+    ) -> Result<(), VaultError> {
+        let mut transaction = vault_pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO SectionHistory
+            (entry_id, section_name, section_type, section_data, replaced_at)
+            SELECT entry_id, section_name, section_type, section_data,
+            CAST(strftime('%s', 'now') AS INTEGER) FROM EntryData
+            WHERE entry_id = ? AND section_name = ? AND section_data IS NOT ?;",
+        )
+        .bind(entry_id)
+        .bind(&section_name)
+        .bind(section_data.as_slice())
+        .execute(&mut *transaction)
+        .await?;
+
+        let updated = sqlx::query(
+            "UPDATE EntryData SET section_type = ?, section_data = ?,
+            modified_at = CASE WHEN section_data IS ? THEN modified_at
+                ELSE CAST(strftime('%s', 'now') AS INTEGER) END
+            WHERE entry_id = ? AND section_name = ?;",
         )
+        .bind(&section_type)
+        .bind(section_data.as_slice())
+        .bind(section_data.as_slice())
+        .bind(entry_id)
+        .bind(&section_name)
+        .execute(&mut *transaction)
+        .await?;
+
+        if updated.rows_affected() == 0 {
+            sqlx::query(
+                "INSERT INTO EntryData
+                (section_name, section_type, section_data, entry_id, modified_at)
+                VALUES (?, ?, ?, ?, CAST(strftime('%s', 'now') AS INTEGER));",
+            )
+            .bind(&section_name)
+            .bind(&section_type)
+            .bind(section_data.as_slice())
+            .bind(entry_id)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn remove_entry_section(
+        self,
+        vault_pool: SqlitePool,
+        entry_id: i64,
+        section_name: String,
+    ) -> Result<(), VaultError> {
+        let mut transaction = vault_pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO SectionHistory
+            (entry_id, section_name, section_type, section_data, replaced_at)
+            SELECT entry_id, section_name, section_type, section_data,
+            CAST(strftime('%s', 'now') AS INTEGER) FROM EntryData
+            WHERE entry_id = ? AND section_name = ?;",
+        )
+        .bind(entry_id)
+        .bind(&section_name)
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query("DELETE FROM EntryData WHERE entry_id = ? AND section_name = ?;")
+            .bind(entry_id)
+            .bind(section_name)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn get_entry_tags(
+        self,
+        vault_pool: SqlitePool,
+        entry_id: i64,
+    ) -> Result<Vec<String>, VaultError> {
+        let mut connection = vault_pool.acquire().await?;
+        connection.close_on_drop();
+        Ok(sqlx::query_scalar(
+            "SELECT tag_name FROM EntryTags WHERE entry_id = ? AND tag_name IS NOT NULL
+            ORDER BY tag_id;",
+        )
+        .bind(entry_id)
+        .fetch_all(connection.as_mut())
+        .await?)
+    }
+
+    // Replaces every tag of the entry at once
+    async fn set_entry_tags(
+        self,
+        vault_pool: SqlitePool,
+        entry_id: i64,
+        tags: Vec<String>,
+    ) -> Result<(), VaultError> {
+        let mut transaction = vault_pool.begin().await?;
+        sqlx::query("DELETE FROM EntryTags WHERE entry_id = ?;")
+            .bind(entry_id)
+            .execute(&mut *transaction)
+            .await?;
+        for tag in tags {
+            sqlx::query("INSERT INTO EntryTags (tag_name, entry_id) VALUES (?, ?);")
+                .bind(tag)
+                .bind(entry_id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    // Ranks every entry with a url section against the page, see url_match.rs
+    async fn get_url_candidates(
+        self,
+        vault_pool: SqlitePool,
+        url: String,
+    ) -> Result<Vec<UrlCandidate>, VaultError> {
+        let Some(page) = parse_url(&url) else {
+            return Ok(Vec::new());
+        };
+        let mut connection = vault_pool.acquire().await?;
+        connection.close_on_drop();
+        let rows: Vec<(i64, Option<String>, Option<i64>, String, Vec<u8>)> = sqlx::query_as(
+            "SELECT Entries.entry_id, Entries.entry_name, Entries.folder_id,
+                EntryData.section_type, EntryData.section_data
+                FROM EntryData JOIN Entries ON Entries.entry_id = EntryData.entry_id
+                WHERE Entries.is_deleted = FALSE AND EntryData.section_data IS NOT NULL
+                AND (EntryData.section_type LIKE 'url%' OR EntryData.section_type = ?);",
+        )
+        .bind(EQUIVALENT_DOMAINS_SECTION_TYPE)
+        .fetch_all(connection.as_mut())
+        .await?;
+
+        let matcher = UrlMatcher::new(
+            rows.iter()
+                .filter(|row| row.3 == EQUIVALENT_DOMAINS_SECTION_TYPE)
+                .map(|row| UrlMatcher::parse_group(&row.4)),
+        );
+        let urls =
+            rows.into_iter()
+                .filter_map(|(entry_id, entry_name, folder_id, section_type, data)| {
+                    Some(EntryUrl {
+                        entry_id,
+                        entry_name,
+                        folder_id,
+                        mode: UrlMatchMode::from_section_type(&section_type)?,
+                        pattern: String::from_utf8_lossy(&data).into_owned(),
+                    })
+                });
+        Ok(matcher.rank(&page, urls))
+    }
+
+    // One kind of field across all entries, e.g. every password for an audit
+    async fn get_sections_by_type(
+        self,
+        vault_pool: SqlitePool,
+        section_type: String,
+    ) -> Result<Vec<EntryData>, VaultError> {
+        let mut connection = vault_pool.acquire().await?;
+        connection.close_on_drop();
+        Ok(sqlx::query_as(
+            "SELECT EntryData.data_id, EntryData.section_name, EntryData.section_type,
+            EntryData.section_data, EntryData.entry_id
+            FROM EntryData JOIN Entries ON Entries.entry_id = EntryData.entry_id
+            WHERE Entries.is_deleted = FALSE AND EntryData.section_type = ?;",
+        )
+        .bind(section_type)
+        .fetch_all(connection.as_mut())
+        .await?)
     }
 
     async fn initialize_vault_files(
         self,
         mut master_passphrase: String,
         vault_pool: SqlitePool,
-        error_notifications: Arc<Mutex<VecDeque<String>>>,
-    ) -> Result<u8, u8> {
+    ) -> Result<(), VaultError> {
         let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
         let salt = SaltString::generate(&mut OsRng);
         let mut output: Vec<u8>;
@@ -57,18 +497,14 @@ impl VaultManagement for Arc<ApplicationAsyncState> {
         let mut output_key = [0u8; 32];
         let mut cipher;
         let mut connection;
-        let mut keyfile = fs::File::create(
-            self.app_directory
-                .read()
-                .unwrap()
-                .directory_handler
-                .data_local_dir()
-                .join("main.keyfile"),
-        )
-        .expect_throw(
-            error_notifications.clone(),
-            "Failed to open the keyfile for writing",
-        )?;
+        let vault = self
+            .app_directory
+            .read()
+            .unwrap()
+            .new_vault
+            .clone()
+            .expect_throw(VaultError::NoVaultSelected)?;
+        let mut keyfile = fs::File::create(&vault.keyfile_path)?;
 
         keyfile_contents = KeyfileContents {
             hashed_password: String::default(),
@@ -87,10 +523,7 @@ impl VaultManagement for Arc<ApplicationAsyncState> {
                 &mut output_key,
                 self.mem_block.lock().unwrap().deref_mut(),
             )
-            .expect_throw(
-                error_notifications.clone(),
-                "Unexpected Error: failed to hash password",
-            )?;
+            .expect_throw(VaultError::Crypto)?;
 
         // println!("Salt: {}", keyfile_contents.derivation_salt);
         // println!("{:?}", output_key);
@@ -104,46 +537,27 @@ impl VaultManagement for Arc<ApplicationAsyncState> {
         //         .expect("Unexpected Error: failed to hash password in loop");
         // }
 
-        cipher = chacha20poly1305::XChaCha20Poly1305::new_from_slice(&output_key).expect_throw(
-            error_notifications.clone(),
-            "Unexpected Error: Failed to generate chacha20 key from slice",
-        )?;
+        cipher = chacha20poly1305::XChaCha20Poly1305::new_from_slice(&output_key)
+            .expect_throw(VaultError::Crypto)?;
 
         keyfile_contents.encrypted_master_passphrase = chacha20poly1305::ChaChaPoly1305::encrypt(
             &mut cipher,
             &nonce,
             master_passphrase.as_bytes(),
-        )
-        .expect_throw(
-            error_notifications.clone(),
-            "Unexpected Error: could not encrypt payload",
         )?;
 
-        keyfile_contents.hashed_password = argon2_async::hash(output_key).await.expect_throw(
-            error_notifications.clone(),
-            "Unexpected Error: could not hash password",
-        )?;
+        keyfile_contents.hashed_password = argon2_async::hash(output_key)
+            .await
+            .expect_throw(VaultError::Crypto)?;
 
-        output = to_allocvec(&keyfile_contents).expect_throw(
-            error_notifications.clone(),
-            "Unexpected Error: Failed to serialize data",
-        )?;
+        output = to_allocvec(&keyfile_contents).expect_throw(VaultError::CorruptKeyfile)?;
 
-        keyfile
-            .write_all(&output)
-            .expect_throw(error_notifications.clone(), "Failed to write data to file")?;
+        keyfile.write_all(&output)?;
 
         vault_pool.set_connect_options(
             // TODO: make this a macro or function for reproducability
             SqliteConnectOptions::new()
-                .filename(
-                    self.app_directory
-                        .read()
-                        .unwrap()
-                        .directory_handler
-                        .data_local_dir()
-                        .join("main.db"),
-                )
+                .filename(&vault.database_path)
                 .pragma("key", format!("'{}'", master_passphrase))
                 .pragma("cipher_memory_security", "ON")
                 .foreign_keys(true)
@@ -151,10 +565,7 @@ impl VaultManagement for Arc<ApplicationAsyncState> {
                 .create_if_missing(true),
         );
 
-        connection = vault_pool.acquire().await.expect_throw(
-            error_notifications.clone(),
-            "Unexpected Error (2): failed to open vault connection.",
-        )?;
+        connection = vault_pool.acquire().await?;
 
         sqlx::query!(
             "
@@ -167,8 +578,11 @@ impl VaultManagement for Arc<ApplicationAsyncState> {
             "
         )
         .execute(connection.as_mut())
-        .await
-        .expect_throw(error_notifications.clone(), "Unexpected Error: could not setup database schema")?;
+        .await?;
+
+        sqlx::query("PRAGMA user_version = 1;")
+            .execute(connection.as_mut())
+            .await?;
 
         sqlx::query!(
             "BEGIN;
@@ -178,8 +592,9 @@ impl VaultManagement for Arc<ApplicationAsyncState> {
             COMMIT;"
         )
         .execute(connection.as_mut())
-        .await
-        .expect_throw(error_notifications.clone(), "Unexpected Error: could not add initial entries to database")?;
+        .await?;
+
+        migrate_schema(connection.as_mut()).await?;
 
         // self.vault_connection
         //     .as_mut()
@@ -211,17 +626,13 @@ impl VaultManagement for Arc<ApplicationAsyncState> {
 
         master_passphrase.zeroize();
         output.zeroize();
-        Ok(0)
+        Ok(())
     }
 
     // There is a darn memory leak here, but I cannot find it
     // TODO: FIX MEMORY LEAK
     // It has something to do with the argon2 library
-    async fn login_to_vault(
-        self,
-        vault_pool: SqlitePool,
-        error_notifications: Arc<Mutex<VecDeque<String>>>,
-    ) -> Result<(), u8> {
+    async fn login_to_vault(self, vault_pool: SqlitePool) -> Result<(), VaultError> {
         let keyfile_contents: KeyfileContents;
         let mut cipher;
         let mut master_passphrase: String;
@@ -229,28 +640,22 @@ impl VaultManagement for Arc<ApplicationAsyncState> {
         let mut output_key = [0u8; 32];
         let mut output_key_string: String;
 
+        let vault = self
+            .app_directory
+            .read()
+            .unwrap()
+            .selected_vault
+            .clone()
+            .expect_throw(VaultError::NoVaultSelected)?;
+
         {
-            let mut keyfile = fs::File::open(
-                self.app_directory
-                    .read()
-                    .unwrap()
-                    .directory_handler
-                    .data_local_dir()
-                    .join("main.keyfile"),
-            )
-            .expect_throw(
-                error_notifications.clone(),
-                "Unexpected Error: Failed to open the keyfile for reading",
-            )?;
-
-            keyfile.read_to_end(&mut buffer).expect_throw(
-                error_notifications.clone(),
-                "Unexpected Error: failed to read the keyfile into a buffer",
-            )?;
+            let mut keyfile = fs::File::open(&vault.keyfile_path)?;
+
+            keyfile.read_to_end(&mut buffer)?;
         }
 
         {
-            keyfile_contents = from_bytes(&buffer).expect("Unexpected Error: malformed keyfile");
+            keyfile_contents = from_bytes(&buffer)?;
             argon2_async::get_hasher()
                 .await
                 .unwrap()
@@ -260,29 +665,24 @@ impl VaultManagement for Arc<ApplicationAsyncState> {
                     &mut output_key,
                     self.mem_block.lock().unwrap().deref_mut(),
                 )
-                .expect_throw(
-                    error_notifications.clone(),
-                    "Unexpected Error: could not derive key",
-                )?;
+                .expect_throw(VaultError::Crypto)?;
         }
 
         // TODO: implement verify feature with argon2-async
         let out =
             argon2_async::verify_with_vec(output_key.to_vec(), keyfile_contents.hashed_password)
                 .await
-                .expect_throw(error_notifications.clone(), "Failed to verify password")?;
+                .expect_throw(VaultError::CorruptKeyfile)?;
 
         if !out {
-            let mut err = error_notifications.lock().unwrap();
-            println!("wrong password");
-            err.push_back("Wrong password".to_string());
-            return Err(0);
+            println!("[INFO]: Wrong password");
+            buffer.zeroize();
+            output_key.zeroize();
+            return Err(VaultError::WrongPassword);
         }
 
-        cipher = chacha20poly1305::XChaCha20Poly1305::new_from_slice(&output_key).expect_throw(
-            error_notifications.clone(),
-            "Unexpected Error: Failed to generate chacha20 key from slice",
-        )?;
+        cipher = chacha20poly1305::XChaCha20Poly1305::new_from_slice(&output_key)
+            .expect_throw(VaultError::Crypto)?;
 
         master_passphrase = String::from_utf8(
             chacha20poly1305::ChaChaPoly1305::decrypt(
@@ -290,34 +690,29 @@ impl VaultManagement for Arc<ApplicationAsyncState> {
                 XNonce::from_slice(&keyfile_contents.nonce),
                 keyfile_contents.encrypted_master_passphrase.as_slice(),
             )
-            .expect_throw(
-                error_notifications.clone(),
-                "Unexpected Error: could not decode ciphertext",
-            )?,
+            .expect_throw(VaultError::CorruptKeyfile)?,
         )
-        .expect_throw(
-            error_notifications.clone(),
-            "Unexpected Error: Could not derive master passphrase from ciphertext",
-        )?;
+        .expect_throw(VaultError::CorruptKeyfile)?;
 
         {
             vault_pool.set_connect_options(
                 SqliteConnectOptions::new()
-                    .filename(
-                        self.app_directory
-                            .read()
-                            .unwrap()
-                            .directory_handler
-                            .data_local_dir()
-                            .join("main.db"),
-                    )
+                    .filename(&vault.database_path)
                     .pragma("key", format!("'{}'", master_passphrase))
                     .pragma("cipher_memory_security", "ON")
                     .foreign_keys(true)
                     .journal_mode(SqliteJournalMode::Off)
-                    .create_if_missing(true),
+                    .create_if_missing(false),
             );
         }
+        master_passphrase.zeroize();
+        buffer.zeroize();
+        output_key.zeroize();
+
+        // Opening a connection is the first point where sqlcipher actually uses the key
+        let mut connection = vault_pool.acquire().await?;
+        migrate_schema(connection.as_mut()).await?;
+        drop(connection);
 
         // println!("{}", master_passphrase);
         // self.vault_pool.acquire();
@@ -337,9 +732,6 @@ impl VaultManagement for Arc<ApplicationAsyncState> {
         //     .unwrap()
         //     .pragma_update(None, "key", &master_passphrase)
         //     .expect_throw(&mut self.error_notification.clone(), "Failed to set the PRAGMA key")?;
-        master_passphrase.zeroize();
-        buffer.zeroize();
-        output_key.zeroize();
         Ok(())
     }
 }
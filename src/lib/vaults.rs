@@ -0,0 +1,166 @@
+use crate::*;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const DATABASE_EXTENSION: &str = "db";
+pub const KEYFILE_EXTENSION: &str = "keyfile";
+pub const DEFAULT_VAULT_NAME: &str = "main";
+
+// A vault is a sqlcipher database plus the keyfile holding its encrypted passphrase,
+// both sharing the same file stem (e.g. "personal.db" and "personal.keyfile")
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VaultLocation {
+    pub name: String,
+    pub database_path: PathBuf,
+    pub keyfile_path: PathBuf,
+}
+
+impl VaultLocation {
+    pub fn in_directory(directory: &Path, name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            database_path: directory.join(format!("{name}.{DATABASE_EXTENSION}")),
+            keyfile_path: directory.join(format!("{name}.{KEYFILE_EXTENSION}")),
+        }
+    }
+
+    // Accepts either the database or the keyfile of a vault stored anywhere on disk
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_stem()?.to_str()?;
+        let directory = path.parent().unwrap_or(Path::new("."));
+        Some(Self::in_directory(directory, name))
+    }
+
+    pub fn exists(&self) -> bool {
+        self.database_path.is_file() && self.keyfile_path.is_file()
+    }
+
+    pub fn rename(&self, new_name: &str) -> io::Result<Self> {
+        let directory = self.database_path.parent().unwrap_or(Path::new("."));
+        let renamed = Self::in_directory(directory, new_name);
+
+        if renamed.database_path.exists() || renamed.keyfile_path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "a vault with this name already exists",
+            ));
+        }
+
+        fs::rename(&self.keyfile_path, &renamed.keyfile_path)?;
+        if let Err(error) = fs::rename(&self.database_path, &renamed.database_path) {
+            // Keep the pair together if only half of the vault could be moved
+            fs::rename(&renamed.keyfile_path, &self.keyfile_path)?;
+            return Err(error);
+        }
+
+        Ok(renamed)
+    }
+
+    pub fn delete(&self) -> io::Result<()> {
+        fs::remove_file(&self.keyfile_path)?;
+        fs::remove_file(&self.database_path)
+    }
+}
+
+impl fmt::Display for VaultLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+pub fn validate_vault_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() {
+        return Err("Please give the vault a name");
+    }
+    if name.len() > 64 {
+        return Err("Vault names can be at most 64 characters long");
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == ' ')
+    {
+        return Err("Vault names may only contain letters, numbers, spaces, '-' and '_'");
+    }
+    Ok(())
+}
+
+// Every keyfile in the directory with a matching database is considered a vault
+pub fn list_vaults(directory: &Path) -> Vec<VaultLocation> {
+    let mut vaults: Vec<VaultLocation> = fs::read_dir(directory)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.extension().and_then(|extension| extension.to_str())
+                        == Some(KEYFILE_EXTENSION)
+                })
+                .filter_map(|path| VaultLocation::from_path(&path))
+                .filter(|vault| vault.exists())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    vaults.sort_by(|a, b| a.name.cmp(&b.name));
+    vaults
+}
+
+impl Default for StorageServices {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StorageServices {
+    // Creates the application data directory if needed and preselects the default vault
+    pub fn new() -> Self {
+        let directory_handler =
+            ProjectDirs::from("dev", "Schell", "Keylight").expect("Operating System Unsupported");
+        let path = directory_handler.data_local_dir();
+
+        if !path.exists() {
+            fs::create_dir_all(path)
+                .expect("Unexpected error, failed to create the application data directory");
+        }
+
+        let vaults = list_vaults(path);
+        let selected_vault = vaults
+            .iter()
+            .find(|vault| vault.name == DEFAULT_VAULT_NAME)
+            .or(vaults.first())
+            .cloned();
+
+        Self {
+            directory_handler,
+            vaults,
+            selected_vault,
+            new_vault: None,
+        }
+    }
+
+    pub fn vault_directory(&self) -> &Path {
+        self.directory_handler.data_local_dir()
+    }
+
+    pub fn config_directory(&self) -> &Path {
+        self.directory_handler.config_dir()
+    }
+
+    pub fn refresh_vaults(&mut self) {
+        self.vaults = list_vaults(self.vault_directory());
+
+        // Vaults opened from an arbitrary path are not part of the data directory listing
+        if let Some(selected) = &self.selected_vault {
+            if selected.exists() && !self.vaults.contains(selected) {
+                self.vaults.push(selected.clone());
+            }
+        }
+    }
+
+    pub fn selected_vault_exists(&self) -> bool {
+        self.selected_vault
+            .as_ref()
+            .is_some_and(|vault| vault.exists())
+    }
+}
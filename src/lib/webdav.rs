@@ -0,0 +1,431 @@
+use crate::*;
+use percent_encoding::percent_decode_str;
+use reqwest::header::{HeaderValue, IF_NONE_MATCH};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use url::Url;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+// Change sets kept on a WebDAV server such as Nextcloud, ownCloud or Apache mod_dav. The server is
+// just another SyncStore, the files it holds are sealed the same way as in a sync folder (sync.rs).
+//
+// Change sets are written once and never replaced, so every upload is a PUT with
+// "If-None-Match: *", the ETag precondition that only lets it create the file. A file that already
+// exists is read back: the same bytes mean an earlier attempt went through after all, anything else
+// was written by another device using this device's id and is never overwritten.
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/></d:prop></d:propfind>"#;
+
+// Kept inside the vault next to the sync folder, the password never touches the disk unencrypted
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct WebDavSettings {
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    pub enabled: bool,
+}
+
+// Messages are Debug, the password is left out
+impl fmt::Debug for WebDavSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebDavSettings")
+            .field("url", &self.url)
+            .field("username", &self.username)
+            .field("enabled", &self.enabled)
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct WebDavStore {
+    client: reqwest::Client,
+    // Always ends with "/", change set paths are joined onto it
+    base: Url,
+    settings: WebDavSettings,
+}
+
+impl WebDavStore {
+    pub fn new(settings: WebDavSettings) -> Result<Self, SyncError> {
+        let mut base = Url::parse(settings.url.trim())
+            .map_err(|error| SyncError::Remote(format!("Invalid WebDAV url: {error}")))?;
+        if !matches!(base.scheme(), "http" | "https") {
+            return Err(SyncError::Remote(
+                "The WebDAV url has to start with https:// or http://".to_string(),
+            ));
+        }
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        Ok(Self {
+            client: http_client()?,
+            base,
+            settings,
+        })
+    }
+
+    fn url(&self, path: &str) -> Result<Url, SyncError> {
+        self.base
+            .join(path)
+            .map_err(|error| SyncError::Remote(format!("Invalid sync path {path}: {error}")))
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .basic_auth(&self.settings.username, Some(&self.settings.password))
+    }
+
+    // Creates every missing collection above the path, the url's own included. Servers refuse to
+    // do it as part of the PUT
+    async fn create_collections(&self, path: &str) -> Result<(), SyncError> {
+        let segments: Vec<&str> = path.split('/').collect();
+        for end in 0..segments.len() {
+            let collection = format!("{}/", segments[..end].join("/"));
+            let url = match end {
+                0 => self.base.clone(),
+                _ => self.url(&collection)?,
+            };
+            let response = send_with_retries(|| {
+                self.request(Method::from_bytes(b"MKCOL").unwrap(), url.clone())
+            })
+            .await?;
+            // 405 is the answer for collections that exist already
+            if !response.status().is_success()
+                && response.status() != StatusCode::METHOD_NOT_ALLOWED
+            {
+                return Err(status_error(&response, &collection));
+            }
+        }
+        Ok(())
+    }
+
+    async fn put_new(&self, path: &str, contents: &[u8]) -> Result<Response, SyncError> {
+        let url = self.url(path)?;
+        send_with_retries(|| {
+            self.request(Method::PUT, url.clone())
+                .header(IF_NONE_MATCH, HeaderValue::from_static("*"))
+                .body(contents.to_vec())
+        })
+        .await
+    }
+}
+
+fn status_error(response: &Response, path: &str) -> SyncError {
+    match response.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => SyncError::Remote(
+            "The WebDAV server did not accept the username or password".to_string(),
+        ),
+        status => SyncError::Remote(format!("The WebDAV server answered {status} for {path}")),
+    }
+}
+
+// The href and whether it is a collection, for every resource of a multistatus answer
+fn parse_multistatus(body: &str) -> Result<Vec<(String, bool)>, SyncError> {
+    let document = roxmltree::Document::parse(body)
+        .map_err(|error| SyncError::Remote(format!("Unreadable WebDAV listing: {error}")))?;
+    Ok(document
+        .descendants()
+        .filter(|node| node.has_tag_name(("DAV:", "response")))
+        .filter_map(|response| {
+            let href = response
+                .children()
+                .find(|node| node.has_tag_name(("DAV:", "href")))?
+                .text()?
+                .trim()
+                .to_string();
+            let is_collection = response
+                .descendants()
+                .any(|node| node.has_tag_name(("DAV:", "collection")));
+            Some((href, is_collection))
+        })
+        .collect())
+}
+
+impl SyncStore for WebDavStore {
+    // Depth 1 at a time, many servers refuse "Depth: infinity"
+    async fn list(&self, directory: &str) -> Result<Vec<String>, SyncError> {
+        let mut files = Vec::new();
+        let mut pending = vec![directory.to_string()];
+        while let Some(directory) = pending.pop() {
+            let listed = self.url(&format!("{directory}/"))?;
+            let response = send_with_retries(|| {
+                self.request(Method::from_bytes(b"PROPFIND").unwrap(), listed.clone())
+                    .header("Depth", "1")
+                    .header("Content-Type", "application/xml; charset=utf-8")
+                    .body(PROPFIND_BODY)
+            })
+            .await?;
+            match response.status() {
+                StatusCode::NOT_FOUND => continue,
+                StatusCode::MULTI_STATUS => {}
+                _ => return Err(status_error(&response, &directory)),
+            }
+
+            // Servers answer with absolute paths or urls and encode them their own way
+            let listed_path = percent_decode_str(listed.path())
+                .decode_utf8_lossy()
+                .into_owned();
+            for (href, is_collection) in parse_multistatus(&response.text().await?)? {
+                let Ok(href) = listed.join(&href) else {
+                    continue;
+                };
+                let href_path = percent_decode_str(href.path())
+                    .decode_utf8_lossy()
+                    .into_owned();
+                let Some(name) = href_path.strip_prefix(&listed_path) else {
+                    continue;
+                };
+                let name = name.trim_end_matches('/');
+                // The listed collection itself, or something nested deeper than asked for
+                if name.is_empty() || name.contains('/') {
+                    continue;
+                }
+                let path = format!("{directory}/{name}");
+                if is_collection {
+                    pending.push(path);
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    async fn read(&self, path: &str) -> Result<Vec<u8>, SyncError> {
+        let url = self.url(path)?;
+        let response = send_with_retries(|| self.request(Method::GET, url.clone())).await?;
+        if !response.status().is_success() {
+            return Err(status_error(&response, path));
+        }
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    async fn write(&self, path: &str, contents: &[u8]) -> Result<(), SyncError> {
+        let mut response = self.put_new(path, contents).await?;
+        // The first change set of a device also needs its directory
+        if matches!(
+            response.status(),
+            StatusCode::CONFLICT | StatusCode::NOT_FOUND
+        ) {
+            self.create_collections(path).await?;
+            response = self.put_new(path, contents).await?;
+        }
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::PRECONDITION_FAILED => {
+                let existing = Zeroizing::new(self.read(path).await?);
+                match existing.as_slice() == contents {
+                    true => Ok(()),
+                    false => Err(SyncError::Remote(format!(
+                        "{path} was already written by another device, \
+                        two devices may be sharing one device id"
+                    ))),
+                }
+            }
+            _ => Err(status_error(&response, path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::mock::{self, MockResponse};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    // Set to a collection the tests may write into, such as one served by
+    // `rclone serve webdav --addr 127.0.0.1:8080 /tmp/dav`, to run them against a real server
+    const SERVER_URL_VARIABLE: &str = "KEYLIGHT_TEST_WEBDAV_URL";
+    const SERVER_USERNAME_VARIABLE: &str = "KEYLIGHT_TEST_WEBDAV_USERNAME";
+    const SERVER_PASSWORD_VARIABLE: &str = "KEYLIGHT_TEST_WEBDAV_PASSWORD";
+
+    // Just enough of WebDAV for WebDavStore, answering 503 to the first `busy` requests
+    #[derive(Default)]
+    struct MockServer {
+        files: Mutex<HashMap<String, Vec<u8>>>,
+        collections: Mutex<Vec<String>>,
+        busy: AtomicUsize,
+        requests: AtomicUsize,
+    }
+
+    impl MockServer {
+        async fn start(busy: usize) -> (Arc<Self>, String) {
+            let server = Arc::new(Self {
+                collections: Mutex::new(vec!["/".to_string()]),
+                busy: AtomicUsize::new(busy),
+                ..Default::default()
+            });
+            let serving = server.clone();
+            let url = mock::serve(move |request| {
+                serving.requests.fetch_add(1, Ordering::SeqCst);
+                let (status, body) = serving.respond(
+                    &request.method,
+                    &request.target,
+                    &request.headers,
+                    request.body,
+                );
+                MockResponse::new(status).body(body)
+            })
+            .await;
+            (server, url)
+        }
+
+        fn respond(
+            &self,
+            method: &str,
+            path: &str,
+            headers: &HashMap<String, String>,
+            body: Vec<u8>,
+        ) -> (&'static str, Vec<u8>) {
+            let busy = self
+                .busy
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |busy| {
+                    busy.checked_sub(1)
+                });
+            if busy.is_ok() {
+                return ("503 Service Unavailable", Vec::new());
+            }
+            let mut files = self.files.lock().unwrap();
+            let mut collections = self.collections.lock().unwrap();
+            let parent = match path.trim_end_matches('/').rsplit_once('/') {
+                Some((parent, _)) => format!("{parent}/"),
+                None => "/".to_string(),
+            };
+            match method {
+                "PUT" if !collections.contains(&parent) => ("409 Conflict", Vec::new()),
+                "PUT" if files.contains_key(path) && headers.get("if-none-match").is_some() => {
+                    ("412 Precondition Failed", Vec::new())
+                }
+                "PUT" => {
+                    files.insert(path.to_string(), body);
+                    ("201 Created", Vec::new())
+                }
+                "GET" => match files.get(path) {
+                    Some(contents) => ("200 OK", contents.clone()),
+                    None => ("404 Not Found", Vec::new()),
+                },
+                "MKCOL" if collections.iter().any(|collection| collection == path) => {
+                    ("405 Method Not Allowed", Vec::new())
+                }
+                "MKCOL" if !collections.contains(&parent) => ("409 Conflict", Vec::new()),
+                "MKCOL" => {
+                    collections.push(path.to_string());
+                    ("201 Created", Vec::new())
+                }
+                "PROPFIND" if !collections.iter().any(|collection| collection == path) => {
+                    ("404 Not Found", Vec::new())
+                }
+                "PROPFIND" => {
+                    let children = files
+                        .keys()
+                        .map(|file| (file, ""))
+                        .chain(
+                            collections
+                                .iter()
+                                .map(|collection| (collection, "<d:collection/>")),
+                        )
+                        .filter(|(child, _)| {
+                            child
+                                .trim_end_matches('/')
+                                .rsplit_once('/')
+                                .map(|(parent, _)| format!("{parent}/"))
+                                == Some(path.to_string())
+                                || *child == path
+                        })
+                        .map(|(child, kind)| {
+                            format!(
+                                "<d:response><d:href>{child}</d:href><d:propstat><d:prop>\
+                                <d:resourcetype>{kind}</d:resourcetype></d:prop></d:propstat>\
+                                </d:response>"
+                            )
+                        })
+                        .collect::<String>();
+                    let listing = format!(
+                        r#"<?xml version="1.0"?><d:multistatus xmlns:d="DAV:">{children}</d:multistatus>"#
+                    );
+                    ("207 Multi-Status", listing.into_bytes())
+                }
+                _ => ("405 Method Not Allowed", Vec::new()),
+            }
+        }
+    }
+
+    fn store(url: &str) -> WebDavStore {
+        WebDavStore::new(WebDavSettings {
+            url: url.to_string(),
+            username: std::env::var(SERVER_USERNAME_VARIABLE).unwrap_or_default(),
+            password: std::env::var(SERVER_PASSWORD_VARIABLE).unwrap_or_default(),
+            enabled: true,
+        })
+        .unwrap()
+    }
+
+    // What every server has to get right, the directory keeps runs against a real server apart
+    async fn check_change_sets(store: &WebDavStore, directory: &str) {
+        let first = format!("{directory}/device/00000001.klsync");
+        let second = format!("{directory}/device/00000002.klsync");
+        store.write(&first, b"first change set").await.unwrap();
+        store.write(&second, b"second change set").await.unwrap();
+
+        // An upload that went through but whose answer was lost is sent again with the same bytes
+        store.write(&first, b"first change set").await.unwrap();
+        // Another device writing under this device's id never replaces what is there
+        assert!(matches!(
+            store.write(&first, b"something else").await,
+            Err(SyncError::Remote(_))
+        ));
+        assert_eq!(store.read(&first).await.unwrap(), b"first change set");
+
+        let mut listed = store.list(directory).await.unwrap();
+        listed.sort();
+        assert_eq!(listed, vec![first, second]);
+        assert!(store
+            .list(&format!("{directory}/missing"))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn change_sets_are_never_overwritten() {
+        let (server, url) = MockServer::start(0).await;
+        check_change_sets(&store(&url), "sync").await;
+        assert_eq!(
+            server
+                .files
+                .lock()
+                .unwrap()
+                .get("/sync/device/00000001.klsync"),
+            Some(&b"first change set".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn busy_servers_are_asked_again() {
+        let (server, url) = MockServer::start(2).await;
+        let store = store(&url);
+        store
+            .write("device/00000001.klsync", b"change set")
+            .await
+            .unwrap();
+        // Two 503s, the PUT refused for the missing collection, MKCOL twice and the PUT again
+        assert_eq!(server.requests.load(Ordering::SeqCst), 6);
+        assert_eq!(
+            store.read("device/00000001.klsync").await.unwrap(),
+            b"change set"
+        );
+    }
+
+    #[tokio::test]
+    async fn change_sets_are_never_overwritten_on_a_real_server() {
+        let Ok(url) = std::env::var(SERVER_URL_VARIABLE) else {
+            println!("[INFO]: {SERVER_URL_VARIABLE} is not set, skipping the WebDAV server test");
+            return;
+        };
+        let directory = format!("keylight-test-{}-{}", std::process::id(), unix_now());
+        check_change_sets(&store(&url), &directory).await;
+    }
+}
@@ -1,188 +1,402 @@
-use std::alloc::{dealloc, Layout};
-use std::borrow::BorrowMut;
-use std::cell::LazyCell;
-use std::collections::VecDeque;
-use std::io::{Read, Write};
-use std::ops::{Deref, DerefMut};
-use std::sync::atomic::AtomicU8;
-use std::sync::{Arc, LazyLock, Mutex, RwLock, RwLockReadGuard};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
 use std::time::Instant;
 use std::{fs, path};
 
-use argon2::password_hash::{rand_core::OsRng, PasswordHasher};
-use argon2::password_hash::{Salt, SaltString};
-use argon2::{Argon2, Block, PasswordHash, PasswordVerifier};
-use chacha20poly1305::ChaChaPoly1305;
-use chacha20poly1305::{aead::Aead, AeadCore, KeyInit, XChaCha20Poly1305, XNonce};
-use chbs::config::BasicConfig;
-use chbs::probability::Probability;
-use chbs::scheme::ToScheme;
-use directories::ProjectDirs;
 use futures::TryFutureExt;
 use iced::alignment::Horizontal::Left;
 use iced::widget::pane_grid::{self, PaneGrid};
 use iced::widget::{
-    button, container, responsive, row, scrollable, text, text_input, toggler, Button, Column,
+    button, container, responsive, row, scrollable, stack, text, text_input, Button, Column,
     TextInput,
 };
-use iced::{Center, Color, Element, Fill};
+use iced::{Center, Color, Element, Fill, Subscription};
 use iced::{Length, Padding, Task};
-use postcard::{from_bytes, to_allocvec};
-use serde::{Deserialize, Serialize};
-use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode};
+use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::SqlitePool;
 use tokio::task::block_in_place;
-use zeroize::{Zeroize, ZeroizeOnDrop};
+use zeroize::{Zeroize, Zeroizing};
 
+#[path = "./lib/autolock.rs"]
+mod autolock;
+#[path = "./lib/autotype.rs"]
+mod autotype;
+#[path = "./pages/history.rs"]
+mod history_page;
+#[path = "./lib/layout.rs"]
+mod layout;
 #[path = "./pages/login.rs"]
 mod login_page;
 #[path = "./pages/new_vault.rs"]
 mod new_vault_page;
+#[path = "./lib/notifications.rs"]
+mod notifications;
 #[path = "./pages/passwords.rs"]
 mod password_page;
-#[path = "./lib/vault.rs"]
-mod vault;
-use vault::*;
+#[path = "./pages/security.rs"]
+mod security_page;
+#[path = "./lib/settings.rs"]
+mod settings;
+#[path = "./pages/settings.rs"]
+mod settings_page;
+#[path = "./pages/sync.rs"]
+mod sync_page;
+#[path = "./lib/themes.rs"]
+mod themes;
+use autolock::*;
+use autotype::*;
+use keylight2::breach::*;
+use keylight2::clipboard::*;
+use keylight2::cloud::*;
+use keylight2::error::*;
+use keylight2::generator::*;
+use keylight2::health::*;
+use keylight2::history::*;
+use keylight2::http::*;
+use keylight2::s3::*;
+use keylight2::secret_service::*;
+use keylight2::ssh_agent::*;
+use keylight2::state::*;
+use keylight2::strength::*;
+use keylight2::sync::*;
+use keylight2::totp::*;
+use keylight2::url_match::*;
+use keylight2::vault::*;
+use keylight2::vaults::*;
+use keylight2::webdav::*;
+use layout::*;
+use notifications::*;
+use settings::*;
+use themes::*;
 
 #[tokio::main]
 async fn main() -> iced::Result {
     argon2_async::set_config(argon2_async::Config::default()).await;
     block_in_place(|| {
         iced::application("Keylight", Keylight::update, Keylight::view)
-            .theme(|_| iced::Theme::Dark)
+            .subscription(Keylight::subscription)
+            .theme(Keylight::theme)
             .antialiasing(true)
             .run()
     })
 }
 
-#[derive(Zeroize, ZeroizeOnDrop)]
-struct Folder {
-    folder_id: i64,
-    folder_name: Option<String>,
-    folder_icon: Option<String>,
-}
-
 #[derive(Debug, Clone)]
 enum Message {
     PasswordInput(String),
     PasswordVerificationInput(String),
+    MinimumScorePicked(MinimumScore),
     ToggleGoogleDrive(bool),
     ToggleOneDrive(bool),
     ToggleDropBox(bool),
     PaneResized(pane_grid::ResizeEvent),
+    PaneDragged(pane_grid::DragEvent),
+    TogglePaneMaximized(pane_grid::Pane),
+    HidePane(pane_grid::Pane),
+    ShowPane(PaneState),
+    ResetPaneLayout,
+    SavePaneLayout,
     NewVault,
     ImportVault,
+    VaultPicked(VaultLocation),
+    VaultNameInput(String),
+    VaultPathInput(String),
+    RenameVault,
+    DeleteVault,
+    ConfirmDeleteVault,
+    OpenVaultFromPath,
     NextVaultCreationState,
     DeincrementVaultCreationState,
     ResetVaultCreationState,
     VerifyMasterPassword,
     LogIn,
+    LoginFinished(Result<(), VaultError>),
+    VaultCreated(Result<(), VaultError>),
+    NotificationTick(Instant),
+    DismissNotification(u64),
+    NotificationActionPressed(u64),
+    ToggleNotificationHistory,
+    ClearNotificationHistory,
+    UserActivity,
+    IdleCheck(Instant),
+    IdleTimeoutPicked(IdleTimeout),
+    ToggleLockOnSleep(bool),
+    ToggleLockOnScreenLock(bool),
+    AutoLock(LockReason),
+    Lock,
+    LogOut,
+    FolderPicked(i64),
+    EntriesLoaded(Result<(), VaultError>),
+    EntryPicked(i64),
+    EntryDataLoaded(Result<(), VaultError>),
+    CopySection(i64),
+    ClipboardFinished(Result<ClipboardOutcome, ClipboardError>),
+    ClipboardTimeoutPicked(ClipboardTimeout),
+    TogglePrimarySelection(bool),
+    TotpTick,
+    AutoTypeEntry(i64, std::time::Duration),
+    AutoTypeHotkey(String),
+    AutoTypeHotkeyUnavailable(String),
+    AutoTypeCandidates(String, Result<Vec<AutoTypeCandidate>, VaultError>),
+    AutoTypeFinished(Result<(), AutoTypeError>),
+    ToggleSecretService(bool),
+    SecretServiceFailed(String),
+    SecretServiceConfirm(SecretServiceConfirmation),
+    SecretServiceAnswer(SecretServiceConfirmation, bool),
+    ToggleSshAgent(bool),
+    SshAgentStarted(path::PathBuf),
+    SshAgentFailed(String),
+    SshAgentConfirm(SshConfirmation),
+    SshAgentAnswer(SshConfirmation, bool),
+    BreachDatasetInput(String),
+    CheckBreaches,
+    BreachesChecked(Result<BreachCounts, BreachError>),
+    OpenSecurityDashboard,
+    CloseSecurityDashboard,
+    HealthReportBuilt(Result<HealthReport, VaultError>),
+    PasswordAgeLimitPicked(PasswordAgeLimit),
+    ShowReportEntry(i64, i64),
+    OpenSyncPage,
+    CloseSyncPage,
+    SyncStateLoaded(Result<SyncSettingsRow, VaultError>),
+    SyncFolderInput(String),
+    SaveSyncFolder,
+    SyncFolderSaved(Result<(), VaultError>),
+    WebDavUrlInput(String),
+    WebDavUsernameInput(String),
+    WebDavPasswordInput(String),
+    ToggleWebDav(bool),
+    SaveWebDav,
+    WebDavSaved(Result<(), VaultError>),
+    S3EndpointInput(String),
+    S3RegionInput(String),
+    S3BucketInput(String),
+    S3AccessKeyInput(String),
+    S3SecretKeyInput(String),
+    ToggleS3(bool),
+    SaveS3,
+    S3Saved(Result<(), VaultError>),
+    CloudClientIdInput(CloudService, String),
+    CloudClientSecretInput(CloudService, String),
+    ConnectCloud(CloudService),
+    CloudAuthorizationStarted(Result<PendingAuthorization, SyncError>),
+    OpenAuthorizationPage,
+    CloudCodeInput(String),
+    FinishCloudAuthorization,
+    CancelCloudAuthorization,
+    CloudConnected(String, Result<CloudTokens, SyncError>),
+    DisconnectCloud(CloudService),
+    CloudSaved(CloudService, Result<(), VaultError>),
+    OpenHistoryPage,
+    CloseHistoryPage,
+    HistorySettingsLoaded(Result<Option<GitHistorySettings>, VaultError>),
+    ToggleGitHistory(bool),
+    GitRemoteInput(String),
+    GitUsernameInput(String),
+    GitPasswordInput(String),
+    SaveGitHistory,
+    GitHistorySaved(Result<(), VaultError>),
+    HistoryNow,
+    HistoryTick,
+    HistoryFinished(Result<HistorySummary, SyncError>),
+    HistoryListed(Result<Vec<HistoryCommit>, SyncError>),
+    HistoryCommitPicked(String),
+    HistoryChangesLoaded(String, Result<Vec<String>, SyncError>),
+    RestoreHistory(String),
+    HistoryRestored(Result<usize, SyncError>),
+    OpenSettingsPage,
+    CloseSettingsPage,
+    VaultSettingsLoaded(Result<VaultSettings, SettingsError>),
+    VaultSettingsSaved(Result<(), SettingsError>),
+    SaveBreachDataset,
+    ResetSettings,
+    ThemePicked(ThemeChoice),
+    ColorSchemeChanged(ColorScheme),
+    ThemesFileCheck,
+    SyncNow,
+    SyncTick(Instant),
+    SyncFinished(Result<SyncSummary, SyncError>),
+    ResolveSyncConflict(i64, bool),
+    SyncConflictResolved(Result<(), SyncError>),
+    FoldersReloaded(Result<(), VaultError>),
     ManualUpdate,
 }
 
-enum Page {
-    LogIn,
-    Passwords,
-    NewVault,
-}
-
-#[derive(Clone, Copy)]
-enum PaneState {
-    Folders,
-    Entries,
-    Data,
-}
-
-#[derive(Serialize, Deserialize)]
-struct KeyfileContents {
-    hashed_password: String,
-    derivation_salt: String,
-    nonce: Vec<u8>,
-    encrypted_master_passphrase: Vec<u8>,
-}
-
 struct SyncServices {
     google_drive_enabled: bool,
     onedrive_enabled: bool,
     dropbox_enabled: bool,
+    webdav_enabled: bool,
+    s3_enabled: bool,
 }
 
-struct StorageServices {
-    directory_handler: ProjectDirs,
-    main_db_exists: bool,
-}
+impl SyncServices {
+    fn cloud_enabled(&self, service: CloudService) -> bool {
+        match service {
+            CloudService::GoogleDrive => self.google_drive_enabled,
+            CloudService::OneDrive => self.onedrive_enabled,
+            CloudService::Dropbox => self.dropbox_enabled,
+        }
+    }
 
-struct ApplicationAsyncState {
-    master_password: RwLock<String>,
-    master_password_confirmation: RwLock<String>,
-    app_directory: RwLock<StorageServices>,
-    page: Mutex<Page>,
-    mem_block: Mutex<Vec<argon2::Block>>,
-    display_folders: Mutex<Vec<Folder>>,
-    vault_creation_state: AtomicU8,
+    fn set_cloud_enabled(&mut self, service: CloudService, enabled: bool) {
+        match service {
+            CloudService::GoogleDrive => self.google_drive_enabled = enabled,
+            CloudService::OneDrive => self.onedrive_enabled = enabled,
+            CloudService::Dropbox => self.dropbox_enabled = enabled,
+        }
+    }
+
+    // Only one remote target is used at a time, another one can only be turned on once it is off
+    fn can_turn_on(&self, enabled: bool) -> bool {
+        enabled
+            || !(self.google_drive_enabled
+                || self.onedrive_enabled
+                || self.dropbox_enabled
+                || self.webdav_enabled
+                || self.s3_enabled)
+    }
 }
 
 // TODO: Modify Zeroize library to be able to zeroize all of these contents
 struct Keylight {
-    panes: pane_grid::State<PaneState>,
     vault_pool: SqlitePool,
     master_passphrase: Vec<String>,
-    folder_id_picked: i64,
+    vault: UnlockedVault,
+    vault_name_input: String,
+    vault_path_input: String,
+    vault_deletion_armed: bool,
     application_loading: bool,
     sync_services: SyncServices,
     async_state: Arc<ApplicationAsyncState>,
-    error_notification: Arc<Mutex<VecDeque<String>>>,
+    notifications: NotificationCenter,
+    settings: AppSettings,
+    custom_themes: CustomThemes,
+    color_scheme: ColorScheme,
+    // Whether the services run right now, the settings only say whether they start on unlock
+    secret_service_enabled: bool,
+    ssh_agent_enabled: bool,
+    // Kept across a lock, the check started before it still has to finish before the next one
+    breach_check_running: bool,
+    health_report_running: bool,
+}
+
+// Everything shown about the unlocked vault, replaced as a whole when it is locked so nothing of
+// it is left for the next vault
+struct UnlockedVault {
+    folder_id_picked: i64,
+    entry_id_picked: i64,
+    settings: VaultSettings,
+    panes: pane_grid::State<PaneState>,
+    // Resizing waits for a moment before the layout is saved
+    pane_layout_pending: bool,
+    breach_dataset_input: String,
+    breach_counts: BreachCounts,
+    health_report: Option<HealthReport>,
+    master_password_strength: Option<Strength>,
+    sync: SyncStatus,
+    history: HistoryStatus,
+}
+
+impl Default for UnlockedVault {
+    fn default() -> Self {
+        Self {
+            folder_id_picked: 0,
+            entry_id_picked: 0,
+            settings: VaultSettings::default(),
+            panes: default_panes(),
+            pane_layout_pending: false,
+            breach_dataset_input: String::default(),
+            breach_counts: BreachCounts::new(),
+            health_report: None,
+            master_password_strength: None,
+            sync: SyncStatus::default(),
+            history: HistoryStatus::default(),
+        }
+    }
+}
+
+// sync folder, WebDAV server, S3 bucket, cloud storage services, conflicts
+type SyncSettingsRow = (
+    Option<path::PathBuf>,
+    Option<WebDavSettings>,
+    Option<S3Settings>,
+    HashMap<CloudService, CloudSettings>,
+    Vec<SyncConflict>,
+);
+
+// The vault's sync targets and the outcome of the last sync, reloaded on every unlock
+#[derive(Default)]
+struct SyncStatus {
+    folder: Option<path::PathBuf>,
+    folder_input: String,
+    webdav: Option<WebDavSettings>,
+    webdav_input: WebDavSettings,
+    s3: Option<S3Settings>,
+    s3_input: S3Settings,
+    // Client ids are kept here while typed, the tokens only once connected
+    cloud: HashMap<CloudService, CloudSettings>,
+    pending_authorization: Option<PendingAuthorization>,
+    authorization_code_input: String,
+    device_id: Option<String>,
+    running: bool,
+    last_summary: Option<SyncSummary>,
+    conflicts: Vec<SyncConflict>,
+}
+
+// The vault's git history, reloaded on every unlock
+#[derive(Default)]
+struct HistoryStatus {
+    settings: Option<GitHistorySettings>,
+    input: GitHistorySettings,
+    running: bool,
+    last_summary: Option<HistorySummary>,
+    commits: Vec<HistoryCommit>,
+    picked: Option<String>,
+    changes: Vec<String>,
 }
 
 impl Keylight {
     fn new() -> Self {
-        let directory_handler =
-            ProjectDirs::from("dev", "Schell", "Keylight").expect("Operating System Unsupported");
-        let path = path::Path::new(directory_handler.data_local_dir());
-        let main_db_exists = path.join("main.db").exists();
-        let (mut panes, pane) = pane_grid::State::new(PaneState::Folders);
-
-        panes.split(pane_grid::Axis::Vertical, pane, PaneState::Data);
-        panes.split(pane_grid::Axis::Vertical, pane, PaneState::Entries);
-
-        if !path.exists() {
-            fs::create_dir_all(path)
-                .expect("Unexpected error, failed to create the application data directory");
-        }
+        let async_state = Arc::new(ApplicationAsyncState::new(StorageServices::new()));
+        let mut notifications = NotificationCenter::default();
+        let config_directory = async_state
+            .app_directory
+            .read()
+            .unwrap()
+            .config_directory()
+            .to_path_buf();
+        let settings = load_app_settings(&config_directory).unwrap_or_else(|error| {
+            notifications.push(Notification::error(error.to_string()));
+            AppSettings::default()
+        });
+        let mut custom_themes = CustomThemes::default();
+        custom_themes.reload(&config_directory);
 
         Self {
-            panes,
             master_passphrase: Vec::new(),
-            folder_id_picked: 0,
-            error_notification: Arc::new(Mutex::new(VecDeque::new())),
+            vault: UnlockedVault::default(),
+            vault_name_input: String::default(),
+            vault_path_input: String::default(),
+            vault_deletion_armed: false,
+            notifications,
+            settings,
+            custom_themes,
+            color_scheme: ColorScheme::default(),
+            secret_service_enabled: false,
+            ssh_agent_enabled: false,
+            breach_check_running: false,
+            health_report_running: false,
             application_loading: false,
-            vault_pool: SqlitePool::connect_lazy(
-                directory_handler
-                    .data_local_dir()
-                    .join("main.db")
-                    .to_str()
-                    .expect("Unexpected Error: Could not convert app directory to string"),
-            )
-            .expect("Unexpected Error: failed to access db"),
-            async_state: Arc::new(ApplicationAsyncState {
-                master_password: RwLock::new(String::default()),
-                master_password_confirmation: RwLock::new(String::default()),
-                page: Mutex::new(Page::LogIn),
-                display_folders: Mutex::new(Vec::new()),
-                mem_block: Mutex::new(vec![
-                    Block::default();
-                    Argon2::default().params().block_count()
-                ]),
-                app_directory: RwLock::new(StorageServices {
-                    directory_handler,
-                    main_db_exists,
-                }),
-                vault_creation_state: AtomicU8::new(0),
-            }),
+            // The database file is only known once a vault is unlocked or created
+            vault_pool: SqlitePool::connect_lazy_with(SqliteConnectOptions::new()),
+            async_state,
             sync_services: SyncServices {
                 google_drive_enabled: false,
                 onedrive_enabled: false,
                 dropbox_enabled: false,
+                webdav_enabled: false,
+                s3_enabled: false,
             },
         }
     }
@@ -196,15 +410,13 @@ impl Keylight {
                 let state1 = self.async_state.clone();
                 let state2 = self.async_state.clone();
                 let state3 = self.async_state.clone();
-                let err1 = self.error_notification.clone();
-                let err2 = self.error_notification.clone();
 
                 // TODO: add a loading bar
                 Task::perform(
                     state1
-                        .login_to_vault(vault_pool1, err1)
+                        .login_to_vault(vault_pool1)
                         .and_then(|_| async move {
-                            Ok::<Vec<Folder>, u8>(state2.get_folders(vault_pool2, err2).await?)
+                            Ok::<Vec<Folder>, VaultError>(state2.get_folders(vault_pool2).await?)
                         })
                         .and_then(|folders| async move {
                             *state3.page.lock().unwrap() = Page::Passwords;
@@ -213,14 +425,145 @@ impl Keylight {
                             *folders_access = folders;
                             Ok(())
                         }),
-                    |_| Message::ManualUpdate,
+                    Message::LoginFinished,
                 )
             }
+            Message::LoginFinished(result) => {
+                self.application_loading = false;
+                self.settings.auto_lock.register_activity();
+                if let Err(error) = result {
+                    if error == VaultError::WrongPassword {
+                        self.async_state.master_password.write().unwrap().zeroize();
+                    }
+                    self.notifications
+                        .push(Notification::from_vault_error(&error, Message::LogIn));
+                    return Task::none();
+                }
+                self.vault_opened()
+            }
             Message::NewVault => {
+                let name = self.vault_name_input.trim().to_string();
+                if let Err(error) = validate_vault_name(&name) {
+                    self.notifications.push(Notification::warning(error));
+                    return Task::none();
+                }
+
+                let mut app_directory = self.async_state.app_directory.write().unwrap();
+                let vault = VaultLocation::in_directory(app_directory.vault_directory(), &name);
+                if vault.database_path.exists() || vault.keyfile_path.exists() {
+                    self.notifications
+                        .push(Notification::error("A vault with this name already exists"));
+                    return Task::none();
+                }
+
+                println!("[INFO]: Creating vault \"{}\"", vault.name);
+                app_directory.new_vault = Some(vault);
+                self.vault_name_input.clear();
+                self.vault.master_password_strength = None;
                 *self.async_state.page.lock().unwrap() = Page::NewVault;
                 Task::none()
             }
+
+            // Vault picker functions
+            Message::VaultPicked(vault) => {
+                self.vault_deletion_armed = false;
+                self.async_state
+                    .app_directory
+                    .write()
+                    .unwrap()
+                    .selected_vault = Some(vault);
+                Task::none()
+            }
+            Message::VaultNameInput(input) => {
+                self.vault_name_input = input;
+                Task::none()
+            }
+            Message::VaultPathInput(input) => {
+                self.vault_path_input = input;
+                Task::none()
+            }
+            Message::RenameVault => {
+                let name = self.vault_name_input.trim().to_string();
+                if let Err(error) = validate_vault_name(&name) {
+                    self.notifications.push(Notification::warning(error));
+                    return Task::none();
+                }
+
+                let mut app_directory = self.async_state.app_directory.write().unwrap();
+                let Some(vault) = app_directory.selected_vault.clone() else {
+                    return Task::none();
+                };
+
+                match vault.rename(&name) {
+                    Ok(renamed) => {
+                        self.notifications.push(Notification::info(format!(
+                            "Renamed vault \"{}\" to \"{}\"",
+                            vault.name, name
+                        )));
+                        app_directory.selected_vault = Some(renamed);
+                        app_directory.refresh_vaults();
+                        self.vault_name_input.clear();
+                    }
+                    Err(error) => {
+                        println!("[ERROR]: {:?}", error);
+                        self.notifications
+                            .push(Notification::error("Failed to rename the vault"));
+                    }
+                }
+                Task::none()
+            }
+            Message::DeleteVault => {
+                self.vault_deletion_armed = !self.vault_deletion_armed;
+                Task::none()
+            }
+            Message::ConfirmDeleteVault => {
+                self.vault_deletion_armed = false;
+                let mut app_directory = self.async_state.app_directory.write().unwrap();
+                let Some(vault) = app_directory.selected_vault.take() else {
+                    return Task::none();
+                };
+
+                if let Err(error) = vault.delete() {
+                    println!("[ERROR]: {:?}", error);
+                    self.notifications
+                        .push(Notification::error("Failed to delete the vault"));
+                    app_directory.selected_vault = Some(vault);
+                    return Task::none();
+                }
+
+                self.notifications.push(Notification::info(format!(
+                    "Deleted vault \"{}\"",
+                    vault.name
+                )));
+                app_directory.refresh_vaults();
+                app_directory.selected_vault = app_directory.vaults.first().cloned();
+                Task::none()
+            }
+            Message::OpenVaultFromPath => {
+                let vault = VaultLocation::from_path(path::Path::new(self.vault_path_input.trim()))
+                    .filter(|vault| vault.exists());
+
+                match vault {
+                    Some(vault) => {
+                        let mut app_directory = self.async_state.app_directory.write().unwrap();
+                        println!("[INFO]: Opened vault from {:?}", vault.database_path);
+                        app_directory.selected_vault = Some(vault);
+                        app_directory.refresh_vaults();
+                        self.vault_path_input.clear();
+                    }
+                    None => {
+                        self.notifications.push(Notification::error(
+                            "No vault (database and keyfile) was found at this path",
+                        ));
+                    }
+                }
+                Task::none()
+            }
             Message::PasswordInput(input) => {
+                // Only a new master password is rated, unlocking does not need the estimate
+                if matches!(*self.async_state.page.lock().unwrap(), Page::NewVault) {
+                    self.vault.master_password_strength = Some(self.rate_master_password(&input));
+                }
                 let mut write = self.async_state.master_password.write().unwrap();
                 (*write).zeroize();
                 *write = input;
@@ -238,12 +581,30 @@ impl Keylight {
                 *write = input;
                 Task::none()
             }
+            Message::MinimumScorePicked(minimum) => {
+                self.settings.master_password_minimum = minimum;
+                self.save_settings();
+                Task::none()
+            }
             Message::NextVaultCreationState => {
-                if self.async_state.master_password.read().unwrap().len() < 16 {
-                    let mut error_notification = self.error_notification.lock().unwrap();
-                    error_notification.push_back(
-                        "Please make your Master password at least 16 characters long".to_string(),
-                    );
+                let master_password = self.async_state.master_password.read().unwrap();
+                let strength = self.rate_master_password(&master_password);
+                let common = is_common_password(&master_password);
+                drop(master_password);
+
+                if common {
+                    self.notifications.push(Notification::warning(
+                        "This is one of the most common passwords, please pick another one",
+                    ));
+                } else if !self.settings.master_password_minimum.allows(&strength) {
+                    self.notifications.push(Notification::warning(format!(
+                        "Your Master password is {}, {}",
+                        strength.label().to_lowercase(),
+                        self.settings
+                            .master_password_minimum
+                            .to_string()
+                            .to_lowercase()
+                    )));
                 } else {
                     println!("[INFO]: Incremented vault creation state");
                     self.async_state
@@ -257,14 +618,7 @@ impl Keylight {
                     .load(std::sync::atomic::Ordering::Acquire)
                     == 1
                 {
-                    let mut config = BasicConfig::default();
-                    config.words = 24;
-                    config.capitalize_first = Probability::half();
-                    config.capitalize_words = Probability::Sometimes(0.2);
-                    config.separator = " ".to_string();
-                    let scheme = config.to_scheme();
-                    self.master_passphrase = scheme
-                        .generate()
+                    self.master_passphrase = generate_passphrase(24)
                         .split_whitespace()
                         .map(|st| st.to_string())
                         .collect();
@@ -280,20 +634,17 @@ impl Keylight {
                     let state1 = self.async_state.clone();
                     let state2 = self.async_state.clone();
                     let state3 = self.async_state.clone();
-                    let state4 = self.async_state.clone();
-                    let err1 = self.error_notification.clone();
-                    let err2 = self.error_notification.clone();
 
                     // TODO: implement a progress bar
                     return Task::perform(
                         state1
                             .clone()
-                            .initialize_vault_files(
-                                self.master_passphrase.join("~"),
-                                vault_pool1,
-                                err1,
-                            )
+                            .initialize_vault_files(self.master_passphrase.join("~"), vault_pool1)
                             .and_then(|_| async move {
+                                let mut app_directory = state2.app_directory.write().unwrap();
+                                app_directory.selected_vault = app_directory.new_vault.take();
+                                app_directory.refresh_vaults();
+                                drop(app_directory);
                                 state2
                                     .vault_creation_state
                                     .store(0, std::sync::atomic::Ordering::Release);
@@ -302,24 +653,34 @@ impl Keylight {
                                 Ok(())
                             })
                             .and_then(|_| async move {
-                                state4.get_folders(vault_pool2, err2).await?;
+                                let folders = state3.clone().get_folders(vault_pool2).await?;
+                                let mut folders_access = state3.display_folders.lock().unwrap();
+                                (*folders_access).zeroize();
+                                *folders_access = folders;
                                 println!("[INFO]: Successfully fetched folders to password page");
                                 Ok(())
-                            })
-                            // TODO: change UiRecovery to "String" errors to return correct errors
-                            .or_else(|_| async move {
-                                state3
-                                    .vault_creation_state
-                                    .fetch_sub(1, std::sync::atomic::Ordering::Release);
-                                println!("[ERROR]: Vault creation state thwarted");
-                                Ok::<(), ()>(())
                             }),
-                        // TODO Return a better result
-                        |_| Message::ManualUpdate,
+                        Message::VaultCreated,
                     );
                 }
                 Task::none()
             }
+            Message::VaultCreated(result) => {
+                self.settings.auto_lock.register_activity();
+                if let Err(error) = result {
+                    self.async_state
+                        .vault_creation_state
+                        .fetch_sub(1, std::sync::atomic::Ordering::Release);
+                    println!("[ERROR]: Vault creation state thwarted");
+                    self.notifications.push(Notification::from_vault_error(
+                        &error,
+                        Message::NextVaultCreationState,
+                    ));
+                    return Task::none();
+                }
+                self.vault.master_password_strength = None;
+                self.vault_opened()
+            }
             Message::DeincrementVaultCreationState => {
                 println!("[INFO]: Decremented Vault Creation State");
                 self.async_state
@@ -353,35 +714,1203 @@ impl Keylight {
                         .vault_creation_state
                         .fetch_add(1, std::sync::atomic::Ordering::AcqRel);
                 } else {
-                    self.error_notification
-                        .lock()
-                        .unwrap()
-                        .push_back("Passwords do not match".to_string());
+                    self.notifications
+                        .push(Notification::warning("Passwords do not match"));
                 }
 
                 Task::none()
             }
 
             // Sync Service Toggles
-            Message::ToggleDropBox(toggler) => {
-                self.sync_services.dropbox_enabled = toggler;
+            Message::ToggleDropBox(toggler) => self.toggle_cloud(CloudService::Dropbox, toggler),
+            Message::ToggleOneDrive(toggler) => self.toggle_cloud(CloudService::OneDrive, toggler),
+            Message::ToggleGoogleDrive(toggler) => {
+                self.toggle_cloud(CloudService::GoogleDrive, toggler)
+            }
+
+            // Misc
+            Message::PaneResized(resize) => {
+                self.vault.panes.resize(resize.split, resize.ratio);
+                self.vault.pane_layout_pending = true;
+                Task::none()
+            }
+            Message::PaneDragged(pane_grid::DragEvent::Dropped { pane, target }) => {
+                self.vault.panes.drop(pane, target);
+                self.save_pane_layout()
+            }
+            Message::PaneDragged(_) => Task::none(),
+            Message::TogglePaneMaximized(pane) => {
+                match self.vault.panes.maximized() {
+                    Some(_) => self.vault.panes.restore(),
+                    None => self.vault.panes.maximize(pane),
+                }
+                self.save_pane_layout()
+            }
+            // The last pane stays, the page would be empty otherwise
+            Message::HidePane(pane) => {
+                if self.vault.panes.len() > 1 {
+                    self.vault.panes.close(pane);
+                }
+                self.save_pane_layout()
+            }
+            Message::ShowPane(state) => {
+                self.show_pane(state);
+                self.save_pane_layout()
+            }
+            Message::ResetPaneLayout => {
+                self.vault.panes = default_panes();
+                self.save_pane_layout()
+            }
+            Message::SavePaneLayout => self.save_pane_layout(),
+
+            // Notifications
+            Message::NotificationTick(now) => {
+                self.notifications.expire(now);
                 Task::none()
             }
-            Message::ToggleOneDrive(toggler) => {
-                self.sync_services.onedrive_enabled = toggler;
+            Message::DismissNotification(id) => {
+                self.notifications.dismiss(id);
                 Task::none()
             }
-            Message::ToggleGoogleDrive(toggler) => {
-                self.sync_services.google_drive_enabled = toggler;
+            Message::NotificationActionPressed(id) => {
+                match self
+                    .notifications
+                    .dismiss(id)
+                    .and_then(|notification| notification.action)
+                {
+                    Some((_, action)) => self.update(action),
+                    None => Task::none(),
+                }
+            }
+            Message::ToggleNotificationHistory => {
+                self.notifications.show_history = !self.notifications.show_history;
+                Task::none()
+            }
+            Message::ClearNotificationHistory => {
+                self.notifications.clear_history();
                 Task::none()
             }
 
-            // Misc
-            Message::PaneResized(resize) => {
-                self.panes.resize(resize.split, resize.ratio);
+            // Auto-lock
+            Message::UserActivity => {
+                self.settings.auto_lock.register_activity();
+                Task::none()
+            }
+            Message::IdleCheck(now) => {
+                if self.settings.auto_lock.is_idle(now) {
+                    return self.update(Message::AutoLock(LockReason::Idle));
+                }
+                Task::none()
+            }
+            Message::IdleTimeoutPicked(timeout) => {
+                self.settings.auto_lock.idle_timeout = timeout;
+                self.settings.auto_lock.register_activity();
+                self.save_settings();
+                Task::none()
+            }
+            Message::ToggleLockOnSleep(toggler) => {
+                self.settings.auto_lock.lock_on_sleep = toggler;
+                self.save_settings();
+                Task::none()
+            }
+            Message::ToggleLockOnScreenLock(toggler) => {
+                self.settings.auto_lock.lock_on_screen_lock = toggler;
+                self.save_settings();
+                Task::none()
+            }
+            Message::AutoLock(reason) => {
+                let enabled = match reason {
+                    LockReason::Idle => true,
+                    LockReason::SystemSleep => self.settings.auto_lock.lock_on_sleep,
+                    LockReason::ScreenLock => self.settings.auto_lock.lock_on_screen_lock,
+                };
+                if !enabled || !self.async_state.page.lock().unwrap().is_unlocked() {
+                    return Task::none();
+                }
+
+                self.notifications
+                    .push(Notification::info(format!("Vault locked {reason}")));
+                self.lock_vault()
+            }
+
+            // Passwords page
+            Message::FolderPicked(folder_id) => {
+                self.vault.folder_id_picked = folder_id;
+                self.vault.entry_id_picked = 0;
+                self.async_state
+                    .display_entry_data
+                    .lock()
+                    .unwrap()
+                    .zeroize();
+                let vault_pool = self.vault_pool.clone();
+                let state1 = self.async_state.clone();
+                let state2 = self.async_state.clone();
+
+                Task::perform(
+                    state1
+                        .get_entries(vault_pool, folder_id)
+                        .map_ok(move |entries| {
+                            let mut entries_access = state2.display_entries.lock().unwrap();
+                            (*entries_access).zeroize();
+                            *entries_access = entries;
+                        }),
+                    Message::EntriesLoaded,
+                )
+            }
+            Message::EntryPicked(entry_id) => {
+                self.vault.entry_id_picked = entry_id;
+                let vault_pool = self.vault_pool.clone();
+                let state1 = self.async_state.clone();
+                let state2 = self.async_state.clone();
+
+                Task::perform(
+                    state1
+                        .get_entry_data(vault_pool, entry_id)
+                        .map_ok(move |entry_data| {
+                            let mut data_access = state2.display_entry_data.lock().unwrap();
+                            (*data_access).zeroize();
+                            *data_access = entry_data;
+                        }),
+                    Message::EntryDataLoaded,
+                )
+            }
+            Message::EntriesLoaded(result) | Message::EntryDataLoaded(result) => {
+                if let Err(error) = result {
+                    self.notifications.push(Notification::from_vault_error(
+                        &error,
+                        Message::FolderPicked(self.vault.folder_id_picked),
+                    ));
+                }
+                Task::none()
+            }
+            Message::CopySection(data_id) => {
+                let entry_data = self.async_state.display_entry_data.lock().unwrap();
+                let Some(section) = entry_data.iter().find(|section| section.data_id == data_id)
+                else {
+                    return Task::none();
+                };
+
+                let mut data =
+                    String::from_utf8_lossy(section.section_data.as_deref().unwrap_or_default())
+                        .into_owned();
+                let value = if section.section_type.as_deref() == Some("totp") {
+                    match Totp::parse(&data) {
+                        Some(totp) => zeroize::Zeroizing::new(totp.current_code()),
+                        None => {
+                            data.zeroize();
+                            drop(entry_data);
+                            self.notifications.push(Notification::error(
+                                "This section does not hold a valid TOTP secret",
+                            ));
+                            return Task::none();
+                        }
+                    }
+                } else {
+                    zeroize::Zeroizing::new(data.clone())
+                };
+                data.zeroize();
+
+                let section_name = section.section_name.clone().unwrap_or_default();
+                drop(entry_data);
+
+                let clear_after = self.settings.clipboard.clear_after.duration();
+                self.notifications
+                    .push(Notification::info(match clear_after {
+                        Some(clear_after) => format!(
+                            "Copied {section_name}, the clipboard will be cleared in {}s",
+                            clear_after.as_secs()
+                        ),
+                        None => format!("Copied {section_name}"),
+                    }));
+
+                Task::perform(
+                    copy_sensitive(
+                        value,
+                        self.settings.clipboard.use_primary_selection,
+                        clear_after,
+                    ),
+                    Message::ClipboardFinished,
+                )
+            }
+            Message::ClipboardFinished(result) => {
+                match result {
+                    Ok(ClipboardOutcome::Cleared) => {
+                        self.notifications
+                            .push(Notification::info("Cleared the clipboard"));
+                    }
+                    Ok(_) => {}
+                    Err(error) => {
+                        self.notifications
+                            .push(Notification::error(error.to_string()));
+                    }
+                }
+                Task::none()
+            }
+            Message::ClipboardTimeoutPicked(timeout) => {
+                self.settings.clipboard.clear_after = timeout;
+                self.save_settings();
+                Task::none()
+            }
+            Message::TogglePrimarySelection(toggler) => {
+                self.settings.clipboard.use_primary_selection = toggler;
+                self.save_settings();
+                Task::none()
+            }
+            Message::TotpTick => Task::none(),
+
+            // Auto-type
+            Message::AutoTypeEntry(entry_id, delay) => {
+                if delay >= MANUAL_AUTO_TYPE_DELAY {
+                    self.notifications.push(Notification::info(format!(
+                        "Focus the target window, typing starts in {}s",
+                        delay.as_secs()
+                    )));
+                }
+                Task::perform(
+                    auto_type_entry(
+                        self.async_state.clone(),
+                        self.vault_pool.clone(),
+                        entry_id,
+                        delay,
+                    ),
+                    Message::AutoTypeFinished,
+                )
+            }
+            Message::AutoTypeHotkey(window_title) => {
+                println!("[INFO]: Auto-type requested for \"{window_title}\"");
+                let title = window_title.clone();
+                Task::perform(
+                    find_autotype_entries(self.vault_pool.clone(), window_title),
+                    move |result| Message::AutoTypeCandidates(title.clone(), result),
+                )
+            }
+            // Shown once until the next log out, the subscription starts again on every unlock
+            Message::AutoTypeHotkeyUnavailable(reason) => {
+                if !self
+                    .notifications
+                    .history()
+                    .any(|notification| notification.message == reason)
+                {
+                    self.notifications.push(Notification::warning(reason));
+                }
+                Task::none()
+            }
+            Message::AutoTypeCandidates(window_title, result) => match result {
+                Ok(candidates) if candidates.len() == 1 => self.update(Message::AutoTypeEntry(
+                    candidates[0].entry_id,
+                    HOTKEY_AUTO_TYPE_DELAY,
+                )),
+                Ok(candidates) if candidates.is_empty() => {
+                    self.notifications.push(Notification::warning(format!(
+                        "No entry matches the window \"{window_title}\""
+                    )));
+                    Task::none()
+                }
+                // Picking one moves the focus to Keylight, so the user gets time to switch back
+                Ok(candidates) => {
+                    for candidate in candidates {
+                        self.notifications.push(
+                            Notification::info(format!(
+                                "\"{}\" matches \"{window_title}\"",
+                                candidate.entry_name
+                            ))
+                            .with_action(
+                                "Auto-type",
+                                Message::AutoTypeEntry(candidate.entry_id, MANUAL_AUTO_TYPE_DELAY),
+                            ),
+                        );
+                    }
+                    Task::none()
+                }
+                Err(error) => {
+                    self.notifications.push(Notification::from_vault_error(
+                        &error,
+                        Message::AutoTypeHotkey(window_title),
+                    ));
+                    Task::none()
+                }
+            },
+            Message::AutoTypeFinished(result) => {
+                if let Err(error) = result {
+                    self.notifications
+                        .push(Notification::error(error.to_string()));
+                }
+                Task::none()
+            }
+
+            // Secret Service
+            Message::ToggleSecretService(toggler) => {
+                self.secret_service_enabled = toggler;
+                self.settings.secret_service = toggler;
+                self.save_settings();
+                Task::none()
+            }
+            Message::SecretServiceFailed(error) => {
+                self.secret_service_enabled = false;
+                self.notifications.push(Notification::error(error));
+                Task::none()
+            }
+            // Dismissing or ignoring the notification lets the prompt time out as dismissed
+            Message::SecretServiceConfirm(confirmation) => {
+                self.notifications.push(
+                    Notification::warning(confirmation.question.clone())
+                        .with_action("Allow", Message::SecretServiceAnswer(confirmation, true))
+                        .with_timeout(Some(SECRET_SERVICE_CONFIRM_TIMEOUT)),
+                );
+                Task::none()
+            }
+            Message::SecretServiceAnswer(confirmation, allowed) => {
+                confirmation.answer(allowed);
                 Task::none()
             }
 
+            // SSH agent
+            Message::ToggleSshAgent(toggler) => {
+                self.ssh_agent_enabled = toggler;
+                self.settings.ssh_agent = toggler;
+                self.save_settings();
+                Task::none()
+            }
+            Message::SshAgentStarted(socket_path) => {
+                self.notifications.push(Notification::info(format!(
+                    "SSH agent started, use it with SSH_AUTH_SOCK={}",
+                    socket_path.display()
+                )));
+                Task::none()
+            }
+            Message::SshAgentFailed(error) => {
+                self.ssh_agent_enabled = false;
+                self.notifications.push(Notification::error(error));
+                Task::none()
+            }
+            // Dismissing or ignoring the notification lets the request time out as denied
+            Message::SshAgentConfirm(confirmation) => {
+                self.notifications.push(
+                    Notification::warning(format!(
+                        "An SSH client wants to sign with \"{}\"",
+                        confirmation.key_comment
+                    ))
+                    .with_action("Allow", Message::SshAgentAnswer(confirmation, true))
+                    .with_timeout(Some(SSH_CONFIRM_TIMEOUT)),
+                );
+                Task::none()
+            }
+            Message::SshAgentAnswer(confirmation, allowed) => {
+                confirmation.answer(allowed);
+                Task::none()
+            }
+
+            Message::BreachDatasetInput(input) => {
+                self.vault.breach_dataset_input = input;
+                Task::none()
+            }
+            Message::CheckBreaches => {
+                let dataset = path::PathBuf::from(self.vault.breach_dataset_input.trim());
+                if self.breach_check_running || dataset.as_os_str().is_empty() {
+                    return Task::none();
+                }
+                self.breach_check_running = true;
+                let check = Task::perform(
+                    check_vault_breaches(
+                        self.async_state.clone(),
+                        self.vault_pool.clone(),
+                        dataset,
+                    ),
+                    Message::BreachesChecked,
+                );
+                Task::batch([check, self.update(Message::SaveBreachDataset)])
+            }
+            Message::BreachesChecked(result) => {
+                self.breach_check_running = false;
+                // The vault may have been locked while the dataset was searched
+                if !self.async_state.page.lock().unwrap().is_unlocked() {
+                    return Task::none();
+                }
+                match result {
+                    Ok(counts) if counts.is_empty() => {
+                        self.notifications.push(Notification::info(
+                            "No password of this vault appears in the breach dataset",
+                        ));
+                        self.vault.breach_counts = counts;
+                    }
+                    Ok(counts) => {
+                        self.notifications.push(Notification::warning(format!(
+                            "{} entries use a password found in data breaches",
+                            counts.len()
+                        )));
+                        self.vault.breach_counts = counts;
+                    }
+                    Err(error) => {
+                        self.notifications
+                            .push(Notification::error(error.to_string()));
+                    }
+                }
+                Task::none()
+            }
+
+            // Security dashboard
+            Message::OpenSecurityDashboard => {
+                *self.async_state.page.lock().unwrap() = Page::Security;
+                if self.health_report_running {
+                    return Task::none();
+                }
+                self.health_report_running = true;
+                Task::perform(
+                    build_health_report(self.vault_pool.clone()),
+                    Message::HealthReportBuilt,
+                )
+            }
+            Message::CloseSecurityDashboard => {
+                *self.async_state.page.lock().unwrap() = Page::Passwords;
+                Task::none()
+            }
+            Message::HealthReportBuilt(result) => {
+                self.health_report_running = false;
+                if !self.async_state.page.lock().unwrap().is_unlocked() {
+                    return Task::none();
+                }
+                match result {
+                    Ok(report) => self.vault.health_report = Some(report),
+                    Err(error) => {
+                        self.notifications.push(Notification::from_vault_error(
+                            &error,
+                            Message::OpenSecurityDashboard,
+                        ));
+                    }
+                }
+                Task::none()
+            }
+            Message::PasswordAgeLimitPicked(limit) => {
+                self.vault.settings.password_age_limit = limit;
+                self.save_vault_settings()
+            }
+            Message::ShowReportEntry(folder_id, entry_id) => {
+                *self.async_state.page.lock().unwrap() = Page::Passwords;
+                let entries = self.update(Message::FolderPicked(folder_id));
+                Task::batch([entries, self.update(Message::EntryPicked(entry_id))])
+            }
+
+            // Settings
+            Message::OpenSettingsPage => {
+                *self.async_state.page.lock().unwrap() = Page::Settings;
+                Task::none()
+            }
+            Message::CloseSettingsPage => {
+                *self.async_state.page.lock().unwrap() = Page::Passwords;
+                Task::none()
+            }
+            Message::VaultSettingsLoaded(result) => {
+                match result {
+                    Ok(settings) => {
+                        self.vault.breach_dataset_input = settings
+                            .breach_dataset
+                            .as_ref()
+                            .map(|dataset| dataset.display().to_string())
+                            .unwrap_or_default();
+                        self.vault.panes = settings
+                            .panes
+                            .as_ref()
+                            .and_then(SavedPanes::restore)
+                            .unwrap_or_else(default_panes);
+                        self.vault.settings = settings;
+                    }
+                    Err(error) => {
+                        self.notifications
+                            .push(Notification::error(error.to_string()));
+                    }
+                }
+                Task::none()
+            }
+            Message::VaultSettingsSaved(result) => {
+                if let Err(error) = result {
+                    self.notifications
+                        .push(Notification::warning(error.to_string()));
+                }
+                Task::none()
+            }
+            Message::SaveBreachDataset => {
+                let dataset = Some(path::PathBuf::from(self.vault.breach_dataset_input.trim()))
+                    .filter(|dataset| !dataset.as_os_str().is_empty());
+                if dataset == self.vault.settings.breach_dataset {
+                    return Task::none();
+                }
+                self.vault.settings.breach_dataset = dataset;
+                self.save_vault_settings()
+            }
+            Message::ResetSettings => {
+                self.settings.reset();
+                self.vault.settings.reset();
+                self.vault.breach_dataset_input.clear();
+                self.vault.panes = default_panes();
+                self.secret_service_enabled = self.settings.secret_service;
+                self.ssh_agent_enabled = self.settings.ssh_agent;
+                self.save_settings();
+                self.notifications
+                    .push(Notification::info("Restored the default settings"));
+                self.save_vault_settings()
+            }
+            Message::ThemePicked(choice) => {
+                self.settings.theme = choice;
+                self.save_settings();
+                Task::none()
+            }
+            Message::ColorSchemeChanged(scheme) => {
+                self.color_scheme = scheme;
+                Task::none()
+            }
+            Message::ThemesFileCheck => {
+                let config_directory = self.config_directory();
+                if self.custom_themes.reload(&config_directory) {
+                    if let Some(error) = &self.custom_themes.error {
+                        self.notifications
+                            .push(Notification::warning(error.clone()));
+                    }
+                }
+                Task::none()
+            }
+
+            // Sync
+            Message::OpenSyncPage => {
+                *self.async_state.page.lock().unwrap() = Page::Sync;
+                self.load_sync_state()
+            }
+            Message::CloseSyncPage => {
+                *self.async_state.page.lock().unwrap() = Page::Passwords;
+                Task::none()
+            }
+            Message::SyncStateLoaded(result) => {
+                match result {
+                    Ok((folder, webdav, s3, cloud, conflicts)) => {
+                        self.vault.sync.folder_input = folder
+                            .as_ref()
+                            .map(|folder| folder.display().to_string())
+                            .unwrap_or_default();
+                        self.vault.sync.folder = folder;
+                        self.vault.sync.webdav_input = webdav.clone().unwrap_or_default();
+                        self.sync_services.webdav_enabled =
+                            webdav.as_ref().is_some_and(|webdav| webdav.enabled);
+                        self.vault.sync.webdav = webdav;
+                        self.vault.sync.s3_input = s3.clone().unwrap_or_default();
+                        self.sync_services.s3_enabled = s3.as_ref().is_some_and(|s3| s3.enabled);
+                        self.vault.sync.s3 = s3;
+                        for service in CloudService::ALL {
+                            let enabled = cloud.get(&service).is_some_and(|settings| {
+                                settings.enabled && settings.is_connected()
+                            });
+                            self.sync_services.set_cloud_enabled(service, enabled);
+                        }
+                        self.vault.sync.cloud = cloud;
+                        self.vault.sync.conflicts = conflicts;
+                        // Unlocking syncs right away instead of waiting for the first interval
+                        if self.vault.sync.last_summary.is_none() {
+                            return self.update(Message::SyncNow);
+                        }
+                    }
+                    Err(error) => {
+                        self.notifications.push(Notification::from_vault_error(
+                            &error,
+                            Message::OpenSyncPage,
+                        ));
+                    }
+                }
+                Task::none()
+            }
+            Message::SyncFolderInput(input) => {
+                self.vault.sync.folder_input = input;
+                Task::none()
+            }
+            Message::SaveSyncFolder => {
+                let input = self.vault.sync.folder_input.trim();
+                let folder = (!input.is_empty()).then(|| path::PathBuf::from(input));
+                if folder.as_ref().is_some_and(|folder| !folder.is_dir()) {
+                    self.notifications.push(Notification::warning(
+                        "The sync folder must be an existing directory",
+                    ));
+                    return Task::none();
+                }
+                self.vault.sync.folder = folder.clone();
+                Task::perform(
+                    set_sync_folder(self.vault_pool.clone(), folder),
+                    Message::SyncFolderSaved,
+                )
+            }
+            Message::SyncFolderSaved(result) => match result {
+                Ok(()) if self.sync_target().is_some() => self.update(Message::SyncNow),
+                Ok(()) => Task::none(),
+                Err(error) => {
+                    self.notifications.push(Notification::from_vault_error(
+                        &error,
+                        Message::SaveSyncFolder,
+                    ));
+                    Task::none()
+                }
+            },
+            Message::WebDavUrlInput(input) => {
+                self.vault.sync.webdav_input.url = input;
+                Task::none()
+            }
+            Message::WebDavUsernameInput(input) => {
+                self.vault.sync.webdav_input.username = input;
+                Task::none()
+            }
+            Message::WebDavPasswordInput(input) => {
+                self.vault.sync.webdav_input.password = input;
+                Task::none()
+            }
+            Message::ToggleWebDav(toggler) => {
+                self.vault.sync.webdav_input.enabled = toggler;
+                self.update(Message::SaveWebDav)
+            }
+            Message::SaveWebDav => {
+                let mut settings = self.vault.sync.webdav_input.clone();
+                settings.url = settings.url.trim().to_string();
+                let settings = match settings.url.is_empty() {
+                    true => None,
+                    false => Some(settings),
+                };
+                if let Some(Err(error)) = settings.clone().map(WebDavStore::new) {
+                    self.notifications
+                        .push(Notification::warning(error.to_string()));
+                    return Task::none();
+                }
+                self.sync_services.webdav_enabled =
+                    settings.as_ref().is_some_and(|settings| settings.enabled);
+                self.vault.sync.webdav = settings.clone();
+                Task::perform(
+                    set_sync_target_settings(self.vault_pool.clone(), "webdav", settings),
+                    Message::WebDavSaved,
+                )
+            }
+            Message::WebDavSaved(result) => match result {
+                Ok(()) if self.sync_target().is_some() => self.update(Message::SyncNow),
+                Ok(()) => Task::none(),
+                Err(error) => {
+                    self.notifications
+                        .push(Notification::from_vault_error(&error, Message::SaveWebDav));
+                    Task::none()
+                }
+            },
+            Message::S3EndpointInput(input) => {
+                self.vault.sync.s3_input.endpoint = input;
+                Task::none()
+            }
+            Message::S3RegionInput(input) => {
+                self.vault.sync.s3_input.region = input;
+                Task::none()
+            }
+            Message::S3BucketInput(input) => {
+                self.vault.sync.s3_input.bucket = input;
+                Task::none()
+            }
+            Message::S3AccessKeyInput(input) => {
+                self.vault.sync.s3_input.access_key_id = input;
+                Task::none()
+            }
+            Message::S3SecretKeyInput(input) => {
+                self.vault.sync.s3_input.secret_access_key = input;
+                Task::none()
+            }
+            Message::ToggleS3(toggler) => {
+                self.vault.sync.s3_input.enabled = toggler;
+                self.update(Message::SaveS3)
+            }
+            Message::SaveS3 => {
+                let mut settings = self.vault.sync.s3_input.clone();
+                settings.endpoint = settings.endpoint.trim().to_string();
+                settings.bucket = settings.bucket.trim().to_string();
+                let settings = match settings.endpoint.is_empty() {
+                    true => None,
+                    false => Some(settings),
+                };
+                if let Some(Err(error)) = settings.clone().map(S3Store::new) {
+                    self.notifications
+                        .push(Notification::warning(error.to_string()));
+                    return Task::none();
+                }
+                self.sync_services.s3_enabled =
+                    settings.as_ref().is_some_and(|settings| settings.enabled);
+                self.vault.sync.s3 = settings.clone();
+                Task::perform(
+                    set_sync_target_settings(self.vault_pool.clone(), "s3", settings),
+                    Message::S3Saved,
+                )
+            }
+            Message::S3Saved(result) => match result {
+                Ok(()) if self.sync_target().is_some() => self.update(Message::SyncNow),
+                Ok(()) => Task::none(),
+                Err(error) => {
+                    self.notifications
+                        .push(Notification::from_vault_error(&error, Message::SaveS3));
+                    Task::none()
+                }
+            },
+            Message::CloudClientIdInput(service, input) => {
+                self.vault.sync.cloud.entry(service).or_default().client_id = input;
+                Task::none()
+            }
+            Message::CloudClientSecretInput(service, input) => {
+                self.vault
+                    .sync
+                    .cloud
+                    .entry(service)
+                    .or_default()
+                    .client_secret = input;
+                Task::none()
+            }
+            Message::ConnectCloud(service) => {
+                let mut settings = self
+                    .vault
+                    .sync
+                    .cloud
+                    .get(&service)
+                    .cloned()
+                    .unwrap_or_default();
+                settings.client_id = settings.client_id.trim().to_string();
+                settings.client_secret = settings.client_secret.trim().to_string();
+                self.vault.sync.pending_authorization = None;
+                self.vault.sync.authorization_code_input.zeroize();
+                Task::perform(
+                    async move {
+                        let transport = ReqwestTransport::new()?;
+                        start_authorization(&transport, service, &service.endpoints(), &settings)
+                            .await
+                    },
+                    Message::CloudAuthorizationStarted,
+                )
+            }
+            Message::CloudAuthorizationStarted(result) => {
+                let pending = match result {
+                    Ok(pending) => pending,
+                    Err(error) => {
+                        self.notifications
+                            .push(Notification::warning(error.to_string()));
+                        return Task::none();
+                    }
+                };
+                self.vault.sync.pending_authorization = Some(pending.clone());
+                let open_page = self.update(Message::OpenAuthorizationPage);
+                if pending.needs_code() {
+                    return open_page;
+                }
+
+                // The device flow finishes on its own once the user entered the code
+                let service = pending.service;
+                let authorization_id = pending.authorization_id.clone();
+                let settings = self
+                    .vault
+                    .sync
+                    .cloud
+                    .get(&service)
+                    .cloned()
+                    .unwrap_or_default();
+                let poll = Task::perform(
+                    async move {
+                        let transport = ReqwestTransport::new()?;
+                        finish_device_authorization(
+                            &transport,
+                            &service.endpoints(),
+                            &settings,
+                            pending,
+                        )
+                        .await
+                    },
+                    move |result| Message::CloudConnected(authorization_id.clone(), result),
+                );
+                Task::batch([open_page, poll])
+            }
+            Message::OpenAuthorizationPage => {
+                if let Some(pending) = &self.vault.sync.pending_authorization {
+                    let page = pending.page.clone();
+                    return Task::future(async move {
+                        let opened = tokio::process::Command::new("xdg-open")
+                            .arg(&page)
+                            .status()
+                            .await;
+                        if !matches!(opened, Ok(status) if status.success()) {
+                            println!("[ERROR]: Could not open {page} in a browser");
+                        }
+                        Message::ManualUpdate
+                    });
+                }
+                Task::none()
+            }
+            Message::CloudCodeInput(input) => {
+                self.vault.sync.authorization_code_input = input;
+                Task::none()
+            }
+            Message::FinishCloudAuthorization => {
+                let Some(pending) = self.vault.sync.pending_authorization.clone() else {
+                    return Task::none();
+                };
+                let code =
+                    Zeroizing::new(self.vault.sync.authorization_code_input.trim().to_string());
+                if !pending.needs_code() || code.is_empty() {
+                    return Task::none();
+                }
+                let service = pending.service;
+                let authorization_id = pending.authorization_id.clone();
+                let settings = self
+                    .vault
+                    .sync
+                    .cloud
+                    .get(&service)
+                    .cloned()
+                    .unwrap_or_default();
+                Task::perform(
+                    async move {
+                        let transport = ReqwestTransport::new()?;
+                        finish_code_authorization(
+                            &transport,
+                            &service.endpoints(),
+                            &settings,
+                            pending,
+                            &code,
+                        )
+                        .await
+                    },
+                    move |result| Message::CloudConnected(authorization_id.clone(), result),
+                )
+            }
+            Message::CancelCloudAuthorization => {
+                self.vault.sync.pending_authorization = None;
+                self.vault.sync.authorization_code_input.zeroize();
+                Task::none()
+            }
+            Message::CloudConnected(authorization_id, result) => {
+                // A login that was cancelled, replaced or outlived the unlock it started in
+                let Some(pending) = self
+                    .vault
+                    .sync
+                    .pending_authorization
+                    .take_if(|pending| pending.authorization_id == authorization_id)
+                else {
+                    return Task::none();
+                };
+                self.vault.sync.authorization_code_input.zeroize();
+                let service = pending.service;
+                let tokens = match result {
+                    Ok(tokens) => tokens,
+                    Err(error) => {
+                        self.notifications.push(
+                            Notification::warning(error.to_string())
+                                .with_action("Try again", Message::ConnectCloud(service)),
+                        );
+                        return Task::none();
+                    }
+                };
+                println!("[INFO]: Connected {service}");
+                let can_turn_on = self
+                    .sync_services
+                    .can_turn_on(self.sync_services.cloud_enabled(service));
+                let settings = self.vault.sync.cloud.entry(service).or_default();
+                settings.client_id = settings.client_id.trim().to_string();
+                settings.client_secret = settings.client_secret.trim().to_string();
+                tokens.store_in(settings);
+                settings.enabled = can_turn_on;
+                self.sync_services.set_cloud_enabled(service, can_turn_on);
+                self.save_cloud(service)
+            }
+            Message::DisconnectCloud(service) => {
+                let settings = self.vault.sync.cloud.entry(service).or_default();
+                settings.access_token.zeroize();
+                settings.refresh_token.zeroize();
+                settings.expires_at = 0;
+                settings.enabled = false;
+                self.sync_services.set_cloud_enabled(service, false);
+                self.save_cloud(service)
+            }
+            Message::CloudSaved(service, result) => match result {
+                Ok(()) if self.sync_target().is_some() => self.update(Message::SyncNow),
+                Ok(()) => Task::none(),
+                Err(error) => {
+                    self.notifications.push(Notification::from_vault_error(
+                        &error,
+                        Message::DisconnectCloud(service),
+                    ));
+                    Task::none()
+                }
+            },
+            Message::OpenHistoryPage => {
+                *self.async_state.page.lock().unwrap() = Page::History;
+                self.list_history()
+            }
+            Message::CloseHistoryPage => {
+                *self.async_state.page.lock().unwrap() = Page::Passwords;
+                self.vault.history.picked = None;
+                self.vault.history.changes.clear();
+                Task::none()
+            }
+            Message::HistorySettingsLoaded(result) => match result {
+                Ok(settings) => {
+                    self.vault.history.input = settings.clone().unwrap_or_default();
+                    self.vault.history.settings = settings;
+                    // The changes made since the last unlock are committed right away
+                    self.update(Message::HistoryNow)
+                }
+                Err(error) => {
+                    self.notifications.push(Notification::from_vault_error(
+                        &error,
+                        Message::OpenHistoryPage,
+                    ));
+                    Task::none()
+                }
+            },
+            Message::ToggleGitHistory(toggler) => {
+                self.vault.history.input.enabled = toggler;
+                self.update(Message::SaveGitHistory)
+            }
+            Message::GitRemoteInput(input) => {
+                self.vault.history.input.remote_url = input;
+                Task::none()
+            }
+            Message::GitUsernameInput(input) => {
+                self.vault.history.input.username = input;
+                Task::none()
+            }
+            Message::GitPasswordInput(input) => {
+                self.vault.history.input.password = input;
+                Task::none()
+            }
+            Message::SaveGitHistory => {
+                let mut settings = self.vault.history.input.clone();
+                settings.remote_url = settings.remote_url.trim().to_string();
+                let settings = (settings != GitHistorySettings::default()).then_some(settings);
+                self.vault.history.settings = settings.clone();
+                Task::perform(
+                    set_sync_target_settings(self.vault_pool.clone(), "git", settings),
+                    Message::GitHistorySaved,
+                )
+            }
+            Message::GitHistorySaved(result) => match result {
+                Ok(()) => self.update(Message::HistoryNow),
+                Err(error) => {
+                    self.notifications.push(Notification::from_vault_error(
+                        &error,
+                        Message::SaveGitHistory,
+                    ));
+                    Task::none()
+                }
+            },
+            Message::HistoryTick | Message::HistoryNow => {
+                let Some(settings) = self.vault.history.settings.clone() else {
+                    return Task::none();
+                };
+                if !settings.enabled || self.vault.history.running {
+                    return Task::none();
+                }
+                let Some(device_id) = self.device_id() else {
+                    return Task::none();
+                };
+                self.vault.history.running = true;
+                Task::perform(
+                    sync_history(
+                        self.vault_pool.clone(),
+                        self.data_directory(),
+                        device_id,
+                        settings,
+                    ),
+                    Message::HistoryFinished,
+                )
+            }
+            Message::HistoryFinished(result) => {
+                self.vault.history.running = false;
+                // The vault may have been locked meanwhile
+                if !self.async_state.page.lock().unwrap().is_unlocked() {
+                    return Task::none();
+                }
+                match result {
+                    Ok(summary) => {
+                        if summary.conflicts > 0 {
+                            self.notifications.push(
+                                Notification::warning(format!(
+                                    "The history of another device changed {} records that were \
+                                    changed here too",
+                                    summary.conflicts
+                                ))
+                                .with_action("Review", Message::OpenSyncPage),
+                            );
+                        }
+                        let (committed, received) = (summary.committed, summary.received > 0);
+                        self.vault.history.last_summary = Some(summary);
+                        let mut tasks = Vec::new();
+                        if received {
+                            tasks.extend([self.load_sync_state(), self.reload_folders()]);
+                        }
+                        if (committed || received)
+                            && matches!(*self.async_state.page.lock().unwrap(), Page::History)
+                        {
+                            tasks.push(self.list_history());
+                        }
+                        Task::batch(tasks)
+                    }
+                    Err(error) => {
+                        self.notifications.push(
+                            Notification::error(error.to_string())
+                                .with_action("Retry", Message::HistoryNow),
+                        );
+                        Task::none()
+                    }
+                }
+            }
+            Message::HistoryListed(result) => {
+                match result {
+                    Ok(commits) => self.vault.history.commits = commits,
+                    Err(error) => {
+                        self.notifications
+                            .push(Notification::error(error.to_string()));
+                    }
+                }
+                Task::none()
+            }
+            Message::HistoryCommitPicked(commit_id) => {
+                self.vault.history.picked = Some(commit_id.clone());
+                self.vault.history.changes.clear();
+                Task::perform(
+                    history_changes(
+                        self.vault_pool.clone(),
+                        self.data_directory(),
+                        commit_id.clone(),
+                    ),
+                    move |result| Message::HistoryChangesLoaded(commit_id.clone(), result),
+                )
+            }
+            Message::HistoryChangesLoaded(commit_id, result) => {
+                // Another commit may have been picked meanwhile
+                if self.vault.history.picked.as_ref() != Some(&commit_id) {
+                    return Task::none();
+                }
+                match result {
+                    Ok(changes) => self.vault.history.changes = changes,
+                    Err(error) => {
+                        self.notifications
+                            .push(Notification::error(error.to_string()));
+                    }
+                }
+                Task::none()
+            }
+            Message::RestoreHistory(commit_id) => {
+                if self.vault.history.running {
+                    return Task::none();
+                }
+                let Some(device_id) = self.device_id() else {
+                    return Task::none();
+                };
+                self.vault.history.running = true;
+                Task::perform(
+                    restore_history(
+                        self.vault_pool.clone(),
+                        self.data_directory(),
+                        device_id,
+                        commit_id,
+                    ),
+                    Message::HistoryRestored,
+                )
+            }
+            Message::HistoryRestored(result) => {
+                self.vault.history.running = false;
+                if !self.async_state.page.lock().unwrap().is_unlocked() {
+                    return Task::none();
+                }
+                match result {
+                    Ok(restored) => {
+                        self.notifications.push(Notification::info(format!(
+                            "Restored {restored} records from the history"
+                        )));
+                        self.vault.history.picked = None;
+                        self.vault.history.changes.clear();
+                        Task::batch([self.list_history(), self.reload_folders()])
+                    }
+                    Err(error) => {
+                        self.notifications
+                            .push(Notification::error(error.to_string()));
+                        Task::none()
+                    }
+                }
+            }
+            Message::SyncTick(_) | Message::SyncNow => {
+                let Some(target) = self.sync_target() else {
+                    return Task::none();
+                };
+                if self.vault.sync.running {
+                    return Task::none();
+                }
+                let Some(device_id) = self.device_id() else {
+                    return Task::none();
+                };
+                self.vault.sync.running = true;
+                let vault_pool = self.vault_pool.clone();
+                Task::perform(
+                    sync_vault_to(target, vault_pool, device_id),
+                    Message::SyncFinished,
+                )
+            }
+            Message::SyncFinished(result) => {
+                self.vault.sync.running = false;
+                // The vault may have been locked while syncing
+                if !self.async_state.page.lock().unwrap().is_unlocked() {
+                    return Task::none();
+                }
+                match result {
+                    Ok(summary) => {
+                        if summary.conflicts > 0 {
+                            self.notifications.push(
+                                Notification::warning(format!(
+                                    "Sync found {} conflicting changes",
+                                    summary.conflicts
+                                ))
+                                .with_action("Review", Message::OpenSyncPage),
+                            );
+                        }
+                        if summary.unreadable > 0 {
+                            self.notifications.push(Notification::warning(format!(
+                                "{} change sets of other devices could not be read",
+                                summary.unreadable
+                            )));
+                        }
+                        let received = summary.received > 0;
+                        self.vault.sync.last_summary = Some(summary);
+                        match received {
+                            true => Task::batch([self.load_sync_state(), self.reload_folders()]),
+                            false => self.load_sync_state(),
+                        }
+                    }
+                    Err(error) => {
+                        self.notifications.push(
+                            Notification::error(error.to_string())
+                                .with_action("Retry", Message::SyncNow),
+                        );
+                        Task::none()
+                    }
+                }
+            }
+            Message::ResolveSyncConflict(conflict_id, restore_other) => Task::perform(
+                resolve_sync_conflict(self.vault_pool.clone(), conflict_id, restore_other),
+                Message::SyncConflictResolved,
+            ),
+            Message::SyncConflictResolved(result) => {
+                if let Err(error) = result {
+                    self.notifications
+                        .push(Notification::error(error.to_string()));
+                }
+                Task::batch([self.load_sync_state(), self.reload_folders()])
+            }
+            Message::FoldersReloaded(result) => {
+                if let Err(error) = result {
+                    self.notifications.push(Notification::from_vault_error(
+                        &error,
+                        Message::FolderPicked(self.vault.folder_id_picked),
+                    ));
+                    return Task::none();
+                }
+                match self.vault.folder_id_picked {
+                    0 => self.open_first_folder(),
+                    folder_id => self.update(Message::FolderPicked(folder_id)),
+                }
+            }
+
+            Message::Lock => self.lock_vault(),
+            Message::LogOut => self.log_out(),
+
             Message::ManualUpdate => {
                 self.application_loading = false;
                 Task::none()
@@ -391,78 +1920,237 @@ impl Keylight {
         }
     }
 
-    fn view(&self) -> Element<'_, Message> {
-        match *self.async_state.page.lock().unwrap() {
-            Page::LogIn => self.login_page(),
-            Page::Passwords => self.passwords_page(),
-            Page::NewVault => self.new_vault_page(),
-            _ => self.login_page(),
+    // The vault name is the first thing an attacker holding the vault files would try
+    fn rate_master_password(&self, master_password: &str) -> Strength {
+        let app_directory = self.async_state.app_directory.read().unwrap();
+        let vault_name = app_directory
+            .new_vault
+            .as_ref()
+            .map(|vault| vault.name.as_str())
+            .unwrap_or_default();
+        estimate_strength(master_password, &[vault_name, "keylight"])
+    }
+
+    // Names this installation in change sets and history commits
+    fn device_id(&mut self) -> Option<String> {
+        if let Some(device_id) = &self.vault.sync.device_id {
+            return Some(device_id.clone());
+        }
+        let app_directory = self.async_state.app_directory.read().unwrap();
+        match sync_device_id(app_directory.vault_directory()) {
+            Ok(device_id) => {
+                drop(app_directory);
+                self.vault.sync.device_id = Some(device_id.clone());
+                Some(device_id)
+            }
+            Err(error) => {
+                drop(app_directory);
+                println!("[ERROR]: {:?}", error);
+                self.notifications.push(Notification::error(
+                    "Could not create an id for this device",
+                ));
+                None
+            }
         }
     }
-}
 
-pub trait UiRecovery<T> {
-    fn expect_throw(
-        self,
-        send_to: Arc<Mutex<VecDeque<String>>>,
-        message: &'static str,
-    ) -> Result<T, u8>;
-}
+    // Everything to load once the vault is unlocked or created
+    fn vault_opened(&mut self) -> Task<Message> {
+        self.secret_service_enabled = self.settings.secret_service;
+        self.ssh_agent_enabled = self.settings.ssh_agent;
+        Task::batch([
+            self.open_first_folder(),
+            self.load_sync_state(),
+            self.load_history_settings(),
+            Task::perform(
+                load_vault_settings(self.vault_pool.clone()),
+                Message::VaultSettingsLoaded,
+            ),
+        ])
+    }
 
-impl<T, E: std::fmt::Debug> UiRecovery<T> for Result<T, E> {
-    fn expect_throw(
-        self,
-        send_to: Arc<Mutex<VecDeque<String>>>,
-        message: &'static str,
-    ) -> Result<T, u8> {
-        if self.is_ok() {
-            drop(send_to);
-            return Ok(self.ok().unwrap());
-        } else {
-            send_to.lock().unwrap().push_back(message.to_string());
-            println!("[ERROR]: {:?}", self.err().unwrap());
-            drop(send_to);
-            return Err(0);
+    // Saved right away on every change, settings.toml is small
+    fn save_settings(&mut self) {
+        let config_directory = self.config_directory();
+        if let Err(error) = save_app_settings(&config_directory, &self.settings) {
+            self.notifications
+                .push(Notification::warning(error.to_string()));
         }
     }
-}
 
-impl<T> UiRecovery<T> for Option<T> {
-    fn expect_throw(
-        self,
-        send_to: Arc<Mutex<VecDeque<String>>>,
-        message: &'static str,
-    ) -> Result<T, u8> {
-        if self.is_some() {
-            drop(send_to);
-            return Ok(self.unwrap());
-        } else {
-            send_to.lock().unwrap().push_back(message.to_string());
-            println!("[ERROR]: Failed to unwrap None type");
-            drop(send_to);
-            return Err(0);
+    fn config_directory(&self) -> path::PathBuf {
+        let app_directory = self.async_state.app_directory.read().unwrap();
+        app_directory.config_directory().to_path_buf()
+    }
+
+    fn save_vault_settings(&self) -> Task<Message> {
+        Task::perform(
+            save_vault_settings(self.vault_pool.clone(), self.vault.settings.clone()),
+            Message::VaultSettingsSaved,
+        )
+    }
+
+    fn data_directory(&self) -> path::PathBuf {
+        let app_directory = self.async_state.app_directory.read().unwrap();
+        app_directory.vault_directory().to_path_buf()
+    }
+
+    fn load_history_settings(&self) -> Task<Message> {
+        Task::perform(
+            get_sync_target_settings(self.vault_pool.clone(), "git"),
+            Message::HistorySettingsLoaded,
+        )
+    }
+
+    fn list_history(&self) -> Task<Message> {
+        Task::perform(
+            list_history(self.vault_pool.clone(), self.data_directory()),
+            Message::HistoryListed,
+        )
+    }
+
+    fn load_sync_state(&self) -> Task<Message> {
+        let vault_pool = self.vault_pool.clone();
+        Task::perform(
+            async move {
+                let folder = get_sync_folder(vault_pool.clone()).await?;
+                let webdav = get_sync_target_settings(vault_pool.clone(), "webdav").await?;
+                let s3 = get_sync_target_settings(vault_pool.clone(), "s3").await?;
+                let mut cloud = HashMap::new();
+                for service in CloudService::ALL {
+                    if let Some(settings) =
+                        get_sync_target_settings(vault_pool.clone(), service.state_key()).await?
+                    {
+                        cloud.insert(service, settings);
+                    }
+                }
+                Ok((
+                    folder,
+                    webdav,
+                    s3,
+                    cloud,
+                    get_sync_conflicts(vault_pool).await?,
+                ))
+            },
+            Message::SyncStateLoaded,
+        )
+    }
+
+    // A turned on WebDAV server, S3 bucket or cloud storage service takes the place of the sync
+    // folder, the sync page only lets one of them be turned on
+    fn sync_target(&self) -> Option<SyncTarget> {
+        match (&self.vault.sync.webdav, &self.vault.sync.s3) {
+            (Some(webdav), _) if self.sync_services.webdav_enabled => {
+                return Some(SyncTarget::WebDav(webdav.clone()))
+            }
+            (_, Some(s3)) if self.sync_services.s3_enabled => {
+                return Some(SyncTarget::S3(s3.clone()))
+            }
+            _ => {}
+        }
+        CloudService::ALL
+            .into_iter()
+            .filter(|service| self.sync_services.cloud_enabled(*service))
+            .find_map(|service| {
+                let settings = self.vault.sync.cloud.get(&service)?;
+                settings
+                    .is_connected()
+                    .then(|| SyncTarget::Cloud(service, settings.clone()))
+            })
+            .or_else(|| self.vault.sync.folder.clone().map(SyncTarget::Folder))
+    }
+
+    fn toggle_cloud(&mut self, service: CloudService, enabled: bool) -> Task<Message> {
+        let Some(settings) = self.vault.sync.cloud.get_mut(&service) else {
+            return Task::none();
+        };
+        if !settings.is_connected() {
+            return Task::none();
         }
+        settings.enabled = enabled;
+        self.sync_services.set_cloud_enabled(service, enabled);
+        self.save_cloud(service)
     }
-}
 
-impl Default for Keylight {
-    fn default() -> Self {
-        Keylight::new()
+    // The client id is kept even while disconnected, so connecting again is one click
+    fn save_cloud(&self, service: CloudService) -> Task<Message> {
+        let settings = self
+            .vault
+            .sync
+            .cloud
+            .get(&service)
+            .filter(|settings| !settings.client_id.is_empty() || settings.is_connected())
+            .cloned();
+        Task::perform(
+            set_sync_target_settings(self.vault_pool.clone(), service.state_key(), settings),
+            move |result| Message::CloudSaved(service, result),
+        )
     }
-}
 
-impl Zeroize for ApplicationAsyncState {
-    fn zeroize(&mut self) {
-        self.master_password.write().unwrap().zeroize();
-        self.master_password_confirmation.write().unwrap().zeroize();
-        self.mem_block.lock().unwrap().zeroize();
-        self.display_folders.lock().unwrap().zeroize();
+    // Synced changes may touch any folder, the shown entries are loaded again afterwards
+    fn reload_folders(&self) -> Task<Message> {
+        let vault_pool = self.vault_pool.clone();
+        let state = self.async_state.clone();
+        Task::perform(
+            async move {
+                let folders = state.clone().get_folders(vault_pool).await?;
+                let mut folders_access = state.display_folders.lock().unwrap();
+                (*folders_access).zeroize();
+                *folders_access = folders;
+                Ok(())
+            },
+            Message::FoldersReloaded,
+        )
+    }
+
+    fn open_first_folder(&mut self) -> Task<Message> {
+        let first_folder = self
+            .async_state
+            .display_folders
+            .lock()
+            .unwrap()
+            .first()
+            .map(|folder| folder.folder_id);
+
+        match first_folder {
+            Some(folder_id) => self.update(Message::FolderPicked(folder_id)),
+            None => Task::none(),
+        }
+    }
+
+    fn view(&self) -> Element<'_, Message> {
+        let page = match *self.async_state.page.lock().unwrap() {
+            Page::LogIn => self.login_page(),
+            Page::Passwords => self.passwords_page(),
+            Page::NewVault => self.new_vault_page(),
+            Page::Security => self.security_page(),
+            Page::Sync => self.sync_page(),
+            Page::History => self.history_page(),
+            Page::Settings => self.settings_page(),
+        };
+
+        stack![page, self.notifications_overlay()].into()
+    }
+
+    fn subscription(&self) -> Subscription<Message> {
+        Subscription::batch([
+            self.notification_subscription(),
+            self.auto_lock_subscription(),
+            self.totp_subscription(),
+            self.auto_type_subscription(),
+            self.secret_service_subscription(),
+            self.ssh_agent_subscription(),
+            self.sync_subscription(),
+            self.history_subscription(),
+            self.theme_subscription(),
+            self.pane_layout_subscription(),
+        ])
     }
 }
 
-impl Drop for ApplicationAsyncState {
-    fn drop(&mut self) {
-        self.zeroize();
+impl Default for Keylight {
+    fn default() -> Self {
+        Keylight::new()
     }
 }
 
@@ -0,0 +1,147 @@
+use crate::*;
+use iced::widget::{column, toggler};
+// History Page
+impl Keylight {
+    fn history_commits_view(&self) -> Element<Message> {
+        let mut commits = column![text("Versions").size(18)].spacing(2);
+        if self.vault.history.commits.is_empty() {
+            commits = commits.push(text("Nothing was committed yet"));
+        }
+        for commit in &self.vault.history.commits {
+            let picked = self.vault.history.picked.as_ref() == Some(&commit.id);
+            commits = commits.push(
+                button(column![
+                    text(commit.summary.as_str()),
+                    text(format!(
+                        "{}, {}",
+                        Self::age_label(commit.time),
+                        self.device_label(&commit.device_id)
+                    ))
+                    .size(12),
+                ])
+                .width(Fill)
+                .style(match picked {
+                    true => button::primary,
+                    false => button::text,
+                })
+                .on_press(Message::HistoryCommitPicked(commit.id.clone())),
+            );
+        }
+        commits.into()
+    }
+
+    fn history_changes_view(&self) -> Element<Message> {
+        let Some(commit_id) = &self.vault.history.picked else {
+            return text("Pick a version to see what it changed").into();
+        };
+        let mut changes = column![row![
+            text(format!("Version {}", &commit_id[..8])).size(18),
+            iced::widget::horizontal_space(),
+            button("Restore this version").on_press_maybe(
+                (!self.vault.history.running).then(|| Message::RestoreHistory(commit_id.clone()))
+            ),
+        ]
+        .align_y(Center)]
+        .spacing(2);
+        if self.vault.history.changes.is_empty() {
+            changes = changes.push(text("This version changed nothing in the vault"));
+        }
+        for change in &self.vault.history.changes {
+            changes = changes.push(text(change.as_str()));
+        }
+        changes.into()
+    }
+
+    pub(crate) fn history_page(&self) -> Element<Message> {
+        let toolbar = row![
+            button("Back").on_press(Message::CloseHistoryPage),
+            iced::widget::horizontal_space(),
+            button("Lock").on_press(Message::Lock),
+        ]
+        .spacing(5)
+        .padding(5)
+        .align_y(Center);
+
+        let enabled = self.vault.history.input.enabled;
+        let status = match (&self.vault.history.last_summary, self.vault.history.running) {
+            (_, true) => "Updating the history...".to_string(),
+            (Some(summary), false) if summary.received > 0 => format!(
+                "The last update took over {} changes from the remote",
+                summary.received
+            ),
+            (Some(_), false) => "The history is up to date".to_string(),
+            (None, false) if enabled => "Not updated yet".to_string(),
+            (None, false) => "The history is off".to_string(),
+        };
+        let input = &self.vault.history.input;
+        let settings = column![
+            text("Git history").size(18),
+            text(
+                "Every change to the vault is committed, encrypted, to a git repository on this \
+                device. With a remote the history is shared like a sync target: edits to different \
+                entries are merged, edits to the same entry keep the newest and list the other one \
+                as a conflict on the sync page."
+            )
+            .size(12),
+            toggler(enabled)
+                .label("Keep a history")
+                .on_toggle(Message::ToggleGitHistory),
+            row![
+                text_input(
+                    "Remote, empty keeps the history on this device",
+                    &input.remote_url
+                )
+                .on_input(Message::GitRemoteInput)
+                .on_submit(Message::SaveGitHistory),
+                text_input("Username", &input.username)
+                    .on_input(Message::GitUsernameInput)
+                    .on_submit(Message::SaveGitHistory)
+                    .width(200),
+                text_input("Password or token", &input.password)
+                    .secure(true)
+                    .on_input(Message::GitPasswordInput)
+                    .on_submit(Message::SaveGitHistory)
+                    .width(200),
+                button("Save").on_press(Message::SaveGitHistory),
+                button("Update now").on_press_maybe(
+                    (enabled && !self.vault.history.running).then_some(Message::HistoryNow)
+                ),
+            ]
+            .spacing(5)
+            .align_y(Center),
+            text(status),
+        ]
+        .spacing(5);
+
+        container(column![
+            toolbar,
+            column![
+                settings,
+                row![
+                    scrollable(self.history_commits_view()).width(Length::FillPortion(2)),
+                    scrollable(self.history_changes_view()).width(Length::FillPortion(3)),
+                ]
+                .spacing(15)
+                .height(Fill),
+            ]
+            .spacing(15)
+            .padding(10),
+        ])
+        .width(Fill)
+        .height(Fill)
+        .into()
+    }
+
+    pub(crate) fn history_subscription(&self) -> Subscription<Message> {
+        let enabled = self
+            .vault
+            .history
+            .settings
+            .as_ref()
+            .is_some_and(|settings| settings.enabled);
+        if !enabled || !self.async_state.page.lock().unwrap().is_unlocked() {
+            return Subscription::none();
+        }
+        iced::time::every(HISTORY_INTERVAL).map(|_| Message::HistoryTick)
+    }
+}
@@ -1,5 +1,5 @@
 use crate::*;
-use iced::widget::column;
+use iced::widget::{column, pick_list};
 impl Keylight {
     pub(crate) fn login_page(&self) -> Element<Message> {
         let mut password_input: TextInput<Message> = text_input(
@@ -17,49 +17,76 @@ impl Keylight {
             login_button = login_button.on_press(Message::LogIn);
         }
 
-        let database_options = if self
-            .async_state
-            .app_directory
-            .read()
-            .unwrap()
-            .main_db_exists
-        {
+        let app_directory = self.async_state.app_directory.read().unwrap();
+        let vault_picker = pick_list(
+            app_directory.vaults.clone(),
+            app_directory.selected_vault.clone(),
+            Message::VaultPicked,
+        )
+        .placeholder("No vaults found")
+        .width(255);
+
+        let database_options = if app_directory.selected_vault_exists() {
             row![password_input, login_button,].spacing(5)
         } else {
-            row![
-                button("New Database")
-                    .width(150)
-                    .on_press(Message::NewVault),
-                button("Import Database").width(150),
-            ]
-            .spacing(5)
+            row![button("Import Database").width(150)].spacing(5)
         };
 
+        let mut delete_button = button(if self.vault_deletion_armed {
+            "Cancel"
+        } else {
+            "Delete"
+        })
+        .width(100);
+        let mut confirm_delete_button = button("Confirm Delete").width(150);
+        let mut vault_name_input = text_input("Vault Name", &self.vault_name_input).width(150);
+        let mut vault_path_input =
+            text_input("Path to a .db or .keyfile", &self.vault_path_input).width(150);
+        let mut new_vault_button = button("New Vault").width(100);
+        let mut rename_vault_button = button("Rename").width(100);
+        let mut open_vault_button = button("Open").width(100);
+
+        if !self.application_loading {
+            vault_name_input = vault_name_input
+                .on_input(Message::VaultNameInput)
+                .on_submit(Message::NewVault);
+            vault_path_input = vault_path_input
+                .on_input(Message::VaultPathInput)
+                .on_submit(Message::OpenVaultFromPath);
+            new_vault_button = new_vault_button.on_press(Message::NewVault);
+            open_vault_button = open_vault_button.on_press(Message::OpenVaultFromPath);
+
+            if app_directory.selected_vault_exists() {
+                rename_vault_button = rename_vault_button.on_press(Message::RenameVault);
+                delete_button = delete_button.on_press(Message::DeleteVault);
+                confirm_delete_button = confirm_delete_button.on_press(Message::ConfirmDeleteVault);
+            }
+        }
+
+        let mut vault_management = column![
+            row![vault_name_input, new_vault_button, rename_vault_button].spacing(5),
+            row![vault_path_input, open_vault_button, delete_button].spacing(5),
+        ]
+        .spacing(5);
+
+        if self.vault_deletion_armed {
+            vault_management = vault_management.push(
+                row![
+                    text!("This permanently deletes the selected vault")
+                        .color(Color::from_rgb8(255, 0, 0)),
+                    confirm_delete_button
+                ]
+                .spacing(5)
+                .align_y(Center),
+            );
+        }
+
         container(
             column![
                 row![text!("Keylight").size(50)],
-                column![
-                    database_options,
-                    toggler(self.sync_services.google_drive_enabled)
-                        .label("Google Drive")
-                        .on_toggle(Message::ToggleGoogleDrive),
-                    toggler(self.sync_services.onedrive_enabled)
-                        .label("OneDrive")
-                        .on_toggle(Message::ToggleOneDrive),
-                    toggler(self.sync_services.dropbox_enabled)
-                        .label("DropBox")
-                        .on_toggle(Message::ToggleDropBox)
-                ]
-                .align_x(Left)
-                .spacing(5),
-                text(
-                    self.error_notification
-                        .lock()
-                        .unwrap()
-                        .pop_front()
-                        .unwrap_or_default()
-                )
-                .color(Color::from_rgb8(255, 0, 0)),
+                column![vault_picker, database_options, vault_management,]
+                    .align_x(Left)
+                    .spacing(5),
             ]
             .padding(Padding::from([0, 70]))
             .spacing(20)
@@ -1,7 +1,40 @@
 use crate::*;
-use iced::widget::column;
+use iced::widget::{column, pick_list, progress_bar};
 // New Vault Page
 impl Keylight {
+    fn strength_meter(&self) -> Column<Message> {
+        let Some(strength) = &self.vault.master_password_strength else {
+            return column![];
+        };
+        let master_password = self.async_state.master_password.read().unwrap();
+        if master_password.is_empty() {
+            return column![];
+        }
+
+        let mut meter = column![
+            progress_bar(0.0..=4.0, strength.score as f32 + 0.5).height(8),
+            text!(
+                "{}, about {} to crack offline",
+                strength.label(),
+                strength.crack_time(SLOW_HASH_GUESSES_PER_SECOND)
+            ),
+        ]
+        .spacing(5);
+        if is_common_password(&master_password) {
+            meter = meter.push(
+                text("This is one of the most common passwords and is tried first")
+                    .style(text::danger),
+            );
+        }
+        if let Some(warning) = strength.warning {
+            meter = meter.push(text(warning).style(text::danger));
+        }
+        for suggestion in &strength.suggestions {
+            meter = meter.push(text(*suggestion));
+        }
+        meter
+    }
+
     fn boxed_text(&self, prefix: &str, text: &str) -> Element<Message> {
         text!("{prefix} {text}")
             .height(Length::FillPortion(1))
@@ -27,14 +60,12 @@ impl Keylight {
                     button("Next").on_press(Message::NextVaultCreationState)
                 ]
                 .spacing(5),
-                text(
-                    self.error_notification
-                        .lock()
-                        .unwrap()
-                        .pop_front()
-                        .unwrap_or_default()
-                )
-                .color(Color::from_rgb(255., 0., 0.))
+                self.strength_meter(),
+                pick_list(
+                    MinimumScore::ALL,
+                    Some(self.settings.master_password_minimum),
+                    Message::MinimumScorePicked,
+                ),
             ]
             .width(300)
             .spacing(5)]
@@ -67,14 +98,6 @@ impl Keylight {
                     button("Next").on_press(Message::VerifyMasterPassword),
                 ]
                 .spacing(5),
-                text(
-                    self.error_notification
-                        .lock()
-                        .unwrap()
-                        .pop_front()
-                        .unwrap_or_default()
-                )
-                .color(Color::from_rgb(255., 0., 0.))
             ]
             .width(300)
             .spacing(5)]
@@ -107,14 +130,6 @@ impl Keylight {
                         button("Next").on_press(Message::NextVaultCreationState),
                     ]
                     .spacing(5),
-                    text(
-                        self.error_notification
-                            .lock()
-                            .unwrap()
-                            .pop_front()
-                            .unwrap_or_default()
-                    )
-                    .color(Color::from_rgb(255., 0., 0.))
                 ]
                 .spacing(5)
                 .width(300)]
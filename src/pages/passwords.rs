@@ -1,34 +1,282 @@
 use crate::*;
+use futures::SinkExt;
 use iced::widget::column;
 impl Keylight {
-    pub(crate) fn passwords_page(&self) -> Element<Message> {
-        //let folders = block_on(self.get_folders());
+    fn folders_pane(&self) -> Element<Message> {
+        let mut folders_col: Column<'_, Message> = column![].spacing(2);
+
+        for folder in self.async_state.display_folders.lock().unwrap().as_slice() {
+            folders_col = folders_col.push(
+                button(text(folder.folder_name.clone().unwrap_or(
+                    "Unexpected Error: Folder does not have name".to_string(),
+                )))
+                .width(Fill)
+                .style(if folder.folder_id == self.vault.folder_id_picked {
+                    button::primary
+                } else {
+                    button::text
+                })
+                .on_press(Message::FolderPicked(folder.folder_id)),
+            );
+        }
+
+        scrollable(folders_col).into()
+    }
+
+    fn entries_pane(&self) -> Element<Message> {
+        let mut entries_col: Column<'_, Message> = column![].spacing(2);
+
+        for entry in self.async_state.display_entries.lock().unwrap().as_slice() {
+            let mut label = row![text(
+                entry
+                    .entry_name
+                    .clone()
+                    .unwrap_or("Unnamed entry".to_string()),
+            )
+            .width(Fill)]
+            .spacing(5);
+            if let Some(count) = self.vault.breach_counts.get(&entry.entry_id) {
+                label = label.push(text(format!("Breached {count}×")).style(text::danger));
+            }
+
+            entries_col = entries_col.push(
+                button(label)
+                    .width(Fill)
+                    .style(if entry.entry_id == self.vault.entry_id_picked {
+                        button::primary
+                    } else {
+                        button::text
+                    })
+                    .on_press(Message::EntryPicked(entry.entry_id)),
+            );
+        }
+
+        scrollable(entries_col).into()
+    }
+
+    fn data_pane(&self) -> Element<Message> {
+        let mut data_col: Column<'_, Message> = column![].spacing(5).padding(5);
 
-        let pane_grid =
-            PaneGrid::new(&self.panes, |_, state, _is_maximized| {
-                pane_grid::Content::new(responsive(move |_size| {
-                    let mut folders_col: Column<'_, Message> = column![];
+        if self.vault.entry_id_picked != 0 {
+            data_col = data_col.push(button("Auto-type").on_press(Message::AutoTypeEntry(
+                self.vault.entry_id_picked,
+                MANUAL_AUTO_TYPE_DELAY,
+            )));
+        }
 
-                    for folder in self.async_state.display_folders.lock().unwrap().as_slice() {
-                        folders_col =
-                            folders_col.push(text(folder.folder_name.clone().unwrap_or(
-                                "Unexpected Error: Folder does not have name".to_string(),
-                            )));
+        for section in self
+            .async_state
+            .display_entry_data
+            .lock()
+            .unwrap()
+            .as_slice()
+        {
+            let section_type = section.section_type.as_deref().unwrap_or_default();
+            let value = match section_type {
+                "password" => "••••••••".to_string(),
+                // The public half is readable without the passphrase, so the fingerprint always shows
+                SSH_KEY_SECTION_TYPE => ssh_key::PrivateKey::from_openssh(
+                    section.section_data.as_deref().unwrap_or_default(),
+                )
+                .map(|key| key.fingerprint(ssh_key::HashAlg::Sha256).to_string())
+                .unwrap_or_else(|_| "Invalid SSH key".to_string()),
+                "totp" => {
+                    let data = String::from_utf8_lossy(
+                        section.section_data.as_deref().unwrap_or_default(),
+                    );
+                    match Totp::parse(&data) {
+                        Some(totp) => {
+                            format!("{} ({}s)", totp.current_code(), totp.seconds_remaining())
+                        }
+                        None => "Invalid TOTP secret".to_string(),
                     }
-                    
-
-                    let folders_scrollable = scrollable(folders_col);
-                    
-                    
-                    match state {
-                        PaneState::Data => text!("Data!").into(),
-                        PaneState::Entries => text!("Entries!").into(),
-                        PaneState::Folders => column![folders_scrollable].into(),
+                }
+                _ => {
+                    let data = String::from_utf8_lossy(
+                        section.section_data.as_deref().unwrap_or_default(),
+                    )
+                    .into_owned();
+                    // Base domain matching is the default and goes unmentioned
+                    match UrlMatchMode::from_section_type(section_type) {
+                        Some(mode) if mode != UrlMatchMode::BaseDomain => {
+                            format!("{data} ({mode})")
+                        }
+                        _ => data,
                     }
-                }))
-            })
-            .on_resize(10, Message::PaneResized);
+                }
+            };
+
+            data_col = data_col.push(
+                row![
+                    text(section.section_name.clone().unwrap_or_default()).width(120),
+                    text(value).width(Fill),
+                    button("Copy").on_press(Message::CopySection(section.data_id)),
+                ]
+                .spacing(5)
+                .align_y(Center),
+            );
+        }
+
+        scrollable(data_col).into()
+    }
+
+    // TOTP codes roll over on their own, so the data pane is redrawn while one is visible
+    pub(crate) fn totp_subscription(&self) -> Subscription<Message> {
+        let showing_totp = self
+            .async_state
+            .display_entry_data
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|section| section.section_type.as_deref() == Some("totp"));
+
+        if showing_totp && matches!(*self.async_state.page.lock().unwrap(), Page::Passwords) {
+            iced::time::every(std::time::Duration::from_secs(1)).map(|_| Message::TotpTick)
+        } else {
+            Subscription::none()
+        }
+    }
+
+    // The provider lives as long as the subscription, locking the vault leaves the page and drops it
+    pub(crate) fn secret_service_subscription(&self) -> Subscription<Message> {
+        if !self.secret_service_enabled || !self.async_state.page.lock().unwrap().is_unlocked() {
+            return Subscription::none();
+        }
+
+        let state = self.async_state.clone();
+        let vault_pool = self.vault_pool.clone();
+        Subscription::run_with_id(
+            "secret-service",
+            iced::stream::channel(10, |mut output| async move {
+                let (confirmations, mut requests) = tokio::sync::mpsc::channel(10);
+                let _connection = match serve_secret_service(
+                    state,
+                    vault_pool,
+                    None,
+                    Some(confirmations),
+                )
+                .await
+                {
+                    Ok(connection) => connection,
+                    Err(error) => {
+                        let _ = output
+                            .send(Message::SecretServiceFailed(error.to_string()))
+                            .await;
+                        return;
+                    }
+                };
+
+                while let Some(confirmation) = requests.recv().await {
+                    let _ = output
+                        .send(Message::SecretServiceConfirm(confirmation))
+                        .await;
+                }
+            }),
+        )
+    }
+
+    // Same lifetime as the Secret Service: dropping the subscription removes the socket
+    pub(crate) fn ssh_agent_subscription(&self) -> Subscription<Message> {
+        let vault = self
+            .async_state
+            .app_directory
+            .read()
+            .unwrap()
+            .selected_vault
+            .clone();
+        let Some(vault) = vault.filter(|_| {
+            self.ssh_agent_enabled && self.async_state.page.lock().unwrap().is_unlocked()
+        }) else {
+            return Subscription::none();
+        };
+
+        let state = self.async_state.clone();
+        let vault_pool = self.vault_pool.clone();
+        Subscription::run_with_id(
+            "ssh-agent",
+            iced::stream::channel(10, |mut output| async move {
+                let socket = match bind_ssh_agent(ssh_agent_socket_path(&vault)) {
+                    Ok(socket) => socket,
+                    Err(error) => {
+                        let _ = output
+                            .send(Message::SshAgentFailed(error.to_string()))
+                            .await;
+                        return;
+                    }
+                };
+                let _ = output
+                    .send(Message::SshAgentStarted(socket.path().to_path_buf()))
+                    .await;
+
+                let (confirmations, mut requests) = tokio::sync::mpsc::channel(10);
+                let agent = serve_ssh_agent(state, vault_pool, socket, Some(confirmations));
+                tokio::pin!(agent);
+
+                loop {
+                    tokio::select! {
+                        _ = &mut agent => return,
+                        Some(confirmation) = requests.recv() => {
+                            let _ = output.send(Message::SshAgentConfirm(confirmation)).await;
+                        }
+                    }
+                }
+            }),
+        )
+    }
+
+    pub(crate) fn passwords_page(&self) -> Element<Message> {
+        //let folders = block_on(self.get_folders());
+
+        let pane_grid = PaneGrid::new(&self.vault.panes, |pane, state, is_maximized| {
+            // Panes are dragged by their title bar
+            let controls = row![
+                button(text(if is_maximized { "Restore" } else { "Maximize" }).size(12))
+                    .style(button::text)
+                    .on_press(Message::TogglePaneMaximized(pane)),
+                button(text("Hide").size(12))
+                    .style(button::text)
+                    .on_press_maybe(
+                        (self.vault.panes.len() > 1).then_some(Message::HidePane(pane))
+                    ),
+            ];
+            pane_grid::Content::new(responsive(move |_size| match state {
+                PaneState::Data => self.data_pane(),
+                PaneState::Entries => self.entries_pane(),
+                PaneState::Folders => self.folders_pane(),
+            }))
+            .title_bar(
+                pane_grid::TitleBar::new(text(state.to_string()))
+                    .controls(pane_grid::Controls::new(controls))
+                    .padding(5),
+            )
+        })
+        .on_resize(10, Message::PaneResized)
+        .on_drag(Message::PaneDragged);
+
+        let mut show_panes = row![].spacing(5);
+        for state in self.hidden_panes() {
+            show_panes = show_panes
+                .push(button(text(format!("Show {state}"))).on_press(Message::ShowPane(state)));
+        }
+
+        let toolbar = row![
+            show_panes,
+            button("Reset layout").on_press(Message::ResetPaneLayout),
+            iced::widget::horizontal_space(),
+            button("Settings").on_press(Message::OpenSettingsPage),
+            button("Sync").on_press(Message::OpenSyncPage),
+            button("History").on_press(Message::OpenHistoryPage),
+            button("Security").on_press(Message::OpenSecurityDashboard),
+            button("Lock").on_press(Message::Lock),
+            button("Log out").on_press(Message::LogOut),
+        ]
+        .spacing(5)
+        .padding(5)
+        .align_y(Center);
 
-        container(pane_grid).width(Fill).height(Fill).into()
+        container(column![toolbar, pane_grid])
+            .width(Fill)
+            .height(Fill)
+            .into()
     }
 }
@@ -0,0 +1,155 @@
+use crate::*;
+use iced::widget::{column, pick_list};
+// Security Dashboard Page
+impl Keylight {
+    fn report_entry_button(entry: &ReportEntry) -> Button<'_, Message> {
+        button(text(entry.entry_name.as_str()))
+            .style(button::text)
+            .on_press_maybe(
+                entry
+                    .folder_id
+                    .map(|folder_id| Message::ShowReportEntry(folder_id, entry.entry_id)),
+            )
+    }
+
+    fn report_section<'a>(
+        title: String,
+        rows: impl IntoIterator<Item = Element<'a, Message>>,
+    ) -> Column<'a, Message> {
+        let mut section = column![text(title).size(18)].spacing(2);
+        for row in rows {
+            section = section.push(row);
+        }
+        section
+    }
+
+    fn health_report_view<'a>(&'a self, report: &'a HealthReport) -> Element<'a, Message> {
+        let weak = Self::report_section(
+            format!("Weak passwords ({})", report.weak.len()),
+            report.weak.iter().map(|weak| {
+                row![
+                    Self::report_entry_button(&weak.entry).width(200),
+                    text(format!("{}/4", weak.score))
+                        .style(text::danger)
+                        .width(40),
+                    text(weak.warning.unwrap_or_default()),
+                ]
+                .spacing(5)
+                .align_y(Center)
+                .into()
+            }),
+        );
+
+        let reused = Self::report_section(
+            format!("Reused passwords ({} groups)", report.reused.len()),
+            report.reused.iter().map(|group| {
+                let mut group_row = row![text(format!("Shared by {}:", group.len())).width(100)]
+                    .spacing(5)
+                    .align_y(Center);
+                for entry in group {
+                    group_row = group_row.push(Self::report_entry_button(entry));
+                }
+                group_row.into()
+            }),
+        );
+
+        let old: Vec<&(ReportEntry, u64)> = report
+            .old_passwords(self.vault.settings.password_age_limit)
+            .collect();
+        let old = Self::report_section(
+            format!("Old passwords ({})", old.len()),
+            old.into_iter().map(|(entry, days)| {
+                row![
+                    Self::report_entry_button(entry).width(200),
+                    text(format!("Unchanged for {days} days")),
+                ]
+                .spacing(5)
+                .align_y(Center)
+                .into()
+            }),
+        );
+
+        let missing_totp = Self::report_section(
+            format!(
+                "Logins without two-factor codes ({})",
+                report.missing_totp.len()
+            ),
+            report
+                .missing_totp
+                .iter()
+                .map(|entry| Self::report_entry_button(entry).into()),
+        );
+
+        let breached = Self::report_section(
+            format!("Breached passwords ({})", self.vault.breach_counts.len()),
+            report.password_entries.iter().filter_map(|entry| {
+                let count = self.vault.breach_counts.get(&entry.entry_id)?;
+                Some(
+                    row![
+                        Self::report_entry_button(entry).width(200),
+                        text(format!("Seen {count}× in data breaches")).style(text::danger),
+                    ]
+                    .spacing(5)
+                    .align_y(Center)
+                    .into(),
+                )
+            }),
+        );
+
+        column![
+            text(format!("{} passwords checked", report.password_count)),
+            weak,
+            reused,
+            old,
+            missing_totp,
+            breached,
+        ]
+        .spacing(15)
+        .padding(10)
+        .into()
+    }
+
+    pub(crate) fn security_page(&self) -> Element<Message> {
+        let toolbar = row![
+            button("Back").on_press(Message::CloseSecurityDashboard),
+            button(if self.health_report_running {
+                "Checking..."
+            } else {
+                "Refresh"
+            })
+            .on_press_maybe(
+                (!self.health_report_running).then_some(Message::OpenSecurityDashboard)
+            ),
+            pick_list(
+                PasswordAgeLimit::ALL,
+                Some(self.vault.settings.password_age_limit),
+                Message::PasswordAgeLimitPicked,
+            ),
+            iced::widget::horizontal_space(),
+            text_input("Pwned Passwords dataset", &self.vault.breach_dataset_input)
+                .on_input(Message::BreachDatasetInput)
+                .on_submit(Message::CheckBreaches)
+                .width(200),
+            button(if self.breach_check_running {
+                "Checking..."
+            } else {
+                "Check breaches"
+            })
+            .on_press_maybe((!self.breach_check_running).then_some(Message::CheckBreaches)),
+            button("Lock").on_press(Message::Lock),
+        ]
+        .spacing(5)
+        .padding(5)
+        .align_y(Center);
+
+        let body: Element<Message> = match &self.vault.health_report {
+            Some(report) => scrollable(self.health_report_view(report)).into(),
+            None => container(text("Checking the vault...")).center(Fill).into(),
+        };
+
+        container(column![toolbar, body])
+            .width(Fill)
+            .height(Fill)
+            .into()
+    }
+}
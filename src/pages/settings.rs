@@ -0,0 +1,147 @@
+use crate::*;
+use iced::widget::{column, pick_list, toggler};
+// Settings Page
+impl Keylight {
+    fn application_settings_view(&self) -> Element<Message> {
+        let settings_file = {
+            let app_directory = self.async_state.app_directory.read().unwrap();
+            settings_path(app_directory.config_directory())
+        };
+        column![
+            text("Application").size(18),
+            text(format!(
+                "Saved to {}, the same for every vault",
+                settings_file.display()
+            ))
+            .size(12),
+            pick_list(
+                IdleTimeout::ALL,
+                Some(self.settings.auto_lock.idle_timeout),
+                Message::IdleTimeoutPicked,
+            ),
+            toggler(self.settings.auto_lock.lock_on_sleep)
+                .label("Lock when the system goes to sleep")
+                .on_toggle(Message::ToggleLockOnSleep),
+            toggler(self.settings.auto_lock.lock_on_screen_lock)
+                .label("Lock when the screen is locked")
+                .on_toggle(Message::ToggleLockOnScreenLock),
+            pick_list(
+                ClipboardTimeout::ALL,
+                Some(self.settings.clipboard.clear_after),
+                Message::ClipboardTimeoutPicked,
+            ),
+            toggler(self.settings.clipboard.use_primary_selection)
+                .label("Copy to the primary selection")
+                .on_toggle(Message::TogglePrimarySelection),
+            toggler(self.secret_service_enabled)
+                .label("Secret Service")
+                .on_toggle(Message::ToggleSecretService),
+            toggler(self.ssh_agent_enabled)
+                .label("SSH agent")
+                .on_toggle(Message::ToggleSshAgent),
+            row![
+                text("New vaults"),
+                pick_list(
+                    MinimumScore::ALL,
+                    Some(self.settings.master_password_minimum),
+                    Message::MinimumScorePicked,
+                ),
+            ]
+            .spacing(5)
+            .align_y(Center),
+        ]
+        .spacing(8)
+        .into()
+    }
+
+    fn appearance_view(&self) -> Element<Message> {
+        let themes_file = themes_path(&self.config_directory());
+        let mut appearance = column![
+            text("Appearance").size(18),
+            pick_list(
+                self.theme_choices(),
+                Some(self.settings.theme.clone()),
+                Message::ThemePicked,
+            ),
+            text(format!(
+                "Custom palettes are read from {} as they are saved",
+                themes_file.display()
+            ))
+            .size(12),
+        ]
+        .spacing(8);
+        if let ThemeChoice::Custom(name) = &self.settings.theme {
+            if !self
+                .custom_themes
+                .themes
+                .iter()
+                .any(|theme| theme.to_string() == *name)
+            {
+                appearance = appearance.push(text(format!(
+                    "{name} is not in {}, Dark is used instead",
+                    themes_file.display()
+                )));
+            }
+        }
+        if let Some(error) = &self.custom_themes.error {
+            appearance = appearance.push(text(error.as_str()));
+        }
+        appearance.into()
+    }
+
+    fn vault_settings_view(&self) -> Element<Message> {
+        column![
+            text("This vault").size(18),
+            text("Saved inside the vault, encrypted with it").size(12),
+            row![
+                text("Old passwords"),
+                pick_list(
+                    PasswordAgeLimit::ALL,
+                    Some(self.vault.settings.password_age_limit),
+                    Message::PasswordAgeLimitPicked,
+                ),
+            ]
+            .spacing(5)
+            .align_y(Center),
+            row![
+                text_input("Pwned Passwords dataset", &self.vault.breach_dataset_input)
+                    .on_input(Message::BreachDatasetInput)
+                    .on_submit(Message::SaveBreachDataset),
+                button("Save").on_press(Message::SaveBreachDataset),
+            ]
+            .spacing(5)
+            .align_y(Center),
+        ]
+        .spacing(8)
+        .into()
+    }
+
+    pub(crate) fn settings_page(&self) -> Element<Message> {
+        let toolbar = row![
+            button("Back").on_press(Message::CloseSettingsPage),
+            iced::widget::horizontal_space(),
+            button("Restore defaults").on_press(Message::ResetSettings),
+            button("Lock").on_press(Message::Lock),
+        ]
+        .spacing(5)
+        .padding(5)
+        .align_y(Center);
+
+        container(column![
+            toolbar,
+            scrollable(
+                column![
+                    self.appearance_view(),
+                    self.application_settings_view(),
+                    self.vault_settings_view()
+                ]
+                .spacing(25)
+                .padding(10)
+                .max_width(600)
+            ),
+        ])
+        .width(Fill)
+        .height(Fill)
+        .into()
+    }
+}
@@ -0,0 +1,304 @@
+use crate::*;
+use iced::widget::{column, toggler};
+// Sync Page
+impl Keylight {
+    // Device ids are long and random, the start is enough to tell them apart
+    pub(crate) fn device_label(&self, device_id: &str) -> String {
+        if self.vault.sync.device_id.as_deref() == Some(device_id) {
+            "this device".to_string()
+        } else {
+            format!("device {}", &device_id[..device_id.len().min(8)])
+        }
+    }
+
+    // How long ago a unix time was, also used by the history page
+    pub(crate) fn age_label(time: i64) -> String {
+        let minutes = (unix_now() - time).max(0) / 60;
+        match minutes {
+            0 => "just now".to_string(),
+            1..60 => format!("{minutes} minutes ago"),
+            60..1440 => format!("{} hours ago", minutes / 60),
+            _ => format!("{} days ago", minutes / 1440),
+        }
+    }
+
+    fn conflicts_view(&self) -> Element<Message> {
+        let mut conflicts =
+            column![text(format!("Conflicts ({})", self.vault.sync.conflicts.len())).size(18)]
+                .spacing(5);
+        if self.vault.sync.conflicts.is_empty() {
+            conflicts = conflicts.push(text("Every device agrees on every record"));
+        }
+
+        for conflict in &self.vault.sync.conflicts {
+            let age = Self::age_label(conflict.other_modified_at);
+            conflicts = conflicts.push(
+                row![
+                    column![
+                        text(conflict.label.as_str()),
+                        text(format!(
+                            "Kept the version from {}, the other one is from {} ({age})",
+                            self.device_label(&conflict.kept_device),
+                            self.device_label(&conflict.other_device),
+                        ))
+                        .size(12),
+                    ]
+                    .width(Fill),
+                    button("Keep")
+                        .on_press(Message::ResolveSyncConflict(conflict.conflict_id, false)),
+                    button("Use other")
+                        .on_press(Message::ResolveSyncConflict(conflict.conflict_id, true)),
+                ]
+                .spacing(5)
+                .align_y(Center),
+            );
+        }
+        conflicts.into()
+    }
+
+    fn cloud_view(&self, service: CloudService) -> Element<Message> {
+        let settings = self.vault.sync.cloud.get(&service);
+        let connected = settings.is_some_and(CloudSettings::is_connected);
+        let client_id = settings.map_or("", |settings| settings.client_id.as_str());
+        let toggle = match service {
+            CloudService::GoogleDrive => Message::ToggleGoogleDrive,
+            CloudService::OneDrive => Message::ToggleOneDrive,
+            CloudService::Dropbox => Message::ToggleDropBox,
+        };
+        let enabled = self.sync_services.cloud_enabled(service);
+
+        let mut credentials = row![text_input("Client id", client_id)
+            .on_input(move |input| Message::CloudClientIdInput(service, input))
+            .on_submit(Message::ConnectCloud(service))]
+        .spacing(5)
+        .align_y(Center);
+        // Google's installed apps get a client secret along with the id
+        if service == CloudService::GoogleDrive {
+            let client_secret = settings.map_or("", |settings| settings.client_secret.as_str());
+            credentials = credentials.push(
+                text_input("Client secret", client_secret)
+                    .secure(true)
+                    .on_input(move |input| Message::CloudClientSecretInput(service, input))
+                    .on_submit(Message::ConnectCloud(service)),
+            );
+        }
+        credentials = match connected {
+            true => {
+                credentials.push(button("Disconnect").on_press(Message::DisconnectCloud(service)))
+            }
+            false => credentials.push(button("Connect").on_press(Message::ConnectCloud(service))),
+        };
+
+        let mut section = column![
+            text(service.to_string()).size(18),
+            toggler(enabled)
+                .label(format!("Sync through {service}"))
+                .on_toggle_maybe(
+                    (connected && self.sync_services.can_turn_on(enabled)).then_some(toggle)
+                ),
+            credentials,
+        ]
+        .spacing(5);
+
+        if let Some(pending) = self
+            .vault
+            .sync
+            .pending_authorization
+            .as_ref()
+            .filter(|pending| pending.service == service)
+        {
+            let open_page = button("Open page").on_press(Message::OpenAuthorizationPage);
+            let cancel = button("Cancel").on_press(Message::CancelCloudAuthorization);
+            section = section.push(match &pending.user_code {
+                Some(user_code) => column![
+                    text(format!(
+                        "Enter the code {user_code} at {} to allow access, this page updates once you did",
+                        pending.page
+                    )),
+                    row![open_page, cancel].spacing(5),
+                ]
+                .spacing(5),
+                None => column![
+                    text(format!(
+                        "Allow access at {}, then paste the code it shows here",
+                        pending.page
+                    )),
+                    row![
+                        text_input("Code", &self.vault.sync.authorization_code_input)
+                            .on_input(Message::CloudCodeInput)
+                            .on_submit(Message::FinishCloudAuthorization),
+                        button("Finish").on_press(Message::FinishCloudAuthorization),
+                        open_page,
+                        cancel,
+                    ]
+                    .spacing(5)
+                    .align_y(Center),
+                ]
+                .spacing(5),
+            });
+        }
+        section.into()
+    }
+
+    pub(crate) fn sync_page(&self) -> Element<Message> {
+        let toolbar = row![
+            button("Back").on_press(Message::CloseSyncPage),
+            iced::widget::horizontal_space(),
+            button("Lock").on_press(Message::Lock),
+        ]
+        .spacing(5)
+        .padding(5)
+        .align_y(Center);
+
+        let status = match (&self.vault.sync.last_summary, self.vault.sync.running) {
+            (_, true) => "Syncing...".to_string(),
+            (Some(summary), false) => format!(
+                "Last sync sent {} and received {} changes",
+                summary.sent, summary.received
+            ),
+            (None, false) if self.sync_target().is_some() => "Not synced yet".to_string(),
+            (None, false) => "Sync is off".to_string(),
+        };
+
+        let folder =
+            column![
+            text("Sync folder").size(18),
+            text(
+                "Change sets are written here encrypted. Use a network share or a folder another \
+                tool replicates, other devices join by opening a copy of this vault. Copies made \
+                before this version of Keylight opened the vault never sync, copy it again from here."
+            )
+            .size(12),
+            row![
+                text_input("Directory, empty turns sync off", &self.vault.sync.folder_input)
+                    .on_input(Message::SyncFolderInput)
+                    .on_submit(Message::SaveSyncFolder),
+                button("Save").on_press(Message::SaveSyncFolder),
+                button("Sync now").on_press_maybe(
+                    (self.sync_target().is_some() && !self.vault.sync.running)
+                        .then_some(Message::SyncNow)
+                ),
+            ]
+            .spacing(5)
+            .align_y(Center),
+            text(status),
+        ]
+            .spacing(5);
+
+        let webdav_input = &self.vault.sync.webdav_input;
+        let webdav =
+            column![
+            text("WebDAV").size(18),
+            text(
+                "Nextcloud, ownCloud or any other WebDAV server, used instead of the sync folder \
+                while turned on. All devices of the vault have to sync to the same place."
+            )
+            .size(12),
+            toggler(self.sync_services.webdav_enabled)
+                .label("Sync through WebDAV")
+                .on_toggle_maybe(
+                    (self.vault.sync.webdav.is_some()
+                        && self.sync_services.can_turn_on(self.sync_services.webdav_enabled))
+                        .then_some(Message::ToggleWebDav)
+                ),
+            text_input(
+                "https://cloud.example.com/remote.php/dav/files/user/keylight",
+                &webdav_input.url
+            )
+            .on_input(Message::WebDavUrlInput)
+            .on_submit(Message::SaveWebDav),
+            row![
+                text_input("Username", &webdav_input.username)
+                    .on_input(Message::WebDavUsernameInput)
+                    .on_submit(Message::SaveWebDav),
+                text_input("Password or app password", &webdav_input.password)
+                    .secure(true)
+                    .on_input(Message::WebDavPasswordInput)
+                    .on_submit(Message::SaveWebDav),
+                button("Save").on_press(Message::SaveWebDav),
+            ]
+            .spacing(5)
+            .align_y(Center),
+        ]
+            .spacing(5);
+
+        let s3_input = &self.vault.sync.s3_input;
+        let s3 =
+            column![
+            text("S3").size(18),
+            text(
+                "AWS, MinIO, Backblaze B2 or any other S3 compatible storage, used instead of the \
+                sync folder while turned on. Turn on versioning for the bucket to keep change sets \
+                from being replaced."
+            )
+            .size(12),
+            toggler(self.sync_services.s3_enabled)
+                .label("Sync through S3")
+                .on_toggle_maybe(
+                    (self.vault.sync.s3.is_some()
+                        && self.sync_services.can_turn_on(self.sync_services.s3_enabled))
+                        .then_some(Message::ToggleS3)
+                ),
+            row![
+                text_input("https://s3.eu-central-1.amazonaws.com", &s3_input.endpoint)
+                    .on_input(Message::S3EndpointInput)
+                    .on_submit(Message::SaveS3),
+                text_input("Region, us-east-1 if empty", &s3_input.region)
+                    .on_input(Message::S3RegionInput)
+                    .on_submit(Message::SaveS3)
+                    .width(200),
+                text_input("Bucket", &s3_input.bucket)
+                    .on_input(Message::S3BucketInput)
+                    .on_submit(Message::SaveS3)
+                    .width(200),
+            ]
+            .spacing(5),
+            row![
+                text_input("Access key id", &s3_input.access_key_id)
+                    .on_input(Message::S3AccessKeyInput)
+                    .on_submit(Message::SaveS3),
+                text_input("Secret access key", &s3_input.secret_access_key)
+                    .secure(true)
+                    .on_input(Message::S3SecretKeyInput)
+                    .on_submit(Message::SaveS3),
+                button("Save").on_press(Message::SaveS3),
+            ]
+            .spacing(5)
+            .align_y(Center),
+        ]
+            .spacing(5);
+
+        let cloud = CloudService::ALL.into_iter().fold(
+            column![
+                text("Cloud storage").size(18),
+                text(
+                    "Change sets go to the app folder of the service, used instead of the sync \
+                    folder while turned on. Connecting needs the client id of an app registered \
+                    with the service, the login stays inside the vault."
+                )
+                .size(12),
+            ]
+            .spacing(10),
+            |cloud, service| cloud.push(self.cloud_view(service)),
+        );
+
+        container(column![
+            toolbar,
+            scrollable(
+                column![folder, webdav, s3, cloud, self.conflicts_view()]
+                    .spacing(15)
+                    .padding(10)
+            ),
+        ])
+        .width(Fill)
+        .height(Fill)
+        .into()
+    }
+
+    pub(crate) fn sync_subscription(&self) -> Subscription<Message> {
+        if self.sync_target().is_none() || !self.async_state.page.lock().unwrap().is_unlocked() {
+            return Subscription::none();
+        }
+        iced::time::every(SYNC_INTERVAL).map(Message::SyncTick)
+    }
+}